@@ -0,0 +1,1347 @@
+//! Dashboard and JSON API served on the local network (hardware-independent)
+//!
+//! - `GET /`: HTML page with the current readings and device state
+//! - `GET /api/v1/readings`: last good reading, its age and comfort level
+//! - `GET /api/v1/history`: readings kept for trends, oldest first
+//! - `GET /api/v1/status`: firmware, uptime, WiFi link and error counters
+//! - `GET /api/v1/weather`: outdoor conditions and forecast, when configured
+//! - `GET /api/v1/firmware`: OTA slot running and whether it is on trial
+//! - `GET /metrics`: the same readings and counters for Prometheus
+//!
+//! The configuration is read and changed with:
+//!
+//! - `GET /api/v1/config`: settings, without passwords and keys
+//! - `PUT /api/v1/config`: object with the settings to change, e.g.
+//!   `{"refresh_interval_s": 30, "offsets": {"temperature_c": -0.5}}`
+//! - `GET /api/v1/networks`: known WiFi networks, preferred first
+//! - `POST /api/v1/networks`: `{"ssid": ..., "password": ...}` to remember
+//! - `DELETE /api/v1/networks?ssid=...`: forget a network
+//!
+//! `POST /api/v1/firmware` installs the application image in its body, with
+//! its SHA-256 in an `X-Firmware-SHA256` header and, when an update key is
+//! configured, its HMAC-SHA-256 in `X-Firmware-Signature`, both in hex.
+//!
+//! When an API token is configured these need an `Authorization: Bearer`
+//! header with it. Temperatures are in Celsius, humidity in % and pressure in
+//! hPa. Times are Unix times, null until the clock is synchronized.
+
+use core::fmt::{self, Write};
+
+use embassy_time::Instant;
+use heapless::String;
+
+use crate::config::{Config, WifiNetwork};
+use crate::error::{GonkError, UpdateError};
+use crate::http::{self, Method, Request, Status};
+use crate::json::{self, ObjectWriter, Parser};
+use crate::metrics;
+use crate::model::{Freshness, Model, format_stale, format_value};
+use crate::mqtt;
+use crate::ota::Update;
+use crate::screen::{Page, format_uptime};
+use crate::settings::{Rotation, TemperatureUnit};
+use crate::sha256;
+use crate::traits::DisplayType;
+use crate::tz::{MAX_TZ_LEN, TimeFormat, TimeZone};
+use crate::wifi::{self, WifiState};
+
+/// Largest response body, the history being the longest
+pub const MAX_RESPONSE: usize = 16 * 1024;
+/// Seconds between two reloads of the dashboard
+const DASHBOARD_REFRESH: u32 = 30;
+
+pub type Body = String<MAX_RESPONSE>;
+
+/// Status and type of a response whose body was written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reply {
+    pub status: Status,
+    pub content_type: &'static str,
+}
+
+impl Reply {
+    const HTML: &'static str = "text/html; charset=utf-8";
+    const JSON: &'static str = "application/json";
+    const TEXT: &'static str = "text/plain";
+
+    /// Plain text reply with the reason of `status` as body
+    pub fn error(status: Status, body: &mut Body) -> Self {
+        body.clear();
+        let _ = body.push_str(status.reason());
+        Self {
+            status,
+            content_type: Self::TEXT,
+        }
+    }
+}
+
+/// Answer `request` from the model, the body is written to `body`
+pub fn handle(request: &Request, model: &Model, now: Instant, body: &mut Body) -> Reply {
+    type Page = fn(&mut Body, &Model, Instant) -> fmt::Result;
+    let (page, content_type): (Page, _) = match request.path {
+        "/" => (write_dashboard, Reply::HTML),
+        "/api/v1/readings" => (write_readings, Reply::JSON),
+        "/api/v1/history" => (write_history, Reply::JSON),
+        "/api/v1/status" => (write_status, Reply::JSON),
+        "/api/v1/weather" => (write_weather, Reply::JSON),
+        "/api/v1/firmware" => (write_firmware, Reply::JSON),
+        "/metrics" => (metrics::write_metrics, metrics::CONTENT_TYPE),
+        _ => return Reply::error(Status::NotFound, body),
+    };
+    if !matches!(request.method, Method::Get | Method::Head) {
+        return Reply::error(Status::MethodNotAllowed, body);
+    }
+
+    body.clear();
+    match page(body, model, now) {
+        Ok(()) => Reply {
+            status: Status::Ok,
+            content_type,
+        },
+        Err(fmt::Error) => Reply::error(Status::InternalServerError, body),
+    }
+}
+
+fn write_readings<W: Write>(out: &mut W, model: &Model, now: Instant) -> fmt::Result {
+    let mut object = ObjectWriter::new(out)?;
+    object.number("temperature_c", model.temperature, 2)?;
+    object.number("humidity_pct", model.humidity, 2)?;
+    object.number("pressure_hpa", model.pressure.map(|p| p / 100.0), 2)?;
+    match model.last_reading {
+        Some(at) => object.integer("age_s", now.saturating_duration_since(at).as_secs() as i64)?,
+        None => object.null("age_s")?,
+    }
+    let time = model.last_reading.and_then(|at| model.clock.unix_time(at));
+    write_time(&mut object, "time", time)?;
+    object.boolean("stale", matches!(model.freshness(now), Freshness::Stale(_)))?;
+    match model.temperature {
+        Some(temperature) => {
+            let mut comfort = String::<16>::try_from(model.thresholds.status(temperature))
+                .map_err(|_| fmt::Error)?;
+            comfort.make_ascii_lowercase();
+            object.string("comfort", &comfort)?
+        }
+        None => object.null("comfort")?,
+    }
+    object.finish()
+}
+
+fn write_history<W: Write>(out: &mut W, model: &Model, _now: Instant) -> fmt::Result {
+    let mut object = ObjectWriter::new(out)?;
+    let readings = object.member("readings")?;
+    readings.write_char('[')?;
+    for (i, reading) in model.history.oldest_ordered().enumerate() {
+        if i > 0 {
+            readings.write_char(',')?;
+        }
+        let mut entry = ObjectWriter::new(&mut *readings)?;
+        entry.integer("uptime_s", reading.timestamp.as_secs() as i64)?;
+        write_time(&mut entry, "time", model.clock.unix_time(reading.timestamp))?;
+        entry.number("temperature_c", reading.temperature, 2)?;
+        entry.number("humidity_pct", reading.humidity, 2)?;
+        entry.number("pressure_hpa", reading.pressure.map(|p| p / 100.0), 2)?;
+        entry.finish()?;
+    }
+    readings.write_char(']')?;
+    object.finish()
+}
+
+fn write_status<W: Write>(out: &mut W, model: &Model, now: Instant) -> fmt::Result {
+    let mut object = ObjectWriter::new(out)?;
+    object.string("firmware", env!("CARGO_PKG_VERSION"))?;
+    object.integer("uptime_s", now.as_secs() as i64)?;
+    write_time(&mut object, "time", model.clock.unix_time(now))?;
+    object.string("ip", &model.ip_address)?;
+
+    let mut link = ObjectWriter::new(object.member("wifi")?)?;
+    let (state, ssid) = match &model.wifi {
+        WifiState::Disconnected => ("disconnected", None),
+        WifiState::Scanning => ("scanning", None),
+        WifiState::Connecting(ssid) => ("connecting", Some(ssid)),
+        WifiState::Connected(connected) => ("connected", Some(&connected.ssid)),
+        WifiState::Provisioning(ssid) => ("provisioning", Some(ssid)),
+    };
+    link.string("state", state)?;
+    if let Some(ssid) = ssid {
+        link.string("ssid", ssid)?;
+    }
+    if let WifiState::Connected(connected) = &model.wifi {
+        link.string("bssid", &wifi::format_bssid(connected.bssid))?;
+        link.integer("channel", connected.channel as i64)?;
+        link.integer("rssi_dbm", connected.rssi as i64)?;
+    }
+    link.finish()?;
+
+    let clock = &model.clock;
+    let mut sync = ObjectWriter::new(object.member("clock")?)?;
+    sync.boolean("synced", clock.is_synced())?;
+    match clock.last_sync() {
+        Some(at) => sync.integer(
+            "last_sync_s",
+            now.saturating_duration_since(at).as_secs() as i64,
+        )?,
+        None => sync.null("last_sync_s")?,
+    }
+    sync.number("drift_ppm", Some(clock.drift_ppm()), 2)?;
+    if let Some(local) = model.local_time(now) {
+        sync.string("zone", model.timezone.abbreviation(local.dst))?;
+        sync.integer("utc_offset_s", local.offset as i64)?;
+    }
+    sync.finish()?;
+
+    let errors = &model.errors;
+    let mut counters = ObjectWriter::new(object.member("errors")?)?;
+    for (name, count) in [
+        ("bus", errors.bus),
+        ("timeout", errors.timeout),
+        ("sensor", errors.sensor),
+        ("display", errors.display),
+        ("network", errors.network),
+        ("storage", errors.storage),
+        ("update", errors.update),
+    ] {
+        counters.integer(name, count as i64)?;
+    }
+    counters.finish()?;
+    object.finish()
+}
+
+fn write_firmware<W: Write>(out: &mut W, model: &Model, _now: Instant) -> fmt::Result {
+    let mut object = ObjectWriter::new(out)?;
+    object.string("version", env!("CARGO_PKG_VERSION"))?;
+    let mut slot = String::<8>::new();
+    match model.boot.slot {
+        Some(index) => write!(slot, "ota_{}", index)?,
+        None => slot.push_str("factory").map_err(|_| fmt::Error)?,
+    }
+    object.string("slot", &slot)?;
+    object.boolean("trial", model.boot.trial)?;
+    object.finish()
+}
+
+fn write_time<W: Write>(
+    object: &mut ObjectWriter<W>,
+    name: &str,
+    time: Option<i64>,
+) -> fmt::Result {
+    match time {
+        Some(time) => object.integer(name, time),
+        None => object.null(name),
+    }
+}
+
+fn write_weather<W: Write>(out: &mut W, model: &Model, now: Instant) -> fmt::Result {
+    let mut object = ObjectWriter::new(out)?;
+    let Some(weather) = &model.weather else {
+        object.null("age_s")?;
+        object.null("current")?;
+        return object.finish();
+    };
+    let age = now.saturating_duration_since(weather.updated).as_secs();
+    object.integer("age_s", age as i64)?;
+    object.boolean("stale", !weather.is_current(now))?;
+
+    let c = &weather.current;
+    let mut current = ObjectWriter::new(object.member("current")?)?;
+    current.string("place", &c.place)?;
+    current.integer("time", c.observed_at)?;
+    current.number("temperature_c", Some(c.temperature), 2)?;
+    current.number("feels_like_c", Some(c.feels_like), 2)?;
+    current.number("humidity_pct", Some(c.humidity), 0)?;
+    current.number("pressure_hpa", Some(c.pressure / 100.0), 0)?;
+    current.number("wind_speed_ms", Some(c.wind_speed), 1)?;
+    current.string("description", &c.description)?;
+    current.finish()?;
+
+    let forecast = object.member("forecast")?;
+    forecast.write_char('[')?;
+    for (i, f) in weather.forecast.iter().enumerate() {
+        if i > 0 {
+            forecast.write_char(',')?;
+        }
+        let mut entry = ObjectWriter::new(&mut *forecast)?;
+        entry.integer("time", f.time)?;
+        entry.number("temperature_c", Some(f.temperature), 2)?;
+        entry.number("humidity_pct", Some(f.humidity), 0)?;
+        entry.number("precipitation", Some(f.precipitation), 2)?;
+        entry.string("description", &f.description)?;
+        entry.finish()?;
+    }
+    forecast.write_char(']')?;
+    object.finish()
+}
+
+fn write_row<W: Write>(out: &mut W, name: &str, value: &str) -> fmt::Result {
+    write!(out, "<tr><th>{}</th><td>", name)?;
+    http::write_html_escaped(out, value)?;
+    out.write_str("</td></tr>")
+}
+
+fn write_dashboard<W: Write>(out: &mut W, model: &Model, now: Instant) -> fmt::Result {
+    write!(
+        out,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+<meta name=\"viewport\" content=\"width=device-width\">\
+<meta http-equiv=\"refresh\" content=\"{}\"><title>Gonk</title>\
+<style>body{{font-family:sans-serif}}th{{text-align:left;padding-right:1em}}</style>\
+</head><body><h1>Gonk</h1><table>",
+        DASHBOARD_REFRESH
+    )?;
+
+    let temperature = format_value(model.display_temperature(now), 1, model.units.suffix());
+    write_row(out, "Temperature", &temperature)?;
+    let humidity = format_value(model.current(model.humidity, now), 1, " %");
+    write_row(out, "Humidity", &humidity)?;
+    let pressure = model.current(model.pressure, now).map(|p| p / 100.0);
+    write_row(out, "Pressure", &format_value(pressure, 1, " hPa"))?;
+    let mut updated = String::<16>::new();
+    match (model.freshness(now), model.last_reading) {
+        (Freshness::Stale(age), _) => updated = format_stale(age),
+        (Freshness::Fresh, Some(at)) => {
+            write!(
+                updated,
+                "{} s ago",
+                now.saturating_duration_since(at).as_secs()
+            )?;
+        }
+        _ => updated.write_str("never")?,
+    }
+    write_row(out, "Updated", &updated)?;
+    if let Some(local) = model.local_time(now) {
+        let mut time = String::<32>::new();
+        write!(
+            time,
+            "{} {}, {}",
+            model.time_format.format(&local.time, true),
+            model.timezone.abbreviation(local.dst),
+            local.time.format_date()
+        )?;
+        write_row(out, "Time", &time)?;
+    }
+
+    if let Some(weather) = model.weather.as_ref().filter(|w| w.is_current(now)) {
+        let c = &weather.current;
+        let mut outdoor = String::<64>::new();
+        write!(
+            outdoor,
+            "{:.1}{}, {:.0} %, {} ({})",
+            model.units.convert(c.temperature),
+            model.units.suffix(),
+            c.humidity,
+            c.description,
+            c.place
+        )?;
+        write_row(out, "Outdoor", &outdoor)?;
+    }
+
+    let mut link = String::<64>::new();
+    match &model.wifi {
+        WifiState::Connected(connected) => {
+            write!(link, "{} ({} dBm)", connected.ssid, connected.rssi)?
+        }
+        WifiState::Connecting(ssid) => write!(link, "joining {}", ssid)?,
+        WifiState::Provisioning(ssid) => write!(link, "setup portal on {}", ssid)?,
+        WifiState::Scanning => link.write_str("scanning")?,
+        WifiState::Disconnected => link.write_str("disconnected")?,
+    }
+    write_row(out, "WiFi", &link)?;
+    write_row(out, "IP", &model.ip_address)?;
+    write_row(out, "Uptime", &format_uptime(now))?;
+    let mut errors = String::<16>::new();
+    write!(errors, "{}", model.errors.total())?;
+    write_row(out, "Errors", &errors)?;
+
+    out.write_str(
+        "</table><p><a href=\"/api/v1/readings\">readings</a> \
+<a href=\"/api/v1/history\">history</a> <a href=\"/api/v1/status\">status</a> \
+<a href=\"/api/v1/weather\">weather</a></p>\
+</body></html>",
+    )
+}
+
+/// Requests answered by `handle_config()` rather than `handle()`
+pub fn is_config_request(request: &Request) -> bool {
+    matches!(request.path, "/api/v1/config" | "/api/v1/networks")
+}
+
+/// A configuration request that cannot be applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rejected {
+    status: Status,
+    reason: &'static str,
+}
+
+fn invalid(reason: &'static str) -> Rejected {
+    Rejected {
+        status: Status::BadRequest,
+        reason,
+    }
+}
+
+impl From<json::Error> for Rejected {
+    fn from(_: json::Error) -> Self {
+        invalid("malformed JSON")
+    }
+}
+
+/// Answer a configuration request from `config`
+///
+/// Returns the changed configuration, to save and apply, along with the reply.
+pub fn handle_config(
+    request: &Request,
+    config: &Config,
+    body: &mut Body,
+) -> (Reply, Option<Config>) {
+    if !authorized(request, &config.api_token) {
+        return (Reply::error(Status::Unauthorized, body), None);
+    }
+
+    let read = matches!(request.method, Method::Get | Method::Head);
+    let result = match (request.method, request.path) {
+        (_, "/api/v1/config") if read => Ok(None),
+        (Method::Put, "/api/v1/config") => update_config(request, config).map(Some),
+        (_, "/api/v1/networks") if read => Ok(None),
+        (Method::Post, "/api/v1/networks") => add_network(request, config).map(Some),
+        (Method::Delete, "/api/v1/networks") => remove_network(request, config).map(Some),
+        _ => return (Reply::error(Status::MethodNotAllowed, body), None),
+    };
+    let updated = match result {
+        Ok(updated) => updated,
+        Err(rejected) => return (write_error(rejected.status, rejected.reason, body), None),
+    };
+
+    body.clear();
+    let shown = updated.as_ref().unwrap_or(config);
+    let written = match request.path {
+        "/api/v1/config" => write_config(body, shown),
+        _ => write_networks(body, shown),
+    };
+    let reply = match written {
+        Ok(()) => Reply {
+            status: Status::Ok,
+            content_type: Reply::JSON,
+        },
+        Err(fmt::Error) => Reply::error(Status::InternalServerError, body),
+    };
+    (reply, updated)
+}
+
+/// JSON reply `{"error": reason}`
+fn write_error(status: Status, reason: &str, body: &mut Body) -> Reply {
+    body.clear();
+    let written = ObjectWriter::new(&mut *body).and_then(|mut object| {
+        object.string("error", reason)?;
+        object.finish()
+    });
+    match written {
+        Ok(()) => Reply {
+            status,
+            content_type: Reply::JSON,
+        },
+        Err(fmt::Error) => Reply::error(status, body),
+    }
+}
+
+/// Whether the request carries the API token, always when there is none
+fn authorized(request: &Request, token: &str) -> bool {
+    if token.is_empty() {
+        return true;
+    }
+    let Some(given) = request
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    // Compare every byte so that the time taken does not tell how much matched
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// `value` if it lies in `min..=max`
+fn ranged(value: f32, min: f32, max: f32, reason: &'static str) -> Result<f32, Rejected> {
+    if (min..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(invalid(reason))
+    }
+}
+
+fn update_config(request: &Request, config: &Config) -> Result<Config, Rejected> {
+    let text = request.body_str().ok_or(invalid("body is not UTF-8"))?;
+    let mut updated = config.clone();
+    let mut parser = Parser::new(text);
+
+    parser.object(|parser, name| -> Result<(), Rejected> {
+        let settings = &mut updated.settings;
+        match name {
+            "units" => {
+                settings.units = match parser.string::<16>()?.as_str() {
+                    "celsius" => TemperatureUnit::Celsius,
+                    "fahrenheit" => TemperatureUnit::Fahrenheit,
+                    _ => return Err(invalid("units must be celsius or fahrenheit")),
+                }
+            }
+            "refresh_interval_s" => {
+                let reason = "refresh_interval_s must be between 2 and 3600";
+                let value = ranged(parser.number()?, 2.0, 3600.0, reason)?;
+                settings.refresh_interval_s = value as u16;
+            }
+            "contrast" => {
+                let reason = "contrast must be between 0 and 255";
+                settings.contrast = ranged(parser.number()?, 0.0, 255.0, reason)? as u8;
+            }
+            "screen_timeout_s" => {
+                let reason = "screen_timeout_s must be between 0 and 3600";
+                let value = ranged(parser.number()?, 0.0, 3600.0, reason)?;
+                settings.screen_timeout_s = value as u16;
+            }
+            "rotation" => {
+                settings.rotation = match parser.string::<16>()?.as_str() {
+                    "normal" => Rotation::Normal,
+                    "flipped" => Rotation::Flipped,
+                    _ => return Err(invalid("rotation must be normal or flipped")),
+                }
+            }
+            "display" => {
+                let name = parser.string::<16>()?;
+                updated.display = match name.as_str() {
+                    "auto" => None,
+                    name => Some(
+                        DisplayType::from_name(name)
+                            .ok_or(invalid("display must be auto, epaper or ssd1306"))?,
+                    ),
+                };
+            }
+            "pages" => {
+                let pages = &mut updated.layout.pages;
+                pages.clear();
+                parser.array(|parser| -> Result<(), Rejected> {
+                    let page = Page::from_name(parser.string::<16>()?.as_str())
+                        .ok_or(invalid("unknown page"))?;
+                    pages
+                        .push(page)
+                        .map_err(|_| invalid("pages lists a page twice"))
+                })?;
+            }
+            "auto_rotate" => updated.layout.auto_rotate = parser.boolean()?,
+            "rotate_every_s" => {
+                let reason = "rotate_every_s must be between 2 and 3600";
+                let value = ranged(parser.number()?, 2.0, 3600.0, reason)?;
+                updated.layout.rotate_every_s = value as u16;
+            }
+            "thresholds" => {
+                let thresholds = &mut updated.thresholds;
+                parser.object(|parser, name| -> Result<(), Rejected> {
+                    let reason = "thresholds must be between -40 and 85";
+                    let value = ranged(parser.number()?, -40.0, 85.0, reason)?;
+                    match name {
+                        "cold" => thresholds.cold = value,
+                        "cool" => thresholds.cool = value,
+                        "comfortable" => thresholds.comfortable = value,
+                        "warm" => thresholds.warm = value,
+                        _ => return Err(invalid("unknown threshold")),
+                    }
+                    Ok(())
+                })?;
+            }
+            "offsets" => {
+                let offsets = &mut updated.offsets;
+                parser.object(|parser, name| -> Result<(), Rejected> {
+                    let value = parser.number()?;
+                    match name {
+                        "temperature_c" => {
+                            let reason = "temperature_c offset must be between -10 and 10";
+                            offsets.temperature = ranged(value, -10.0, 10.0, reason)?;
+                        }
+                        "humidity_pct" => {
+                            let reason = "humidity_pct offset must be between -20 and 20";
+                            offsets.humidity = ranged(value, -20.0, 20.0, reason)?;
+                        }
+                        "pressure_hpa" => {
+                            let reason = "pressure_hpa offset must be between -50 and 50";
+                            offsets.pressure = ranged(value, -50.0, 50.0, reason)? * 100.0;
+                        }
+                        _ => return Err(invalid("unknown offset")),
+                    }
+                    Ok(())
+                })?;
+            }
+            "openweather_api_key" => {
+                updated.openweather_api_key = parser
+                    .string()
+                    .map_err(|_| invalid("openweather_api_key is too long"))?;
+            }
+            "timezone" => {
+                let timezone: String<MAX_TZ_LEN> = parser
+                    .string()
+                    .map_err(|_| invalid("timezone is longer than 48 bytes"))?;
+                if TimeZone::parse(&timezone).is_none() {
+                    return Err(invalid("timezone must be a POSIX TZ string"));
+                }
+                updated.timezone = timezone;
+            }
+            "clock_format" => {
+                let reason = "clock_format must be 24h or 12h";
+                updated.time_format = match parser.string::<4>().map_err(|_| invalid(reason))? {
+                    format if format == "24h" => TimeFormat::H24,
+                    format if format == "12h" => TimeFormat::H12,
+                    _ => return Err(invalid(reason)),
+                };
+            }
+            "weather_location" => {
+                updated.weather_location = parser
+                    .string()
+                    .map_err(|_| invalid("weather_location is longer than 48 bytes"))?;
+            }
+            "mqtt_broker" => {
+                let reason = "mqtt_broker must be host or host:port";
+                let broker: String<48> = parser.string().map_err(|_| invalid(reason))?;
+                if !broker.is_empty() && mqtt::parse_broker(&broker).is_none() {
+                    return Err(invalid(reason));
+                }
+                updated.mqtt_broker = broker;
+            }
+            "mqtt_username" => {
+                updated.mqtt_username = parser
+                    .string()
+                    .map_err(|_| invalid("mqtt_username is longer than 32 bytes"))?;
+            }
+            "mqtt_password" => {
+                updated.mqtt_password = parser
+                    .string()
+                    .map_err(|_| invalid("mqtt_password is longer than 64 bytes"))?;
+            }
+            "ota_key" => {
+                updated.ota_key = parser
+                    .string()
+                    .map_err(|_| invalid("ota_key is longer than 64 bytes"))?;
+            }
+            "api_token" => {
+                updated.api_token = parser
+                    .string()
+                    .map_err(|_| invalid("api_token is longer than 32 bytes"))?;
+            }
+            _ => return Err(invalid("unknown setting")),
+        }
+        Ok(())
+    })?;
+    parser.finish()?;
+
+    let t = &updated.thresholds;
+    if !(t.cold < t.cool && t.cool < t.comfortable && t.comfortable < t.warm) {
+        return Err(invalid("thresholds must increase from cold to warm"));
+    }
+    if !updated.layout.is_valid() {
+        return Err(invalid(
+            "pages must list the settings page and no page twice",
+        ));
+    }
+    Ok(updated)
+}
+
+fn add_network(request: &Request, config: &Config) -> Result<Config, Rejected> {
+    let text = request.body_str().ok_or(invalid("body is not UTF-8"))?;
+    let mut network = WifiNetwork {
+        ssid: String::new(),
+        password: String::new(),
+    };
+    let mut parser = Parser::new(text);
+    parser.object(|parser, name| -> Result<(), Rejected> {
+        match name {
+            "ssid" => {
+                network.ssid = parser
+                    .string()
+                    .map_err(|_| invalid("ssid is longer than 32 bytes"))?;
+            }
+            "password" => {
+                network.password = parser
+                    .string()
+                    .map_err(|_| invalid("password is longer than 63 bytes"))?;
+            }
+            _ => return Err(invalid("unknown field")),
+        }
+        Ok(())
+    })?;
+    parser.finish()?;
+
+    if network.ssid.is_empty() {
+        return Err(invalid("ssid is missing"));
+    }
+    if !network.has_valid_password() {
+        return Err(invalid("password must have 8 to 63 characters"));
+    }
+    let mut updated = config.clone();
+    updated.remember_network(network);
+    Ok(updated)
+}
+
+fn remove_network(request: &Request, config: &Config) -> Result<Config, Rejected> {
+    let ssid = request
+        .query
+        .and_then(|query| http::form_value::<32>(query, "ssid"))
+        .ok_or(invalid("ssid is missing"))?;
+    let mut updated = config.clone();
+    let index = updated
+        .networks
+        .iter()
+        .position(|network| network.ssid == ssid)
+        .ok_or(Rejected {
+            status: Status::NotFound,
+            reason: "unknown network",
+        })?;
+    updated.networks.remove(index);
+    Ok(updated)
+}
+
+fn write_config<W: Write>(out: &mut W, config: &Config) -> fmt::Result {
+    let settings = &config.settings;
+    let mut object = ObjectWriter::new(out)?;
+    let units = match settings.units {
+        TemperatureUnit::Celsius => "celsius",
+        TemperatureUnit::Fahrenheit => "fahrenheit",
+    };
+    object.string("units", units)?;
+    object.integer("refresh_interval_s", settings.refresh_interval_s as i64)?;
+    object.integer("contrast", settings.contrast as i64)?;
+    object.integer("screen_timeout_s", settings.screen_timeout_s as i64)?;
+    let rotation = match settings.rotation {
+        Rotation::Normal => "normal",
+        Rotation::Flipped => "flipped",
+    };
+    object.string("rotation", rotation)?;
+    let display = match config.display {
+        None => "auto",
+        Some(DisplayType::EPaper) => "epaper",
+        Some(DisplayType::SSD1306) => "ssd1306",
+    };
+    object.string("display", display)?;
+
+    let layout = &config.layout;
+    let pages = object.member("pages")?;
+    pages.write_char('[')?;
+    for (i, page) in layout.pages.iter().enumerate() {
+        if i > 0 {
+            pages.write_char(',')?;
+        }
+        json::write_str(&mut *pages, page.name())?;
+    }
+    pages.write_char(']')?;
+    object.boolean("auto_rotate", layout.auto_rotate)?;
+    object.integer("rotate_every_s", layout.rotate_every_s as i64)?;
+
+    let t = &config.thresholds;
+    let mut thresholds = ObjectWriter::new(object.member("thresholds")?)?;
+    thresholds.number("cold", Some(t.cold), 1)?;
+    thresholds.number("cool", Some(t.cool), 1)?;
+    thresholds.number("comfortable", Some(t.comfortable), 1)?;
+    thresholds.number("warm", Some(t.warm), 1)?;
+    thresholds.finish()?;
+
+    let o = &config.offsets;
+    let mut offsets = ObjectWriter::new(object.member("offsets")?)?;
+    offsets.number("temperature_c", Some(o.temperature), 2)?;
+    offsets.number("humidity_pct", Some(o.humidity), 2)?;
+    offsets.number("pressure_hpa", Some(o.pressure / 100.0), 2)?;
+    offsets.finish()?;
+
+    object.boolean(
+        "openweather_api_key_set",
+        !config.openweather_api_key.is_empty(),
+    )?;
+    object.string("weather_location", &config.weather_location)?;
+    object.string("timezone", &config.timezone)?;
+    let clock_format = match config.time_format {
+        TimeFormat::H24 => "24h",
+        TimeFormat::H12 => "12h",
+    };
+    object.string("clock_format", clock_format)?;
+    object.string("mqtt_broker", &config.mqtt_broker)?;
+    object.string("mqtt_username", &config.mqtt_username)?;
+    object.boolean("mqtt_password_set", !config.mqtt_password.is_empty())?;
+    object.boolean("ota_key_set", !config.ota_key.is_empty())?;
+    object.boolean("api_token_set", !config.api_token.is_empty())?;
+    object.finish()
+}
+
+fn write_networks<W: Write>(out: &mut W, config: &Config) -> fmt::Result {
+    let mut object = ObjectWriter::new(out)?;
+    let networks = object.member("networks")?;
+    networks.write_char('[')?;
+    for (i, network) in config.networks.iter().enumerate() {
+        if i > 0 {
+            networks.write_char(',')?;
+        }
+        let mut entry = ObjectWriter::new(&mut *networks)?;
+        entry.string("ssid", &network.ssid)?;
+        entry.boolean("open", network.password.is_empty())?;
+        entry.finish()?;
+    }
+    networks.write_char(']')?;
+    object.finish()
+}
+
+/// Firmware uploads, whose body is streamed to an `Update` rather than
+/// buffered
+pub fn is_firmware_upload(request: &Request) -> bool {
+    request.method == Method::Post && request.path == "/api/v1/firmware"
+}
+
+/// Start the update uploaded by `request`, from its headers
+///
+/// `capacity` is the size of the update slot. Returns the reply to send when
+/// the upload is refused.
+pub fn begin_update(
+    request: &Request,
+    config: &Config,
+    capacity: Result<u32, GonkError>,
+    body: &mut Body,
+) -> Result<Update, Reply> {
+    if !authorized(request, &config.api_token) {
+        return Err(Reply::error(Status::Unauthorized, body));
+    }
+    if request.header("Content-Length").is_none() {
+        return Err(write_error(
+            Status::BadRequest,
+            "Content-Length is missing",
+            body,
+        ));
+    }
+    let Some(digest) = request
+        .header("X-Firmware-SHA256")
+        .and_then(sha256::parse_hex)
+    else {
+        let reason = "X-Firmware-SHA256 must have 64 hex digits";
+        return Err(write_error(Status::BadRequest, reason, body));
+    };
+    let signature = match request.header("X-Firmware-Signature") {
+        Some(text) => match sha256::parse_hex(text) {
+            Some(signature) => Some(signature),
+            None => {
+                let reason = "X-Firmware-Signature must have 64 hex digits";
+                return Err(write_error(Status::BadRequest, reason, body));
+            }
+        },
+        None => None,
+    };
+
+    let size = u32::try_from(request.content_length).unwrap_or(u32::MAX);
+    capacity
+        .and_then(|capacity| Update::begin(size, digest, signature, &config.ota_key, capacity))
+        .map_err(|e| update_error(e, body))
+}
+
+/// Reply to an upload once the image was received, checked and activated
+pub fn finish_update(result: Result<(), GonkError>, body: &mut Body) -> Reply {
+    if let Err(e) = result {
+        return update_error(e, body);
+    }
+    body.clear();
+    let written = ObjectWriter::new(&mut *body).and_then(|mut object| {
+        object.boolean("restarting", true)?;
+        object.finish()
+    });
+    match written {
+        Ok(()) => Reply {
+            status: Status::Ok,
+            content_type: Reply::JSON,
+        },
+        Err(fmt::Error) => Reply::error(Status::InternalServerError, body),
+    }
+}
+
+fn update_error(error: GonkError, body: &mut Body) -> Reply {
+    let status = match error {
+        GonkError::Update(UpdateError::TooLarge) => Status::PayloadTooLarge,
+        GonkError::Update(UpdateError::Unsupported) | GonkError::Storage(_) => {
+            Status::InternalServerError
+        }
+        _ => Status::BadRequest,
+    };
+    let mut reason = String::<32>::new();
+    let _ = write!(reason, "{}", error);
+    write_error(status, &reason, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::parse_request;
+    use crate::model::HISTORY_LEN;
+    use crate::traits::Reading;
+    use crate::weather::{CurrentWeather, Forecast, ForecastEntry, Weather};
+    use crate::wifi::Link;
+
+    fn model() -> Model {
+        let mut model = Model::new();
+        model.record_reading(&Reading {
+            temperature: Some(21.456),
+            humidity: Some(40.0),
+            pressure: Some(101325.0),
+            ..Reading::new(Instant::from_secs(10))
+        });
+        model.ip_address = String::try_from("192.168.1.20").unwrap();
+        model.wifi = WifiState::Connected(Link {
+            ssid: String::try_from("home").unwrap(),
+            bssid: [0x24, 0x6F, 0x28, 0x01, 0xAB, 0x0C],
+            channel: 6,
+            rssi: -61,
+        });
+        model
+    }
+
+    fn get(path: &str, model: &Model, now: Instant) -> (Reply, Body) {
+        let mut raw = String::<128>::new();
+        write!(raw, "GET {} HTTP/1.1\r\nHost: gonk\r\n\r\n", path).unwrap();
+        let request = parse_request(raw.as_bytes()).unwrap().unwrap();
+        let mut body = Body::new();
+        let reply = handle(&request, model, now, &mut body);
+        (reply, body)
+    }
+
+    #[test]
+    fn serves_readings() {
+        let (reply, body) = get("/api/v1/readings", &model(), Instant::from_secs(13));
+
+        assert_eq!(reply.status, Status::Ok);
+        assert_eq!(reply.content_type, "application/json");
+        assert_eq!(
+            body,
+            "{\"temperature_c\":21.46,\"humidity_pct\":40.00,\"pressure_hpa\":1013.25,\
+\"age_s\":3,\"time\":null,\"stale\":false,\"comfort\":\"comfortable\"}"
+        );
+
+        let mut synced = model();
+        synced
+            .clock
+            .synchronize(Instant::from_secs(0), 1_792_238_400_000_000);
+        let (_, body) = get("/api/v1/readings", &synced, Instant::from_secs(13));
+        assert!(body.contains("\"age_s\":3,\"time\":1792238410,"));
+
+        let (_, body) = get("/api/v1/readings", &Model::new(), Instant::from_secs(13));
+        assert_eq!(
+            body,
+            "{\"temperature_c\":null,\"humidity_pct\":null,\"pressure_hpa\":null,\
+\"age_s\":null,\"time\":null,\"stale\":false,\"comfort\":null}"
+        );
+    }
+
+    #[test]
+    fn serves_full_history() {
+        let mut model = Model::new();
+        for i in 0..HISTORY_LEN as u64 + 5 {
+            model.record_reading(&Reading {
+                temperature: Some(-12.34),
+                humidity: Some(100.0),
+                pressure: Some(110_000.0),
+                ..Reading::new(Instant::from_secs(100_000 + i))
+            });
+        }
+        model
+            .clock
+            .synchronize(Instant::from_secs(0), 1_792_238_400_000_000);
+
+        let (reply, body) = get("/api/v1/history", &model, Instant::from_secs(200_000));
+        assert_eq!(reply.status, Status::Ok);
+        assert!(body.starts_with(
+            "{\"readings\":[{\"uptime_s\":100005,\"time\":1792338405,\"temperature_c\":-12.34,\
+\"humidity_pct\":100.00,\"pressure_hpa\":1100.00},"
+        ));
+        assert!(body.ends_with("}]}"));
+        assert_eq!(body.matches("uptime_s").count(), HISTORY_LEN);
+    }
+
+    #[test]
+    fn serves_status() {
+        let (_, body) = get("/api/v1/status", &model(), Instant::from_secs(3_700));
+
+        assert!(body.starts_with("{\"firmware\":\""));
+        assert!(body.contains(
+            "\"uptime_s\":3700,\"time\":null,\"ip\":\"192.168.1.20\",\"wifi\":{\"state\":\"connected\",\
+\"ssid\":\"home\",\"bssid\":\"24:6F:28:01:AB:0C\",\"channel\":6,\"rssi_dbm\":-61},\
+\"clock\":{\"synced\":false,\"last_sync_s\":null,\"drift_ppm\":0.00},\"errors\":{\"bus\":0,"
+        ));
+        assert!(body.ends_with("\"storage\":0,\"update\":0}}"));
+    }
+
+    #[test]
+    fn serves_metrics() {
+        let (reply, body) = get("/metrics", &model(), Instant::from_secs(13));
+
+        assert_eq!(reply.status, Status::Ok);
+        assert_eq!(
+            reply.content_type,
+            "text/plain; version=0.0.4; charset=utf-8"
+        );
+        assert!(body.contains("\ngonk_temperature_celsius 21.456\n"));
+        assert!(body.contains("\ngonk_uptime_seconds 13\n"));
+    }
+
+    #[test]
+    fn serves_weather() {
+        let mut model = model();
+        let (_, body) = get("/api/v1/weather", &model, Instant::from_secs(13));
+        assert_eq!(body, "{\"age_s\":null,\"current\":null}");
+
+        let mut forecast = Forecast::new();
+        forecast
+            .push(ForecastEntry {
+                time: 1760702400,
+                temperature: 9.5,
+                humidity: 75.0,
+                precipitation: 0.2,
+                description: String::try_from("light rain").unwrap(),
+            })
+            .unwrap();
+        model.weather = Some(Weather {
+            current: CurrentWeather {
+                place: String::try_from("Berlin").unwrap(),
+                observed_at: 1760700000,
+                temperature: 12.34,
+                feels_like: 11.5,
+                humidity: 81.0,
+                pressure: 101200.0,
+                wind_speed: 4.12,
+                description: String::try_from("mist").unwrap(),
+            },
+            forecast,
+            updated: Instant::from_secs(10),
+        });
+        let (_, body) = get("/api/v1/weather", &model, Instant::from_secs(13));
+        assert_eq!(
+            body,
+            "{\"age_s\":3,\"stale\":false,\"current\":{\"place\":\"Berlin\",\"time\":1760700000,\
+\"temperature_c\":12.34,\"feels_like_c\":11.50,\"humidity_pct\":81,\"pressure_hpa\":1012,\
+\"wind_speed_ms\":4.1,\"description\":\"mist\"},\"forecast\":[{\"time\":1760702400,\
+\"temperature_c\":9.50,\"humidity_pct\":75,\"precipitation\":0.20,\"description\":\"light rain\"}]}"
+        );
+
+        let (_, body) = get("/", &model, Instant::from_secs(13));
+        assert!(body.contains("<tr><th>Outdoor</th><td>12.3 C, 81 %, mist (Berlin)</td></tr>"));
+    }
+
+    #[test]
+    fn serves_dashboard() {
+        let mut model = model();
+        model.wifi = WifiState::Connecting(String::try_from("<script>").unwrap());
+        let (reply, body) = get("/", &model, Instant::from_secs(13));
+
+        assert_eq!(reply.content_type, "text/html; charset=utf-8");
+        assert!(body.contains("<tr><th>Temperature</th><td>21.5 C</td></tr>"));
+        assert!(body.contains("<tr><th>Pressure</th><td>1013.2 hPa</td></tr>"));
+        assert!(body.contains("<tr><th>Updated</th><td>3 s ago</td></tr>"));
+        assert!(body.contains("joining &lt;script&gt;"));
+        assert!(!body.contains("<th>Time</th>"));
+
+        model
+            .clock
+            .synchronize(Instant::from_secs(0), 1_792_238_400_000_000);
+        let (_, body) = get("/", &model, Instant::from_secs(13));
+        assert!(body.contains("<tr><th>Time</th><td>12:00:13 UTC, Sat 17 Oct 2026</td></tr>"));
+
+        model.timezone = TimeZone::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        model.time_format = TimeFormat::H12;
+        let (_, body) = get("/", &model, Instant::from_secs(13));
+        assert!(body.contains("<td>2:00:13 PM CEST, Sat 17 Oct 2026</td>"));
+        assert!(body.ends_with("</html>"));
+    }
+
+    #[test]
+    fn rejects_unknown_paths_and_methods() {
+        let (reply, body) = get("/api/v2/readings", &model(), Instant::from_secs(13));
+        assert_eq!(reply.status, Status::NotFound);
+        assert_eq!(body, "Not Found");
+
+        let request = parse_request(b"DELETE /api/v1/status HTTP/1.1\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        let mut body = Body::new();
+        let reply = handle(&request, &model(), Instant::from_secs(13), &mut body);
+        assert_eq!(reply.status, Status::MethodNotAllowed);
+    }
+
+    fn config_request(raw: &str, config: &Config) -> (Reply, Body, Option<Config>) {
+        let request = parse_request(raw.as_bytes()).unwrap().unwrap();
+        assert!(is_config_request(&request));
+        let mut body = Body::new();
+        let (reply, updated) = handle_config(&request, config, &mut body);
+        (reply, body, updated)
+    }
+
+    fn put_config(json: &str, config: &Config) -> (Reply, Body, Option<Config>) {
+        let mut raw = String::<1024>::new();
+        write!(
+            raw,
+            "PUT /api/v1/config HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            json.len(),
+            json
+        )
+        .unwrap();
+        config_request(&raw, config)
+    }
+
+    #[test]
+    fn updates_config() {
+        let (reply, body, updated) = put_config(
+            r#"{"units": "fahrenheit", "refresh_interval_s": 30, "display": "ssd1306",
+                "thresholds": {"warm": 28.5}, "offsets": {"temperature_c": -0.5,
+                "pressure_hpa": 1.5}, "api_token": "s3cret", "weather_location": "Berlin,DE",
+                "timezone": "CET-1CEST,M3.5.0,M10.5.0/3", "clock_format": "12h",
+                "mqtt_broker": "broker.local", "mqtt_username": "gonk", "mqtt_password": "p4ss",
+                "ota_key": "0tA-k3y", "pages": ["clock", "readings", "settings"],
+                "auto_rotate": true, "rotate_every_s": 20}"#,
+            &Config::default(),
+        );
+
+        assert_eq!(reply.status, Status::Ok);
+        let updated = updated.unwrap();
+        assert_eq!(updated.settings.units, TemperatureUnit::Fahrenheit);
+        assert_eq!(updated.settings.refresh_interval_s, 30);
+        assert_eq!(updated.display, Some(DisplayType::SSD1306));
+        assert_eq!(updated.thresholds.warm, 28.5);
+        assert_eq!(updated.offsets.temperature, -0.5);
+        assert_eq!(updated.offsets.pressure, 150.0);
+        assert_eq!(updated.api_token, "s3cret");
+        assert_eq!(updated.weather_location, "Berlin,DE");
+        assert_eq!(updated.timezone, "CET-1CEST,M3.5.0,M10.5.0/3");
+        assert_eq!(updated.time_format, TimeFormat::H12);
+        assert_eq!(updated.mqtt_broker, "broker.local");
+        assert_eq!(updated.mqtt_password, "p4ss");
+        assert_eq!(updated.ota_key, "0tA-k3y");
+        assert_eq!(
+            updated.layout.pages,
+            [Page::Clock, Page::Readings, Page::Settings]
+        );
+        assert!(updated.layout.auto_rotate);
+        assert_eq!(updated.layout.rotate_every_s, 20);
+        assert!(body.starts_with("{\"units\":\"fahrenheit\",\"refresh_interval_s\":30,"));
+        assert!(body.contains(
+            "\"display\":\"ssd1306\",\"pages\":[\"clock\",\"readings\",\"settings\"],\
+\"auto_rotate\":true,\"rotate_every_s\":20,"
+        ));
+        assert!(body.contains(
+            "\"offsets\":{\"temperature_c\":-0.50,\"humidity_pct\":0.00,\"pressure_hpa\":1.50}"
+        ));
+        assert!(body.ends_with(
+            "\"weather_location\":\"Berlin,DE\",\"timezone\":\"CET-1CEST,M3.5.0,M10.5.0/3\",\
+\"clock_format\":\"12h\",\"mqtt_broker\":\"broker.local\",\"mqtt_username\":\"gonk\",\
+\"mqtt_password_set\":true,\"ota_key_set\":true,\"api_token_set\":true}"
+        ));
+        assert!(!body.contains("s3cret"));
+        assert!(!body.contains("p4ss"));
+        assert!(!body.contains("0tA-k3y"));
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        let config = Config::default();
+        for (json, reason) in [
+            (
+                r#"{"refresh_interval_s": 0}"#,
+                "refresh_interval_s must be between 2 and 3600",
+            ),
+            (
+                r#"{"units": "kelvin"}"#,
+                "units must be celsius or fahrenheit",
+            ),
+            (
+                r#"{"thresholds": {"cold": 26}}"#,
+                "thresholds must increase from cold to warm",
+            ),
+            (
+                r#"{"offsets": {"humidity_pct": 50}}"#,
+                "humidity_pct offset must be between -20 and 20",
+            ),
+            (
+                r#"{"timezone": "Europe/Berlin"}"#,
+                "timezone must be a POSIX TZ string",
+            ),
+            (
+                r#"{"clock_format": "twelve"}"#,
+                "clock_format must be 24h or 12h",
+            ),
+            (
+                r#"{"mqtt_broker": "broker.local:mqtt"}"#,
+                "mqtt_broker must be host or host:port",
+            ),
+            (r#"{"pages": ["readings", "radar"]}"#, "unknown page"),
+            (
+                r#"{"pages": ["readings", "clock"]}"#,
+                "pages must list the settings page and no page twice",
+            ),
+            (
+                r#"{"pages": ["settings", "clock", "settings"]}"#,
+                "pages must list the settings page and no page twice",
+            ),
+            (
+                r#"{"rotate_every_s": 1}"#,
+                "rotate_every_s must be between 2 and 3600",
+            ),
+            (r#"{"refresh": 10}"#, "unknown setting"),
+            (r#"{"contrast": 10"#, "malformed JSON"),
+        ] {
+            let (reply, body, updated) = put_config(json, &config);
+            assert_eq!(reply.status, Status::BadRequest);
+            assert_eq!(reply.content_type, "application/json");
+            let mut expected = String::<96>::new();
+            write!(expected, "{{\"error\":\"{}\"}}", reason).unwrap();
+            assert_eq!(body, expected);
+            assert_eq!(updated, None);
+        }
+    }
+
+    #[test]
+    fn manages_networks() {
+        let mut config = Config::default();
+        config.remember_network(WifiNetwork::new("office", "password1").unwrap());
+
+        let add = |json: &str, config: &Config| {
+            let mut raw = String::<256>::new();
+            write!(
+                raw,
+                "POST /api/v1/networks HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+                json.len(),
+                json
+            )
+            .unwrap();
+            config_request(&raw, config)
+        };
+        let (reply, body, updated) = add(r#"{"ssid": "home", "password": "password2"}"#, &config);
+        assert_eq!(reply.status, Status::Ok);
+        assert_eq!(
+            body,
+            "{\"networks\":[{\"ssid\":\"home\",\"open\":false},{\"ssid\":\"office\",\"open\":false}]}"
+        );
+        let config = updated.unwrap();
+
+        let (reply, _, _) = add(r#"{"ssid": "cafe", "password": "short"}"#, &config);
+        assert_eq!(reply.status, Status::BadRequest);
+
+        let (reply, body, updated) = config_request(
+            "DELETE /api/v1/networks?ssid=office HTTP/1.1\r\n\r\n",
+            &config,
+        );
+        assert_eq!(reply.status, Status::Ok);
+        assert_eq!(body, "{\"networks\":[{\"ssid\":\"home\",\"open\":false}]}");
+        assert_eq!(updated.unwrap().networks.len(), 1);
+
+        let (reply, _, _) = config_request(
+            "DELETE /api/v1/networks?ssid=gone HTTP/1.1\r\n\r\n",
+            &config,
+        );
+        assert_eq!(reply.status, Status::NotFound);
+    }
+
+    #[test]
+    fn config_needs_token_when_set() {
+        let config = Config {
+            api_token: String::try_from("s3cret").unwrap(),
+            ..Config::default()
+        };
+
+        let (reply, _, _) = config_request("GET /api/v1/config HTTP/1.1\r\n\r\n", &config);
+        assert_eq!(reply.status, Status::Unauthorized);
+        let (reply, _, _) = config_request(
+            "GET /api/v1/config HTTP/1.1\r\nAuthorization: Bearer s3crex\r\n\r\n",
+            &config,
+        );
+        assert_eq!(reply.status, Status::Unauthorized);
+        let (reply, _, _) = config_request(
+            "GET /api/v1/config HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n",
+            &config,
+        );
+        assert_eq!(reply.status, Status::Ok);
+
+        // The readings stay open
+        let (reply, _) = get("/api/v1/readings", &model(), Instant::from_secs(13));
+        assert_eq!(reply.status, Status::Ok);
+    }
+
+    /// Headers of a firmware upload, then `begin_update()` on them
+    fn upload(headers: &str, config: &Config) -> (Result<Update, Reply>, Body) {
+        let mut raw = String::<512>::new();
+        write!(raw, "POST /api/v1/firmware HTTP/1.1\r\n{}\r\n", headers).unwrap();
+        let request = http::parse_head(raw.as_bytes()).unwrap().unwrap();
+        assert!(is_firmware_upload(&request));
+        let mut body = Body::new();
+        let result = begin_update(&request, config, Ok(0x1F_0000), &mut body);
+        (result, body)
+    }
+
+    #[test]
+    fn starts_firmware_updates() {
+        const DIGEST: &str = "X-Firmware-SHA256: \
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\r\n";
+        let config = Config {
+            api_token: String::try_from("s3cret").unwrap(),
+            ..Config::default()
+        };
+        let mut headers = String::<256>::new();
+        write!(
+            headers,
+            "Authorization: Bearer s3cret\r\nContent-Length: 786432\r\n{}",
+            DIGEST
+        )
+        .unwrap();
+
+        let (result, _) = upload(&headers, &config);
+        assert_eq!(result.unwrap().size(), 786_432);
+
+        let (result, _) = upload(&headers[30..], &config);
+        assert_eq!(result.err().unwrap().status, Status::Unauthorized);
+
+        let (result, body) = upload(
+            "Content-Length: 4\r\nX-Firmware-SHA256: e3b0\r\n",
+            &Config::default(),
+        );
+        assert_eq!(result.err().unwrap().status, Status::BadRequest);
+        assert_eq!(
+            body,
+            r#"{"error":"X-Firmware-SHA256 must have 64 hex digits"}"#
+        );
+
+        let (result, body) = upload(&headers.replace("786432", "4194304"), &config);
+        assert_eq!(result.err().unwrap().status, Status::PayloadTooLarge);
+        assert_eq!(body, r#"{"error":"image too large"}"#);
+
+        // The key asks for a signature
+        let signed = Config {
+            ota_key: String::try_from("0tA-k3y").unwrap(),
+            ..config
+        };
+        let (result, body) = upload(&headers, &signed);
+        assert_eq!(result.err().unwrap().status, Status::BadRequest);
+        assert_eq!(body, r#"{"error":"bad image signature"}"#);
+
+        let mut body = Body::new();
+        let reply = finish_update(Err(UpdateError::DigestMismatch.into()), &mut body);
+        assert_eq!(reply.status, Status::BadRequest);
+        assert_eq!(body, r#"{"error":"SHA-256 mismatch"}"#);
+        let reply = finish_update(Ok(()), &mut body);
+        assert_eq!(reply.status, Status::Ok);
+        assert_eq!(body, r#"{"restarting":true}"#);
+    }
+
+    #[test]
+    fn serves_firmware_slot() {
+        let mut model = model();
+        let (_, body) = get("/api/v1/firmware", &model, Instant::from_secs(13));
+        assert_eq!(
+            body,
+            concat!(
+                "{\"version\":\"",
+                env!("CARGO_PKG_VERSION"),
+                "\",\"slot\":\"factory\",\"trial\":false}"
+            )
+        );
+
+        model.boot.slot = Some(1);
+        model.boot.trial = true;
+        let (_, body) = get("/api/v1/firmware", &model, Instant::from_secs(13));
+        assert!(body.ends_with("\"slot\":\"ota_1\",\"trial\":true}"));
+    }
+}
@@ -2,37 +2,30 @@
 #![no_main]
 
 use embassy_executor::Spawner;
-use embassy_time::{Duration, Timer};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
 use esp_backtrace as _;
 use esp_hal::{
     gpio::{Input, InputConfig, Pull},
     timer::timg::TimerGroup,
 };
 
-esp_bootloader_esp_idf::esp_app_desc!();
-
-#[embassy_executor::task(pool_size = 2)]
-async fn button_watcher(mut button: Input<'static>, tag: &'static str) {
-    esp_println::println!("Watching for button '{}' presses...", tag);
-
-    loop {
-        button.wait_for_falling_edge().await;
-
-        // Debounce delay - wait for button to stabilize
-        Timer::after(Duration::from_millis(50)).await;
+use gonk::hardware;
+use gonk::input::{ButtonEvent, ButtonInput, ButtonTimings};
 
-        if button.is_low() {
-            esp_println::println!("Button '{}' was pressed!", tag);
+esp_bootloader_esp_idf::esp_app_desc!();
 
-            // Wait for button release to avoid multiple triggers
-            button.wait_for_rising_edge().await;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Button {
+    Green,
+    Blue,
+}
 
-            esp_println::println!("Button '{}' was released!", tag);
+static EVENTS: Channel<CriticalSectionRawMutex, ButtonEvent<Button>, 4> = Channel::new();
 
-            // Debounce the release
-            Timer::after(Duration::from_millis(50)).await;
-        }
-    }
+#[embassy_executor::task]
+async fn button_watcher(pins: [Input<'static>; 2]) {
+    let input = ButtonInput::new([Button::Green, Button::Blue], ButtonTimings::default());
+    hardware::run_buttons(pins, input, EVENTS.sender()).await
 }
 
 #[esp_rtos::main]
@@ -47,13 +40,13 @@ async fn main(spawner: Spawner) {
     let blue_button = Input::new(peripherals.GPIO13, config);
 
     spawner
-        .spawn(button_watcher(green_button, "green"))
+        .spawn(button_watcher([green_button, blue_button]))
         .unwrap();
-    spawner.spawn(button_watcher(blue_button, "blue")).unwrap();
 
-    loop {
-        esp_println::println!("Main loop working...");
+    esp_println::println!("Watching for button events...");
 
-        Timer::after(Duration::from_millis(1000)).await;
+    loop {
+        let event = EVENTS.receive().await;
+        esp_println::println!("{:?}", event);
     }
 }
@@ -1,11 +1,15 @@
 #![no_std]
 #![no_main]
 
+extern crate alloc;
+
+use alloc::boxed::Box;
 use core::panic::PanicInfo;
 use embassy_executor::Spawner;
-use embassy_net::{Runner, StackResources};
-use embassy_time::{Duration, Timer};
-use embedded_graphics::prelude::Point;
+use embassy_futures::select::{Either, Either3, select, select3};
+use embassy_net::{Runner, Stack, StackResources};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer, with_deadline, with_timeout};
 use esp_alloc as _;
 use esp_backtrace as _;
 use esp_backtrace as _;
@@ -13,6 +17,8 @@ use esp_hal::{
     analog::adc::{Adc, AdcConfig, Attenuation},
     clock::CpuClock,
     delay::Delay,
+    efuse::Efuse,
+    gpio::{Input, InputConfig, Pull},
     peripherals, ram,
     rng::Rng,
     timer::timg::TimerGroup,
@@ -23,21 +29,59 @@ use esp_println::{logger, println};
 use esp_radio::{
     Controller,
     wifi::{
-        ClientConfig, ModeConfig, ScanConfig, WifiController, WifiDevice, WifiEvent, WifiStaState,
+        AccessPointConfig, ClientConfig, ModeConfig, ScanConfig, WifiController, WifiDevice,
+        WifiError, WifiEvent, WifiStaState,
     },
 };
 
+use gonk::clock;
+use gonk::config::{self, ConfigStore, WifiNetwork};
 use gonk::display;
+use gonk::error::GonkError;
 use gonk::hardware;
+use gonk::input::{ButtonInput, ButtonTimings};
+use gonk::logic;
+use gonk::mdns;
 use gonk::model;
+use gonk::mqtt;
+use gonk::network;
+use gonk::ota::{self, Verdict};
+use gonk::portal;
+use gonk::screen;
+use gonk::settings::Settings;
+use gonk::sntp;
+use gonk::traits::{Display, DisplayType, EnvironmentalSensor, NoDisplay};
+use gonk::tz::TimeZone;
+use gonk::weather::{self, WeatherClient};
+use gonk::wifi::{self, Decision, ScanResult, WifiEvents, WifiManager, WifiState};
 
 const HEART_BEAT_INTERVAL_MS: u64 = 5_000;
-const REFRESH_INTERVAL_S: u64 = 6;
-const SSID: &str = env!("SSID");
-const PASSWORD: &str = env!("PASSWORD");
+/// Access points kept from a scan
+const MAX_SCAN: usize = 16;
+/// How often the signal strength is read while connected
+const SIGNAL_REFRESH: Duration = Duration::from_secs(30);
+/// Longest time for both weather requests
+const WEATHER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest time for an SNTP exchange, DNS lookup included
+const SNTP_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time left for the reply to an upload before restarting into the new image
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+/// How often an image on trial is judged
+const TRIAL_CHECK: Duration = Duration::from_secs(5);
 
 esp_bootloader_esp_idf::esp_app_desc!();
 
+static BUTTONS: screen::ButtonChannel = screen::ButtonChannel::new();
+/// Raised to open the WiFi setup portal
+static PROVISION: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Network entered in the setup portal
+static CREDENTIALS: Signal<CriticalSectionRawMutex, WifiNetwork> = Signal::new();
+static WIFI_EVENTS: WifiEvents = WifiEvents::new();
+/// Raised when the configuration was changed through the API
+static CONFIG_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Raised when an uploaded image was installed, to boot on the next reset
+static FIRMWARE_INSTALLED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
 #[embassy_executor::task]
 async fn run_heartbeat() {
     loop {
@@ -46,98 +90,545 @@ async fn run_heartbeat() {
     }
 }
 
-async fn update_display<'a>(
-    display: &mut display::Display<'a>,
+async fn update_display<D: Display + ?Sized>(
+    display: &mut D,
+    screen: &screen::Screen,
     model: &'static embassy_sync::mutex::Mutex<
         embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
         model::Model,
     >,
-) -> Result<(), &'static str> {
-    display.clear()?;
+) -> Result<(), GonkError> {
+    let m = model.lock().await;
+    screen::render(display, screen, &m, Instant::now())
+}
+
+/// Count a failed display update or power change
+async fn record_display_failure(
+    model: &'static embassy_sync::mutex::Mutex<CriticalSectionRawMutex, model::Model>,
+    error: &GonkError,
+) {
+    let mut m = model.lock().await;
+    m.errors.record(error);
+    m.counters.display_failures = m.counters.display_failures.saturating_add(1);
+}
+
+/// Apply the settings that are not read from `Screen`
+async fn apply_settings<D: Display + ?Sized>(
+    display: &mut D,
+    model: &'static embassy_sync::mutex::Mutex<
+        embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
+        model::Model,
+    >,
+    settings: &Settings,
+) -> Result<(), GonkError> {
+    {
+        let mut m = model.lock().await;
+        m.units = settings.units;
+        m.set_refresh_interval(settings.refresh_interval());
+    }
+    display.set_contrast(settings.contrast)?;
+    display.set_rotation(settings.rotation)
+}
+
+/// Show times in the configured time zone and format, and the comfort level
+/// with the configured thresholds
+async fn apply_model_config(
+    model: &'static embassy_sync::mutex::Mutex<CriticalSectionRawMutex, model::Model>,
+    config: &config::Config,
+) {
+    let timezone = TimeZone::parse(&config.timezone).unwrap_or_else(|| {
+        println!("[CLOCK] Invalid time zone {}, using UTC", config.timezone);
+        TimeZone::utc()
+    });
+    let mut m = model.lock().await;
+    m.timezone = timezone;
+    m.time_format = config.time_format;
+    m.thresholds = config.thresholds;
+}
+
+#[embassy_executor::task]
+async fn run_buttons(pins: [Input<'static>; 2]) {
+    let input = ButtonInput::new(
+        [screen::Button::Green, screen::Button::Blue],
+        ButtonTimings::default(),
+    );
+    hardware::run_buttons(pins, input, BUTTONS.sender()).await
+}
+
+type Configuration = config::Configuration<hardware::PartitionStorage<'static>>;
+/// Configuration shared by the tasks, with the store it is saved to
+type SharedConfig = embassy_sync::mutex::Mutex<CriticalSectionRawMutex, Configuration>;
+type Firmware = embassy_sync::mutex::Mutex<CriticalSectionRawMutex, hardware::OtaSlots<'static>>;
+
+/// Stored configuration, or the defaults with the values given in `.env` at
+/// build time when there is none
+fn load_configuration(
+    mut store: Result<ConfigStore<hardware::PartitionStorage<'static>>, GonkError>,
+) -> Configuration {
+    let loaded = store
+        .as_mut()
+        .map_err(|e| *e)
+        .and_then(|store| store.load());
+    let current = match loaded {
+        Ok(config) => config,
+        Err(e) => {
+            println!("[CONFIG] Using defaults: {}", e);
+            let mut config = config::Config::default();
+            if let (Some(ssid), Some(password)) = (option_env!("SSID"), option_env!("PASSWORD"))
+                && let Some(network) = WifiNetwork::new(ssid, password)
+            {
+                config.remember_network(network);
+            }
+            config.display = option_env!("GONK_DISPLAY").and_then(DisplayType::from_name);
+            config
+        }
+    };
+    Configuration { current, store }
+}
 
-    let line_height = 10;
-    let mut y = 0;
+/// Stop the radio if it runs and start it again in `mode`
+async fn restart_wifi(
+    controller: &mut WifiController<'static>,
+    mode: &ModeConfig,
+) -> Result<(), WifiError> {
+    if matches!(controller.is_started(), Ok(true)) {
+        controller.stop_async().await?;
+    }
+    controller.set_config(mode)?;
+    controller.start_async().await
+}
 
-    display.draw_text("Gonk Sensor Readings", 0, 0)?;
-    y += line_height;
+/// Show the WiFi state and tell the other tasks when it changed
+async fn set_wifi_state(
+    model: &'static embassy_sync::mutex::Mutex<CriticalSectionRawMutex, model::Model>,
+    state: WifiState,
+) {
+    let mut m = model.lock().await;
+    if m.wifi != state {
+        m.wifi = state.clone();
+        WIFI_EVENTS.immediate_publisher().publish_immediate(state);
+    }
+}
 
-    let start = Point {
-        x: 0,
-        y: y + line_height / 2,
+/// Open the setup access point until a network is entered in the portal, and
+/// remember that network
+///
+/// With known networks the portal closes after `PORTAL_TIMEOUT` so that they
+/// are tried again.
+async fn provision(
+    controller: &mut WifiController<'static>,
+    config: &'static SharedConfig,
+    model: &'static embassy_sync::mutex::Mutex<CriticalSectionRawMutex, model::Model>,
+    ssid: &heapless::String<32>,
+) {
+    println!("[WIFI] Setup portal open on {}", ssid);
+    set_wifi_state(model, WifiState::Provisioning(ssid.clone())).await;
+    CREDENTIALS.reset();
+    let access_point =
+        ModeConfig::AccessPoint(AccessPointConfig::default().with_ssid(ssid.as_str().into()));
+    if let Err(e) = restart_wifi(controller, &access_point).await {
+        println!("[WIFI] Failed to start the access point: {:?}", e);
+    }
+
+    let known = !config.lock().await.current.networks.is_empty();
+    let entered = if known {
+        with_timeout(portal::PORTAL_TIMEOUT, CREDENTIALS.wait())
+            .await
+            .ok()
+    } else {
+        Some(CREDENTIALS.wait().await)
     };
-    let end = Point {
-        x: 127,
-        y: y + line_height / 2,
+    match &entered {
+        Some(network) => {
+            println!("[WIFI] Setup entered network {}", network.ssid);
+            // Leave time for the confirmation page to reach the phone
+            Timer::after(Duration::from_secs(2)).await;
+        }
+        None => println!("[WIFI] Setup portal timed out"),
+    }
+    // Started again in client mode by `connection`
+    if let Err(e) = controller.stop_async().await {
+        println!("[WIFI] Failed to stop the access point: {:?}", e);
+    }
+    set_wifi_state(model, WifiState::Disconnected).await;
+
+    let Some(network) = entered else {
+        return;
     };
-    display.draw_line(start, end)?;
-    y += line_height;
+    let mut config = config.lock().await;
+    config.current.remember_network(network);
+    if let Err(e) = config.save() {
+        model.lock().await.errors.record(&e);
+        println!("[CONFIG] Save error: {}", e);
+    }
+}
 
-    {
-        let m = model.lock().await;
-        let temp_str: heapless::String<32> =
-            heapless::format!("Temp: {:.2} C", m.temperature).unwrap();
-        display.draw_text(&temp_str, 0, y)?;
-        y += line_height;
-
-        let humidity_str: heapless::String<32> =
-            heapless::format!("Humidity: {:.2} %", m.humidity).unwrap();
-        display.draw_text(&humidity_str, 0, y)?;
-        y += line_height;
-
-        let ip_str: heapless::String<32> = heapless::format!("IP: {}", m.ip_address).unwrap();
-        display.draw_text(&ip_str, 0, y)?;
+/// Access points in range, strongest first
+async fn scan(controller: &mut WifiController<'static>) -> heapless::Vec<ScanResult, MAX_SCAN> {
+    let mut visible = heapless::Vec::new();
+    let scan_config = ScanConfig::default().with_max(MAX_SCAN);
+    match controller.scan_with_config_async(scan_config).await {
+        Ok(result) => {
+            for ap in result {
+                let Ok(ssid) = heapless::String::try_from(ap.ssid.as_str()) else {
+                    continue;
+                };
+                let _ = visible.push(ScanResult {
+                    ssid,
+                    bssid: ap.bssid,
+                    channel: ap.channel,
+                    rssi: ap.signal_strength,
+                });
+            }
+        }
+        Err(e) => println!("[WIFI] Scan failed: {:?}", e),
     }
+    visible
+}
 
-    Ok(())
+/// Follow the link until it drops, returns `true` when setup was requested
+async fn stay_connected(
+    controller: &mut WifiController<'static>,
+    model: &'static embassy_sync::mutex::Mutex<CriticalSectionRawMutex, model::Model>,
+) -> bool {
+    loop {
+        let disconnected = controller.wait_for_event(WifiEvent::StaDisconnected);
+        match select3(disconnected, PROVISION.wait(), Timer::after(SIGNAL_REFRESH)).await {
+            Either3::First(_) => return false,
+            Either3::Second(()) => return true,
+            Either3::Third(()) => {}
+        }
+        // The link may have dropped while nothing was waiting for the event
+        if !matches!(esp_radio::wifi::sta_state(), WifiStaState::Connected) {
+            return false;
+        }
+        if let Ok(rssi) = controller.rssi() {
+            let mut m = model.lock().await;
+            if let WifiState::Connected(link) = &mut m.wifi {
+                link.rssi = rssi as i8;
+            }
+        }
+    }
 }
 
 #[embassy_executor::task]
-async fn connection(mut controller: WifiController<'static>) {
+async fn connection(
+    mut controller: WifiController<'static>,
+    config: &'static SharedConfig,
+    model: &'static embassy_sync::mutex::Mutex<CriticalSectionRawMutex, model::Model>,
+    setup_ssid: heapless::String<32>,
+) {
     println!("start connection task");
     println!("Device capabilities: {:?}", controller.capabilities());
+    let rng = Rng::new();
+    let mut manager = WifiManager::new();
+    let mut requested = false;
     loop {
-        match esp_radio::wifi::sta_state() {
-            WifiStaState::Connected => {
-                // wait until we're no longer connected
-                controller.wait_for_event(WifiEvent::StaDisconnected).await;
-                Timer::after(Duration::from_millis(5000)).await
+        if matches!(esp_radio::wifi::sta_state(), WifiStaState::Connected) {
+            requested = stay_connected(&mut controller, model).await;
+            if !requested {
+                let mut m = model.lock().await;
+                m.counters.wifi_reconnects = m.counters.wifi_reconnects.saturating_add(1);
             }
-            _ => {}
+            set_wifi_state(model, WifiState::Disconnected).await;
+            println!("[WIFI] Disconnected");
         }
-        if !matches!(controller.is_started(), Ok(true)) {
-            let client_config = ModeConfig::Client(
-                ClientConfig::default()
-                    .with_ssid(SSID.into())
-                    .with_password(PASSWORD.into()),
-            );
-            controller.set_config(&client_config).unwrap();
-            println!("Starting wifi");
-            controller.start_async().await.unwrap();
-            println!("Wifi started!");
-
-            println!("Scan");
-            let scan_config = ScanConfig::default().with_max(10);
-            let result = controller
-                .scan_with_config_async(scan_config)
-                .await
-                .unwrap();
-            for ap in result {
-                println!("{:?}", ap);
+
+        let decision = if requested {
+            Decision::Provision
+        } else {
+            if !matches!(controller.is_started(), Ok(true)) {
+                let client = ModeConfig::Client(ClientConfig::default());
+                if let Err(e) = restart_wifi(&mut controller, &client).await {
+                    println!("[WIFI] Failed to start: {:?}", e);
+                }
+            }
+            set_wifi_state(model, WifiState::Scanning).await;
+            let visible = scan(&mut controller).await;
+            let networks = config.lock().await.current.networks.clone();
+            manager.decide(&networks, &visible)
+        };
+        requested = false;
+
+        match decision {
+            Decision::Provision => {
+                provision(&mut controller, config, model, &setup_ssid).await;
+                manager.reset();
+                continue;
+            }
+            Decision::Connect { network, ap } => {
+                println!(
+                    "[WIFI] Connecting to {} ({}, channel {}, {} dBm)",
+                    network.ssid,
+                    wifi::format_bssid(ap.bssid),
+                    ap.channel,
+                    ap.rssi
+                );
+                set_wifi_state(model, WifiState::Connecting(network.ssid.clone())).await;
+                let client = ModeConfig::Client(
+                    ClientConfig::default()
+                        .with_ssid(network.ssid.as_str().into())
+                        .with_password(network.password.as_str().into())
+                        .with_bssid(ap.bssid)
+                        .with_channel(ap.channel),
+                );
+                let connected = match controller.set_config(&client) {
+                    Ok(()) => controller.connect_async().await,
+                    Err(e) => Err(e),
+                };
+                match connected {
+                    Ok(()) => {
+                        println!("[WIFI] Connected to {}", network.ssid);
+                        manager.record_success(&network.ssid);
+                        set_wifi_state(model, WifiState::Connected(ap.into())).await;
+                        continue;
+                    }
+                    Err(e) => {
+                        println!("[WIFI] Failed to connect to {}: {:?}", network.ssid, e);
+                        manager.record_failure(Some(&network.ssid));
+                    }
+                }
+            }
+            Decision::NothingVisible => {
+                println!("[WIFI] No known network in range");
+                manager.record_failure(None);
             }
         }
-        println!("About to connect...");
 
-        match controller.connect_async().await {
-            Ok(_) => println!("Wifi connected!"),
-            Err(e) => {
-                println!("Failed to connect to wifi: {e:?}");
-                Timer::after(Duration::from_millis(5000)).await
+        set_wifi_state(model, WifiState::Disconnected).await;
+        let retry = manager.retry_delay(rng.random());
+        println!("[WIFI] Retrying in {} ms", retry.as_millis());
+        if let Either::Second(()) = select(Timer::after(retry), PROVISION.wait()).await {
+            requested = true;
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn track_address(
+    stack: Stack<'static>,
+    model: &'static embassy_sync::mutex::Mutex<CriticalSectionRawMutex, model::Model>,
+) {
+    loop {
+        println!("[INFO] Waiting to get IP address...");
+        stack.wait_config_up().await;
+        if let Some(config) = stack.config_v4() {
+            println!("[INFO] Got IP: {}", config.address);
+            let mut m = model.lock().await;
+            m.ip_address = heapless::format!("{}", config.address)
+                .unwrap_or_else(|_| heapless::String::try_from("INVALID").unwrap());
+        }
+        stack.wait_config_down().await;
+        model.lock().await.ip_address = heapless::String::try_from("UNKNOWN").unwrap();
+    }
+}
+
+#[embassy_executor::task]
+async fn setup_dns(stack: Stack<'static>) {
+    network::run_dns(stack).await
+}
+
+#[embassy_executor::task]
+async fn setup_dhcp(stack: Stack<'static>) {
+    network::run_dhcp(stack).await
+}
+
+#[embassy_executor::task]
+async fn setup_page(stack: Stack<'static>) {
+    network::run_portal(stack, &CREDENTIALS).await
+}
+
+#[embassy_executor::task]
+async fn web_server(
+    stack: Stack<'static>,
+    model: &'static embassy_sync::mutex::Mutex<CriticalSectionRawMutex, model::Model>,
+    config: &'static SharedConfig,
+    firmware: &'static Firmware,
+) {
+    network::run_server(
+        stack,
+        model,
+        config,
+        &CONFIG_CHANGED,
+        firmware,
+        &FIRMWARE_INSTALLED,
+    )
+    .await
+}
+
+/// Restart into an uploaded image once it was installed
+#[embassy_executor::task]
+async fn restart_after_update() {
+    FIRMWARE_INSTALLED.wait().await;
+    println!("[OTA] New image installed, restarting");
+    Timer::after(RESTART_DELAY).await;
+    esp_hal::system::software_reset()
+}
+
+/// Keep the image running once it proved healthy, or boot the previous one
+/// back
+#[embassy_executor::task]
+async fn firmware_trial(
+    model: &'static embassy_sync::mutex::Mutex<CriticalSectionRawMutex, model::Model>,
+    firmware: &'static Firmware,
+) {
+    loop {
+        Timer::after(TRIAL_CHECK).await;
+        let verdict = ota::judge_trial(&*model.lock().await, Instant::now());
+        match verdict {
+            Verdict::Wait => {}
+            Verdict::Confirm => {
+                match firmware.lock().await.confirm() {
+                    Ok(()) => {
+                        println!("[OTA] New image confirmed");
+                        model.lock().await.boot.trial = false;
+                    }
+                    Err(e) => println!("[OTA] Failed to confirm the image: {}", e),
+                }
+                return;
+            }
+            Verdict::RollBack => {
+                println!("[OTA] New image never joined the network, rolling back");
+                if let Err(e) = firmware.lock().await.roll_back() {
+                    println!("[OTA] Failed to roll back: {}", e);
+                    return;
+                }
+                esp_hal::system::software_reset()
             }
         }
     }
 }
 
+/// Keep the outdoor weather of the model up to date while OpenWeather is
+/// configured
+#[embassy_executor::task]
+async fn outdoor_weather(
+    stack: Stack<'static>,
+    model: &'static embassy_sync::mutex::Mutex<CriticalSectionRawMutex, model::Model>,
+    config: &'static SharedConfig,
+) {
+    let mut client = network::TcpHttpClient::new(stack);
+    let mut buf = [0u8; weather::MAX_RESPONSE];
+    loop {
+        stack.wait_config_up().await;
+        let (location, api_key) = {
+            let c = config.lock().await;
+            (
+                c.current.weather_location.clone(),
+                c.current.openweather_api_key.clone(),
+            )
+        };
+        // Checked again later, the configuration can change at any time
+        let Some(service) = WeatherClient::new(&location, &api_key) else {
+            Timer::after(weather::RETRY_INTERVAL).await;
+            continue;
+        };
+
+        let fetched = with_timeout(
+            WEATHER_TIMEOUT,
+            service.fetch(&mut client, &mut buf, Instant::now()),
+        )
+        .await
+        .unwrap_or(Err(GonkError::Timeout));
+        let delay = match fetched {
+            Ok(outdoor) => {
+                println!(
+                    "[WEATHER] {}: {:.1} C, {}",
+                    outdoor.current.place, outdoor.current.temperature, outdoor.current.description
+                );
+                model.lock().await.weather = Some(outdoor);
+                weather::REFRESH_INTERVAL
+            }
+            Err(e) => {
+                println!("[WEATHER] Update failed: {}", e);
+                model.lock().await.errors.record(&e);
+                weather::RETRY_INTERVAL
+            }
+        };
+        Timer::after(delay).await;
+    }
+}
+
+/// Keep the clock of the model synchronized with an SNTP server
+#[embassy_executor::task]
+async fn time_sync(
+    stack: Stack<'static>,
+    model: &'static embassy_sync::mutex::Mutex<CriticalSectionRawMutex, model::Model>,
+) {
+    let rng = Rng::new();
+    loop {
+        stack.wait_config_up().await;
+        let nonce = (rng.random() as u64) << 32 | rng.random() as u64;
+        let sample = with_timeout(
+            SNTP_TIMEOUT,
+            network::sntp_sample(stack, sntp::DEFAULT_SERVER, nonce),
+        )
+        .await
+        .unwrap_or(Err(GonkError::Timeout));
+        let delay = match sample {
+            Ok(sample) => {
+                let mut m = model.lock().await;
+                m.clock.synchronize(sample.at, sample.unix_micros);
+                if let Some(utc) = m.clock.utc(sample.at) {
+                    println!(
+                        "[CLOCK] {} {} UTC, correction {} us, drift {:.2} ppm",
+                        utc.format_date(),
+                        utc.format_time(),
+                        m.clock.last_correction_us(),
+                        m.clock.drift_ppm()
+                    );
+                }
+                clock::SYNC_INTERVAL
+            }
+            Err(e) => {
+                println!("[CLOCK] Sync failed: {}", e);
+                model.lock().await.errors.record(&e);
+                clock::RETRY_INTERVAL
+            }
+        };
+        Timer::after(delay).await;
+    }
+}
+
+/// Make the device and its services known as gonk-<mac>.local
+#[embassy_executor::task]
+async fn mdns_responder(
+    stack: Stack<'static>,
+    model: &'static embassy_sync::mutex::Mutex<CriticalSectionRawMutex, model::Model>,
+) {
+    let responder = mdns::Responder::new(Efuse::mac_address());
+    println!("[MDNS] Answering for {}", responder.host_name());
+    network::run_mdns(stack, model, &responder).await
+}
+
+/// Publish the readings to the MQTT broker while one is configured,
+/// reconnecting whenever the connection is lost
 #[embassy_executor::task]
+async fn mqtt_publisher(
+    stack: Stack<'static>,
+    model: &'static embassy_sync::mutex::Mutex<CriticalSectionRawMutex, model::Model>,
+    config: &'static SharedConfig,
+) {
+    let topics = mqtt::Topics::new(Efuse::mac_address());
+    let mut failures = 0;
+    loop {
+        stack.wait_config_up().await;
+        let result = network::run_mqtt_session(stack, model, config, &topics, &mut failures).await;
+        let delay = match result {
+            // Not configured, or the settings changed
+            Ok(()) => mqtt::PUBLISH_INTERVAL,
+            Err(e) => {
+                println!("[MQTT] Connection lost: {}", e);
+                model.lock().await.errors.record(&e);
+                failures += 1;
+                mqtt::reconnect_delay(failures)
+            }
+        };
+        Timer::after(delay).await;
+    }
+}
+
+#[embassy_executor::task(pool_size = 2)]
 async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
     runner.run().await
 }
@@ -154,10 +645,12 @@ macro_rules! mk_static {
 async fn init_wifi(
     spawner: Spawner,
     device: peripherals::WIFI<'static>,
+    config: &'static SharedConfig,
     model: &'static embassy_sync::mutex::Mutex<
         embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
         model::Model,
     >,
+    firmware: &'static Firmware,
 ) {
     esp_alloc::heap_allocator!(#[ram(reclaimed)] size: 64 * 1024);
     esp_alloc::heap_allocator!(size: 36 * 1024);
@@ -169,7 +662,7 @@ async fn init_wifi(
 
     let wifi_interface = interfaces.sta;
 
-    let config = embassy_net::Config::dhcpv4(Default::default());
+    let net_config = embassy_net::Config::dhcpv4(Default::default());
 
     let rng = Rng::new();
     let seed = (rng.random() as u64) << 32 | rng.random() as u64;
@@ -177,63 +670,55 @@ async fn init_wifi(
     // Init network stack
     let (stack, runner) = embassy_net::new(
         wifi_interface,
-        config,
-        mk_static!(StackResources<3>, StackResources::<3>::new()),
+        net_config,
+        mk_static!(StackResources<8>, StackResources::<8>::new()),
         seed,
     );
 
-    spawner.spawn(connection(controller)).ok();
-    spawner.spawn(net_task(runner)).ok();
-
-    let mut rx_buffer = [0; 4096];
-    let mut tx_buffer = [0; 4096];
-
-    loop {
-        if stack.is_link_up() {
-            break;
-        }
-        Timer::after(Duration::from_millis(500)).await;
-    }
+    // The setup portal serves DNS, DHCP and HTTP on the access point
+    let (setup_stack, setup_runner) = embassy_net::new(
+        interfaces.ap,
+        network::portal_config(),
+        mk_static!(StackResources<4>, StackResources::<4>::new()),
+        seed.rotate_left(32),
+    );
+    let setup_ssid = portal::portal_ssid(Efuse::mac_address());
 
-    println!("[INFO] Waiting to get IP address...");
-    loop {
-        if let Some(config) = stack.config_v4() {
-            println!("[INFO] Got IP: {}", config.address);
-            {
-                let mut m = model.lock().await;
-                m.ip_address = heapless::format!("{}", config.address)
-                    .unwrap_or_else(|_| heapless::String::try_from("INVALID").unwrap());
-            }
-            break;
-        }
-        Timer::after(Duration::from_millis(500)).await;
-    }
+    spawner
+        .spawn(connection(controller, config, model, setup_ssid))
+        .ok();
+    spawner.spawn(net_task(runner)).ok();
+    spawner.spawn(net_task(setup_runner)).ok();
+    spawner.spawn(track_address(stack, model)).ok();
+    spawner.spawn(setup_dns(setup_stack)).ok();
+    spawner.spawn(setup_dhcp(setup_stack)).ok();
+    spawner.spawn(setup_page(setup_stack)).ok();
+    spawner
+        .spawn(web_server(stack, model, config, firmware))
+        .ok();
+    spawner.spawn(outdoor_weather(stack, model, config)).ok();
+    spawner.spawn(time_sync(stack, model)).ok();
+    spawner.spawn(mqtt_publisher(stack, model, config)).ok();
+    spawner.spawn(mdns_responder(stack, model)).ok();
 }
 
-async fn update_model<'a>(
+async fn update_model<S: EnvironmentalSensor>(
     model: &'static embassy_sync::mutex::Mutex<
         embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex,
         model::Model,
     >,
-    bme280: &mut hardware::BME280Hardware<'a>,
-) -> Result<(), &'static str> {
+    sensor: &mut S,
+    offsets: &config::Offsets,
+) -> Result<(), GonkError> {
     let mut m = model.lock().await;
 
-    match bme280.read() {
-        Ok(measurements) => {
-            m.humidity = measurements.humidity;
-            m.pressure = measurements.pressure;
-            m.temperature = measurements.temperature;
-        }
-        Err(e) => {
-            println!("[BME280] Read error: {:?}", e);
-            m.humidity = -999.0;
-            m.pressure = -999.0;
-            m.temperature = -999.0;
-        }
+    let result = logic::update_model_with_sensor(&mut m, sensor, offsets);
+    if let Err(e) = &result {
+        m.errors.record(e);
     }
+    println!("[SENSOR] {}", logic::format_readings(&m, Instant::now()));
 
-    Ok(())
+    result.map(|_| ())
 }
 
 #[esp_rtos::main]
@@ -244,12 +729,7 @@ async fn main(spawner: Spawner) {
 
     let model = mk_static!(
         embassy_sync::mutex::Mutex<embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex, model::Model>,
-        embassy_sync::mutex::Mutex::new(model::Model {
-            temperature: 0.0,
-            pressure: 0.0,
-            humidity: 0.0,
-            ip_address: heapless::String::try_from("UNKNOWN").unwrap(),
-        })
+        embassy_sync::mutex::Mutex::new(model::Model::new())
     );
 
     println!("=== Gonk ===");
@@ -263,49 +743,241 @@ async fn main(spawner: Spawner) {
         println!("[ERROR] Failed to spawn task: {:?}", e);
     }
 
-    init_wifi(spawner, peripherals.WIFI, model).await;
+    // The configuration and the OTA slots write to the same flash
+    let flash = mk_static!(
+        hardware::SharedFlash<'static>,
+        hardware::share_flash(peripherals.FLASH)
+    );
+    let store = hardware::PartitionStorage::open(flash, config::PARTITION).map(ConfigStore::new);
+    let shared_config = mk_static!(
+        SharedConfig,
+        embassy_sync::mutex::Mutex::new(load_configuration(store))
+    );
+    let (pins, configured, settings, layout) = {
+        let config = &shared_config.lock().await.current;
+        println!(
+            "[CONFIG] {} network(s), display {:?}, {:?}",
+            config.networks.len(),
+            config.display,
+            config.pins
+        );
+        apply_model_config(model, config).await;
+        (
+            config.pins,
+            config.display,
+            config.settings,
+            config.layout.clone(),
+        )
+    };
+
+    let firmware = mk_static!(
+        Firmware,
+        embassy_sync::mutex::Mutex::new(hardware::OtaSlots::new(flash))
+    );
+    let boot = firmware.lock().await.boot_info();
+    println!(
+        "[OTA] Running slot {:?}, on trial: {}",
+        boot.slot, boot.trial
+    );
+    model.lock().await.boot = boot;
+    if boot.trial {
+        spawner.spawn(firmware_trial(model, firmware)).ok();
+    }
+    spawner.spawn(restart_after_update()).ok();
+
+    init_wifi(spawner, peripherals.WIFI, shared_config, model, firmware).await;
 
     // Initialize BME280 sensor
     println!("=== BME280 Temperature Sensor ===");
-    let mut bme280 =
-        hardware::BME280Hardware::new(peripherals.I2C0, peripherals.GPIO8, peripherals.GPIO9);
+    // SAFETY: the configured pins are checked against an allow-list of free
+    // GPIOs and to be distinct, see `Pins::is_valid()`
+    let mut bme280 = hardware::BME280Hardware::new(
+        peripherals.I2C0,
+        unsafe { hardware::gpio(pins.sensor_sda) },
+        unsafe { hardware::gpio(pins.sensor_scl) },
+    );
+    match bme280.init() {
+        Ok(variant) => println!("[BME280] Detected {:?}", variant),
+        Err(e) => println!("[BME280] Init error: {}", e),
+    }
+    model.lock().await.sensors = bme280.capabilities();
+
+    // A configured display type skips the probe
+    let mut display_bus = hardware::display_bus(
+        peripherals.I2C1,
+        unsafe { hardware::gpio(pins.display_sda) },
+        unsafe { hardware::gpio(pins.display_scl) },
+    );
+    let probed = logic::probe_ssd1306(&mut display_bus);
+    let display_type = logic::select_display(configured, probed);
+    println!(
+        "[DISPLAY] Using {:?} (configured {:?}, probed {:?})",
+        display_type, configured, probed
+    );
 
-    let display_hardware =
-        hardware::SSD1306Hardware::new(peripherals.I2C1, peripherals.GPIO2, peripherals.GPIO1)
-            .unwrap();
+    let opened: Result<Box<dyn Display>, GonkError> = match display_type {
+        DisplayType::SSD1306 => {
+            let address = probed.unwrap_or(logic::SSD1306_ADDRESSES[0]);
+            hardware::SSD1306Hardware::with_bus(display_bus, address)
+                .map(|hardware| Box::new(display::Display::new(hardware)) as Box<dyn Display>)
+        }
+        DisplayType::EPaper => {
+            let display_hardware = hardware::DisplayHardware::new(
+                peripherals.SPI2,
+                peripherals.GPIO10,
+                peripherals.GPIO11,
+                peripherals.GPIO14,
+                peripherals.GPIO15,
+                peripherals.GPIO16,
+                peripherals.GPIO17,
+            );
+            hardware::EPaperHardware::new(display_hardware)
+                .map(|epaper| Box::new(epaper) as Box<dyn Display>)
+        }
+    };
+    // A missing or broken panel must not take the rest of the firmware down
+    let mut display = match opened {
+        Ok(display) => display,
+        Err(e) => {
+            println!("[DISPLAY] Setup error: {}, running without a display", e);
+            record_display_failure(model, &e).await;
+            Box::new(NoDisplay)
+        }
+    };
+    if let Err(e) = display.init() {
+        println!("[DISPLAY] Init error: {}", e);
+        record_display_failure(model, &e).await;
+    }
 
-    let mut display = display::Display::new(display_hardware);
+    if let Err(e) = apply_settings(display.as_mut(), model, &settings).await {
+        println!("[SETTINGS] Apply error: {}", e);
+    }
 
     // --- ADC setup for GPIO1 (ADC1) ---
     let mut adc_config = AdcConfig::new();
     let mut adc_pin = adc_config.enable_pin(peripherals.GPIO4, Attenuation::_11dB);
     let mut adc = Adc::new(peripherals.ADC1, adc_config);
 
+    let input_config = InputConfig::default().with_pull(Pull::Up);
+    let green_button = Input::new(unsafe { hardware::gpio(pins.button_green) }, input_config);
+    let blue_button = Input::new(unsafe { hardware::gpio(pins.button_blue) }, input_config);
+    if let Err(e) = spawner.spawn(run_buttons([green_button, blue_button])) {
+        println!("[ERROR] Failed to spawn task: {:?}", e);
+    }
+
+    let mut screen = screen::Screen::new(Instant::now(), settings);
+    screen.set_layout(layout, Instant::now());
+    let mut next_reading = Instant::now();
+    let mut display_on = true;
+    let mut wifi_events = WIFI_EVENTS.subscriber().unwrap();
+
     loop {
-        if let Err(e) = update_model(model, &mut bme280).await {
-            println!("[ERROR] Display update failed: {}", e);
+        if Instant::now() >= next_reading {
+            next_reading = Instant::now() + screen.settings().refresh_interval();
+
+            let offsets = shared_config.lock().await.current.offsets;
+            if let Err(e) = update_model(model, &mut bme280, &offsets).await {
+                println!("[SENSOR] Read error: {}", e);
+            }
+
+            // Read raw ADC
+            match adc.read_oneshot(&mut adc_pin) {
+                Ok(raw) => {
+                    // Convert to voltage
+                    let vadc = (raw as f32) * 3.3 / 4095.0;
+                    // Divider correction: Vin = Vadc * (Rtop+Rbottom)/Rbottom = Vadc * 133/100
+                    let vin = vadc * 1.33;
+                    model.lock().await.supply_voltage = Some(vin);
+
+                    println!("[ADC] raw={} Vadc≈{:.3}V Vin≈{:.3}V", raw, vadc, vin);
+                }
+                Err(e) => println!("[ADC] Read error: {:?}", e),
+            }
+            model.lock().await.free_heap = Some(esp_alloc::HEAP.free());
         }
 
-        if let Err(e) = update_display(&mut display, model).await {
+        let now = Instant::now();
+        screen.tick(now);
+        let awake = screen.awake(now);
+        if awake != display_on {
+            display_on = awake;
+            if let Err(e) = display.set_power(awake) {
+                record_display_failure(model, &e).await;
+                println!("[ERROR] Display power failed: {}", e);
+            }
+        }
+        if awake && let Err(e) = update_display(display.as_mut(), &screen, model).await {
+            record_display_failure(model, &e).await;
             println!("[ERROR] Display update failed: {}", e);
         }
 
-        // Read raw ADC
-        let raw = match adc.read_oneshot(&mut adc_pin) {
-            Ok(value) => value,
-            Err(e) => {
-                println!("[ADC] Read error: {:?}", e);
-                Timer::after(Duration::from_secs(REFRESH_INTERVAL_S)).await;
+        // Sleep until the next reading, page rotation, screen timeout or minute
+        // of the clock page, unless a button is pressed, the WiFi state changes
+        // or the configuration is changed through the API
+        let next_minute = match screen.page() {
+            screen::Page::Clock => model.lock().await.clock.next_minute(Instant::now()),
+            _ => None,
+        };
+        let deadline = [
+            screen.next_rotation(),
+            screen.sleep_at().filter(|_| awake),
+            next_minute.filter(|_| awake),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(next_reading, Instant::min);
+        let woken = with_deadline(
+            deadline,
+            select3(
+                BUTTONS.receive(),
+                wifi_events.next_message_pure(),
+                CONFIG_CHANGED.wait(),
+            ),
+        )
+        .await;
+        let event = match woken {
+            Ok(Either3::First(event)) => event,
+            Ok(Either3::Third(())) => {
+                let (settings, layout) = {
+                    let config = &shared_config.lock().await.current;
+                    apply_model_config(model, config).await;
+                    (config.settings, config.layout.clone())
+                };
+                println!("[SETTINGS] Changed remotely {:?}", settings);
+                screen.set_settings(settings);
+                screen.set_layout(layout, Instant::now());
+                if let Err(e) = apply_settings(display.as_mut(), model, &settings).await {
+                    println!("[SETTINGS] Apply error: {}", e);
+                }
+                next_reading = next_reading.min(Instant::now() + settings.refresh_interval());
                 continue;
             }
+            _ => continue,
         };
-
-        // Convert to voltage
-        let vadc = (raw as f32) * 3.3 / 4095.0;
-        // Divider correction: Vin = Vadc * (Rtop+Rbottom)/Rbottom = Vadc * 133/100
-        let vin = vadc * 1.33;
-
-        println!("[ADC] raw={} Vadc≈{:.3}V Vin≈{:.3}V", raw, vadc, vin);
-        Timer::after(Duration::from_secs(REFRESH_INTERVAL_S)).await;
+        match screen.handle(event, Instant::now()) {
+            screen::Action::Apply(settings) => {
+                if let Err(e) = apply_settings(display.as_mut(), model, &settings).await {
+                    println!("[SETTINGS] Apply error: {}", e);
+                }
+                let saved = {
+                    let mut config = shared_config.lock().await;
+                    config.current.settings = settings;
+                    config.save()
+                };
+                match saved {
+                    Ok(()) => println!("[SETTINGS] Saved {:?}", settings),
+                    Err(e) => {
+                        model.lock().await.errors.record(&e);
+                        println!("[SETTINGS] Save error: {}", e);
+                    }
+                }
+                next_reading = next_reading.min(Instant::now() + settings.refresh_interval());
+            }
+            screen::Action::Provision => {
+                println!("[WIFI] Setup requested");
+                PROVISION.signal(());
+            }
+            screen::Action::None | screen::Action::Redraw => {}
+        }
     }
 }
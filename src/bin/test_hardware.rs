@@ -1,26 +1,20 @@
 #![no_std]
 #![no_main]
 
-use core::panic::PanicInfo;
 use embassy_executor::Spawner;
 use embassy_time::{Duration, Timer};
 use esp_backtrace as _;
-use esp_hal::{delay::Delay, timer::timg::TimerGroup};
+use esp_hal::timer::timg::TimerGroup;
 
-use gonk::{hardware::BME280Hardware, logic::AppLogic};
-
-#[panic_handler]
-fn panic(info: &PanicInfo) -> ! {
-    esp_println::println!("[PANIC] {:?}", info);
-    let delay = Delay::new();
-    loop {
-        delay.delay_millis(1_000);
-    }
-}
+use gonk::{
+    bme280::{self, ChipVariant},
+    hardware::BME280Hardware,
+};
 
 esp_bootloader_esp_idf::esp_app_desc!();
 
-// Test result tracking
+// On-device result tracking for checks that need real hardware. Everything
+// hardware-independent is covered by `make test` on the host.
 struct TestResults {
     passed: u32,
     failed: u32,
@@ -58,23 +52,6 @@ impl TestResults {
         }
     }
 
-    fn assert_close(&mut self, value: f32, expected: f32, tolerance: f32, test_name: &str) {
-        self.total += 1;
-        if (value - expected).abs() < tolerance {
-            self.passed += 1;
-            esp_println::println!("  ✓ {}", test_name);
-        } else {
-            self.failed += 1;
-            esp_println::println!(
-                "  ✗ {} FAILED: {:.2} not close to {:.2} (tolerance: {:.2})",
-                test_name,
-                value,
-                expected,
-                tolerance
-            );
-        }
-    }
-
     fn print_summary(&self) {
         esp_println::println!("\n==========================================");
         esp_println::println!("Test Summary:");
@@ -90,79 +67,6 @@ impl TestResults {
     }
 }
 
-fn test_app_logic(results: &mut TestResults) {
-    esp_println::println!("\n[TEST] AppLogic Tests");
-
-    // Test single reading
-    let mut app = AppLogic::new();
-    app.record_temperature(22.5);
-    results.assert_eq(
-        app.average_temperature(),
-        Some(22.5),
-        "single reading average",
-    );
-    results.assert_eq(
-        app.temperature_status(),
-        "Comfortable",
-        "single reading status",
-    );
-
-    // Test multiple readings
-    let mut app = AppLogic::new();
-    app.record_temperature(20.0);
-    app.record_temperature(22.0);
-    app.record_temperature(24.0);
-    if let Some(avg) = app.average_temperature() {
-        results.assert_close(avg, 22.0, 0.01, "multiple readings average");
-    } else {
-        results.assert(false, "multiple readings average (None returned)");
-    }
-
-    // Test rolling buffer
-    let mut app = AppLogic::new();
-    for i in 0..5 {
-        app.record_temperature((i * 10 + 2) as f32);
-    }
-    app.record_temperature(100.0);
-    if let Some(avg) = app.average_temperature() {
-        results.assert_close(avg, 41.6, 0.2, "rolling buffer average");
-    } else {
-        results.assert(false, "rolling buffer average (None returned)");
-    }
-
-    // Test temperature status categories
-    let mut app = AppLogic::new();
-    app.record_temperature(5.0);
-    results.assert_eq(app.temperature_status(), "Cold", "cold status");
-
-    let mut app = AppLogic::new();
-    app.record_temperature(15.0);
-    results.assert_eq(app.temperature_status(), "Cool", "cool status");
-
-    let mut app = AppLogic::new();
-    app.record_temperature(22.0);
-    results.assert_eq(
-        app.temperature_status(),
-        "Comfortable",
-        "comfortable status",
-    );
-
-    let mut app = AppLogic::new();
-    app.record_temperature(28.0);
-    results.assert_eq(app.temperature_status(), "Warm", "warm status");
-
-    let mut app = AppLogic::new();
-    app.record_temperature(35.0);
-    results.assert_eq(app.temperature_status(), "Hot", "hot status");
-
-    // Test format_temperature
-    let mut app = AppLogic::new();
-    app.record_temperature(22.5);
-    let formatted = app.format_temperature(22.5);
-    results.assert(formatted.contains("22.5"), "format contains temperature");
-    results.assert(formatted.contains("Comfortable"), "format contains status");
-}
-
 async fn test_bme280_sensor<SDA, SCL>(
     results: &mut TestResults,
     i2c0: esp_hal::peripherals::I2C0<'static>,
@@ -179,19 +83,30 @@ async fn test_bme280_sensor<SDA, SCL>(
 
     // Test I2C scan
     esp_println::println!("  Running I2C scan...");
-    bme280.scan();
-    results.assert(true, "I2C scan completed");
+    let found = bme280.scan();
+    for address in found.iter() {
+        esp_println::println!("    Found device at address 0x{:02X}", address);
+    }
+    results.assert(
+        found.contains(&bme280::PRIMARY_ADDRESS),
+        "sensor ACKs at primary address",
+    );
 
     // Test initialization
     match bme280.init() {
-        Ok(_) => {
+        Ok(variant) => {
+            esp_println::println!("    Detected {:?}", variant);
             results.assert(true, "BME280 initialization");
 
             // Test chip ID read
             match bme280.read_chip_id() {
                 Ok(chip_id) => {
                     esp_println::println!("    Chip ID: 0x{:02X}", chip_id);
-                    results.assert_eq(chip_id, 0x58, "BME280 chip ID is 0x58");
+                    results.assert_eq(
+                        ChipVariant::from_id(chip_id),
+                        Some(variant),
+                        "chip ID matches detected variant",
+                    );
                 }
                 Err(e) => {
                     esp_println::println!("    Failed to read chip ID: {}", e);
@@ -199,6 +114,15 @@ async fn test_bme280_sensor<SDA, SCL>(
                 }
             }
 
+            // Test status register after init
+            match bme280.read_status() {
+                Ok(status) => results.assert(!status.im_update(), "NVM copy completed"),
+                Err(e) => {
+                    esp_println::println!("    Failed to read status: {}", e);
+                    results.assert(false, "read status");
+                }
+            }
+
             // Test temperature reading (5 samples)
             esp_println::println!("  Reading temperatures (5 samples)...");
             let mut temps = heapless::Vec::<f32, 5>::new();
@@ -238,7 +162,7 @@ async fn test_bme280_sensor<SDA, SCL>(
 }
 
 #[esp_rtos::main]
-async fn main(spawner: Spawner) {
+async fn main(_spawner: Spawner) {
     esp_println::logger::init_logger_from_env();
     let peripherals = esp_hal::init(esp_hal::Config::default());
 
@@ -248,9 +172,6 @@ async fn main(spawner: Spawner) {
 
     let mut results = TestResults::new();
 
-    // Run tests that don't need hardware
-    test_app_logic(&mut results);
-
     // Extract the peripherals we need before initializing RTOS timer
     let i2c0 = peripherals.I2C0;
     let gpio8 = peripherals.GPIO8;
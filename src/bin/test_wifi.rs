@@ -1,7 +1,8 @@
 //! Embassy DHCP Example
 //!
 //!
-//! Set SSID and PASSWORD env variable before running this example.
+//! Connects to the first network of the stored configuration, or to the
+//! SSID and PASSWORD env variables given at build time when there is none.
 //!
 //! This gets an ip address via DHCP then performs an HTTP get request to some
 //! "random" server
@@ -24,6 +25,8 @@ use esp_radio::{
         ClientConfig, ModeConfig, ScanConfig, WifiController, WifiDevice, WifiEvent, WifiStaState,
     },
 };
+use gonk::config::{self, ConfigStore, WifiNetwork};
+use gonk::hardware;
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
@@ -47,8 +50,15 @@ macro_rules! mk_static {
     }};
 }
 
-const SSID: &str = env!("SSID");
-const PASSWORD: &str = env!("PASSWORD");
+/// First stored network, falling back to the build time credentials
+fn stored_network(flash: esp_hal::peripherals::FLASH<'static>) -> Option<WifiNetwork> {
+    let flash = mk_static!(hardware::SharedFlash<'static>, hardware::share_flash(flash));
+    let stored = hardware::PartitionStorage::open(flash, config::PARTITION)
+        .and_then(|storage| ConfigStore::new(storage).load())
+        .ok()
+        .and_then(|config| config.networks.first().cloned());
+    stored.or_else(|| WifiNetwork::new(option_env!("SSID")?, option_env!("PASSWORD")?))
+}
 
 #[esp_rtos::main]
 async fn main(spawner: Spawner) -> ! {
@@ -62,6 +72,10 @@ async fn main(spawner: Spawner) -> ! {
     let timg0 = TimerGroup::new(peripherals.TIMG0);
     esp_rtos::start(timg0.timer0);
 
+    let Some(network) = stored_network(peripherals.FLASH) else {
+        panic!("No WiFi network configured");
+    };
+
     let esp_radio_ctrl = &*mk_static!(Controller<'static>, esp_radio::init().unwrap());
 
     let (controller, interfaces) =
@@ -82,7 +96,7 @@ async fn main(spawner: Spawner) -> ! {
         seed,
     );
 
-    spawner.spawn(connection(controller)).ok();
+    spawner.spawn(connection(controller, network)).ok();
     spawner.spawn(net_task(runner)).ok();
 
     let mut rx_buffer = [0; 4096];
@@ -146,7 +160,7 @@ async fn main(spawner: Spawner) -> ! {
 }
 
 #[embassy_executor::task]
-async fn connection(mut controller: WifiController<'static>) {
+async fn connection(mut controller: WifiController<'static>, network: WifiNetwork) {
     println!("start connection task");
     println!("Device capabilities: {:?}", controller.capabilities());
     loop {
@@ -161,8 +175,8 @@ async fn connection(mut controller: WifiController<'static>) {
         if !matches!(controller.is_started(), Ok(true)) {
             let client_config = ModeConfig::Client(
                 ClientConfig::default()
-                    .with_ssid(SSID.into())
-                    .with_password(PASSWORD.into()),
+                    .with_ssid(network.ssid.as_str().into())
+                    .with_password(network.password.as_str().into()),
             );
             controller.set_config(&client_config).unwrap();
             println!("Starting wifi");
@@ -0,0 +1,672 @@
+//! Native BME280 driver (hardware-independent)
+//!
+//! Talks to the sensor through the `I2cBus` trait and implements the integer
+//! compensation formulas from the Bosch BME280 datasheet (section 4.2.3).
+
+use embassy_time::Instant;
+use embedded_hal::delay::DelayNs;
+
+use crate::error::{GonkError, SensorError};
+use crate::traits::{Capabilities, I2cBus, Reading};
+
+/// Default I2C address (SDO pulled low)
+pub const PRIMARY_ADDRESS: u8 = 0x76;
+/// Alternate I2C address (SDO pulled high)
+pub const SECONDARY_ADDRESS: u8 = 0x77;
+
+const REG_CALIB_00: u8 = 0x88;
+const REG_CHIP_ID: u8 = 0xD0;
+const REG_RESET: u8 = 0xE0;
+const REG_CALIB_26: u8 = 0xE1;
+const REG_CTRL_HUM: u8 = 0xF2;
+const REG_STATUS: u8 = 0xF3;
+const REG_CTRL_MEAS: u8 = 0xF4;
+const REG_CONFIG: u8 = 0xF5;
+const REG_DATA: u8 = 0xF7;
+
+const CALIB_00_LEN: usize = 26;
+const CALIB_26_LEN: usize = 7;
+const DATA_LEN: usize = 8;
+
+const CHIP_ID_BME280: u8 = 0x60;
+const RESET_COMMAND: u8 = 0xB6;
+const STATUS_MEASURING: u8 = 1 << 3;
+const STATUS_IM_UPDATE: u8 = 1 << 0;
+
+/// Oversampling x1 for every channel, forced mode
+const CTRL_HUM_OSRS_X1: u8 = 0b001;
+const CTRL_MEAS_FORCED_X1: u8 = (0b001 << 5) | (0b001 << 2) | 0b01;
+const CONFIG_FILTER_OFF: u8 = 0x00;
+
+/// Operating range from the datasheet
+const TEMPERATURE_RANGE_C: (f32, f32) = (-40.0, 85.0);
+const PRESSURE_RANGE_PA: (f32, f32) = (30_000.0, 110_000.0);
+
+/// Typical measurement time with x1 oversampling, the status register is
+/// polled afterwards in case the conversion is still running
+const MEASUREMENT_DELAY_MS: u32 = 8;
+/// Start-up time after a soft reset
+const STARTUP_DELAY_MS: u32 = 2;
+const STATUS_POLL_ATTEMPTS: u32 = 20;
+
+/// Factory calibration coefficients stored in the sensor NVM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CalibrationData {
+    // Temperature calibration
+    pub dig_t1: u16,
+    pub dig_t2: i16,
+    pub dig_t3: i16,
+    // Pressure calibration
+    pub dig_p1: u16,
+    pub dig_p2: i16,
+    pub dig_p3: i16,
+    pub dig_p4: i16,
+    pub dig_p5: i16,
+    pub dig_p6: i16,
+    pub dig_p7: i16,
+    pub dig_p8: i16,
+    pub dig_p9: i16,
+    // Humidity calibration (NEW for BME280)
+    pub dig_h1: u8,
+    pub dig_h2: i16,
+    pub dig_h3: u8,
+    pub dig_h4: i16,
+    pub dig_h5: i16,
+    pub dig_h6: i8,
+}
+
+impl CalibrationData {
+    /// Decode the two calibration blocks (0x88..=0xA1 and 0xE1..=0xE7)
+    pub fn from_registers(calib_00: &[u8; CALIB_00_LEN], calib_26: &[u8; CALIB_26_LEN]) -> Self {
+        let u16_at = |i: usize| u16::from_le_bytes([calib_00[i], calib_00[i + 1]]);
+        let i16_at = |i: usize| i16::from_le_bytes([calib_00[i], calib_00[i + 1]]);
+
+        Self {
+            dig_t1: u16_at(0),
+            dig_t2: i16_at(2),
+            dig_t3: i16_at(4),
+            dig_p1: u16_at(6),
+            dig_p2: i16_at(8),
+            dig_p3: i16_at(10),
+            dig_p4: i16_at(12),
+            dig_p5: i16_at(14),
+            dig_p6: i16_at(16),
+            dig_p7: i16_at(18),
+            dig_p8: i16_at(20),
+            dig_p9: i16_at(22),
+            // calib_00[24] (0xA0) is reserved
+            dig_h1: calib_00[25],
+            dig_h2: i16::from_le_bytes([calib_26[0], calib_26[1]]),
+            dig_h3: calib_26[2],
+            // dig_h4 and dig_h5 are 12-bit signed values sharing 0xE5
+            dig_h4: ((calib_26[3] as i8 as i16) << 4) | (calib_26[4] & 0x0F) as i16,
+            dig_h5: ((calib_26[5] as i8 as i16) << 4) | (calib_26[4] >> 4) as i16,
+            dig_h6: calib_26[6] as i8,
+        }
+    }
+
+    /// Fine temperature value shared by the pressure and humidity formulas
+    pub fn t_fine(&self, adc_t: i32) -> i32 {
+        let t1 = self.dig_t1 as i32;
+        let t2 = self.dig_t2 as i32;
+        let t3 = self.dig_t3 as i32;
+
+        let var1 = (((adc_t >> 3) - (t1 << 1)) * t2) >> 11;
+        let var2 = (((((adc_t >> 4) - t1) * ((adc_t >> 4) - t1)) >> 12) * t3) >> 14;
+        var1 + var2
+    }
+
+    /// Temperature in hundredths of a degree Celsius ("5123" is 51.23 C)
+    pub fn compensate_temperature(t_fine: i32) -> i32 {
+        (t_fine * 5 + 128) >> 8
+    }
+
+    /// Pressure in Pa as unsigned Q24.8 ("24674867" is 96386.2 Pa)
+    pub fn compensate_pressure(&self, adc_p: i32, t_fine: i32) -> u32 {
+        let mut var1 = t_fine as i64 - 128_000;
+        let mut var2 = var1 * var1 * self.dig_p6 as i64;
+        var2 += (var1 * self.dig_p5 as i64) << 17;
+        var2 += (self.dig_p4 as i64) << 35;
+        var1 = ((var1 * var1 * self.dig_p3 as i64) >> 8) + ((var1 * self.dig_p2 as i64) << 12);
+        var1 = (((1i64 << 47) + var1) * self.dig_p1 as i64) >> 33;
+
+        if var1 == 0 {
+            // Avoid a division by zero on an uncalibrated sensor
+            return 0;
+        }
+
+        let mut p = 1_048_576 - adc_p as i64;
+        p = (((p << 31) - var2) * 3125) / var1;
+        var1 = (self.dig_p9 as i64 * (p >> 13) * (p >> 13)) >> 25;
+        var2 = (self.dig_p8 as i64 * p) >> 19;
+        p = ((p + var1 + var2) >> 8) + ((self.dig_p7 as i64) << 4);
+        p as u32
+    }
+
+    /// Relative humidity in %RH as unsigned Q22.10 ("47445" is 46.333 %RH)
+    pub fn compensate_humidity(&self, adc_h: i32, t_fine: i32) -> u32 {
+        let h1 = self.dig_h1 as i32;
+        let h2 = self.dig_h2 as i32;
+        let h3 = self.dig_h3 as i32;
+        let h4 = self.dig_h4 as i32;
+        let h5 = self.dig_h5 as i32;
+        let h6 = self.dig_h6 as i32;
+
+        let x = t_fine - 76_800;
+        let offset = (((adc_h << 14) - (h4 << 20) - (h5 * x)) + 16_384) >> 15;
+        let scale_t = (((x * h6) >> 10) * (((x * h3) >> 11) + 32_768)) >> 10;
+        let scale = ((scale_t + 2_097_152) * h2 + 8_192) >> 14;
+
+        let mut v = offset * scale;
+        v -= ((((v >> 15) * (v >> 15)) >> 7) * h1) >> 4;
+        v = v.clamp(0, 419_430_400);
+        (v >> 12) as u32
+    }
+}
+
+/// Uncompensated ADC values from one burst read of 0xF7..=0xFE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawMeasurements {
+    pub pressure: i32,
+    pub temperature: i32,
+    pub humidity: i32,
+}
+
+impl RawMeasurements {
+    /// Decode the 20-bit pressure/temperature and 16-bit humidity words
+    pub fn from_registers(data: &[u8; DATA_LEN]) -> Self {
+        let adc20 = |msb: u8, lsb: u8, xlsb: u8| {
+            ((msb as i32) << 12) | ((lsb as i32) << 4) | ((xlsb as i32) >> 4)
+        };
+
+        Self {
+            pressure: adc20(data[0], data[1], data[2]),
+            temperature: adc20(data[3], data[4], data[5]),
+            humidity: ((data[6] as i32) << 8) | data[7] as i32,
+        }
+    }
+}
+
+/// Compensated measurements
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurements {
+    /// Temperature in Celsius
+    pub temperature: f32,
+    /// Pressure in Pa
+    pub pressure: f32,
+    /// Relative humidity in %, `None` on a BMP280
+    pub humidity: Option<f32>,
+}
+
+impl Measurements {
+    /// Apply the compensation formulas to a raw reading
+    pub fn compensate(calibration: &CalibrationData, raw: &RawMeasurements) -> Self {
+        let t_fine = calibration.t_fine(raw.temperature);
+
+        Self {
+            temperature: CalibrationData::compensate_temperature(t_fine) as f32 / 100.0,
+            pressure: calibration.compensate_pressure(raw.pressure, t_fine) as f32 / 256.0,
+            humidity: Some(calibration.compensate_humidity(raw.humidity, t_fine) as f32 / 1024.0),
+        }
+    }
+
+    /// Reject values outside of the sensor operating range, which point to a
+    /// corrupted transfer or a skipped conversion rather than a real reading
+    pub fn check_range(&self) -> Result<(), SensorError> {
+        if !(TEMPERATURE_RANGE_C.0..=TEMPERATURE_RANGE_C.1).contains(&self.temperature)
+            || !(PRESSURE_RANGE_PA.0..=PRESSURE_RANGE_PA.1).contains(&self.pressure)
+        {
+            return Err(SensorError::OutOfRange);
+        }
+        Ok(())
+    }
+
+    /// Convert to a sensor-independent reading
+    pub fn into_reading(self, timestamp: Instant) -> Reading {
+        Reading {
+            temperature: Some(self.temperature),
+            pressure: Some(self.pressure),
+            humidity: self.humidity,
+            ..Reading::new(timestamp)
+        }
+    }
+}
+
+/// Chip family reported by the ID register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChipVariant {
+    /// Temperature and pressure only
+    Bmp280,
+    /// Temperature, pressure and humidity
+    Bme280,
+}
+
+impl ChipVariant {
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            // 0x56 and 0x57 are BMP280 engineering samples
+            0x56..=0x58 => Some(Self::Bmp280),
+            CHIP_ID_BME280 => Some(Self::Bme280),
+            _ => None,
+        }
+    }
+
+    pub fn has_humidity(&self) -> bool {
+        matches!(self, Self::Bme280)
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            temperature: true,
+            pressure: true,
+            humidity: self.has_humidity(),
+            ..Capabilities::default()
+        }
+    }
+}
+
+/// Contents of the status register (0xF3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status(pub u8);
+
+impl Status {
+    /// A conversion is running
+    pub fn measuring(&self) -> bool {
+        self.0 & STATUS_MEASURING != 0
+    }
+
+    /// NVM data is being copied to the image registers
+    pub fn im_update(&self) -> bool {
+        self.0 & STATUS_IM_UPDATE != 0
+    }
+}
+
+/// BME280 driver over any `I2cBus`
+pub struct Bme280<I2C> {
+    i2c: I2C,
+    address: u8,
+    variant: Option<ChipVariant>,
+    calibration: Option<CalibrationData>,
+}
+
+impl<I2C: I2cBus> Bme280<I2C> {
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self {
+            i2c,
+            address,
+            variant: None,
+            calibration: None,
+        }
+    }
+
+    /// Check the chip ID, reset the sensor, read the calibration block and
+    /// configure oversampling
+    pub fn init<D: DelayNs>(&mut self, delay: &mut D) -> Result<ChipVariant, GonkError> {
+        self.variant = None;
+        self.calibration = None;
+
+        let id = self.read_chip_id()?;
+        let variant = ChipVariant::from_id(id).ok_or(SensorError::UnsupportedChip(id))?;
+
+        self.soft_reset(delay)?;
+
+        let mut calib_00 = [0u8; CALIB_00_LEN];
+        let mut calib_26 = [0u8; CALIB_26_LEN];
+        self.read_registers(REG_CALIB_00, &mut calib_00)?;
+        if variant.has_humidity() {
+            self.read_registers(REG_CALIB_26, &mut calib_26)?;
+            // ctrl_hum only takes effect after a write to ctrl_meas
+            self.write_register(REG_CTRL_HUM, CTRL_HUM_OSRS_X1)?;
+        }
+        self.write_register(REG_CONFIG, CONFIG_FILTER_OFF)?;
+
+        self.calibration = Some(CalibrationData::from_registers(&calib_00, &calib_26));
+        self.variant = Some(variant);
+
+        Ok(variant)
+    }
+
+    /// Read the chip identification register
+    pub fn read_chip_id(&mut self) -> Result<u8, GonkError> {
+        let mut id = [0u8; 1];
+        self.read_registers(REG_CHIP_ID, &mut id)?;
+        Ok(id[0])
+    }
+
+    /// Reset the sensor and wait for the NVM copy to complete
+    pub fn soft_reset<D: DelayNs>(&mut self, delay: &mut D) -> Result<(), GonkError> {
+        self.write_register(REG_RESET, RESET_COMMAND)?;
+        delay.delay_ms(STARTUP_DELAY_MS);
+        self.wait_until(delay, |status| !status.im_update())
+    }
+
+    /// Read the status register
+    pub fn read_status(&mut self) -> Result<Status, GonkError> {
+        let mut status = [0u8; 1];
+        self.read_registers(REG_STATUS, &mut status)?;
+        Ok(Status(status[0]))
+    }
+
+    /// Chip family detected during `init()`
+    pub fn variant(&self) -> Option<ChipVariant> {
+        self.variant
+    }
+
+    /// Calibration data read during `init()`
+    pub fn calibration(&self) -> Option<&CalibrationData> {
+        self.calibration.as_ref()
+    }
+
+    /// Trigger a forced-mode conversion and return the raw ADC values
+    pub fn read_raw<D: DelayNs>(&mut self, delay: &mut D) -> Result<RawMeasurements, GonkError> {
+        let variant = self.variant.ok_or(SensorError::NotInitialized)?;
+
+        self.write_register(REG_CTRL_MEAS, CTRL_MEAS_FORCED_X1)?;
+        delay.delay_ms(MEASUREMENT_DELAY_MS);
+        self.wait_until(delay, |status| !status.measuring())?;
+
+        // The BMP280 has no humidity registers, stop the burst after temperature
+        let mut data = [0u8; DATA_LEN];
+        let len = if variant.has_humidity() { DATA_LEN } else { 6 };
+        self.read_registers(REG_DATA, &mut data[..len])?;
+        Ok(RawMeasurements::from_registers(&data))
+    }
+
+    /// Read compensated temperature, pressure and humidity
+    pub fn measure<D: DelayNs>(&mut self, delay: &mut D) -> Result<Measurements, GonkError> {
+        let calibration = self.calibration.ok_or(SensorError::NotInitialized)?;
+        let raw = self.read_raw(delay)?;
+        let mut measurements = Measurements::compensate(&calibration, &raw);
+        if !self.variant.is_some_and(|v| v.has_humidity()) {
+            measurements.humidity = None;
+        }
+        measurements.check_range()?;
+        Ok(measurements)
+    }
+
+    /// Read compensated temperature in Celsius
+    pub fn read_temperature<D: DelayNs>(&mut self, delay: &mut D) -> Result<f32, GonkError> {
+        Ok(self.measure(delay)?.temperature)
+    }
+
+    /// Mutable access to the underlying bus, e.g. for diagnostics
+    pub fn bus_mut(&mut self) -> &mut I2C {
+        &mut self.i2c
+    }
+
+    /// Release the underlying bus
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    fn wait_until<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        ready: impl Fn(Status) -> bool,
+    ) -> Result<(), GonkError> {
+        for _ in 0..STATUS_POLL_ATTEMPTS {
+            if ready(self.read_status()?) {
+                return Ok(());
+            }
+            delay.delay_ms(1);
+        }
+        Err(GonkError::Timeout)
+    }
+
+    fn read_registers(&mut self, register: u8, buffer: &mut [u8]) -> Result<(), GonkError> {
+        self.i2c.write_read(self.address, &[register], buffer)
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), GonkError> {
+        self.i2c.write(self.address, &[register, value])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Register dump of the calibration example from the BMP280 datasheet
+    /// (section 3.12), extended with humidity coefficients from a BME280
+    const CALIB_00: [u8; CALIB_00_LEN] = [
+        0x70, 0x6B, // dig_t1 = 27504
+        0x43, 0x67, // dig_t2 = 26435
+        0x18, 0xFC, // dig_t3 = -1000
+        0x7D, 0x8E, // dig_p1 = 36477
+        0x43, 0xD6, // dig_p2 = -10685
+        0xD0, 0x0B, // dig_p3 = 3024
+        0x27, 0x0B, // dig_p4 = 2855
+        0x8C, 0x00, // dig_p5 = 140
+        0xF9, 0xFF, // dig_p6 = -7
+        0x8C, 0x3C, // dig_p7 = 15500
+        0xF8, 0xC6, // dig_p8 = -14600
+        0x70, 0x17, // dig_p9 = 6000
+        0x00, // reserved
+        0x4B, // dig_h1 = 75
+    ];
+
+    const CALIB_26: [u8; CALIB_26_LEN] = [
+        0x6A, 0x01, // dig_h2 = 362
+        0x00, // dig_h3 = 0
+        0x13, 0x2A, 0x03, // dig_h4 = 314, dig_h5 = 50
+        0x1E, // dig_h6 = 30
+    ];
+
+    /// adc_p = 415148, adc_t = 519888, adc_h = 27000
+    const DATA: [u8; DATA_LEN] = [0x65, 0x5A, 0xC0, 0x7E, 0xED, 0x00, 0x69, 0x78];
+
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    /// Minimal register file answering register reads from the dumps above
+    struct RegisterDump {
+        chip_id: u8,
+        /// Status register value, reported as busy for `busy_reads` reads
+        status: u8,
+        busy_reads: u32,
+        writes: heapless::Vec<(u8, u8), 8>,
+    }
+
+    impl RegisterDump {
+        fn new(chip_id: u8) -> Self {
+            Self {
+                chip_id,
+                status: 0,
+                busy_reads: 0,
+                writes: heapless::Vec::new(),
+            }
+        }
+    }
+
+    impl I2cBus for RegisterDump {
+        fn write(&mut self, _addr: u8, bytes: &[u8]) -> Result<(), GonkError> {
+            self.writes
+                .push((bytes[0], bytes[1]))
+                .expect("too many writes");
+            Ok(())
+        }
+
+        fn write_read(
+            &mut self,
+            _addr: u8,
+            write: &[u8],
+            read: &mut [u8],
+        ) -> Result<(), GonkError> {
+            let status = if self.busy_reads > 0 {
+                self.busy_reads -= 1;
+                self.status
+            } else {
+                0
+            };
+            let chip_id = self.chip_id;
+            let source: &[u8] = match write[0] {
+                REG_CALIB_00 => &CALIB_00,
+                REG_CALIB_26 => &CALIB_26,
+                REG_CHIP_ID => &[chip_id],
+                REG_STATUS => &[status],
+                REG_DATA => &DATA,
+                _ => return Err(GonkError::NACK),
+            };
+            read.copy_from_slice(&source[..read.len()]);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn decodes_calibration_registers() {
+        let calibration = CalibrationData::from_registers(&CALIB_00, &CALIB_26);
+
+        assert_eq!(calibration.dig_t1, 27504);
+        assert_eq!(calibration.dig_t2, 26435);
+        assert_eq!(calibration.dig_t3, -1000);
+        assert_eq!(calibration.dig_p1, 36477);
+        assert_eq!(calibration.dig_p6, -7);
+        assert_eq!(calibration.dig_p9, 6000);
+        assert_eq!(calibration.dig_h1, 75);
+        assert_eq!(calibration.dig_h2, 362);
+        assert_eq!(calibration.dig_h4, 314);
+        assert_eq!(calibration.dig_h5, 50);
+        assert_eq!(calibration.dig_h6, 30);
+    }
+
+    #[test]
+    fn decodes_negative_humidity_coefficients() {
+        let calib_26 = [0x6A, 0x01, 0x00, 0xFE, 0xF7, 0xFF, 0x1E];
+        let calibration = CalibrationData::from_registers(&CALIB_00, &calib_26);
+
+        assert_eq!(calibration.dig_h4, -25);
+        assert_eq!(calibration.dig_h5, -1);
+    }
+
+    #[test]
+    fn decodes_raw_adc_values() {
+        let raw = RawMeasurements::from_registers(&DATA);
+
+        assert_eq!(raw.pressure, 415148);
+        assert_eq!(raw.temperature, 519888);
+        assert_eq!(raw.humidity, 27000);
+    }
+
+    #[test]
+    fn compensates_datasheet_example() {
+        let calibration = CalibrationData::from_registers(&CALIB_00, &CALIB_26);
+        let t_fine = calibration.t_fine(519888);
+
+        assert_eq!(t_fine, 128422);
+        assert_eq!(CalibrationData::compensate_temperature(t_fine), 2508);
+        assert_eq!(
+            calibration.compensate_pressure(415148, t_fine) / 256,
+            100653
+        );
+    }
+
+    #[test]
+    fn clamps_humidity_range() {
+        let calibration = CalibrationData::from_registers(&CALIB_00, &CALIB_26);
+        let t_fine = calibration.t_fine(519888);
+
+        assert_eq!(calibration.compensate_humidity(0, t_fine), 0);
+        assert_eq!(calibration.compensate_humidity(0xFFFF, t_fine), 100 << 10);
+    }
+
+    #[test]
+    fn measures_from_register_dump() {
+        let mut bme280 = Bme280::new(RegisterDump::new(CHIP_ID_BME280), PRIMARY_ADDRESS);
+
+        assert_eq!(
+            bme280.measure(&mut NoDelay),
+            Err(SensorError::NotInitialized.into())
+        );
+        assert_eq!(bme280.init(&mut NoDelay), Ok(ChipVariant::Bme280));
+
+        let measurements = bme280.measure(&mut NoDelay).unwrap();
+        assert!((measurements.temperature - 25.08).abs() < 0.001);
+        assert!((measurements.pressure - 100653.27).abs() < 0.1);
+        assert!((measurements.humidity.unwrap() - 37.91).abs() < 0.01);
+
+        let bus = bme280.release();
+        assert_eq!(
+            bus.writes.as_slice(),
+            &[
+                (REG_RESET, RESET_COMMAND),
+                (REG_CTRL_HUM, CTRL_HUM_OSRS_X1),
+                (REG_CONFIG, CONFIG_FILTER_OFF),
+                (REG_CTRL_MEAS, CTRL_MEAS_FORCED_X1),
+            ]
+        );
+    }
+
+    #[test]
+    fn bmp280_has_no_humidity() {
+        let mut bme280 = Bme280::new(RegisterDump::new(0x58), PRIMARY_ADDRESS);
+
+        assert_eq!(bme280.init(&mut NoDelay), Ok(ChipVariant::Bmp280));
+
+        let measurements = bme280.measure(&mut NoDelay).unwrap();
+        assert!((measurements.temperature - 25.08).abs() < 0.001);
+        assert_eq!(measurements.humidity, None);
+        assert!(!bme280.release().writes.iter().any(|w| w.0 == REG_CTRL_HUM));
+    }
+
+    #[test]
+    fn rejects_values_outside_operating_range() {
+        let mut measurements = Measurements {
+            temperature: 25.08,
+            pressure: 100653.27,
+            humidity: Some(37.9),
+        };
+        assert_eq!(measurements.check_range(), Ok(()));
+
+        measurements.temperature = 120.0;
+        assert_eq!(measurements.check_range(), Err(SensorError::OutOfRange));
+
+        measurements.temperature = 25.08;
+        measurements.pressure = 0.0;
+        assert_eq!(measurements.check_range(), Err(SensorError::OutOfRange));
+    }
+
+    #[test]
+    fn converts_to_reading() {
+        let measurements = Measurements {
+            temperature: 21.0,
+            pressure: 101325.0,
+            humidity: None,
+        };
+        let reading = measurements.into_reading(Instant::from_millis(1_500));
+
+        assert_eq!(reading.timestamp.as_millis(), 1_500);
+        assert_eq!(reading.temperature, Some(21.0));
+        assert_eq!(reading.pressure, Some(101325.0));
+        assert_eq!(reading.humidity, None);
+        assert_eq!(reading.co2, None);
+        assert!(!ChipVariant::Bmp280.capabilities().humidity);
+        assert!(ChipVariant::Bme280.capabilities().humidity);
+    }
+
+    #[test]
+    fn rejects_unknown_chip_id() {
+        let mut bme280 = Bme280::new(RegisterDump::new(0x61), PRIMARY_ADDRESS);
+
+        assert_eq!(
+            bme280.init(&mut NoDelay),
+            Err(SensorError::UnsupportedChip(0x61).into())
+        );
+        assert_eq!(bme280.variant(), None);
+    }
+
+    #[test]
+    fn polls_status_until_ready() {
+        let mut bus = RegisterDump::new(CHIP_ID_BME280);
+        bus.status = STATUS_IM_UPDATE;
+        bus.busy_reads = 3;
+        let mut bme280 = Bme280::new(bus, PRIMARY_ADDRESS);
+        assert!(bme280.init(&mut NoDelay).is_ok());
+
+        bme280.bus_mut().status = STATUS_MEASURING;
+        bme280.bus_mut().busy_reads = STATUS_POLL_ATTEMPTS;
+        assert_eq!(bme280.measure(&mut NoDelay), Err(GonkError::Timeout));
+    }
+}
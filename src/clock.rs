@@ -0,0 +1,277 @@
+//! Wall-clock time kept from SNTP samples (hardware-independent)
+//!
+//! Each sync anchors the Unix time to a local `Instant`. Between syncs the
+//! time is extrapolated from the anchor, corrected by the drift of the local
+//! clock measured by the previous syncs.
+
+use core::fmt::Write;
+
+use embassy_time::{Duration, Instant};
+use heapless::String;
+
+/// Time between two syncs
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Time before trying again after a failed sync
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// Shortest time between two syncs for the drift to be measured
+const MIN_DRIFT_SPAN: Duration = Duration::from_secs(10 * 60);
+/// Corrections larger than this are steps of the server time, not drift
+const MAX_DRIFT_CORRECTION_US: i64 = 1_000_000;
+/// Largest drift believed, in parts per billion, crystals do far better
+const MAX_DRIFT_PPB: i64 = 500_000;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Wall-clock time service
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Clock {
+    /// Unix time in microseconds at a local instant, from the last sync
+    anchor: Option<(Instant, i64)>,
+    /// Rate error of the local clock, positive when it runs slow
+    drift_ppb: i64,
+    /// Correction applied by the last sync, in microseconds
+    last_correction_us: i64,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.anchor.is_some()
+    }
+
+    /// When the last sync happened
+    pub fn last_sync(&self) -> Option<Instant> {
+        self.anchor.map(|(at, _)| at)
+    }
+
+    /// Whether it is time to sync again
+    pub fn needs_sync(&self, now: Instant) -> bool {
+        self.last_sync()
+            .is_none_or(|at| now.saturating_duration_since(at) >= SYNC_INTERVAL)
+    }
+
+    /// Measured drift of the local clock, in parts per million
+    pub fn drift_ppm(&self) -> f32 {
+        self.drift_ppb as f32 / 1000.0
+    }
+
+    /// Difference between the server time and the time kept at the last sync
+    pub fn last_correction_us(&self) -> i64 {
+        self.last_correction_us
+    }
+
+    /// Take the server time `unix_micros` measured at the local instant `at`
+    pub fn synchronize(&mut self, at: Instant, unix_micros: i64) {
+        if let (Some((anchor, _)), Some(kept)) = (self.anchor, self.unix_micros(at)) {
+            let error = unix_micros - kept;
+            self.last_correction_us = error;
+
+            let span = at.saturating_duration_since(anchor);
+            if span >= MIN_DRIFT_SPAN && error.abs() <= MAX_DRIFT_CORRECTION_US {
+                let span = span.as_micros() as i64;
+                self.drift_ppb = (self.drift_ppb + error * 1_000_000_000 / span)
+                    .clamp(-MAX_DRIFT_PPB, MAX_DRIFT_PPB);
+            }
+        }
+        self.anchor = Some((at, unix_micros));
+    }
+
+    /// Unix time in microseconds at the local instant `at`, past or future
+    pub fn unix_micros(&self, at: Instant) -> Option<i64> {
+        let (anchor, unix) = self.anchor?;
+        let elapsed = at.as_micros() as i64 - anchor.as_micros() as i64;
+        Some(unix + elapsed + elapsed * self.drift_ppb / 1_000_000_000)
+    }
+
+    /// Unix time in seconds at the local instant `at`, e.g. of a reading
+    pub fn unix_time(&self, at: Instant) -> Option<i64> {
+        self.unix_micros(at)
+            .map(|micros| micros.div_euclid(1_000_000))
+    }
+
+    /// Local instant the minute after `now` starts, to update a clock face
+    pub fn next_minute(&self, now: Instant) -> Option<Instant> {
+        let micros = self.unix_micros(now)?;
+        let left = 60_000_000 - micros.rem_euclid(60_000_000);
+        Some(now + Duration::from_micros(left as u64))
+    }
+
+    /// UTC date and time at the local instant `at`
+    pub fn utc(&self, at: Instant) -> Option<DateTime> {
+        self.unix_time(at).map(DateTime::from_unix)
+    }
+}
+
+/// Calendar date and time of day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i32,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to 31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// 0 for Sunday to 6 for Saturday
+    pub weekday: u8,
+}
+
+impl DateTime {
+    pub fn from_unix(secs: i64) -> Self {
+        let days = secs.div_euclid(SECONDS_PER_DAY);
+        let time = secs.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        Self {
+            year,
+            month,
+            day,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+            // 1970-01-01 was a Thursday
+            weekday: (days + 4).rem_euclid(7) as u8,
+        }
+    }
+
+    /// e.g. "14:05:09"
+    pub fn format_time(&self) -> String<8> {
+        let mut buffer = String::new();
+        let _ = write!(
+            buffer,
+            "{:02}:{:02}:{:02}",
+            self.hour, self.minute, self.second
+        );
+        buffer
+    }
+
+    /// e.g. "Sat 17 Oct 2026"
+    pub fn format_date(&self) -> String<16> {
+        let mut buffer = String::new();
+        let _ = write!(
+            buffer,
+            "{} {} {} {}",
+            weekday_name(self.weekday),
+            self.day,
+            month_name(self.month),
+            self.year
+        );
+        buffer
+    }
+}
+
+/// Days since 1970-01-01 of a date of the proleptic Gregorian calendar
+pub fn days_from_civil(year: i32, month: u8, day: u8) -> i64 {
+    // Years starting in March, so that the leap day ends the year
+    let year = year as i64 - (month <= 2) as i64;
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Year, month and day of a number of days since 1970-01-01
+pub fn civil_from_days(days: i64) -> (i32, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u8;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u8;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year as i32, month, day)
+}
+
+pub fn weekday_name(weekday: u8) -> &'static str {
+    ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"][weekday as usize % 7]
+}
+
+pub fn month_name(month: u8) -> &'static str {
+    [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ][(month as usize + 11) % 12]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-10-17 12:00:00 UTC
+    const NOON: i64 = 1_792_238_400;
+
+    #[test]
+    fn converts_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        for days in (-800_000..800_000).step_by(997) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+
+        let time = DateTime::from_unix(NOON + 3_909);
+        assert_eq!(time.format_date(), "Sat 17 Oct 2026");
+        assert_eq!(time.format_time(), "13:05:09");
+        assert_eq!(DateTime::from_unix(-1).format_time(), "23:59:59");
+    }
+
+    #[test]
+    fn keeps_time_between_syncs() {
+        let mut clock = Clock::new();
+        assert!(clock.needs_sync(Instant::from_secs(0)));
+        assert_eq!(clock.unix_time(Instant::from_secs(5)), None);
+
+        clock.synchronize(Instant::from_secs(10), NOON * 1_000_000 + 250_000);
+        assert!(clock.is_synced());
+        assert_eq!(clock.unix_time(Instant::from_secs(70)), Some(NOON + 60));
+        // Readings taken before the first sync get a time too
+        assert_eq!(clock.unix_time(Instant::from_secs(4)), Some(NOON - 6));
+        assert!(!clock.needs_sync(Instant::from_secs(10) + SYNC_INTERVAL / 2));
+        assert!(clock.needs_sync(Instant::from_secs(10) + SYNC_INTERVAL));
+        assert_eq!(
+            clock.next_minute(Instant::from_secs(70)),
+            Some(Instant::from_micros(129_750_000))
+        );
+    }
+
+    #[test]
+    fn measures_drift() {
+        let mut clock = Clock::new();
+        let start = NOON * 1_000_000;
+        clock.synchronize(Instant::from_secs(0), start);
+
+        // The local clock lost 36 ms in an hour: 10 ppm slow
+        clock.synchronize(Instant::from_secs(3_600), start + 3_600_036_000);
+        assert_eq!(clock.last_correction_us(), 36_000);
+        assert_eq!(clock.drift_ppm(), 10.0);
+        assert_eq!(
+            clock.unix_micros(Instant::from_secs(7_200)),
+            Some(start + 7_200_072_000)
+        );
+
+        // A step of the server time is taken without changing the drift
+        clock.synchronize(Instant::from_secs(7_200), start + 7_260_072_000);
+        assert_eq!(clock.drift_ppm(), 10.0);
+        assert_eq!(
+            clock.unix_time(Instant::from_secs(7_200)),
+            Some(NOON + 7_260)
+        );
+
+        // Too soon after the previous sync to tell drift from jitter
+        clock.synchronize(Instant::from_secs(7_260), start + 7_320_082_600);
+        assert_eq!(clock.drift_ppm(), 10.0);
+    }
+}
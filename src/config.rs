@@ -0,0 +1,1067 @@
+//! Persistent device configuration (hardware-independent)
+//!
+//! The configuration is stored as a CRC-checked record in one of two flash
+//! slots. A save writes the slot not holding the newest record, with a higher
+//! sequence number, so that losing power mid-write leaves the previous record
+//! intact. Fields are only ever appended to the payload, along with a new
+//! `CONFIG_VERSION`: an older record decodes with defaults for the fields its
+//! version does not have. Records of a newer version are not read.
+
+use heapless::{String, Vec};
+
+use crate::error::{GonkError, StorageError};
+use crate::screen::{Layout, Page};
+use crate::settings::{Rotation, Settings, TemperatureUnit};
+use crate::traits::{DisplayType, Reading, Storage};
+use crate::tz::{MAX_TZ_LEN, TimeFormat};
+
+/// Label of the data partition holding the configuration, see `partitions.csv`
+pub const PARTITION: &str = "gonk";
+/// Version written in new records, raised with every group of fields appended
+///
+/// 1. WiFi networks, display, pins, settings, thresholds, OpenWeather API key
+/// 2. Sensor offsets, API token
+/// 3. Weather location
+/// 4. Time zone, time format
+/// 5. MQTT broker and credentials
+/// 6. OTA key
+/// 7. Display layout
+pub const CONFIG_VERSION: u16 = 7;
+/// Number of WiFi networks remembered
+pub const MAX_NETWORKS: usize = 4;
+
+/// Size of a slot, one flash sector
+pub const SLOT_SIZE: u32 = 4096;
+const SLOTS: u32 = 2;
+const MAGIC: [u8; 4] = *b"GONK";
+/// Magic, version, sequence number, payload length and payload CRC
+const HEADER_LEN: usize = 16;
+const MAX_PAYLOAD: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WifiNetwork {
+    pub ssid: String<32>,
+    pub password: String<64>,
+}
+
+impl WifiNetwork {
+    /// `None` when the SSID or password is too long
+    pub fn new(ssid: &str, password: &str) -> Option<Self> {
+        Some(Self {
+            ssid: String::try_from(ssid).ok()?,
+            password: String::try_from(password).ok()?,
+        })
+    }
+
+    /// WPA2 passphrase of 8 to 63 characters, or empty for an open network
+    pub fn has_valid_password(&self) -> bool {
+        self.password.is_empty() || (8..=63).contains(&self.password.len())
+    }
+}
+
+/// GPIO numbers of the peripherals wired at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pins {
+    pub sensor_sda: u8,
+    pub sensor_scl: u8,
+    pub display_sda: u8,
+    pub display_scl: u8,
+    pub button_green: u8,
+    pub button_blue: u8,
+}
+
+/// GPIOs of the ESP32-S3 free for the configured peripherals
+///
+/// Left out: GPIO22 to 25, which do not exist, the supply ADC (4), the e-paper
+/// SPI (10, 11, 14 to 17), the strapping pins (0, 3, 45, 46), the USB-JTAG
+/// console (19, 20), flash and octal PSRAM (26 to 37) and UART0 (43, 44).
+const FREE_GPIOS: [u8; 18] = [
+    1, 2, 5, 6, 7, 8, 9, 12, 13, 18, 21, 38, 39, 40, 41, 42, 47, 48,
+];
+
+impl Default for Pins {
+    fn default() -> Self {
+        Self {
+            sensor_sda: 8,
+            sensor_scl: 9,
+            display_sda: 2,
+            display_scl: 1,
+            button_green: 12,
+            button_blue: 13,
+        }
+    }
+}
+
+impl Pins {
+    fn as_array(&self) -> [u8; 6] {
+        [
+            self.sensor_sda,
+            self.sensor_scl,
+            self.display_sda,
+            self.display_scl,
+            self.button_green,
+            self.button_blue,
+        ]
+    }
+
+    /// Every pin exists, is free and is used once
+    pub fn is_valid(&self) -> bool {
+        let pins = self.as_array();
+        pins.iter()
+            .enumerate()
+            .all(|(i, pin)| FREE_GPIOS.contains(pin) && !pins[..i].contains(pin))
+    }
+}
+
+/// Limits of the temperature comfort levels in Celsius
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    pub cold: f32,
+    pub cool: f32,
+    pub comfortable: f32,
+    pub warm: f32,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            cold: 10.0,
+            cool: 20.0,
+            comfortable: 25.0,
+            warm: 30.0,
+        }
+    }
+}
+
+impl Thresholds {
+    /// Comfort level of a temperature
+    pub fn status(&self, temperature: f32) -> &'static str {
+        if temperature < self.cold {
+            "Cold"
+        } else if temperature < self.cool {
+            "Cool"
+        } else if temperature < self.comfortable {
+            "Comfortable"
+        } else if temperature < self.warm {
+            "Warm"
+        } else {
+            "Hot"
+        }
+    }
+}
+
+/// Corrections added to the sensor readings, for a sensor reading off
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Offsets {
+    /// In Celsius
+    pub temperature: f32,
+    /// In % of relative humidity
+    pub humidity: f32,
+    /// In Pa
+    pub pressure: f32,
+}
+
+impl Offsets {
+    /// Correct the quantities present in `reading`
+    pub fn apply(&self, reading: &mut Reading) {
+        if let Some(t) = &mut reading.temperature {
+            *t += self.temperature;
+        }
+        if let Some(h) = &mut reading.humidity {
+            *h = (*h + self.humidity).clamp(0.0, 100.0);
+        }
+        if let Some(p) = &mut reading.pressure {
+            *p += self.pressure;
+        }
+    }
+}
+
+/// Everything the device remembers across reboots
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Known WiFi networks, in order of preference
+    pub networks: Vec<WifiNetwork, MAX_NETWORKS>,
+    /// Display to drive, `None` probes the bus at startup
+    pub display: Option<DisplayType>,
+    pub pins: Pins,
+    pub settings: Settings,
+    pub thresholds: Thresholds,
+    pub openweather_api_key: String<48>,
+    pub offsets: Offsets,
+    /// Secret expected by the configuration API, empty leaves it open
+    pub api_token: String<32>,
+    /// Place name or "latitude,longitude" the weather is fetched for
+    pub weather_location: String<48>,
+    /// POSIX TZ string of local time, empty for UTC
+    pub timezone: String<MAX_TZ_LEN>,
+    pub time_format: TimeFormat,
+    /// MQTT broker as "host[:port]", empty disables publishing
+    pub mqtt_broker: String<48>,
+    /// Empty for an anonymous connection
+    pub mqtt_username: String<32>,
+    pub mqtt_password: String<64>,
+    /// Key firmware images must be signed with, empty accepts any image
+    pub ota_key: String<64>,
+    pub layout: Layout,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            networks: Vec::new(),
+            display: None,
+            pins: Pins::default(),
+            settings: Settings::default(),
+            thresholds: Thresholds::default(),
+            openweather_api_key: String::new(),
+            offsets: Offsets::default(),
+            api_token: String::new(),
+            weather_location: String::new(),
+            timezone: String::new(),
+            time_format: TimeFormat::H24,
+            mqtt_broker: String::new(),
+            mqtt_username: String::new(),
+            mqtt_password: String::new(),
+            ota_key: String::new(),
+            layout: Layout::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Add a network or update its password, it becomes the preferred one
+    ///
+    /// The least preferred network is forgotten when the list is full.
+    pub fn remember_network(&mut self, network: WifiNetwork) {
+        if let Some(index) = self.networks.iter().position(|n| n.ssid == network.ssid) {
+            self.networks.remove(index);
+        } else if self.networks.is_full() {
+            self.networks.pop();
+        }
+        let _ = self.networks.insert(0, network);
+    }
+
+    /// Serialize the fields, returns the payload length
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, GonkError> {
+        let mut encoder = Encoder { buf, pos: 0 };
+
+        encoder.u8(self.networks.len() as u8)?;
+        for network in &self.networks {
+            encoder.str(&network.ssid)?;
+            encoder.str(&network.password)?;
+        }
+        encoder.u8(match self.display {
+            None => 0,
+            Some(DisplayType::EPaper) => 1,
+            Some(DisplayType::SSD1306) => 2,
+        })?;
+        encoder.bytes(&self.pins.as_array())?;
+        encoder.u8(match self.settings.units {
+            TemperatureUnit::Celsius => 0,
+            TemperatureUnit::Fahrenheit => 1,
+        })?;
+        encoder.bytes(&self.settings.refresh_interval_s.to_le_bytes())?;
+        encoder.u8(self.settings.contrast)?;
+        encoder.bytes(&self.settings.screen_timeout_s.to_le_bytes())?;
+        encoder.u8(match self.settings.rotation {
+            Rotation::Normal => 0,
+            Rotation::Flipped => 1,
+        })?;
+        for threshold in [
+            self.thresholds.cold,
+            self.thresholds.cool,
+            self.thresholds.comfortable,
+            self.thresholds.warm,
+        ] {
+            encoder.bytes(&threshold.to_le_bytes())?;
+        }
+        encoder.str(&self.openweather_api_key)?;
+        for offset in [
+            self.offsets.temperature,
+            self.offsets.humidity,
+            self.offsets.pressure,
+        ] {
+            encoder.bytes(&offset.to_le_bytes())?;
+        }
+        encoder.str(&self.api_token)?;
+        encoder.str(&self.weather_location)?;
+        encoder.str(&self.timezone)?;
+        encoder.u8(match self.time_format {
+            TimeFormat::H24 => 0,
+            TimeFormat::H12 => 1,
+        })?;
+        encoder.str(&self.mqtt_broker)?;
+        encoder.str(&self.mqtt_username)?;
+        encoder.str(&self.mqtt_password)?;
+        encoder.str(&self.ota_key)?;
+        encoder.u8(self.layout.pages.len() as u8)?;
+        for page in &self.layout.pages {
+            encoder.u8(page.index())?;
+        }
+        encoder.u8(self.layout.auto_rotate as u8)?;
+        encoder.bytes(&self.layout.rotate_every_s.to_le_bytes())?;
+
+        Ok(encoder.pos)
+    }
+
+    /// Parse a payload written by `encode()` in a record of `version`
+    ///
+    /// Fields the version does not have keep their default. Invalid values,
+    /// missing fields and bytes past the fields of the version are an error.
+    pub fn decode(version: u16, payload: &[u8]) -> Result<Self, GonkError> {
+        if !(1..=CONFIG_VERSION).contains(&version) {
+            return Err(StorageError::Corrupt.into());
+        }
+        let mut config = Config::default();
+        let mut decoder = Decoder { buf: payload };
+
+        for _ in 0..decoder.u8()? {
+            let ssid = decoder.str()?;
+            let password = decoder.str()?;
+            let network = WifiNetwork::new(ssid, password).ok_or(StorageError::Corrupt)?;
+            config
+                .networks
+                .push(network)
+                .map_err(|_| StorageError::Corrupt)?;
+        }
+
+        config.display = match decoder.u8()? {
+            0 => None,
+            1 => Some(DisplayType::EPaper),
+            2 => Some(DisplayType::SSD1306),
+            _ => return Err(StorageError::Corrupt.into()),
+        };
+
+        let [
+            sensor_sda,
+            sensor_scl,
+            display_sda,
+            display_scl,
+            button_green,
+            button_blue,
+        ] = decoder.array()?;
+        let pins = Pins {
+            sensor_sda,
+            sensor_scl,
+            display_sda,
+            display_scl,
+            button_green,
+            button_blue,
+        };
+        if pins.is_valid() {
+            config.pins = pins;
+        }
+
+        config.settings.units = match decoder.u8()? {
+            0 => TemperatureUnit::Celsius,
+            1 => TemperatureUnit::Fahrenheit,
+            _ => return Err(StorageError::Corrupt.into()),
+        };
+        config.settings.refresh_interval_s = u16::from_le_bytes(decoder.array()?);
+        config.settings.contrast = decoder.u8()?;
+        config.settings.screen_timeout_s = u16::from_le_bytes(decoder.array()?);
+        config.settings.rotation = match decoder.u8()? {
+            0 => Rotation::Normal,
+            1 => Rotation::Flipped,
+            _ => return Err(StorageError::Corrupt.into()),
+        };
+
+        let mut thresholds = [0f32; 4];
+        for threshold in thresholds.iter_mut() {
+            *threshold = f32::from_le_bytes(decoder.array()?);
+        }
+        let [cold, cool, comfortable, warm] = thresholds;
+        config.thresholds = Thresholds {
+            cold,
+            cool,
+            comfortable,
+            warm,
+        };
+
+        config.openweather_api_key =
+            String::try_from(decoder.str()?).map_err(|_| StorageError::Corrupt)?;
+        if version < 2 {
+            return decoder.finish(config);
+        }
+
+        let mut offsets = [0f32; 3];
+        for offset in offsets.iter_mut() {
+            *offset = f32::from_le_bytes(decoder.array()?);
+        }
+        let [temperature, humidity, pressure] = offsets;
+        config.offsets = Offsets {
+            temperature,
+            humidity,
+            pressure,
+        };
+        config.api_token = String::try_from(decoder.str()?).map_err(|_| StorageError::Corrupt)?;
+        if version < 3 {
+            return decoder.finish(config);
+        }
+
+        config.weather_location =
+            String::try_from(decoder.str()?).map_err(|_| StorageError::Corrupt)?;
+        if version < 4 {
+            return decoder.finish(config);
+        }
+
+        config.timezone = String::try_from(decoder.str()?).map_err(|_| StorageError::Corrupt)?;
+        config.time_format = match decoder.u8()? {
+            0 => TimeFormat::H24,
+            1 => TimeFormat::H12,
+            _ => return Err(StorageError::Corrupt.into()),
+        };
+        if version < 5 {
+            return decoder.finish(config);
+        }
+
+        config.mqtt_broker = String::try_from(decoder.str()?).map_err(|_| StorageError::Corrupt)?;
+        config.mqtt_username =
+            String::try_from(decoder.str()?).map_err(|_| StorageError::Corrupt)?;
+        config.mqtt_password =
+            String::try_from(decoder.str()?).map_err(|_| StorageError::Corrupt)?;
+        if version < 6 {
+            return decoder.finish(config);
+        }
+
+        config.ota_key = String::try_from(decoder.str()?).map_err(|_| StorageError::Corrupt)?;
+        if version < 7 {
+            return decoder.finish(config);
+        }
+
+        let mut layout = Layout {
+            pages: Vec::new(),
+            auto_rotate: false,
+            rotate_every_s: 0,
+        };
+        for _ in 0..decoder.u8()? {
+            let page = Page::from_index(decoder.u8()?).ok_or(StorageError::Corrupt)?;
+            layout.pages.push(page).map_err(|_| StorageError::Corrupt)?;
+        }
+        layout.auto_rotate = match decoder.u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err(StorageError::Corrupt.into()),
+        };
+        layout.rotate_every_s = u16::from_le_bytes(decoder.array()?);
+        if layout.is_valid() {
+            config.layout = layout;
+        }
+
+        decoder.finish(config)
+    }
+}
+
+struct Encoder<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Encoder<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), GonkError> {
+        let end = self.pos + bytes.len();
+        let target = self
+            .buf
+            .get_mut(self.pos..end)
+            .ok_or(StorageError::Corrupt)?;
+        target.copy_from_slice(bytes);
+        self.pos = end;
+        Ok(())
+    }
+
+    fn u8(&mut self, value: u8) -> Result<(), GonkError> {
+        self.bytes(&[value])
+    }
+
+    /// Length prefixed string
+    fn str(&mut self, value: &str) -> Result<(), GonkError> {
+        let len = u8::try_from(value.len()).map_err(|_| StorageError::Corrupt)?;
+        self.u8(len)?;
+        self.bytes(value.as_bytes())
+    }
+}
+
+struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// `value` when the whole payload has been read
+    fn finish<T>(&self, value: T) -> Result<T, GonkError> {
+        if self.is_empty() {
+            Ok(value)
+        } else {
+            Err(StorageError::Corrupt.into())
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], GonkError> {
+        if len > self.buf.len() {
+            return Err(StorageError::Corrupt.into());
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], GonkError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, GonkError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn str(&mut self) -> Result<&'a str, GonkError> {
+        let len = self.u8()? as usize;
+        core::str::from_utf8(self.bytes(len)?).map_err(|_| StorageError::Corrupt.into())
+    }
+}
+
+/// Configuration in use, with the store it is saved to
+pub struct Configuration<S> {
+    pub current: Config,
+    /// Why the configuration cannot be saved, when the store failed to open
+    pub store: Result<ConfigStore<S>, GonkError>,
+}
+
+impl<S: Storage> Configuration<S> {
+    pub fn save(&mut self) -> Result<(), GonkError> {
+        match &mut self.store {
+            Ok(store) => store.save(&self.current),
+            Err(e) => Err(*e),
+        }
+    }
+}
+
+/// CRC-32 (IEEE 802.3) of `data`
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Header of a valid record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RecordHeader {
+    version: u16,
+    sequence: u32,
+    len: usize,
+    crc: u32,
+}
+
+impl RecordHeader {
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4..6].copy_from_slice(&self.version.to_le_bytes());
+        bytes[6..10].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[10..12].copy_from_slice(&(self.len as u16).to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.crc.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; HEADER_LEN]) -> Option<Self> {
+        if bytes[..4] != MAGIC {
+            return None;
+        }
+        let len = u16::from_le_bytes([bytes[10], bytes[11]]) as usize;
+        (len <= MAX_PAYLOAD).then(|| Self {
+            version: u16::from_le_bytes([bytes[4], bytes[5]]),
+            sequence: u32::from_le_bytes([bytes[6], bytes[7], bytes[8], bytes[9]]),
+            len,
+            crc: u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+        })
+    }
+}
+
+/// Configuration record kept in two alternating storage slots
+pub struct ConfigStore<S> {
+    storage: S,
+    /// Sequence number and slot of the newest valid record
+    newest: Option<(u32, u32)>,
+}
+
+impl<S: Storage> ConfigStore<S> {
+    pub fn new(storage: S) -> Self {
+        Self {
+            storage,
+            newest: None,
+        }
+    }
+
+    /// Read the newest valid record
+    ///
+    /// Fails with `StorageError::Corrupt` when no slot holds one, e.g. on a new
+    /// device, in which case the defaults apply. Records of a newer version,
+    /// left behind by a rolled back update, are passed over: with only those
+    /// the load fails with `StorageError::TooNew`.
+    pub fn load(&mut self) -> Result<Config, GonkError> {
+        let mut newest: Option<(RecordHeader, u32)> = None;
+        let mut readable: Option<(RecordHeader, u32)> = None;
+        let mut payload = [0u8; MAX_PAYLOAD];
+
+        for slot in 0..SLOTS {
+            let Some(header) = self.read_record(slot, &mut payload)? else {
+                continue;
+            };
+            if newest.is_none_or(|(best, _)| header.sequence > best.sequence) {
+                newest = Some((header, slot));
+            }
+            if header.version <= CONFIG_VERSION
+                && readable.is_none_or(|(best, _)| header.sequence > best.sequence)
+            {
+                readable = Some((header, slot));
+            }
+        }
+
+        let (newest, newest_slot) = newest.ok_or(StorageError::Corrupt)?;
+        // The next save gets a higher sequence number than any record and
+        // keeps the record loaded, or else the newer one
+        let Some((header, slot)) = readable else {
+            self.newest = Some((newest.sequence, newest_slot));
+            return Err(StorageError::TooNew.into());
+        };
+        self.newest = Some((newest.sequence, slot));
+        // Read again, the other slot may have overwritten the buffer
+        self.read_record(slot, &mut payload)?;
+        Config::decode(header.version, &payload[..header.len])
+    }
+
+    /// Write `config` to the slot not holding the newest record
+    pub fn save(&mut self, config: &Config) -> Result<(), GonkError> {
+        let mut record = [0u8; HEADER_LEN + MAX_PAYLOAD];
+        let len = config.encode(&mut record[HEADER_LEN..])?;
+
+        let (sequence, slot) = match self.newest {
+            Some((sequence, slot)) => (sequence.wrapping_add(1), (slot + 1) % SLOTS),
+            None => (0, 0),
+        };
+        let header = RecordHeader {
+            version: CONFIG_VERSION,
+            sequence,
+            len,
+            crc: crc32(&record[HEADER_LEN..HEADER_LEN + len]),
+        };
+        record[..HEADER_LEN].copy_from_slice(&header.to_bytes());
+
+        self.storage
+            .write(slot * SLOT_SIZE, &record[..HEADER_LEN + len])?;
+        self.newest = Some((sequence, slot));
+        Ok(())
+    }
+
+    pub fn storage_mut(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    /// Header of the record in `slot` if it is valid, its payload in `payload`
+    fn read_record(
+        &mut self,
+        slot: u32,
+        payload: &mut [u8; MAX_PAYLOAD],
+    ) -> Result<Option<RecordHeader>, GonkError> {
+        let mut header = [0u8; HEADER_LEN];
+        self.storage.read(slot * SLOT_SIZE, &mut header)?;
+        let Some(header) = RecordHeader::from_bytes(&header) else {
+            return Ok(None);
+        };
+
+        let payload = &mut payload[..header.len];
+        self.storage
+            .read(slot * SLOT_SIZE + HEADER_LEN as u32, payload)?;
+        Ok((crc32(payload) == header.crc).then_some(header))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockStorage;
+    use embassy_time::Instant;
+
+    fn custom() -> Config {
+        let mut config = Config::default();
+        config.remember_network(WifiNetwork::new("office", "secret").unwrap());
+        config.remember_network(WifiNetwork::new("home", "hunter22").unwrap());
+        config.display = Some(DisplayType::EPaper);
+        config.pins.button_green = 21;
+        config.settings.units = TemperatureUnit::Fahrenheit;
+        config.thresholds.warm = 28.5;
+        config.openweather_api_key = String::try_from("0123456789abcdef").unwrap();
+        config.offsets.temperature = -1.5;
+        config.api_token = String::try_from("s3cret").unwrap();
+        config.weather_location = String::try_from("Berlin,DE").unwrap();
+        config.timezone = String::try_from("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        config.time_format = TimeFormat::H12;
+        config.mqtt_broker = String::try_from("broker.local:1884").unwrap();
+        config.mqtt_username = String::try_from("gonk").unwrap();
+        config.mqtt_password = String::try_from("p4ss").unwrap();
+        config.ota_key = String::try_from("0tA-k3y").unwrap();
+        config.layout = Layout {
+            pages: [Page::Clock, Page::Weather, Page::Settings]
+                .into_iter()
+                .collect(),
+            auto_rotate: true,
+            rotate_every_s: 30,
+        };
+        config
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn payload_round_trip() {
+        let config = custom();
+        let mut buf = [0u8; MAX_PAYLOAD];
+        let len = config.encode(&mut buf).unwrap();
+
+        assert_eq!(Config::decode(CONFIG_VERSION, &buf[..len]), Ok(config));
+    }
+
+    /// `config` as read from a record of `version`
+    fn as_version(config: &Config, version: u16) -> Config {
+        let mut config = config.clone();
+        if version < 2 {
+            config.offsets = Offsets::default();
+            config.api_token.clear();
+        }
+        if version < 3 {
+            config.weather_location.clear();
+        }
+        if version < 4 {
+            config.timezone.clear();
+            config.time_format = TimeFormat::H24;
+        }
+        if version < 5 {
+            config.mqtt_broker.clear();
+            config.mqtt_username.clear();
+            config.mqtt_password.clear();
+        }
+        if version < 6 {
+            config.ota_key.clear();
+        }
+        if version < 7 {
+            config.layout = Layout::default();
+        }
+        config
+    }
+
+    /// Length of the fields of `config` added by versions 2 to 7
+    fn added_lens(config: &Config) -> [usize; 6] {
+        [
+            12 + 1 + config.api_token.len(),
+            1 + config.weather_location.len(),
+            1 + config.timezone.len() + 1,
+            3 + config.mqtt_broker.len() + config.mqtt_username.len() + config.mqtt_password.len(),
+            1 + config.ota_key.len(),
+            1 + config.layout.pages.len() + 1 + 2,
+        ]
+    }
+
+    #[test]
+    fn previous_versions_get_defaults() {
+        let config = custom();
+        let mut buf = [0u8; MAX_PAYLOAD];
+        let mut end = config.encode(&mut buf).unwrap();
+
+        let added = added_lens(&config);
+        for version in (1..CONFIG_VERSION).rev() {
+            end -= added[version as usize - 1];
+            assert_eq!(
+                Config::decode(version, &buf[..end]),
+                Ok(as_version(&config, version)),
+                "version {}",
+                version
+            );
+            // A record holds every field of its version, and only those
+            assert_eq!(
+                Config::decode(version + 1, &buf[..end]),
+                Err(StorageError::Corrupt.into())
+            );
+            assert_eq!(
+                Config::decode(version, &buf[..end + 1]),
+                Err(StorageError::Corrupt.into())
+            );
+        }
+    }
+
+    #[test]
+    fn version_1_records_hold_every_field() {
+        let config = custom();
+        let mut buf = [0u8; MAX_PAYLOAD];
+        let len = config.encode(&mut buf).unwrap();
+        let version_1 = len - added_lens(&config).iter().sum::<usize>();
+        assert_eq!(
+            Config::decode(1, &buf[..version_1]),
+            Ok(as_version(&config, 1))
+        );
+
+        // Ending after the WiFi networks or before the OpenWeather API key
+        let networks = 1 + config
+            .networks
+            .iter()
+            .map(|network| 2 + network.ssid.len() + network.password.len())
+            .sum::<usize>();
+        let thresholds = version_1 - 1 - config.openweather_api_key.len();
+        for end in [0, networks, thresholds] {
+            assert_eq!(
+                Config::decode(1, &buf[..end]),
+                Err(StorageError::Corrupt.into())
+            );
+        }
+        assert_eq!(
+            Config::decode(0, &buf[..version_1]),
+            Err(StorageError::Corrupt.into())
+        );
+    }
+
+    #[test]
+    fn offsets_correct_readings() {
+        let offsets = Offsets {
+            temperature: -1.5,
+            humidity: 5.0,
+            pressure: 120.0,
+        };
+        let mut reading = Reading {
+            temperature: Some(22.0),
+            humidity: Some(98.0),
+            ..Reading::new(Instant::from_secs(1))
+        };
+        offsets.apply(&mut reading);
+
+        assert_eq!(reading.temperature, Some(20.5));
+        assert_eq!(reading.humidity, Some(100.0));
+        assert_eq!(reading.pressure, None);
+    }
+
+    #[test]
+    fn rejects_damaged_payload() {
+        let mut buf = [0u8; MAX_PAYLOAD];
+        let len = custom().encode(&mut buf).unwrap();
+
+        assert_eq!(
+            Config::decode(CONFIG_VERSION, &buf[..len - 3]),
+            Err(StorageError::Corrupt.into())
+        );
+        assert_eq!(
+            Config::decode(CONFIG_VERSION, &[9, 200]),
+            Err(StorageError::Corrupt.into())
+        );
+        buf[len] = 0;
+        assert_eq!(
+            Config::decode(CONFIG_VERSION, &buf[..len + 1]),
+            Err(StorageError::Corrupt.into())
+        );
+
+        // Unknown temperature unit, following the network count, display and pins
+        let len = Config::default().encode(&mut buf).unwrap();
+        buf[8] = 7;
+        assert_eq!(
+            Config::decode(CONFIG_VERSION, &buf[..len]),
+            Err(StorageError::Corrupt.into())
+        );
+    }
+
+    #[test]
+    fn invalid_pins_fall_back_to_defaults() {
+        let mut config = custom();
+        config.pins.button_blue = config.pins.sensor_sda;
+        assert!(!config.pins.is_valid());
+
+        let mut buf = [0u8; MAX_PAYLOAD];
+        let len = config.encode(&mut buf).unwrap();
+
+        assert_eq!(
+            Config::decode(CONFIG_VERSION, &buf[..len]).unwrap().pins,
+            Pins::default()
+        );
+        assert!(Pins::default().is_valid());
+    }
+
+    #[test]
+    fn invalid_layout_falls_back_to_default() {
+        let mut config = custom();
+        config.layout.pages = [Page::Readings, Page::Clock].into_iter().collect();
+
+        let mut buf = [0u8; MAX_PAYLOAD];
+        let len = config.encode(&mut buf).unwrap();
+
+        assert_eq!(
+            Config::decode(CONFIG_VERSION, &buf[..len]).unwrap().layout,
+            Layout::default()
+        );
+    }
+
+    #[test]
+    fn rejects_missing_and_busy_gpios() {
+        for pin in [22, 19, 20, 43, 0, 33, 49] {
+            let pins = Pins {
+                button_blue: pin,
+                ..Pins::default()
+            };
+            assert!(!pins.is_valid(), "GPIO{}", pin);
+        }
+        let pins = Pins {
+            button_blue: 47,
+            ..Pins::default()
+        };
+        assert!(pins.is_valid());
+    }
+
+    #[test]
+    fn remembers_latest_network_first() {
+        let mut config = Config::default();
+        for ssid in ["a", "b", "c", "d", "e"] {
+            config.remember_network(WifiNetwork::new(ssid, "pw").unwrap());
+        }
+        config.remember_network(WifiNetwork::new("c", "new").unwrap());
+
+        let ssids: Vec<&str, 4> = config.networks.iter().map(|n| n.ssid.as_str()).collect();
+        assert_eq!(ssids, ["c", "e", "d", "b"]);
+        assert_eq!(config.networks[0].password, "new");
+    }
+
+    #[test]
+    fn new_storage_has_no_config() {
+        let mut store = ConfigStore::new(MockStorage::new());
+
+        assert_eq!(store.load(), Err(StorageError::Corrupt.into()));
+    }
+
+    #[test]
+    fn saves_alternate_slots() {
+        let mut store = ConfigStore::new(MockStorage::new());
+        let mut config = custom();
+        store.save(&config).unwrap();
+        config.thresholds.cold = 5.0;
+        store.save(&config).unwrap();
+
+        let data = &store.storage_mut().data;
+        assert_eq!(&data[..4], b"GONK");
+        assert_eq!(&data[SLOT_SIZE as usize..SLOT_SIZE as usize + 4], b"GONK");
+
+        let mut reopened = ConfigStore::new(MockStorage {
+            data: store.storage_mut().data,
+            ..MockStorage::new()
+        });
+        assert_eq!(reopened.load(), Ok(config));
+    }
+
+    #[test]
+    fn torn_write_keeps_previous_config() {
+        let mut store = ConfigStore::new(MockStorage::new());
+        let previous = custom();
+        store.save(&previous).unwrap();
+        store.load().unwrap();
+
+        let mut next = custom();
+        next.thresholds.cold = 5.0;
+        store.storage_mut().tear_after = Some(20);
+        assert!(store.save(&next).is_err());
+
+        let mut reopened = ConfigStore::new(MockStorage {
+            data: store.storage_mut().data,
+            ..MockStorage::new()
+        });
+        assert_eq!(reopened.load(), Ok(previous));
+
+        // The next save goes to the damaged slot again
+        reopened.save(&next).unwrap();
+        assert_eq!(reopened.load(), Ok(next));
+    }
+
+    /// Store `config` in `slot` as a record of `version`
+    fn write_record(
+        storage: &mut MockStorage,
+        slot: u32,
+        version: u16,
+        sequence: u32,
+        config: &Config,
+    ) {
+        let mut record = [0u8; HEADER_LEN + MAX_PAYLOAD];
+        let len = config.encode(&mut record[HEADER_LEN..]).unwrap();
+        let header = RecordHeader {
+            version,
+            sequence,
+            len,
+            crc: crc32(&record[HEADER_LEN..HEADER_LEN + len]),
+        };
+        record[..HEADER_LEN].copy_from_slice(&header.to_bytes());
+        storage
+            .write(slot * SLOT_SIZE, &record[..HEADER_LEN + len])
+            .unwrap();
+    }
+
+    #[test]
+    fn passes_over_newer_records() {
+        let mut storage = MockStorage::new();
+        let mut newer = custom();
+        newer.thresholds.cold = 5.0;
+        write_record(&mut storage, 1, CONFIG_VERSION + 1, 7, &newer);
+        let mut store = ConfigStore::new(storage);
+        assert_eq!(store.load(), Err(StorageError::TooNew.into()));
+
+        // Saved next to the newer record, and read from then on
+        let config = custom();
+        store.save(&config).unwrap();
+        assert_eq!(store.load(), Ok(config.clone()));
+        assert_eq!(store.newest, Some((8, 0)));
+
+        // Saves go past the newer record and keep the one loaded
+        let mut next = custom();
+        next.thresholds.warm = 30.0;
+        store.save(&next).unwrap();
+        assert_eq!(store.load(), Ok(next));
+        assert_eq!(store.newest, Some((9, 1)));
+    }
+
+    #[test]
+    fn migrates_older_records() {
+        let config = custom();
+        let mut payload = [0u8; MAX_PAYLOAD];
+        let len = config.encode(&mut payload).unwrap();
+        // Ending before the layout, as before it was added
+        let short = len - added_lens(&config)[5];
+
+        let mut storage = MockStorage::new();
+        let crc = crc32(&payload[..short]);
+        let header = RecordHeader {
+            version: 6,
+            sequence: 3,
+            len: short,
+            crc,
+        };
+        storage.write(0, &header.to_bytes()).unwrap();
+        storage.write(HEADER_LEN as u32, &payload[..short]).unwrap();
+
+        let mut store = ConfigStore::new(storage);
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded, as_version(&config, 6));
+        assert_eq!(loaded.layout, Layout::default());
+
+        // Saved again in the current version
+        store.save(&loaded).unwrap();
+        let version = SLOT_SIZE as usize + 4;
+        assert_eq!(
+            store.storage_mut().data[version..version + 2],
+            CONFIG_VERSION.to_le_bytes()
+        );
+        assert_eq!(store.load(), Ok(loaded));
+    }
+}
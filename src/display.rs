@@ -2,25 +2,41 @@ use embedded_graphics::{
     mono_font::{MonoTextStyleBuilder, ascii::FONT_6X10},
     pixelcolor::BinaryColor,
     prelude::*,
+    primitives::{Line, PrimitiveStyle},
     text::{Baseline, Text, TextStyleBuilder},
 };
 
+use crate::error::{DisplayError, GonkError};
+use crate::framebuffer::{FrameBuffer, PageDiff};
 use crate::hardware::SSD1306Hardware;
+use crate::settings::Rotation;
+use crate::traits;
 
+/// SSD1306 display drawing whole frames
+///
+/// Primitives are drawn into a local framebuffer between `begin_frame()` and
+/// `commit()`, which sends only the pages that changed since the last frame.
 pub struct Display<'a> {
     hardware: SSD1306Hardware<'a>,
+    frame: FrameBuffer,
+    shown: PageDiff,
 }
 
 impl<'a> Display<'a> {
     pub fn new(hardware: SSD1306Hardware<'a>) -> Self {
-        Self { hardware }
+        Self {
+            hardware,
+            frame: FrameBuffer::new(),
+            shown: PageDiff::new(),
+        }
     }
 
-    pub fn clear(&mut self) -> Result<(), &'static str> {
-        self.hardware.clear()
+    /// Start a new, blank frame
+    pub fn begin_frame(&mut self) {
+        self.frame.clear();
     }
 
-    pub fn draw_text(&mut self, text: &str, x: i32, y: i32) -> Result<(), &'static str> {
+    pub fn draw_text(&mut self, text: &str, x: i32, y: i32) -> Result<(), GonkError> {
         let text_style = MonoTextStyleBuilder::new()
             .font(&FONT_6X10)
             .text_color(BinaryColor::On)
@@ -28,14 +44,73 @@ impl<'a> Display<'a> {
 
         let baseline_style = TextStyleBuilder::new().baseline(Baseline::Top).build();
 
-        let text_obj = Text::with_text_style(text, Point::new(x, y), text_style, baseline_style);
+        Text::with_text_style(text, Point::new(x, y), text_style, baseline_style)
+            .draw(&mut self.frame)
+            .map_err(|_| DisplayError::Draw)?;
 
-        self.hardware.draw_text(text_obj)
+        Ok(())
     }
 
-    pub fn draw_line(&mut self, start: Point, end: Point) -> Result<(), &'static str> {
-        let line = embedded_graphics::primitives::Line::new(start, end);
+    pub fn draw_line(&mut self, start: Point, end: Point) -> Result<(), GonkError> {
+        Line::new(start, end)
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(&mut self.frame)
+            .map_err(|_| DisplayError::Draw)?;
 
-        self.hardware.draw_line(line)
+        Ok(())
+    }
+
+    /// Send the changed parts of the frame to the panel
+    pub fn commit(&mut self) -> Result<(), GonkError> {
+        for span in self.shown.dirty_spans(&self.frame) {
+            let bytes = &self.frame.page(span.page)[span.columns.clone()];
+            if let Err(e) = self
+                .hardware
+                .write_span(span.page, span.columns.start, bytes)
+            {
+                // Part of the frame may have been written
+                self.shown.invalidate();
+                return Err(e);
+            }
+        }
+
+        self.shown.mark_shown(&self.frame);
+        Ok(())
+    }
+}
+
+impl traits::Display for Display<'_> {
+    fn init(&mut self) -> Result<(), GonkError> {
+        self.shown.invalidate();
+        self.hardware.init()
+    }
+
+    fn clear(&mut self) -> Result<(), GonkError> {
+        self.begin_frame();
+        Ok(())
+    }
+
+    fn draw_text(&mut self, text: &str, x: i32, y: i32) -> Result<(), GonkError> {
+        Display::draw_text(self, text, x, y)
+    }
+
+    fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32) -> Result<(), GonkError> {
+        Display::draw_line(self, Point::new(x0, y0), Point::new(x1, y1))
+    }
+
+    fn update(&mut self) -> Result<(), GonkError> {
+        self.commit()
+    }
+
+    fn set_contrast(&mut self, contrast: u8) -> Result<(), GonkError> {
+        self.hardware.set_contrast(contrast)
+    }
+
+    fn set_rotation(&mut self, rotation: Rotation) -> Result<(), GonkError> {
+        self.hardware.set_rotation(rotation)
+    }
+
+    fn set_power(&mut self, on: bool) -> Result<(), GonkError> {
+        self.hardware.set_display_on(on)
     }
 }
@@ -0,0 +1,144 @@
+//! E-paper refresh scheduling (hardware-independent)
+//!
+//! Partial refreshes are fast and do not flash the panel, but every partial
+//! update leaves a little ghosting behind. A full refresh every few frames
+//! clears it. Either blocks for a while, so a frame already shown is not sent
+//! again.
+
+use crate::config::crc32;
+
+/// Number of frames between two full refreshes, including the full one
+pub const DEFAULT_FULL_REFRESH_EVERY: u32 = 10;
+
+/// How the next frame is pushed to the panel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefreshMode {
+    /// Flash the whole panel, clearing any ghosting
+    Full,
+    /// Only update changed pixels
+    Partial,
+}
+
+/// Decides between full and partial refreshes
+#[derive(Debug, Clone, Copy)]
+pub struct RefreshPolicy {
+    full_every: u32,
+    /// Partial refreshes since the last full one, `None` forces a full refresh
+    partial_count: Option<u32>,
+    /// CRC of the frame shown last
+    shown: Option<u32>,
+}
+
+impl RefreshPolicy {
+    pub fn new(full_every: u32) -> Self {
+        Self {
+            full_every: full_every.max(1),
+            partial_count: None,
+            shown: None,
+        }
+    }
+
+    /// Mode of the next frame, the first frame is always a full refresh
+    pub fn next_mode(&mut self) -> RefreshMode {
+        match self.partial_count {
+            Some(count) if count + 1 < self.full_every => {
+                self.partial_count = Some(count + 1);
+                RefreshMode::Partial
+            }
+            _ => {
+                self.partial_count = Some(0);
+                RefreshMode::Full
+            }
+        }
+    }
+
+    /// Mode to show `frame` with, `None` when it is the frame shown last
+    pub fn mode_for(&mut self, frame: &[u8]) -> Option<RefreshMode> {
+        let crc = crc32(frame);
+        if self.shown == Some(crc) {
+            return None;
+        }
+        self.shown = Some(crc);
+        Some(self.next_mode())
+    }
+
+    /// Make the next frame a full refresh, e.g. after the panel lost power
+    pub fn force_full(&mut self) {
+        self.partial_count = None;
+        self.shown = None;
+    }
+}
+
+impl Default for RefreshPolicy {
+    fn default() -> Self {
+        Self::new(DEFAULT_FULL_REFRESH_EVERY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_frame_is_full() {
+        let mut policy = RefreshPolicy::default();
+
+        assert_eq!(policy.next_mode(), RefreshMode::Full);
+        assert_eq!(policy.next_mode(), RefreshMode::Partial);
+    }
+
+    #[test]
+    fn full_refresh_every_n_frames() {
+        let mut policy = RefreshPolicy::new(3);
+        let modes: [RefreshMode; 7] = core::array::from_fn(|_| policy.next_mode());
+
+        assert_eq!(
+            modes,
+            [
+                RefreshMode::Full,
+                RefreshMode::Partial,
+                RefreshMode::Partial,
+                RefreshMode::Full,
+                RefreshMode::Partial,
+                RefreshMode::Partial,
+                RefreshMode::Full,
+            ]
+        );
+    }
+
+    #[test]
+    fn force_full_resets_the_cycle() {
+        let mut policy = RefreshPolicy::new(3);
+        policy.next_mode();
+        policy.next_mode();
+        policy.force_full();
+
+        assert_eq!(policy.next_mode(), RefreshMode::Full);
+        assert_eq!(policy.next_mode(), RefreshMode::Partial);
+    }
+
+    #[test]
+    fn skips_frame_already_shown() {
+        let mut policy = RefreshPolicy::new(3);
+        let frame = [0xFFu8; 64];
+        let mut changed = frame;
+        changed[10] = 0x7F;
+
+        assert_eq!(policy.mode_for(&frame), Some(RefreshMode::Full));
+        assert_eq!(policy.mode_for(&frame), None);
+        assert_eq!(policy.mode_for(&changed), Some(RefreshMode::Partial));
+        assert_eq!(policy.mode_for(&changed), None);
+
+        // A forced refresh shows the same frame again
+        policy.force_full();
+        assert_eq!(policy.mode_for(&changed), Some(RefreshMode::Full));
+    }
+
+    #[test]
+    fn always_full_when_every_frame() {
+        let mut policy = RefreshPolicy::new(0);
+
+        assert_eq!(policy.next_mode(), RefreshMode::Full);
+        assert_eq!(policy.next_mode(), RefreshMode::Full);
+    }
+}
@@ -0,0 +1,244 @@
+//! Crate-wide error type
+
+use core::fmt;
+
+use embedded_hal::{i2c, spi};
+
+/// Sensor specific failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorError {
+    /// The chip ID does not belong to a supported sensor
+    UnsupportedChip(u8),
+    /// `init()` has not completed successfully
+    NotInitialized,
+    /// The sensor does not measure the requested quantity
+    Unsupported,
+    /// The sensor did not return a measurement
+    NoData,
+    /// A compensated value is outside of the sensor operating range
+    OutOfRange,
+}
+
+/// Display specific failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayError {
+    Init,
+    Draw,
+    Flush,
+}
+
+/// Network specific failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkError {
+    /// WiFi is not associated
+    NoLink,
+    /// DHCP did not provide an address
+    NoAddress,
+    /// A host name could not be resolved
+    Dns,
+    /// A TCP connection could not be established
+    Connect,
+    /// Reading or writing a socket failed
+    Io,
+    /// A peer sent a request or packet that could not be parsed
+    BadRequest,
+    /// A server sent a response that could not be parsed
+    BadResponse,
+    /// A server answered with this HTTP status instead of 200
+    Status(u16),
+    /// An MQTT broker refused the connection with this return code
+    Refused(u8),
+}
+
+/// Persistent storage failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageError {
+    /// Reading, erasing or writing the flash failed
+    Io,
+    /// The partition table has no partition to store data in
+    NoPartition,
+    /// The stored record is missing or damaged
+    Corrupt,
+    /// The stored record was written by a newer firmware
+    TooNew,
+}
+
+/// Firmware update failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateError {
+    /// The image does not fit in the update slot
+    TooLarge,
+    /// The image ended before the announced length
+    Incomplete,
+    /// The data does not start like an application image
+    NotAnImage,
+    /// The SHA-256 of the image is not the one announced
+    DigestMismatch,
+    /// The image is not signed with the update key
+    BadSignature,
+    /// The partition table has no OTA slots
+    Unsupported,
+}
+
+/// Errors returned by every fallible operation of the crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GonkError {
+    /// I2C transaction failed, e.g. the device did not acknowledge
+    I2c(i2c::ErrorKind),
+    /// SPI transfer failed
+    Spi(spi::ErrorKind),
+    /// A peripheral did not answer in time
+    Timeout,
+    Sensor(SensorError),
+    Display(DisplayError),
+    Network(NetworkError),
+    Storage(StorageError),
+    Update(UpdateError),
+}
+
+impl GonkError {
+    /// Device did not acknowledge its address
+    pub const NACK: Self = Self::I2c(i2c::ErrorKind::NoAcknowledge(
+        i2c::NoAcknowledgeSource::Address,
+    ));
+}
+
+impl fmt::Display for GonkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GonkError::I2c(kind) => write!(f, "I2C error: {}", kind),
+            GonkError::Spi(kind) => write!(f, "SPI error: {}", kind),
+            GonkError::Timeout => write!(f, "timed out"),
+            GonkError::Sensor(SensorError::UnsupportedChip(id)) => {
+                write!(f, "unsupported sensor chip ID 0x{:02X}", id)
+            }
+            GonkError::Sensor(SensorError::NotInitialized) => write!(f, "sensor not initialized"),
+            GonkError::Sensor(SensorError::Unsupported) => {
+                write!(f, "quantity not measured by sensor")
+            }
+            GonkError::Sensor(SensorError::NoData) => write!(f, "sensor returned no data"),
+            GonkError::Sensor(SensorError::OutOfRange) => write!(f, "sensor value out of range"),
+            GonkError::Display(DisplayError::Init) => write!(f, "failed to initialize display"),
+            GonkError::Display(DisplayError::Draw) => write!(f, "failed to draw on display"),
+            GonkError::Display(DisplayError::Flush) => write!(f, "failed to flush display"),
+            GonkError::Network(NetworkError::NoLink) => write!(f, "WiFi not connected"),
+            GonkError::Network(NetworkError::NoAddress) => write!(f, "no IP address"),
+            GonkError::Network(NetworkError::Dns) => write!(f, "DNS lookup failed"),
+            GonkError::Network(NetworkError::Connect) => write!(f, "connection failed"),
+            GonkError::Network(NetworkError::Io) => write!(f, "socket I/O failed"),
+            GonkError::Network(NetworkError::BadRequest) => write!(f, "malformed request"),
+            GonkError::Network(NetworkError::BadResponse) => write!(f, "malformed response"),
+            GonkError::Network(NetworkError::Status(code)) => write!(f, "HTTP status {}", code),
+            GonkError::Network(NetworkError::Refused(code)) => {
+                write!(f, "connection refused ({})", code)
+            }
+            GonkError::Storage(StorageError::Io) => write!(f, "flash access failed"),
+            GonkError::Storage(StorageError::NoPartition) => write!(f, "no storage partition"),
+            GonkError::Storage(StorageError::Corrupt) => write!(f, "stored data is corrupt"),
+            GonkError::Storage(StorageError::TooNew) => write!(f, "stored data is too new"),
+            GonkError::Update(UpdateError::TooLarge) => write!(f, "image too large"),
+            GonkError::Update(UpdateError::Incomplete) => write!(f, "image incomplete"),
+            GonkError::Update(UpdateError::NotAnImage) => write!(f, "not a firmware image"),
+            GonkError::Update(UpdateError::DigestMismatch) => write!(f, "SHA-256 mismatch"),
+            GonkError::Update(UpdateError::BadSignature) => write!(f, "bad image signature"),
+            GonkError::Update(UpdateError::Unsupported) => write!(f, "no OTA partitions"),
+        }
+    }
+}
+
+impl From<SensorError> for GonkError {
+    fn from(error: SensorError) -> Self {
+        GonkError::Sensor(error)
+    }
+}
+
+impl From<DisplayError> for GonkError {
+    fn from(error: DisplayError) -> Self {
+        GonkError::Display(error)
+    }
+}
+
+impl From<NetworkError> for GonkError {
+    fn from(error: NetworkError) -> Self {
+        GonkError::Network(error)
+    }
+}
+
+impl From<StorageError> for GonkError {
+    fn from(error: StorageError) -> Self {
+        GonkError::Storage(error)
+    }
+}
+
+impl From<UpdateError> for GonkError {
+    fn from(error: UpdateError) -> Self {
+        GonkError::Update(error)
+    }
+}
+
+/// Number of errors seen per subsystem, for diagnostics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ErrorCounters {
+    pub bus: u32,
+    pub timeout: u32,
+    pub sensor: u32,
+    pub display: u32,
+    pub network: u32,
+    pub storage: u32,
+    pub update: u32,
+}
+
+impl ErrorCounters {
+    pub fn record(&mut self, error: &GonkError) {
+        let counter = match error {
+            GonkError::I2c(_) | GonkError::Spi(_) => &mut self.bus,
+            GonkError::Timeout => &mut self.timeout,
+            GonkError::Sensor(_) => &mut self.sensor,
+            GonkError::Display(_) => &mut self.display,
+            GonkError::Network(_) => &mut self.network,
+            GonkError::Storage(_) => &mut self.storage,
+            GonkError::Update(_) => &mut self.update,
+        };
+        *counter = counter.saturating_add(1);
+    }
+
+    pub fn total(&self) -> u32 {
+        self.bus
+            .saturating_add(self.timeout)
+            .saturating_add(self.sensor)
+            .saturating_add(self.display)
+            .saturating_add(self.network)
+            .saturating_add(self.storage)
+            .saturating_add(self.update)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn formats_source_error() {
+        let mut text = heapless::String::<64>::new();
+        write!(text, "{}", GonkError::NACK).unwrap();
+
+        assert!(text.starts_with("I2C error: "));
+        assert!(text.len() > "I2C error: ".len());
+    }
+
+    #[test]
+    fn counts_errors_per_subsystem() {
+        let mut counters = ErrorCounters::default();
+        counters.record(&GonkError::NACK);
+        counters.record(&GonkError::Spi(spi::ErrorKind::Overrun));
+        counters.record(&SensorError::OutOfRange.into());
+        counters.record(&DisplayError::Flush.into());
+
+        assert_eq!(counters.bus, 2);
+        assert_eq!(counters.sensor, 1);
+        assert_eq!(counters.display, 1);
+        assert_eq!(counters.network, 0);
+        assert_eq!(counters.total(), 4);
+    }
+}
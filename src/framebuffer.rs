@@ -0,0 +1,226 @@
+//! Monochrome framebuffer with page-level change tracking (hardware-independent)
+//!
+//! The memory layout matches the SSD1306 display RAM: 8 pages of 8 pixel rows,
+//! one byte per column and page with the least significant bit on top. A
+//! frame is drawn in full, then only the columns that differ from what the
+//! panel shows are sent to it.
+
+use core::convert::Infallible;
+use core::ops::Range;
+
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+use heapless::Vec;
+
+pub const WIDTH: usize = 128;
+pub const HEIGHT: usize = 64;
+pub const PAGES: usize = HEIGHT / 8;
+
+const SIZE: usize = WIDTH * PAGES;
+
+/// Frame being drawn, in display RAM layout
+#[derive(Clone, PartialEq, Eq)]
+pub struct FrameBuffer {
+    buffer: [u8; SIZE],
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        Self { buffer: [0; SIZE] }
+    }
+
+    /// Turn every pixel off
+    pub fn clear(&mut self) {
+        self.buffer = [0; SIZE];
+    }
+
+    /// Column bytes of one page
+    pub fn page(&self, page: usize) -> &[u8] {
+        &self.buffer[page * WIDTH..(page + 1) * WIDTH]
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        x < WIDTH && y < HEIGHT && self.buffer[(y / 8) * WIDTH + x] & (1 << (y % 8)) != 0
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        let byte = &mut self.buffer[(y / 8) * WIDTH + x];
+        let mask = 1 << (y % 8);
+        if on {
+            *byte |= mask;
+        } else {
+            *byte &= !mask;
+        }
+    }
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OriginDimensions for FrameBuffer {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for FrameBuffer {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            // Primitives may be partly off screen
+            if let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y))
+                && x < WIDTH
+                && y < HEIGHT
+            {
+                self.set_pixel(x, y, color.is_on());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Changed columns of one page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirtySpan {
+    pub page: usize,
+    pub columns: Range<usize>,
+}
+
+/// What the panel currently shows
+pub struct PageDiff {
+    shown: FrameBuffer,
+    /// `false` when the panel content is unknown, e.g. after init or a failed flush
+    valid: bool,
+}
+
+impl PageDiff {
+    pub fn new() -> Self {
+        Self {
+            shown: FrameBuffer::new(),
+            valid: false,
+        }
+    }
+
+    /// Forget the panel content so that the next frame is sent in full
+    pub fn invalidate(&mut self) {
+        self.valid = false;
+    }
+
+    /// Column range of every page where `frame` differs from the panel
+    pub fn dirty_spans(&self, frame: &FrameBuffer) -> Vec<DirtySpan, PAGES> {
+        let mut spans = Vec::new();
+
+        for page in 0..PAGES {
+            let columns = if self.valid {
+                let new = frame.page(page);
+                let old = self.shown.page(page);
+                let changed = |x: &usize| new[*x] != old[*x];
+
+                match ((0..WIDTH).find(changed), (0..WIDTH).rev().find(changed)) {
+                    (Some(first), Some(last)) => first..last + 1,
+                    _ => continue,
+                }
+            } else {
+                0..WIDTH
+            };
+
+            // At most one span per page
+            let _ = spans.push(DirtySpan { page, columns });
+        }
+
+        spans
+    }
+
+    /// Record that `frame` has been sent to the panel
+    pub fn mark_shown(&mut self, frame: &FrameBuffer) {
+        self.shown = frame.clone();
+        self.valid = true;
+    }
+}
+
+impl Default for PageDiff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::primitives::{Line, PrimitiveStyle};
+
+    fn draw_line(frame: &mut FrameBuffer, start: Point, end: Point) {
+        Line::new(start, end)
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(frame)
+            .unwrap();
+    }
+
+    #[test]
+    fn pixels_use_display_ram_layout() {
+        let mut frame = FrameBuffer::new();
+        draw_line(&mut frame, Point::new(3, 9), Point::new(3, 9));
+
+        assert!(frame.pixel(3, 9));
+        assert_eq!(frame.page(1)[3], 0b0000_0010);
+        assert!(frame.page(0).iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn ignores_pixels_off_screen() {
+        let mut frame = FrameBuffer::new();
+        draw_line(&mut frame, Point::new(-5, 0), Point::new(200, 0));
+
+        assert!(frame.pixel(0, 0));
+        assert!(frame.pixel(127, 0));
+        assert!(!frame.pixel(128, 0));
+    }
+
+    #[test]
+    fn first_frame_is_sent_in_full() {
+        let diff = PageDiff::new();
+        let spans = diff.dirty_spans(&FrameBuffer::new());
+
+        assert_eq!(spans.len(), PAGES);
+        assert!(spans.iter().all(|span| span.columns == (0..WIDTH)));
+    }
+
+    #[test]
+    fn only_changed_columns_are_dirty() {
+        let mut frame = FrameBuffer::new();
+        draw_line(&mut frame, Point::new(0, 15), Point::new(127, 15));
+        let mut diff = PageDiff::new();
+        diff.mark_shown(&frame);
+
+        // Redrawing the same frame sends nothing
+        frame.clear();
+        draw_line(&mut frame, Point::new(0, 15), Point::new(127, 15));
+        assert!(diff.dirty_spans(&frame).is_empty());
+
+        draw_line(&mut frame, Point::new(10, 40), Point::new(20, 40));
+        assert_eq!(
+            diff.dirty_spans(&frame).as_slice(),
+            &[DirtySpan {
+                page: 5,
+                columns: 10..21
+            }]
+        );
+    }
+
+    #[test]
+    fn invalidate_resends_everything() {
+        let frame = FrameBuffer::new();
+        let mut diff = PageDiff::new();
+        diff.mark_shown(&frame);
+        diff.invalidate();
+
+        assert_eq!(diff.dirty_spans(&frame).len(), PAGES);
+    }
+}
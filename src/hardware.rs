@@ -1,22 +1,41 @@
-use bme280::Measurements;
+use core::cell::RefCell;
+
+use embassy_futures::select::{select, select_array};
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, RawMutex};
+use embassy_sync::channel::Sender;
+use embassy_time::{Instant, Timer};
+use embedded_hal::i2c::Error as _;
 use embedded_hal_bus::spi::ExclusiveDevice;
+use embedded_storage::ReadStorage;
+use esp_bootloader_esp_idf::ota::OtaImageState;
+use esp_bootloader_esp_idf::ota_updater::OtaUpdater;
+use esp_bootloader_esp_idf::partitions::{self, AppPartitionSubType};
 use esp_hal::gpio::AnyPin;
 use esp_hal::{
+    Blocking,
     delay::Delay,
     gpio::{Input, InputConfig, Level, Output, OutputConfig},
-    i2c::master::{Config as I2cConfig, I2c},
-    peripherals::{I2C0, I2C1, SPI2},
+    i2c::master::{Config as I2cConfig, Error as I2cError, I2c},
+    peripherals::{FLASH, I2C0, I2C1, SPI2},
     spi::master::{Config as SpiConfig, Spi},
     time::Rate,
 };
+use esp_storage::FlashStorage;
 
-use bme280::i2c::BME280;
+use crate::bme280::{self, Bme280, ChipVariant, Measurements, RawMeasurements, Status};
+use crate::epaper::{RefreshMode, RefreshPolicy};
+use crate::error::{DisplayError, GonkError, StorageError, UpdateError};
+use crate::input::{ButtonEvent, ButtonInput};
+use crate::logic::SSD1306_ADDRESSES;
+use crate::ota::BootInfo;
+use crate::settings::Rotation;
+use crate::traits::{self, Capabilities, EnvironmentalSensor, FirmwareSlots, I2cBus, Reading};
 
 use embedded_graphics::{
-    mono_font::{MonoTextStyle, MonoTextStyleBuilder, ascii::FONT_6X10},
-    pixelcolor::BinaryColor,
+    mono_font::{MonoTextStyleBuilder, ascii::FONT_6X10},
     prelude::*,
-    primitives::PrimitiveStyle,
+    primitives::{Line, PrimitiveStyle},
     text::{Baseline, Text, TextStyleBuilder},
 };
 use epd_waveshare::{
@@ -25,19 +44,13 @@ use epd_waveshare::{
     prelude::*,
 };
 
-use ssd1306::mode::BufferedGraphicsMode;
+use ssd1306::mode::BasicMode;
 use ssd1306::{I2CDisplayInterface, Ssd1306, prelude::*};
 
 const SPI_FREQ_MHZ: u32 = 10;
 
-#[derive(Debug, Clone, Copy)]
-pub enum DisplayType {
-    EPaper,
-    SSD1306,
-}
-
 pub struct DisplayHardware<'a> {
-    pub spi: ExclusiveDevice<Spi<'a, esp_hal::Blocking>, Output<'a>, Delay>,
+    pub spi: ExclusiveDevice<Spi<'a, Blocking>, Output<'a>, Delay>,
     pub busy: Input<'a>,
     pub dc: Output<'a>,
     pub rst: Output<'a>,
@@ -97,34 +110,27 @@ impl<'a> DisplayHardware<'a> {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-struct CalibrationData {
-    // Temperature calibration
-    dig_t1: u16,
-    dig_t2: i16,
-    dig_t3: i16,
-    // Pressure calibration
-    dig_p1: u16,
-    dig_p2: i16,
-    dig_p3: i16,
-    dig_p4: i16,
-    dig_p5: i16,
-    dig_p6: i16,
-    dig_p7: i16,
-    dig_p8: i16,
-    dig_p9: i16,
-    // Humidity calibration (NEW for BME280)
-    dig_h1: u8,
-    dig_h2: i16,
-    dig_h3: u8,
-    dig_h4: i16,
-    dig_h5: i16,
-    dig_h6: i8,
+impl From<I2cError> for GonkError {
+    fn from(error: I2cError) -> Self {
+        match error {
+            I2cError::Timeout => GonkError::Timeout,
+            e => GonkError::I2c(e.kind()),
+        }
+    }
+}
+
+impl I2cBus for I2c<'_, Blocking> {
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), GonkError> {
+        Ok(I2c::write(self, addr, bytes)?)
+    }
+
+    fn write_read(&mut self, addr: u8, write: &[u8], read: &mut [u8]) -> Result<(), GonkError> {
+        Ok(I2c::write_read(self, addr, write, read)?)
+    }
 }
 
 pub struct BME280Hardware<'a> {
-    address: u8,
-    bme280: BME280<I2c<'a, esp_hal::Blocking>>,
+    bme280: Bme280<I2c<'a, Blocking>>,
     delay: Delay,
 }
 
@@ -142,85 +148,537 @@ impl<'a> BME280Hardware<'a> {
         .with_sda(sda.into())
         .with_scl(scl.into());
 
-        let address = 0x76;
-        let mut bme280 = BME280::new_primary(i2c);
-
-        // Initialize sensor
-        let mut delay = Delay::new();
-        bme280.init(&mut delay).unwrap();
+        let bme280 = Bme280::new(i2c, bme280::PRIMARY_ADDRESS);
 
         Self {
-            address,
             bme280,
-            delay,
+            delay: Delay::new(),
+        }
+    }
+
+    /// Probe every 7-bit address on the sensor bus and return those that ACK
+    ///
+    /// Each probe is an address-only write, so that no device is read from.
+    pub fn scan(&mut self) -> heapless::Vec<u8, 16> {
+        let i2c = self.bme280.bus_mut();
+        let mut found = heapless::Vec::new();
+
+        for address in 0x03..0x78 {
+            if I2c::write(i2c, address, &[]).is_ok() {
+                let _ = found.push(address);
+            }
         }
+
+        found
     }
 
-    pub fn read(&mut self) -> Result<Measurements<esp_hal::i2c::master::Error>, bme280::Error<esp_hal::i2c::master::Error>> {
-        return self.bme280.measure(&mut self.delay);
+    /// Check the chip ID, soft reset and read the calibration data
+    pub fn init(&mut self) -> Result<ChipVariant, GonkError> {
+        self.bme280.init(&mut self.delay)
+    }
+
+    pub fn read_chip_id(&mut self) -> Result<u8, GonkError> {
+        self.bme280.read_chip_id()
+    }
+
+    pub fn soft_reset(&mut self) -> Result<(), GonkError> {
+        self.bme280.soft_reset(&mut self.delay)
+    }
+
+    pub fn read_status(&mut self) -> Result<Status, GonkError> {
+        self.bme280.read_status()
+    }
+
+    pub fn variant(&self) -> Option<ChipVariant> {
+        self.bme280.variant()
+    }
+
+    pub fn read_raw(&mut self) -> Result<RawMeasurements, GonkError> {
+        self.bme280.read_raw(&mut self.delay)
+    }
+
+    pub fn read_temperature(&mut self) -> Result<f32, GonkError> {
+        self.bme280.read_temperature(&mut self.delay)
+    }
+
+    pub fn read(&mut self) -> Result<Measurements, GonkError> {
+        self.bme280.measure(&mut self.delay)
     }
 }
 
+impl EnvironmentalSensor for BME280Hardware<'_> {
+    fn init(&mut self) -> Result<(), GonkError> {
+        BME280Hardware::init(self)?;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.variant()
+            .map(|variant| variant.capabilities())
+            .unwrap_or_default()
+    }
+
+    fn read(&mut self) -> Result<Reading, GonkError> {
+        let measurements = BME280Hardware::read(self)?;
+        Ok(measurements.into_reading(Instant::now()))
+    }
+
+    fn read_temperature(&mut self) -> Result<f32, GonkError> {
+        BME280Hardware::read_temperature(self)
+    }
+}
+
+/// SSD1306 OLED, only moves bytes to the display RAM
+///
+/// Frames are drawn and diffed by `display::Display`.
 pub struct SSD1306Hardware<'a> {
-    display: Ssd1306<
-        I2CInterface<I2c<'a, esp_hal::Blocking>>,
-        DisplaySize128x64,
-        BufferedGraphicsMode<DisplaySize128x64>,
-    >,
+    display: Ssd1306<I2CInterface<I2c<'a, Blocking>>, DisplaySize128x64, BasicMode>,
+}
+
+/// Open the I2C bus of the OLED display, e.g. to probe it before picking a driver
+pub fn display_bus<'a, SDA, SCL>(i2c_periph: I2C1<'a>, sda: SDA, scl: SCL) -> I2c<'a, Blocking>
+where
+    SDA: Into<AnyPin<'a>>,
+    SCL: Into<AnyPin<'a>>,
+{
+    I2c::new(
+        i2c_periph,
+        I2cConfig::default().with_frequency(Rate::from_khz(400)),
+    )
+    .unwrap()
+    .with_sda(sda.into())
+    .with_scl(scl.into())
 }
 
 impl<'a> SSD1306Hardware<'a> {
-    pub fn new<SDA, SCL>(i2c_periph: I2C1<'a>, sda: SDA, scl: SCL) -> Result<Self, &'static str>
+    pub fn new<SDA, SCL>(i2c_periph: I2C1<'a>, sda: SDA, scl: SCL) -> Result<Self, GonkError>
     where
         SDA: Into<AnyPin<'a>>,
         SCL: Into<AnyPin<'a>>,
     {
-        let i2c = I2c::new(
-            i2c_periph,
-            I2cConfig::default().with_frequency(Rate::from_khz(400)),
-        )
-        .unwrap()
-        .with_sda(sda.into())
-        .with_scl(scl.into());
+        Self::with_bus(display_bus(i2c_periph, sda, scl), SSD1306_ADDRESSES[0])
+    }
 
-        let interface = I2CDisplayInterface::new(i2c);
+    /// Drive the display found at `address` on an already opened bus
+    pub fn with_bus(i2c: I2c<'a, Blocking>, address: u8) -> Result<Self, GonkError> {
+        let interface = I2CDisplayInterface::new_custom_address(i2c, address);
 
-        let mut display = Ssd1306::new(
-            interface,
-            DisplaySize128x64,
-            ssd1306::rotation::DisplayRotation::Rotate0,
-        )
-        .into_buffered_graphics_mode();
+        let mut hardware = Self {
+            display: Ssd1306::new(
+                interface,
+                DisplaySize128x64,
+                ssd1306::rotation::DisplayRotation::Rotate0,
+            ),
+        };
+        hardware.init()?;
 
-        display.init().map_err(|_| "Failed to initialize SSD1306")?;
+        Ok(hardware)
+    }
 
-        Ok(Self { display })
+    pub fn init(&mut self) -> Result<(), GonkError> {
+        self.display.init().map_err(|_| DisplayError::Init.into())
     }
 
-    pub fn clear(&mut self) -> Result<(), &'static str> {
+    pub fn set_contrast(&mut self, contrast: u8) -> Result<(), GonkError> {
         self.display
-            .clear(BinaryColor::Off)
-            .map_err(|_| "Failed to clear display")
+            .set_brightness(Brightness::custom(0xF1, contrast))
+            .map_err(|_| DisplayError::Flush.into())
     }
 
-    pub fn draw_text(
-        &mut self,
-        text: Text<'_, MonoTextStyle<'_, BinaryColor>>,
-    ) -> Result<(), &'static str> {
-        text.draw(&mut self.display)
-            .map_err(|_| "Failed to draw text")?;
+    pub fn set_rotation(&mut self, rotation: Rotation) -> Result<(), GonkError> {
+        let rotation = match rotation {
+            Rotation::Normal => ssd1306::rotation::DisplayRotation::Rotate0,
+            Rotation::Flipped => ssd1306::rotation::DisplayRotation::Rotate180,
+        };
+        self.display
+            .set_rotation(rotation)
+            .map_err(|_| DisplayError::Flush.into())
+    }
+
+    pub fn set_display_on(&mut self, on: bool) -> Result<(), GonkError> {
+        self.display
+            .set_display_on(on)
+            .map_err(|_| DisplayError::Flush.into())
+    }
+
+    /// Write the column bytes of one page, starting at column `start`
+    pub fn write_span(&mut self, page: usize, start: usize, bytes: &[u8]) -> Result<(), GonkError> {
+        let x0 = start as u8;
+        let x1 = (start + bytes.len()) as u8;
+        let y0 = (page * 8) as u8;
+
+        self.display
+            .set_draw_area((x0, y0), (x1, y0 + 8))
+            .map_err(|_| DisplayError::Flush)?;
+        self.display
+            .draw(bytes)
+            .map_err(|_| DisplayError::Flush.into())
+    }
+}
+
+type EPaperSpi<'a> = ExclusiveDevice<Spi<'a, Blocking>, Output<'a>, Delay>;
+
+/// Waveshare 2.13" V2 e-paper panel driven through `DisplayHardware`
+///
+/// The panel sleeps between updates and is woken up by the next `update()`.
+pub struct EPaperHardware<'a> {
+    spi: EPaperSpi<'a>,
+    delay: Delay,
+    epd: Epd2in13<EPaperSpi<'a>, Input<'a>, Output<'a>, Output<'a>, Delay>,
+    frame: Display2in13,
+    policy: RefreshPolicy,
+    refresh: Option<RefreshMode>,
+    asleep: bool,
+}
+
+impl<'a> EPaperHardware<'a> {
+    pub fn new(hardware: DisplayHardware<'a>) -> Result<Self, GonkError> {
+        let DisplayHardware {
+            mut spi,
+            busy,
+            dc,
+            rst,
+            mut delay,
+        } = hardware;
+
+        let epd = Epd2in13::new(&mut spi, busy, dc, rst, &mut delay, None)
+            .map_err(|_| DisplayError::Init)?;
+
+        let mut frame = Display2in13::default();
+        frame.set_rotation(Self::frame_rotation(Rotation::Normal));
+
+        Ok(Self {
+            spi,
+            delay,
+            epd,
+            frame,
+            policy: RefreshPolicy::default(),
+            refresh: None,
+            asleep: false,
+        })
+    }
 
-        self.display.flush().map_err(|_| "Failed to flush display")
+    /// Make the next update a full refresh
+    pub fn force_full_refresh(&mut self) {
+        self.policy.force_full();
     }
 
-    pub fn draw_line(
+    /// The panel is mounted in landscape
+    fn frame_rotation(rotation: Rotation) -> epd_waveshare::prelude::DisplayRotation {
+        match rotation {
+            Rotation::Normal => epd_waveshare::prelude::DisplayRotation::Rotate90,
+            Rotation::Flipped => epd_waveshare::prelude::DisplayRotation::Rotate270,
+        }
+    }
+
+    fn wake_up(&mut self) -> Result<(), GonkError> {
+        if self.asleep {
+            self.epd
+                .wake_up(&mut self.spi, &mut self.delay)
+                .map_err(|_| DisplayError::Init)?;
+            self.asleep = false;
+            // The controller restarts with its full refresh waveform
+            self.refresh = None;
+        }
+        Ok(())
+    }
+
+    fn set_refresh(&mut self, mode: RefreshMode) -> Result<(), GonkError> {
+        if self.refresh != Some(mode) {
+            let lut = match mode {
+                RefreshMode::Full => RefreshLut::Full,
+                RefreshMode::Partial => RefreshLut::Quick,
+            };
+            self.epd
+                .set_refresh(&mut self.spi, &mut self.delay, lut)
+                .map_err(|_| DisplayError::Flush)?;
+            self.refresh = Some(mode);
+        }
+        Ok(())
+    }
+
+    /// Send the frame and put the panel back to sleep
+    fn show(&mut self, mode: RefreshMode) -> Result<(), GonkError> {
+        self.wake_up()?;
+        self.set_refresh(mode)?;
+
+        self.epd
+            .update_and_display_frame(&mut self.spi, self.frame.buffer(), &mut self.delay)
+            .map_err(|_| DisplayError::Flush)?;
+
+        // Deep sleep keeps the RAM, so the next partial refresh still has its
+        // reference image
+        self.epd
+            .sleep(&mut self.spi, &mut self.delay)
+            .map_err(|_| DisplayError::Flush)?;
+        self.asleep = true;
+
+        Ok(())
+    }
+}
+
+impl traits::Display for EPaperHardware<'_> {
+    fn init(&mut self) -> Result<(), GonkError> {
+        self.wake_up()?;
+        self.policy.force_full();
+        self.frame
+            .clear(Color::White)
+            .map_err(|_| DisplayError::Draw.into())
+    }
+
+    fn clear(&mut self) -> Result<(), GonkError> {
+        self.frame
+            .clear(Color::White)
+            .map_err(|_| DisplayError::Draw.into())
+    }
+
+    fn draw_text(&mut self, text: &str, x: i32, y: i32) -> Result<(), GonkError> {
+        let text_style = MonoTextStyleBuilder::new()
+            .font(&FONT_6X10)
+            .text_color(Color::Black)
+            .build();
+
+        let baseline_style = TextStyleBuilder::new().baseline(Baseline::Top).build();
+
+        Text::with_text_style(text, Point::new(x, y), text_style, baseline_style)
+            .draw(&mut self.frame)
+            .map_err(|_| DisplayError::Draw)?;
+
+        Ok(())
+    }
+
+    fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32) -> Result<(), GonkError> {
+        Line::new(Point::new(x0, y0), Point::new(x1, y1))
+            .into_styled(PrimitiveStyle::with_stroke(Color::Black, 1))
+            .draw(&mut self.frame)
+            .map_err(|_| DisplayError::Draw.into())
+    }
+
+    fn update(&mut self) -> Result<(), GonkError> {
+        // A refresh waits on BUSY for up to two seconds, blocking every task
+        let Some(mode) = self.policy.mode_for(self.frame.buffer()) else {
+            return Ok(());
+        };
+        let shown = self.show(mode);
+        if shown.is_err() {
+            // Try again with the next frame, whatever the panel shows
+            self.policy.force_full();
+        }
+        shown
+    }
+
+    fn set_rotation(&mut self, rotation: Rotation) -> Result<(), GonkError> {
+        self.frame.set_rotation(Self::frame_rotation(rotation));
+        // Every pixel moves, a partial refresh would leave ghosts behind
+        self.policy.force_full();
+        Ok(())
+    }
+}
+
+/// GPIO by number, for the pins assigned in the stored configuration
+///
+/// # Safety
+///
+/// The pin must exist and not be driven by anything else.
+/// `config::Pins::is_valid()` only accepts GPIOs of an allow-list of free
+/// pins, each used once.
+pub unsafe fn gpio(number: u8) -> AnyPin<'static> {
+    unsafe { AnyPin::steal(number) }
+}
+
+/// SPI flash shared by the configuration store and the OTA slots
+///
+/// A write erases and rewrites whole sectors through the buffer of the
+/// `FlashStorage`, the lock keeps writes to both from interleaving.
+pub type SharedFlash<'a> = BlockingMutex<CriticalSectionRawMutex, RefCell<FlashStorage<'a>>>;
+
+pub fn share_flash(flash: FLASH<'_>) -> SharedFlash<'_> {
+    BlockingMutex::new(RefCell::new(FlashStorage::new(flash)))
+}
+
+/// Data partition of the SPI flash used as `traits::Storage`
+pub struct PartitionStorage<'a> {
+    flash: &'a SharedFlash<'a>,
+    offset: u32,
+    size: u32,
+}
+
+impl<'a> PartitionStorage<'a> {
+    /// Open the data partition named `label` in the partition table
+    pub fn open(flash: &'a SharedFlash<'a>, label: &str) -> Result<Self, GonkError> {
+        let mut table = [0u8; partitions::PARTITION_TABLE_MAX_LEN];
+        let (offset, size) = flash.lock(|storage| {
+            let mut storage = storage.borrow_mut();
+            let partitions = partitions::read_partition_table(&mut *storage, &mut table)
+                .map_err(|_| StorageError::Io)?;
+            let partition = partitions
+                .iter()
+                .find(|entry| {
+                    matches!(entry.partition_type(), partitions::PartitionType::Data(_))
+                        && entry.label_as_str() == label
+                })
+                .ok_or(StorageError::NoPartition)?;
+            Ok::<_, GonkError>((partition.offset(), partition.len()))
+        })?;
+
+        Ok(Self {
+            flash,
+            offset,
+            size,
+        })
+    }
+
+    fn check_range(&self, offset: u32, len: usize) -> Result<u32, GonkError> {
+        match offset.checked_add(len as u32) {
+            Some(end) if end <= self.size => Ok(self.offset + offset),
+            _ => Err(StorageError::Io.into()),
+        }
+    }
+}
+
+impl traits::Storage for PartitionStorage<'_> {
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), GonkError> {
+        let address = self.check_range(offset, buf.len())?;
+        self.flash
+            .lock(|storage| ReadStorage::read(&mut *storage.borrow_mut(), address, buf))
+            .map_err(|_| StorageError::Io.into())
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), GonkError> {
+        let address = self.check_range(offset, data.len())?;
+        self.flash
+            .lock(|storage| {
+                embedded_storage::Storage::write(&mut *storage.borrow_mut(), address, data)
+            })
+            .map_err(|_| StorageError::Io.into())
+    }
+}
+
+/// OTA application slots, selected through the `otadata` partition
+pub struct OtaSlots<'a> {
+    flash: &'a SharedFlash<'a>,
+    table: [u8; partitions::PARTITION_TABLE_MAX_LEN],
+}
+
+impl<'a> OtaSlots<'a> {
+    pub fn new(flash: &'a SharedFlash<'a>) -> Self {
+        Self {
+            flash,
+            table: [0; partitions::PARTITION_TABLE_MAX_LEN],
+        }
+    }
+
+    /// Run `f` on an updater holding the flash, there is none without OTA
+    /// slots in the partition table
+    fn with_updater<R>(
         &mut self,
-        line: embedded_graphics::primitives::Line,
-    ) -> Result<(), &'static str> {
-        line.into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
-            .draw(&mut self.display)
-            .map_err(|_| "Failed to draw line")?;
+        f: impl FnOnce(&mut OtaUpdater<'_, FlashStorage<'a>>) -> Result<R, GonkError>,
+    ) -> Result<R, GonkError> {
+        let table = &mut self.table;
+        self.flash.lock(|storage| {
+            let mut storage = storage.borrow_mut();
+            let mut ota = OtaUpdater::new(&mut *storage, table)
+                .map_err(|_| GonkError::from(UpdateError::Unsupported))?;
+            f(&mut ota)
+        })
+    }
 
-        self.display.flush().map_err(|_| "Failed to flush display")
+    /// Slot running and whether its image is on trial
+    pub fn boot_info(&mut self) -> BootInfo {
+        self.with_updater(|ota| {
+            let slot = match ota.selected_partition() {
+                Ok(AppPartitionSubType::Ota0) => Some(0),
+                Ok(AppPartitionSubType::Ota1) => Some(1),
+                _ => None,
+            };
+            // New when the bootloader leaves the rollback to the application,
+            // pending verification when it rolls back itself after a crash
+            let trial = matches!(
+                ota.current_ota_state(),
+                Ok(OtaImageState::New | OtaImageState::PendingVerify)
+            );
+            Ok(BootInfo { slot, trial })
+        })
+        .unwrap_or_default()
+    }
+
+    /// Keep booting the image running
+    pub fn confirm(&mut self) -> Result<(), GonkError> {
+        self.with_updater(|ota| {
+            ota.set_current_ota_state(OtaImageState::Valid)
+                .map_err(|_| StorageError::Io.into())
+        })
+    }
+
+    /// Boot the previous image from the next reset
+    pub fn roll_back(&mut self) -> Result<(), GonkError> {
+        self.with_updater(|ota| {
+            ota.set_current_ota_state(OtaImageState::Invalid)
+                .and_then(|()| ota.activate_next_partition())
+                .and_then(|()| ota.set_current_ota_state(OtaImageState::Valid))
+                .map_err(|_| StorageError::Io.into())
+        })
+    }
+}
+
+impl FirmwareSlots for OtaSlots<'_> {
+    fn capacity(&mut self) -> Result<u32, GonkError> {
+        self.with_updater(|ota| {
+            let (region, _) = ota.next_partition().map_err(|_| UpdateError::Unsupported)?;
+            Ok(ReadStorage::capacity(&region) as u32)
+        })
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), GonkError> {
+        self.with_updater(|ota| {
+            let (mut region, _) = ota.next_partition().map_err(|_| UpdateError::Unsupported)?;
+            embedded_storage::Storage::write(&mut region, offset, data)
+                .map_err(|_| StorageError::Io.into())
+        })
+    }
+
+    fn activate(&mut self) -> Result<(), GonkError> {
+        self.with_updater(|ota| {
+            ota.activate_next_partition()
+                .and_then(|()| ota.set_current_ota_state(OtaImageState::New))
+                .map_err(|_| StorageError::Io.into())
+        })
+    }
+}
+
+/// Feed the levels of active-low buttons into `input` and send its events
+///
+/// `pins` are in the order of the ids given to `ButtonInput::new()`.
+pub async fn run_buttons<B, M, const N: usize, const Q: usize>(
+    mut pins: [Input<'_>; N],
+    mut input: ButtonInput<B, N>,
+    events: Sender<'_, M, ButtonEvent<B>, Q>,
+) -> !
+where
+    B: Copy + PartialEq,
+    M: RawMutex,
+{
+    loop {
+        {
+            let edges = select_array(pins.each_mut().map(|pin| pin.wait_for_any_edge()));
+            match input.next_deadline() {
+                Some(deadline) => {
+                    select(edges, Timer::at(deadline)).await;
+                }
+                None => {
+                    edges.await;
+                }
+            }
+        }
+
+        let now = Instant::now();
+        for (index, pin) in pins.iter().enumerate() {
+            input.edge_at(index, pin.is_low(), now);
+        }
+        input.poll(now);
+
+        while let Some(event) = input.next_event() {
+            events.send(event).await;
+        }
     }
 }
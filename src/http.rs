@@ -0,0 +1,394 @@
+//! Minimal HTTP/1.1 request parsing and response heads, and response parsing
+//! for clients (hardware-independent)
+
+use core::fmt::{self, Write};
+
+use heapless::{String, Vec};
+
+use crate::error::{GonkError, NetworkError};
+
+/// Largest request read, headers and body included
+pub const MAX_REQUEST: usize = 2048;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Other,
+}
+
+impl Method {
+    fn parse(method: &str) -> Self {
+        match method {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            _ => Method::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request<'a> {
+    pub method: Method,
+    /// Path without the query string
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    /// Header lines, without the request line
+    headers: &'a str,
+    pub body: &'a [u8],
+    /// Body length announced by `Content-Length`
+    pub content_length: usize,
+}
+
+impl<'a> Request<'a> {
+    /// Value of the header `name`, compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers.split("\r\n").find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case(name)
+                .then_some(value.trim())
+        })
+    }
+
+    /// Body as text, `None` when it is not UTF-8
+    pub fn body_str(&self) -> Option<&'a str> {
+        core::str::from_utf8(self.body).ok()
+    }
+}
+
+/// Parse the request at the start of `buf`
+///
+/// Returns `Ok(None)` while the headers or the body announced by
+/// `Content-Length` have not been fully received.
+pub fn parse_request(buf: &[u8]) -> Result<Option<Request<'_>>, GonkError> {
+    let Some(mut request) = parse_head(buf)? else {
+        return Ok(None);
+    };
+    if request.body.len() < request.content_length {
+        return Ok(None);
+    }
+    request.body = &request.body[..request.content_length];
+    Ok(Some(request))
+}
+
+/// Parse the headers of the request at the start of `buf`, for bodies too
+/// large to be buffered
+///
+/// Returns `Ok(None)` while the headers have not been fully received. The
+/// body is then the part of it received so far.
+pub fn parse_head(buf: &[u8]) -> Result<Option<Request<'_>>, GonkError> {
+    let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Ok(None);
+    };
+    let head = core::str::from_utf8(&buf[..end]).map_err(|_| NetworkError::BadRequest)?;
+    let (request_line, headers) = head.split_once("\r\n").unwrap_or((head, ""));
+
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(NetworkError::BadRequest.into());
+    };
+    if !version.starts_with("HTTP/1.") || !target.starts_with('/') {
+        return Err(NetworkError::BadRequest.into());
+    }
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    };
+
+    let mut request = Request {
+        method: Method::parse(method),
+        path,
+        query,
+        headers,
+        body: &buf[end + 4..],
+        content_length: 0,
+    };
+    if let Some(length) = request.header("Content-Length") {
+        request.content_length = length.parse().map_err(|_| NetworkError::BadRequest)?;
+    }
+    Ok(Some(request))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    NoContent,
+    Found,
+    BadRequest,
+    Unauthorized,
+    NotFound,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    InternalServerError,
+}
+
+impl Status {
+    pub fn code(self) -> u16 {
+        match self {
+            Status::Ok => 200,
+            Status::NoContent => 204,
+            Status::Found => 302,
+            Status::BadRequest => 400,
+            Status::Unauthorized => 401,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::PayloadTooLarge => 413,
+            Status::InternalServerError => 500,
+        }
+    }
+
+    pub fn reason(self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::NoContent => "No Content",
+            Status::Found => "Found",
+            Status::BadRequest => "Bad Request",
+            Status::Unauthorized => "Unauthorized",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::PayloadTooLarge => "Payload Too Large",
+            Status::InternalServerError => "Internal Server Error",
+        }
+    }
+}
+
+/// Status line and headers of a response with a body of `content_length` bytes
+///
+/// The connection is closed after every response.
+pub fn response_head(
+    status: Status,
+    content_type: &str,
+    content_length: usize,
+    location: Option<&str>,
+) -> String<256> {
+    let mut head = String::new();
+    let _ = write!(
+        head,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n",
+        status.code(),
+        status.reason(),
+        content_type,
+        content_length
+    );
+    if let Some(location) = location {
+        let _ = write!(head, "Location: {}\r\n", location);
+    }
+    let _ = head.push_str("\r\n");
+    head
+}
+
+/// Response received by a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Response<'a> {
+    pub status: u16,
+    pub body: &'a [u8],
+}
+
+/// Parse a complete response, read until the server closed the connection
+///
+/// The body ends at the `Content-Length` when there is one.
+pub fn parse_response(buf: &[u8]) -> Result<Response<'_>, GonkError> {
+    let end = buf
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or(NetworkError::BadResponse)?;
+    let head = core::str::from_utf8(&buf[..end]).map_err(|_| NetworkError::BadResponse)?;
+    let (status_line, headers) = head.split_once("\r\n").unwrap_or((head, ""));
+
+    let mut parts = status_line.split(' ');
+    let (Some(version), Some(status)) = (parts.next(), parts.next()) else {
+        return Err(NetworkError::BadResponse.into());
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(NetworkError::BadResponse.into());
+    }
+    let status = status.parse().map_err(|_| NetworkError::BadResponse)?;
+
+    let body = &buf[end + 4..];
+    let length = headers.split("\r\n").find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case("Content-Length")
+            .then(|| value.trim().parse::<usize>())
+    });
+    let body = match length {
+        None => body,
+        Some(Ok(length)) if length <= body.len() => &body[..length],
+        Some(_) => return Err(NetworkError::BadResponse.into()),
+    };
+    Ok(Response { status, body })
+}
+
+/// Write `text` percent-encoded for a query string
+pub fn write_url_encoded<W: Write>(out: &mut W, text: &str) -> fmt::Result {
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.write_char(byte as char)?
+            }
+            _ => write!(out, "%{:02X}", byte)?,
+        }
+    }
+    Ok(())
+}
+
+/// Decoded value of `key` in an `application/x-www-form-urlencoded` form
+///
+/// `None` when the key is missing or the value is too long or not UTF-8.
+pub fn form_value<const N: usize>(form: &str, key: &str) -> Option<String<N>> {
+    let encoded = form.split('&').find_map(|pair| {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (name == key).then_some(value)
+    })?;
+
+    let mut bytes = Vec::<u8, N>::new();
+    let mut input = encoded.bytes();
+    while let Some(byte) = input.next() {
+        let decoded = match byte {
+            b'+' => b' ',
+            b'%' => {
+                let high = (input.next()? as char).to_digit(16)?;
+                let low = (input.next()? as char).to_digit(16)?;
+                (high * 16 + low) as u8
+            }
+            byte => byte,
+        };
+        bytes.push(decoded).ok()?;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Write `text` with the HTML special characters escaped
+pub fn write_html_escaped<W: Write>(out: &mut W, text: &str) -> fmt::Result {
+    for c in text.chars() {
+        match c {
+            '&' => out.write_str("&amp;")?,
+            '<' => out.write_str("&lt;")?,
+            '>' => out.write_str("&gt;")?,
+            '"' => out.write_str("&quot;")?,
+            '\'' => out.write_str("&#39;")?,
+            c => out.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_line_and_headers() {
+        let raw = b"GET /api/v1/status?verbose=1 HTTP/1.1\r\nHost: gonk\r\ncontent-type: text/plain\r\n\r\n";
+        let request = parse_request(raw).unwrap().unwrap();
+
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(request.path, "/api/v1/status");
+        assert_eq!(request.query, Some("verbose=1"));
+        assert_eq!(request.header("Content-Type"), Some("text/plain"));
+        assert_eq!(request.header("Accept"), None);
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn waits_for_complete_body() {
+        let raw = b"POST /save HTTP/1.1\r\nContent-Length: 11\r\n\r\nssid=home&p";
+        assert_eq!(parse_request(&raw[..20]), Ok(None));
+        assert_eq!(parse_request(&raw[..raw.len() - 1]), Ok(None));
+
+        let request = parse_request(raw).unwrap().unwrap();
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.body_str(), Some("ssid=home&p"));
+
+        // Streamed bodies start with the head alone
+        assert_eq!(parse_head(&raw[..20]), Ok(None));
+        let head = parse_head(&raw[..raw.len() - 6]).unwrap().unwrap();
+        assert_eq!(head.content_length, 11);
+        assert_eq!(head.body, b"ssid=");
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        let bad = Err(NetworkError::BadRequest.into());
+
+        assert_eq!(parse_request(b"GET\r\n\r\n"), bad);
+        assert_eq!(parse_request(b"GET / SPDY/3\r\n\r\n"), bad);
+        assert_eq!(
+            parse_request(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n"),
+            bad
+        );
+    }
+
+    #[test]
+    fn writes_response_head() {
+        let head = response_head(Status::Found, "text/html", 0, Some("http://192.168.4.1/"));
+
+        assert!(head.starts_with("HTTP/1.1 302 Found\r\n"));
+        assert!(head.contains("Content-Length: 0\r\n"));
+        assert!(head.contains("Location: http://192.168.4.1/\r\n"));
+        assert!(head.ends_with("\r\n\r\n"));
+    }
+
+    #[test]
+    fn decodes_form_values() {
+        let form = "ssid=My+Home%21&password=p%C3%A4ss&empty=";
+
+        assert_eq!(form_value::<32>(form, "ssid").unwrap(), "My Home!");
+        assert_eq!(form_value::<32>(form, "password").unwrap(), "päss");
+        assert_eq!(form_value::<32>(form, "empty").unwrap(), "");
+        assert_eq!(form_value::<32>(form, "missing"), None);
+        assert_eq!(form_value::<4>(form, "ssid"), None);
+        assert_eq!(form_value::<32>("ssid=%ZZ", "ssid"), None);
+    }
+
+    #[test]
+    fn parses_responses() {
+        let raw =
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\ncontent-length: 2\r\n\r\n{}\r\n";
+        assert_eq!(
+            parse_response(raw),
+            Ok(Response {
+                status: 200,
+                body: b"{}"
+            })
+        );
+
+        let closed = b"HTTP/1.0 401 Unauthorized\r\n\r\n{\"cod\":401}";
+        assert_eq!(parse_response(closed).unwrap().body, b"{\"cod\":401}");
+
+        let bad = Err(NetworkError::BadResponse.into());
+        assert_eq!(parse_response(b"HTTP/1.1 200 OK\r\n"), bad);
+        assert_eq!(parse_response(b"SSH-2.0\r\n\r\n"), bad);
+        assert_eq!(
+            parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n{}"),
+            bad
+        );
+    }
+
+    #[test]
+    fn encodes_query_values() {
+        let mut out = String::<64>::new();
+        write_url_encoded(&mut out, "São Paulo,BR").unwrap();
+
+        assert_eq!(out, "S%C3%A3o%20Paulo%2CBR");
+    }
+
+    #[test]
+    fn escapes_html() {
+        let mut out = String::<64>::new();
+        write_html_escaped(&mut out, "<b>\"Tom\" & 'Jerry'</b>").unwrap();
+
+        assert_eq!(
+            out,
+            "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;"
+        );
+    }
+}
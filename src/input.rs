@@ -0,0 +1,452 @@
+//! Button gesture detection (hardware-independent)
+//!
+//! `ButtonInput` turns raw level changes of a set of buttons into clicks,
+//! double-clicks, long presses, hold repeats and two-button chords. It never
+//! reads the clock: edges come with their timestamp and `poll()` is called
+//! with the current time, at the latest by `next_deadline()`.
+
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+
+/// Timings of the gesture detection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonTimings {
+    /// A level must be stable this long to count
+    pub debounce: Duration,
+    /// Maximum time between a release and the next press of a double-click,
+    /// zero reports every click immediately
+    pub double_click: Duration,
+    /// Hold time of a long press
+    pub long_press: Duration,
+    /// Period of the repeat events sent while a long press is held
+    pub hold_repeat: Duration,
+    /// Maximum time between the two presses of a chord
+    pub chord: Duration,
+}
+
+impl Default for ButtonTimings {
+    fn default() -> Self {
+        Self {
+            debounce: Duration::from_millis(30),
+            double_click: Duration::from_millis(300),
+            long_press: Duration::from_millis(800),
+            hold_repeat: Duration::from_millis(200),
+            chord: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Gesture made with the buttons identified by `B`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonEvent<B> {
+    Click(B),
+    DoubleClick(B),
+    /// The button has been held for `long_press`, sent once per press
+    LongPress(B),
+    /// The button is still held, sent every `hold_repeat` after `LongPress`
+    Repeat(B),
+    /// Both buttons pressed together, first pressed first
+    Chord(B, B),
+}
+
+/// Number of events that can wait for `next_event()`
+const QUEUE_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+struct Key {
+    /// Debounced level
+    pressed: bool,
+    /// Last level reported by `edge()` and when it changed
+    raw: bool,
+    raw_since: Instant,
+    /// Start of the current or last press
+    pressed_at: Instant,
+    long_fired: bool,
+    next_repeat: Instant,
+    /// Release of a click that may become a double-click
+    pending_click: Option<Instant>,
+    /// The current press is the second one of a double-click
+    second_press: bool,
+    /// The current press is part of a chord, its release is not a click
+    in_chord: bool,
+}
+
+impl Key {
+    const fn new() -> Self {
+        Self {
+            pressed: false,
+            raw: false,
+            raw_since: Instant::from_ticks(0),
+            pressed_at: Instant::from_ticks(0),
+            long_fired: false,
+            next_repeat: Instant::from_ticks(0),
+            pending_click: None,
+            second_press: false,
+            in_chord: false,
+        }
+    }
+}
+
+/// Gesture detector for `N` buttons
+pub struct ButtonInput<B, const N: usize> {
+    ids: [B; N],
+    keys: [Key; N],
+    timings: ButtonTimings,
+    events: Deque<ButtonEvent<B>, QUEUE_LEN>,
+}
+
+impl<B: Copy + PartialEq, const N: usize> ButtonInput<B, N> {
+    pub fn new(ids: [B; N], timings: ButtonTimings) -> Self {
+        Self {
+            ids,
+            keys: [Key::new(); N],
+            timings,
+            events: Deque::new(),
+        }
+    }
+
+    pub fn timings(&self) -> ButtonTimings {
+        self.timings
+    }
+
+    /// Record the level of a button, `pressed` is the raw pin state at `at`
+    pub fn edge(&mut self, id: B, pressed: bool, at: Instant) {
+        if let Some(index) = self.ids.iter().position(|&b| b == id) {
+            self.edge_at(index, pressed, at);
+        }
+    }
+
+    /// Same as `edge()` with the button given by its position in `ids`
+    pub fn edge_at(&mut self, index: usize, pressed: bool, at: Instant) {
+        let key = &mut self.keys[index];
+        if key.raw != pressed {
+            key.raw = pressed;
+            key.raw_since = at;
+        }
+    }
+
+    /// Apply every level that is now stable and every expired timeout
+    pub fn poll(&mut self, now: Instant) {
+        for index in 0..N {
+            let key = self.keys[index];
+            if key.raw != key.pressed && now >= key.raw_since + self.timings.debounce {
+                if key.raw {
+                    self.press(index, key.raw_since);
+                } else {
+                    self.release(index, key.raw_since);
+                }
+            }
+        }
+
+        for index in 0..N {
+            let id = self.ids[index];
+            let timings = self.timings;
+            let key = &mut self.keys[index];
+            let mut event = None;
+
+            if let Some(released) = key.pending_click
+                && now >= released + timings.double_click
+            {
+                key.pending_click = None;
+                event = Some(ButtonEvent::Click(id));
+            } else if key.pressed && !key.in_chord {
+                if !key.long_fired && now >= key.pressed_at + timings.long_press {
+                    key.long_fired = true;
+                    key.next_repeat = key.pressed_at + timings.long_press + timings.hold_repeat;
+                    event = Some(ButtonEvent::LongPress(id));
+                } else if key.long_fired && now >= key.next_repeat {
+                    key.next_repeat += timings.hold_repeat;
+                    event = Some(ButtonEvent::Repeat(id));
+                }
+            }
+
+            if let Some(event) = event {
+                self.push(event);
+            }
+        }
+    }
+
+    /// Oldest detected gesture
+    pub fn next_event(&mut self) -> Option<ButtonEvent<B>> {
+        self.events.pop_front()
+    }
+
+    /// Latest time `poll()` must be called at for events to be on time
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let timings = self.timings;
+        self.keys
+            .iter()
+            .filter_map(|key| {
+                if key.raw != key.pressed {
+                    Some(key.raw_since + timings.debounce)
+                } else if let Some(released) = key.pending_click {
+                    Some(released + timings.double_click)
+                } else if key.pressed && !key.in_chord {
+                    Some(if key.long_fired {
+                        key.next_repeat
+                    } else {
+                        key.pressed_at + timings.long_press
+                    })
+                } else {
+                    None
+                }
+            })
+            .min()
+    }
+
+    fn press(&mut self, index: usize, at: Instant) {
+        let timings = self.timings;
+        let key = &mut self.keys[index];
+        key.pressed = true;
+        key.pressed_at = at;
+        key.long_fired = false;
+        key.in_chord = false;
+        key.second_press = false;
+
+        // A click not yet reported because `poll()` came late
+        match key.pending_click.take() {
+            Some(released) if at <= released + timings.double_click => key.second_press = true,
+            Some(_) => self.push(ButtonEvent::Click(self.ids[index])),
+            None => {}
+        }
+
+        // A chord needs another button pressed shortly before, and not yet used
+        let partner = (0..N).find(|&other| {
+            let key = &self.keys[other];
+            other != index
+                && key.pressed
+                && !key.in_chord
+                && !key.long_fired
+                && at <= key.pressed_at + timings.chord
+        });
+
+        if let Some(other) = partner {
+            for i in [other, index] {
+                self.keys[i].in_chord = true;
+                self.keys[i].second_press = false;
+                self.keys[i].pending_click = None;
+            }
+            self.push(ButtonEvent::Chord(self.ids[other], self.ids[index]));
+        }
+    }
+
+    fn release(&mut self, index: usize, at: Instant) {
+        let id = self.ids[index];
+        let immediate = self.timings.double_click == Duration::from_ticks(0);
+        let key = &mut self.keys[index];
+        key.pressed = false;
+
+        if key.in_chord || key.long_fired {
+            return;
+        }
+
+        if key.second_press {
+            key.second_press = false;
+            self.push(ButtonEvent::DoubleClick(id));
+        } else if immediate {
+            self.push(ButtonEvent::Click(id));
+        } else {
+            key.pending_click = Some(at);
+        }
+    }
+
+    fn push(&mut self, event: ButtonEvent<B>) {
+        // Drop the oldest event when nobody reads them
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+        let _ = self.events.push_back(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heapless::Vec;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Id {
+        A,
+        B,
+    }
+
+    fn ms(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn input() -> ButtonInput<Id, 2> {
+        ButtonInput::new([Id::A, Id::B], ButtonTimings::default())
+    }
+
+    /// Feed `(button, pressed, ms)` edges, polling at every millisecond
+    fn run(
+        input: &mut ButtonInput<Id, 2>,
+        edges: &[(Id, bool, u64)],
+        until: u64,
+    ) -> Vec<ButtonEvent<Id>, 16> {
+        let mut events = Vec::new();
+        for t in 0..=until {
+            for &(id, pressed, at) in edges {
+                if at == t {
+                    input.edge(id, pressed, ms(at));
+                }
+            }
+            input.poll(ms(t));
+            while let Some(event) = input.next_event() {
+                events.push(event).unwrap();
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn click_after_double_click_window() {
+        let mut input = input();
+        let events = run(&mut input, &[(Id::A, true, 0), (Id::A, false, 100)], 399);
+        assert!(events.is_empty());
+
+        let events = run(&mut input, &[], 400);
+        assert_eq!(events, [ButtonEvent::Click(Id::A)]);
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        let mut input = input();
+        let edges = [
+            (Id::A, true, 0),
+            (Id::A, false, 5),
+            (Id::A, true, 8),
+            (Id::A, false, 100),
+            (Id::A, true, 110),
+            (Id::A, false, 115),
+        ];
+
+        assert_eq!(run(&mut input, &edges, 1000), [ButtonEvent::Click(Id::A)]);
+    }
+
+    #[test]
+    fn double_click() {
+        let mut input = input();
+        let edges = [
+            (Id::A, true, 0),
+            (Id::A, false, 80),
+            (Id::A, true, 250),
+            (Id::A, false, 330),
+        ];
+
+        assert_eq!(
+            run(&mut input, &edges, 1000),
+            [ButtonEvent::DoubleClick(Id::A)]
+        );
+    }
+
+    #[test]
+    fn slow_second_press_is_two_clicks() {
+        let mut input = input();
+        let edges = [
+            (Id::A, true, 0),
+            (Id::A, false, 80),
+            (Id::A, true, 500),
+            (Id::A, false, 580),
+        ];
+
+        assert_eq!(
+            run(&mut input, &edges, 1000),
+            [ButtonEvent::Click(Id::A), ButtonEvent::Click(Id::A)]
+        );
+    }
+
+    #[test]
+    fn long_press_then_repeats() {
+        let mut input = input();
+        let edges = [(Id::B, true, 0), (Id::B, false, 1250)];
+        let events = run(&mut input, &edges, 2000);
+
+        assert_eq!(
+            events,
+            [
+                ButtonEvent::LongPress(Id::B),
+                ButtonEvent::Repeat(Id::B),
+                ButtonEvent::Repeat(Id::B),
+            ]
+        );
+    }
+
+    #[test]
+    fn chord_suppresses_single_events() {
+        let mut input = input();
+        let edges = [
+            (Id::B, true, 0),
+            (Id::A, true, 60),
+            (Id::A, false, 1500),
+            (Id::B, false, 1520),
+        ];
+
+        assert_eq!(
+            run(&mut input, &edges, 2500),
+            [ButtonEvent::Chord(Id::B, Id::A)]
+        );
+    }
+
+    #[test]
+    fn late_second_press_is_not_a_chord() {
+        let mut input = input();
+        let edges = [
+            (Id::A, true, 0),
+            (Id::B, true, 300),
+            (Id::B, false, 400),
+            (Id::A, false, 500),
+        ];
+
+        assert_eq!(
+            run(&mut input, &edges, 1000),
+            [ButtonEvent::Click(Id::B), ButtonEvent::Click(Id::A)]
+        );
+    }
+
+    #[test]
+    fn immediate_clicks_without_double_click() {
+        let mut input = ButtonInput::new(
+            [Id::A, Id::B],
+            ButtonTimings {
+                double_click: Duration::from_ticks(0),
+                ..ButtonTimings::default()
+            },
+        );
+
+        assert_eq!(
+            run(&mut input, &[(Id::A, true, 0), (Id::A, false, 100)], 130),
+            [ButtonEvent::Click(Id::A)]
+        );
+    }
+
+    #[test]
+    fn late_poll_keeps_first_click() {
+        let mut input = input();
+        input.edge(Id::A, true, ms(0));
+        input.poll(ms(50));
+        input.edge(Id::A, false, ms(100));
+        input.poll(ms(150));
+        input.edge(Id::A, true, ms(600));
+        input.poll(ms(650));
+
+        assert_eq!(input.next_event(), Some(ButtonEvent::Click(Id::A)));
+        assert_eq!(input.next_event(), None);
+    }
+
+    #[test]
+    fn deadline_follows_pending_timeouts() {
+        let mut input = input();
+        assert_eq!(input.next_deadline(), None);
+
+        input.edge(Id::A, true, ms(0));
+        assert_eq!(input.next_deadline(), Some(ms(30)));
+
+        input.poll(ms(30));
+        assert_eq!(input.next_deadline(), Some(ms(800)));
+
+        input.edge(Id::A, false, ms(100));
+        input.poll(ms(130));
+        assert_eq!(input.next_deadline(), Some(ms(400)));
+    }
+}
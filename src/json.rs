@@ -0,0 +1,455 @@
+//! JSON output and a streaming JSON reader (hardware-independent)
+
+use core::fmt::{self, Write};
+
+use heapless::String;
+
+/// Nesting of arrays and objects skipped by `Parser::skip()`
+const MAX_DEPTH: usize = 16;
+
+/// Write `text` as a JSON string, quotes included
+pub fn write_str<W: Write>(out: &mut W, text: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for c in text.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+/// Write a number with `precision` decimals, `null` when there is none or it
+/// is not finite
+pub fn write_number<W: Write>(out: &mut W, value: Option<f32>, precision: usize) -> fmt::Result {
+    match value {
+        Some(v) if v.is_finite() => write!(out, "{:.*}", precision, v),
+        _ => out.write_str("null"),
+    }
+}
+
+/// Writes the members of a JSON object, separated by commas
+pub struct ObjectWriter<'a, W: Write> {
+    out: &'a mut W,
+    empty: bool,
+}
+
+impl<'a, W: Write> ObjectWriter<'a, W> {
+    pub fn new(out: &'a mut W) -> Result<Self, fmt::Error> {
+        out.write_char('{')?;
+        Ok(Self { out, empty: true })
+    }
+
+    /// Start the member `name`, its value is written to the returned writer
+    pub fn member(&mut self, name: &str) -> Result<&mut W, fmt::Error> {
+        if !self.empty {
+            self.out.write_char(',')?;
+        }
+        self.empty = false;
+        write_str(self.out, name)?;
+        self.out.write_char(':')?;
+        Ok(self.out)
+    }
+
+    pub fn string(&mut self, name: &str, value: &str) -> fmt::Result {
+        write_str(self.member(name)?, value)
+    }
+
+    pub fn number(&mut self, name: &str, value: Option<f32>, precision: usize) -> fmt::Result {
+        write_number(self.member(name)?, value, precision)
+    }
+
+    pub fn integer(&mut self, name: &str, value: i64) -> fmt::Result {
+        write!(self.member(name)?, "{}", value)
+    }
+
+    pub fn boolean(&mut self, name: &str, value: bool) -> fmt::Result {
+        write!(self.member(name)?, "{}", value)
+    }
+
+    pub fn null(&mut self, name: &str) -> fmt::Result {
+        self.member(name)?.write_str("null")
+    }
+
+    pub fn finish(self) -> fmt::Result {
+        self.out.write_char('}')
+    }
+}
+
+/// A document is not JSON, or a value does not have the type expected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error {
+    /// Byte offset where reading stopped
+    pub position: usize,
+}
+
+/// Reads a JSON document value by value, without building a tree
+///
+/// Every method reads one value of the expected type, `skip()` reads any.
+pub struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Self {
+            text: text.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn error(&self) -> Error {
+        Error { position: self.pos }
+    }
+
+    /// Next byte that is not whitespace, not consumed
+    fn peek(&mut self) -> Option<u8> {
+        while let Some(&byte) = self.text.get(self.pos) {
+            if !matches!(byte, b' ' | b'\t' | b'\n' | b'\r') {
+                return Some(byte);
+            }
+            self.pos += 1;
+        }
+        None
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Error> {
+        if self.peek() != Some(byte) {
+            return Err(self.error());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str) -> Result<(), Error> {
+        self.peek();
+        if !self.text[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.error());
+        }
+        self.pos += word.len();
+        Ok(())
+    }
+
+    /// Read a `null` if it comes next
+    pub fn null(&mut self) -> bool {
+        self.literal("null").is_ok()
+    }
+
+    pub fn boolean(&mut self) -> Result<bool, Error> {
+        match self.peek() {
+            Some(b't') => self.literal("true").map(|_| true),
+            _ => self.literal("false").map(|_| false),
+        }
+    }
+
+    /// Text of a number, checked by the caller
+    fn number_text(&mut self) -> Result<&'a str, Error> {
+        self.peek();
+        let start = self.pos;
+        while let Some(b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E') = self.text.get(self.pos) {
+            self.pos += 1;
+        }
+        // Only ASCII was consumed
+        core::str::from_utf8(&self.text[start..self.pos]).map_err(|_| self.error())
+    }
+
+    pub fn number(&mut self) -> Result<f32, Error> {
+        let text = self.number_text()?;
+        text.parse().map_err(|_| self.error())
+    }
+
+    /// Number without fraction or exponent
+    pub fn integer(&mut self) -> Result<i64, Error> {
+        let text = self.number_text()?;
+        text.parse().map_err(|_| self.error())
+    }
+
+    /// Four hexadecimal digits of a `\u` escape
+    fn hex4(&mut self) -> Result<u32, Error> {
+        let digits = self.text.get(self.pos..self.pos + 4).ok_or(self.error())?;
+        let digits = core::str::from_utf8(digits).map_err(|_| self.error())?;
+        let value = u32::from_str_radix(digits, 16).map_err(|_| self.error())?;
+        self.pos += 4;
+        Ok(value)
+    }
+
+    /// String with its escapes decoded, an error when longer than `N` bytes
+    pub fn string<const N: usize>(&mut self) -> Result<String<N>, Error> {
+        self.read_string(false)
+    }
+
+    /// String with its escapes decoded, cut after `N` bytes
+    pub fn truncated_string<const N: usize>(&mut self) -> Result<String<N>, Error> {
+        self.read_string(true)
+    }
+
+    fn read_string<const N: usize>(&mut self, truncate: bool) -> Result<String<N>, Error> {
+        // Append what fits, the rest is dropped or an error
+        let mut cut = false;
+        let mut push = |out: &mut String<N>, text: &str| {
+            if cut {
+                return truncate;
+            }
+            if out.push_str(text).is_ok() {
+                return true;
+            }
+            cut = true;
+            if truncate {
+                for c in text.chars() {
+                    if out.push(c).is_err() {
+                        break;
+                    }
+                }
+            }
+            truncate
+        };
+
+        self.expect(b'"')?;
+        let mut out = String::new();
+        loop {
+            let start = self.pos;
+            while let Some(&byte) = self.text.get(self.pos) {
+                if byte == b'"' || byte == b'\\' || byte < 0x20 {
+                    break;
+                }
+                self.pos += 1;
+            }
+            // Stops at ASCII bytes only, so on character boundaries
+            let run =
+                core::str::from_utf8(&self.text[start..self.pos]).map_err(|_| self.error())?;
+            if !push(&mut out, run) {
+                return Err(self.error());
+            }
+
+            let escaped = match self.text.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(out);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escape = *self.text.get(self.pos).ok_or(self.error())?;
+                    self.pos += 1;
+                    match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            if (0xD800..0xDC00).contains(&code) {
+                                // High surrogate, followed by the low one
+                                self.literal("\\u")?;
+                                let low = self.hex4()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err(self.error());
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            char::from_u32(code).ok_or(self.error())?
+                        }
+                        _ => return Err(self.error()),
+                    }
+                }
+                _ => return Err(self.error()),
+            };
+            if !push(&mut out, escaped.encode_utf8(&mut [0; 4])) {
+                return Err(self.error());
+            }
+        }
+    }
+
+    /// Read an object, `member` reads the value of each member from its name
+    ///
+    /// Names longer than 32 bytes are an error.
+    pub fn object<E: From<Error>>(
+        &mut self,
+        mut member: impl FnMut(&mut Self, &str) -> Result<(), E>,
+    ) -> Result<(), E> {
+        self.expect(b'{')?;
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            let name = self.string::<32>()?;
+            self.expect(b':')?;
+            member(self, &name)?;
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => return Err(self.error().into()),
+            }
+        }
+    }
+
+    /// Read an array, `item` reads each of its values
+    pub fn array<E: From<Error>>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<(), E>,
+    ) -> Result<(), E> {
+        self.expect(b'[')?;
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(());
+        }
+        loop {
+            item(self)?;
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => return Err(self.error().into()),
+            }
+        }
+    }
+
+    /// Read any value
+    pub fn skip(&mut self) -> Result<(), Error> {
+        self.skip_nested(0)
+    }
+
+    fn skip_nested(&mut self, depth: usize) -> Result<(), Error> {
+        if depth > MAX_DEPTH {
+            return Err(self.error());
+        }
+        match self.peek() {
+            Some(b'{') => self.object(|parser, _| parser.skip_nested(depth + 1)),
+            Some(b'[') => self.array(|parser| parser.skip_nested(depth + 1)),
+            Some(b'"') => self.skip_string(),
+            Some(b't' | b'f') => self.boolean().map(|_| ()),
+            Some(b'n') if self.null() => Ok(()),
+            _ => self.number().map(|_| ()),
+        }
+    }
+
+    /// Read a string of any length
+    fn skip_string(&mut self) -> Result<(), Error> {
+        self.expect(b'"')?;
+        while let Some(&byte) = self.text.get(self.pos) {
+            self.pos += 1;
+            match byte {
+                b'"' => return Ok(()),
+                b'\\' => self.pos += 1,
+                _ => {}
+            }
+        }
+        Err(self.error())
+    }
+
+    /// Check that nothing follows the document
+    pub fn finish(mut self) -> Result<(), Error> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => Err(self.error()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        let mut out = String::<64>::new();
+        write_str(&mut out, "say \"hi\"\\\n\u{1}é").unwrap();
+
+        assert_eq!(out, "\"say \\\"hi\\\"\\\\\\n\\u0001é\"");
+    }
+
+    #[test]
+    fn writes_nested_objects() {
+        let mut out = String::<128>::new();
+        let mut object = ObjectWriter::new(&mut out).unwrap();
+        object.string("name", "gonk").unwrap();
+        object.number("temperature", Some(21.456), 2).unwrap();
+        object.number("humidity", None, 1).unwrap();
+        object.number("pressure", Some(f32::NAN), 1).unwrap();
+        let mut inner = ObjectWriter::new(object.member("wifi").unwrap()).unwrap();
+        inner.integer("rssi", -61).unwrap();
+        inner.boolean("connected", true).unwrap();
+        inner.finish().unwrap();
+        object.null("ip").unwrap();
+        object.finish().unwrap();
+
+        assert_eq!(
+            out,
+            "{\"name\":\"gonk\",\"temperature\":21.46,\"humidity\":null,\"pressure\":null,\
+\"wifi\":{\"rssi\":-61,\"connected\":true},\"ip\":null}"
+        );
+    }
+
+    #[test]
+    fn reads_documents() {
+        let text = r#" {"name": "caf\u00e9 \"A\"\n", "list": [1, -2.5e1, 3],
+            "nested": {"ok": true, "none": null, "deep": [[{}], "x\\"]},
+            "emoji": "\ud83d\ude00", "id": 42} "#;
+        let mut parser = Parser::new(text);
+        let mut name = String::<32>::new();
+        let mut list = heapless::Vec::<f32, 4>::new();
+        let mut id = 0;
+        let mut emoji = String::<8>::new();
+        parser
+            .object(|parser, key| -> Result<(), Error> {
+                match key {
+                    "name" => name = parser.string()?,
+                    "list" => parser.array(|parser| -> Result<(), Error> {
+                        let _ = list.push(parser.number()?);
+                        Ok(())
+                    })?,
+                    "emoji" => emoji = parser.string()?,
+                    "id" => id = parser.integer()?,
+                    _ => parser.skip()?,
+                }
+                Ok(())
+            })
+            .unwrap();
+        parser.finish().unwrap();
+
+        assert_eq!(name, "café \"A\"\n");
+        assert_eq!(list, [1.0, -25.0, 3.0]);
+        assert_eq!(emoji, "\u{1F600}");
+        assert_eq!(id, 42);
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        let skip = |text| {
+            let mut parser = Parser::new(text);
+            parser.skip()?;
+            parser.finish()
+        };
+
+        assert!(skip("{\"a\": 1,}").is_err());
+        assert!(skip("[1 2]").is_err());
+        assert!(skip("\"open").is_err());
+        assert!(skip("{} {}").is_err());
+        assert!(skip("tru").is_err());
+        assert!(skip(core::str::from_utf8(&[b'['; 40]).unwrap()).is_err());
+        assert_eq!(skip("[1, {\"a\": [null]}]"), Ok(()));
+
+        assert!(Parser::new("\"too long\"").string::<4>().is_err());
+        assert_eq!(
+            Parser::new("\"caf\\u00e9 au lait\"").truncated_string::<4>(),
+            Ok(String::<4>::try_from("caf").unwrap())
+        );
+        assert!(Parser::new("1.5").integer().is_err());
+        assert!(Parser::new("\"\\q\"").string::<4>().is_err());
+    }
+}
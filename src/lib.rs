@@ -1,7 +1,36 @@
 #![no_std]
 
+pub mod api;
+pub mod bme280;
+pub mod clock;
+pub mod config;
+#[cfg(target_arch = "xtensa")]
 pub mod display;
+pub mod epaper;
+pub mod error;
+pub mod framebuffer;
+#[cfg(target_arch = "xtensa")]
 pub mod hardware;
+pub mod http;
+pub mod input;
+pub mod json;
 pub mod logic;
+pub mod mdns;
+pub mod menu;
+pub mod metrics;
+#[cfg(test)]
+pub mod mock;
 pub mod model;
+pub mod mqtt;
+#[cfg(target_arch = "xtensa")]
+pub mod network;
+pub mod ota;
+pub mod portal;
+pub mod screen;
+pub mod settings;
+pub mod sha256;
+pub mod sntp;
 pub mod traits;
+pub mod tz;
+pub mod weather;
+pub mod wifi;
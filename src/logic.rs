@@ -1,19 +1,29 @@
 //! Business logic layer (hardware-independent)
 
-use crate::traits::{Display, TemperatureSensor};
+use crate::config::{Offsets, Thresholds};
+use crate::error::GonkError;
+use crate::model::{Freshness, Model, format_stale, format_value};
+use crate::traits::{Display, DisplayType, EnvironmentalSensor, I2cBus, Reading};
 use core::fmt::Write;
+use embassy_time::Instant;
 
 /// Application state for testable business logic
 pub struct AppLogic {
     temperature_readings: [Option<f32>; 5],
     reading_index: usize,
+    thresholds: Thresholds,
 }
 
 impl AppLogic {
     pub fn new() -> Self {
+        Self::with_thresholds(Thresholds::default())
+    }
+
+    pub fn with_thresholds(thresholds: Thresholds) -> Self {
         Self {
             temperature_readings: [None; 5],
             reading_index: 0,
+            thresholds,
         }
     }
 
@@ -28,11 +38,9 @@ impl AppLogic {
         let mut sum = 0.0;
         let mut count = 0;
 
-        for reading in self.temperature_readings.iter() {
-            if let Some(temp) = reading {
-                sum += temp;
-                count += 1;
-            }
+        for temp in self.temperature_readings.iter().flatten() {
+            sum += temp;
+            count += 1;
         }
 
         if count == 0 {
@@ -45,11 +53,7 @@ impl AppLogic {
     /// Get temperature status message
     pub fn temperature_status(&self) -> &'static str {
         match self.average_temperature() {
-            Some(temp) if temp < 10.0 => "Cold",
-            Some(temp) if temp < 20.0 => "Cool",
-            Some(temp) if temp < 25.0 => "Comfortable",
-            Some(temp) if temp < 30.0 => "Warm",
-            Some(_) => "Hot",
+            Some(temp) => self.thresholds.status(temp),
             None => "No data",
         }
     }
@@ -62,12 +66,40 @@ impl AppLogic {
     }
 }
 
+impl Default for AppLogic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// I2C addresses of an SSD1306, selected by its SA0 pin
+pub const SSD1306_ADDRESSES: [u8; 2] = [0x3C, 0x3D];
+
+/// First SSD1306 address that acknowledges an address-only write
+pub fn probe_ssd1306<B: I2cBus>(bus: &mut B) -> Option<u8> {
+    SSD1306_ADDRESSES
+        .into_iter()
+        .find(|&address| bus.write(address, &[]).is_ok())
+}
+
+/// Display backend to start
+///
+/// A configured type wins, otherwise an OLED found on the bus is used and the
+/// e-paper panel, which cannot be probed, is the fallback.
+pub fn select_display(configured: Option<DisplayType>, probed: Option<u8>) -> DisplayType {
+    match (configured, probed) {
+        (Some(display_type), _) => display_type,
+        (None, Some(_)) => DisplayType::SSD1306,
+        (None, None) => DisplayType::EPaper,
+    }
+}
+
 /// Update display with sensor reading
-pub fn update_display_with_sensor<D: Display, T: TemperatureSensor>(
+pub fn update_display_with_sensor<D: Display, S: EnvironmentalSensor>(
     display: &mut D,
-    sensor: &mut T,
+    sensor: &mut S,
     app: &mut AppLogic,
-) -> Result<(), &'static str> {
+) -> Result<(), GonkError> {
     // Read temperature
     let temp = sensor.read_temperature()?;
     app.record_temperature(temp);
@@ -87,3 +119,392 @@ pub fn update_display_with_sensor<D: Display, T: TemperatureSensor>(
     display.update()?;
     Ok(())
 }
+
+/// Read the sensor and store every quantity it measures in the model, corrected
+/// by `offsets`
+///
+/// On error the model keeps its last good values, which become stale over time.
+pub fn update_model_with_sensor<S: EnvironmentalSensor>(
+    model: &mut Model,
+    sensor: &mut S,
+    offsets: &Offsets,
+) -> Result<Reading, GonkError> {
+    let mut reading = sensor.read().inspect_err(|_| {
+        let failures = &mut model.counters.sensor_read_errors;
+        *failures = failures.saturating_add(1);
+    })?;
+    offsets.apply(&mut reading);
+    model.record_reading(&reading);
+    Ok(reading)
+}
+
+/// One line summary of the sensor values for logging
+pub fn format_readings(model: &Model, now: Instant) -> heapless::String<64> {
+    let mut buffer = heapless::String::new();
+    let _ = write!(
+        buffer,
+        "T={} H={} P={}",
+        format_value(model.current(model.temperature, now), 2, " C"),
+        format_value(model.current(model.humidity, now), 2, " %"),
+        format_value(
+            model.current(model.pressure, now).map(|p| p / 100.0),
+            2,
+            " hPa"
+        ),
+    );
+    if let Freshness::Stale(age) = model.freshness(now) {
+        let _ = write!(buffer, " {}", format_stale(age));
+    }
+    buffer
+}
+
+/// Render the main readings screen from the shared model
+pub fn update_display_with_model<D: Display + ?Sized>(
+    display: &mut D,
+    model: &Model,
+    now: Instant,
+) -> Result<(), GonkError> {
+    let line_height = 10;
+    let mut y = 0;
+
+    display.clear()?;
+
+    display.draw_text("Gonk Sensor Readings", 0, y)?;
+    y += line_height;
+
+    display.draw_line(0, y + line_height / 2, 127, y + line_height / 2)?;
+    y += line_height;
+
+    let mut temp_str = heapless::String::<32>::new();
+    let temperature = model.display_temperature(now);
+    let _ = write!(
+        temp_str,
+        "Temp: {}",
+        format_value(temperature, 2, model.units.suffix())
+    );
+    display.draw_text(temp_str.as_str(), 0, y)?;
+    y += line_height;
+
+    let mut humidity_str = heapless::String::<32>::new();
+    let humidity = model.current(model.humidity, now);
+    let _ = write!(
+        humidity_str,
+        "Humidity: {}",
+        format_value(humidity, 2, " %")
+    );
+    display.draw_text(humidity_str.as_str(), 0, y)?;
+    y += line_height;
+
+    let mut ip_str = heapless::String::<32>::new();
+    let _ = write!(ip_str, "IP: {}", model.ip_address);
+    display.draw_text(ip_str.as_str(), 0, y)?;
+    y += line_height;
+
+    if let Freshness::Stale(age) = model.freshness(now) {
+        let mut stale_str = heapless::String::<32>::new();
+        let _ = write!(stale_str, "Sensor: {}", format_stale(age));
+        display.draw_text(stale_str.as_str(), 0, y)?;
+    } else if let Some(comfort) = model.comfort(now) {
+        let mut comfort_str = heapless::String::<32>::new();
+        let _ = write!(comfort_str, "Comfort: {}", comfort);
+        display.draw_text(comfort_str.as_str(), 0, y)?;
+    }
+
+    display.update()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{DisplayError, GonkError};
+    use crate::mock::{DisplayCall, MockDisplay, MockI2c, MockSensor};
+    use crate::settings::TemperatureUnit;
+
+    fn app_with(readings: &[f32]) -> AppLogic {
+        let mut app = AppLogic::new();
+        for reading in readings {
+            app.record_temperature(*reading);
+        }
+        app
+    }
+
+    #[test]
+    fn single_reading_average() {
+        let app = app_with(&[22.5]);
+
+        assert_eq!(app.average_temperature(), Some(22.5));
+        assert_eq!(app.temperature_status(), "Comfortable");
+    }
+
+    #[test]
+    fn multiple_readings_average() {
+        let app = app_with(&[20.0, 22.0, 24.0]);
+
+        assert!((app.average_temperature().unwrap() - 22.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn rolling_buffer_drops_oldest_reading() {
+        let app = app_with(&[2.0, 12.0, 22.0, 32.0, 42.0, 100.0]);
+
+        assert!((app.average_temperature().unwrap() - 41.6).abs() < 0.01);
+    }
+
+    #[test]
+    fn no_data_without_readings() {
+        let app = AppLogic::new();
+
+        assert_eq!(app.average_temperature(), None);
+        assert_eq!(app.temperature_status(), "No data");
+    }
+
+    #[test]
+    fn temperature_status_categories() {
+        assert_eq!(app_with(&[5.0]).temperature_status(), "Cold");
+        assert_eq!(app_with(&[15.0]).temperature_status(), "Cool");
+        assert_eq!(app_with(&[22.0]).temperature_status(), "Comfortable");
+        assert_eq!(app_with(&[28.0]).temperature_status(), "Warm");
+        assert_eq!(app_with(&[35.0]).temperature_status(), "Hot");
+    }
+
+    #[test]
+    fn temperature_status_uses_configured_thresholds() {
+        let mut app = AppLogic::with_thresholds(Thresholds {
+            warm: 26.0,
+            ..Thresholds::default()
+        });
+        app.record_temperature(28.0);
+
+        assert_eq!(app.temperature_status(), "Hot");
+    }
+
+    #[test]
+    fn format_temperature_includes_status() {
+        let app = app_with(&[22.5]);
+
+        assert_eq!(app.format_temperature(22.5).as_str(), "22.5C Comfortable");
+    }
+
+    #[test]
+    fn update_display_with_sensor_draws_reading_and_average() {
+        let mut display = MockDisplay::new();
+        let mut sensor = MockSensor::new();
+        let mut app = app_with(&[20.0]);
+        sensor.push_temperature(24.0);
+
+        update_display_with_sensor(&mut display, &mut sensor, &mut app).unwrap();
+
+        assert!(display.texts().eq(["24.0C Comfortable", "Avg: 22.0C"]));
+        assert_eq!(display.calls.first(), Some(&DisplayCall::Clear));
+        assert_eq!(display.calls.last(), Some(&DisplayCall::Update));
+    }
+
+    #[test]
+    fn update_display_with_sensor_leaves_display_on_read_error() {
+        let mut display = MockDisplay::new();
+        let mut sensor = MockSensor::new();
+        let mut app = AppLogic::new();
+        sensor.push_reading(Err(GonkError::Timeout));
+
+        let result = update_display_with_sensor(&mut display, &mut sensor, &mut app);
+
+        assert_eq!(result, Err(GonkError::Timeout));
+        assert!(display.calls.is_empty());
+        assert_eq!(app.average_temperature(), None);
+    }
+
+    #[test]
+    fn update_model_with_sensor_stores_measured_quantities() {
+        let mut model = Model::new();
+        let mut sensor = MockSensor::new();
+        sensor.push_reading(Ok(Reading {
+            temperature: Some(21.0),
+            pressure: Some(101325.0),
+            ..Reading::new(Instant::from_millis(10))
+        }));
+
+        let reading =
+            update_model_with_sensor(&mut model, &mut sensor, &Offsets::default()).unwrap();
+
+        assert_eq!(reading.timestamp.as_millis(), 10);
+        assert_eq!(model.temperature, Some(21.0));
+        assert_eq!(model.pressure, Some(101325.0));
+        assert_eq!(model.humidity, None);
+        assert_eq!(model.last_reading, Some(Instant::from_millis(10)));
+    }
+
+    #[test]
+    fn update_model_with_sensor_keeps_last_good_values_on_error() {
+        let mut model = Model::new();
+        let mut sensor = MockSensor::new();
+        sensor.push_temperature(21.0);
+        sensor.push_reading(Err(GonkError::Timeout));
+
+        update_model_with_sensor(&mut model, &mut sensor, &Offsets::default()).unwrap();
+        let result = update_model_with_sensor(&mut model, &mut sensor, &Offsets::default());
+
+        assert_eq!(result, Err(GonkError::Timeout));
+        assert_eq!(model.temperature, Some(21.0));
+        assert_eq!(model.counters.sensor_read_errors, 1);
+    }
+
+    #[test]
+    fn update_model_with_sensor_applies_offsets() {
+        let mut model = Model::new();
+        let mut sensor = MockSensor::new();
+        sensor.push_temperature(21.0);
+        let offsets = Offsets {
+            temperature: -0.5,
+            ..Offsets::default()
+        };
+
+        let reading = update_model_with_sensor(&mut model, &mut sensor, &offsets).unwrap();
+
+        assert_eq!(reading.temperature, Some(20.5));
+        assert_eq!(model.temperature, Some(20.5));
+    }
+
+    #[test]
+    fn format_readings_for_logging() {
+        let mut model = Model::new();
+        assert_eq!(
+            format_readings(&model, Instant::from_secs(0)).as_str(),
+            "T=-- H=-- P=--"
+        );
+
+        model.record_reading(&Reading {
+            temperature: Some(21.456),
+            humidity: Some(40.0),
+            pressure: Some(101325.0),
+            ..Reading::new(Instant::from_secs(0))
+        });
+        assert_eq!(
+            format_readings(&model, Instant::from_secs(6)).as_str(),
+            "T=21.46 C H=40.00 % P=1013.25 hPa"
+        );
+        assert_eq!(
+            format_readings(&model, Instant::from_secs(180)).as_str(),
+            "T=-- H=-- P=-- stale (3 min)"
+        );
+    }
+
+    #[test]
+    fn update_display_with_model_draws_readings_screen() {
+        let mut display = MockDisplay::new();
+        let mut model = Model::new();
+        model.ip_address = heapless::String::try_from("192.168.1.20").unwrap();
+        model.record_reading(&Reading {
+            temperature: Some(21.456),
+            humidity: Some(40.0),
+            ..Reading::new(Instant::from_secs(0))
+        });
+
+        update_display_with_model(&mut display, &model, Instant::from_secs(6)).unwrap();
+
+        assert!(display.texts().eq([
+            "Gonk Sensor Readings",
+            "Temp: 21.46 C",
+            "Humidity: 40.00 %",
+            "IP: 192.168.1.20",
+            "Comfort: Comfortable",
+        ]));
+        assert!(display.calls.contains(&DisplayCall::Line(0, 15, 127, 15)));
+        assert_eq!(display.updates(), 1);
+    }
+
+    #[test]
+    fn update_display_with_model_uses_configured_units() {
+        let mut display = MockDisplay::new();
+        let mut model = Model::new();
+        model.units = TemperatureUnit::Fahrenheit;
+        model.record_reading(&Reading {
+            temperature: Some(20.0),
+            ..Reading::new(Instant::from_secs(0))
+        });
+
+        update_display_with_model(&mut display, &model, Instant::from_secs(0)).unwrap();
+
+        assert!(display.texts().any(|text| text == "Temp: 68.00 F"));
+    }
+
+    #[test]
+    fn update_display_with_model_uses_configured_thresholds() {
+        let mut display = MockDisplay::new();
+        let mut model = Model::new();
+        model.thresholds.comfortable = 21.0;
+        model.record_reading(&Reading {
+            temperature: Some(22.0),
+            ..Reading::new(Instant::from_secs(0))
+        });
+
+        update_display_with_model(&mut display, &model, Instant::from_secs(0)).unwrap();
+
+        assert!(display.texts().any(|text| text == "Comfort: Warm"));
+    }
+
+    #[test]
+    fn update_display_with_model_hides_missing_values() {
+        let mut display = MockDisplay::new();
+        let model = Model::new();
+
+        update_display_with_model(&mut display, &model, Instant::from_secs(0)).unwrap();
+
+        assert!(display.texts().any(|text| text == "Temp: --"));
+        assert!(display.texts().any(|text| text == "Humidity: --"));
+    }
+
+    #[test]
+    fn update_display_with_model_shows_stale_age() {
+        let mut display = MockDisplay::new();
+        let mut model = Model::new();
+        model.record_reading(&Reading {
+            temperature: Some(21.0),
+            ..Reading::new(Instant::from_secs(0))
+        });
+
+        update_display_with_model(&mut display, &model, Instant::from_secs(180)).unwrap();
+
+        assert!(display.texts().any(|text| text == "Temp: --"));
+        assert!(display.texts().any(|text| text == "Sensor: stale (3 min)"));
+    }
+
+    #[test]
+    fn update_display_with_model_reports_flush_errors() {
+        let mut display = MockDisplay::new();
+        display.update_error = Some(DisplayError::Flush.into());
+        let model = Model::new();
+
+        assert_eq!(
+            update_display_with_model(&mut display, &model, Instant::from_secs(0)),
+            Err(DisplayError::Flush.into())
+        );
+    }
+
+    #[test]
+    fn probe_finds_oled_on_either_address() {
+        assert_eq!(probe_ssd1306(&mut MockI2c::new(0x3C)), Some(0x3C));
+        assert_eq!(probe_ssd1306(&mut MockI2c::new(0x3D)), Some(0x3D));
+        assert_eq!(probe_ssd1306(&mut MockI2c::new(0x76)), None);
+    }
+
+    #[test]
+    fn configured_display_wins_over_probe() {
+        assert_eq!(
+            select_display(Some(DisplayType::EPaper), Some(0x3C)),
+            DisplayType::EPaper
+        );
+        assert_eq!(select_display(None, Some(0x3D)), DisplayType::SSD1306);
+        assert_eq!(select_display(None, None), DisplayType::EPaper);
+    }
+
+    #[test]
+    fn parses_display_names() {
+        assert_eq!(DisplayType::from_name("ePaper"), Some(DisplayType::EPaper));
+        assert_eq!(
+            DisplayType::from_name("ssd1306"),
+            Some(DisplayType::SSD1306)
+        );
+        assert_eq!(DisplayType::from_name("lcd"), None);
+    }
+}
@@ -0,0 +1,626 @@
+//! mDNS responder and DNS-SD advertisement, RFC 6762 and 6763
+//! (hardware-independent)
+//!
+//! The device answers for `gonk-<mac>.local` and advertises the dashboard as
+//! `_http._tcp` and the JSON API as `_gonk._tcp`, with TXT records giving the
+//! firmware version and the quantities measured. The host name is unique
+//! through the MAC address, so it is announced without probing first.
+//!
+//! Known answers listed in queries are not suppressed, the responses are
+//! small enough.
+
+use core::fmt::Write;
+
+use embassy_time::Duration;
+use heapless::{String, Vec};
+
+use crate::traits::Capabilities;
+
+pub const PORT: u16 = 5353;
+/// IPv4 multicast group of mDNS
+pub const GROUP: [u8; 4] = [224, 0, 0, 251];
+/// Largest packet handled
+pub const MAX_PACKET: usize = 1024;
+/// Unsolicited responses sent when the address is taken or changes
+pub const ANNOUNCEMENTS: u8 = 2;
+/// Time between two announcements
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+/// Longest time before a change of address is noticed when idle
+pub const ADDRESS_CHECK: Duration = Duration::from_secs(5);
+
+/// Name of the service types list
+const SERVICES: &str = "_services._dns-sd._udp.local";
+/// Longest name compared, longer ones are not ours
+const MAX_NAME: usize = 96;
+/// Compression pointers followed in one name, to stop loops
+const MAX_POINTERS: usize = 8;
+
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+/// Class bit of a record replacing the cached ones, or of a question asking
+/// for a unicast response
+const CLASS_TOP_BIT: u16 = 0x8000;
+
+/// Lifetime of the records that change with the address
+const HOST_TTL_S: u32 = 120;
+/// Lifetime of the other records
+const SERVICE_TTL_S: u32 = 4500;
+/// Lifetime given to resolvers that are not mDNS aware
+const LEGACY_TTL_S: u32 = 10;
+
+/// Service advertised, both are served on the HTTP port
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    /// Dashboard, for browsers
+    Http,
+    /// JSON API, for the tools finding every device
+    Gonk,
+}
+
+impl Service {
+    pub const ALL: [Self; 2] = [Self::Http, Self::Gonk];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Http => "_http._tcp.local",
+            Self::Gonk => "_gonk._tcp.local",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Record {
+    /// Address of the host name
+    Address,
+    /// Service type in the list of service types
+    Type(Service),
+    /// Instance of a service type
+    Pointer(Service),
+    /// Host and port of an instance
+    Server(Service),
+    /// Properties of an instance
+    Text(Service),
+}
+
+impl Record {
+    /// Whether the record is the only one of its name and type
+    fn is_unique(self) -> bool {
+        matches!(self, Self::Address | Self::Server(_) | Self::Text(_))
+    }
+}
+
+/// What the device advertises, besides its names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Host {
+    pub address: [u8; 4],
+    /// TCP port of the dashboard and the API
+    pub port: u16,
+    pub sensors: Capabilities,
+}
+
+/// Response written by the responder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reply {
+    pub len: usize,
+    /// Send to the querier rather than the multicast group
+    pub unicast: bool,
+}
+
+/// Answers the queries for the names of one device
+pub struct Responder {
+    /// Host name without the domain, also the instance name of the services
+    name: String<24>,
+    host: String<32>,
+}
+
+impl Responder {
+    pub fn new(mac: [u8; 6]) -> Self {
+        let mut name = String::new();
+        let _ = write!(name, "gonk-");
+        for byte in mac {
+            let _ = write!(name, "{:02x}", byte);
+        }
+        let mut host = String::new();
+        let _ = write!(host, "{}.local", name);
+        Self { name, host }
+    }
+
+    /// Fully qualified host name, e.g. "gonk-246f2801ab0c.local"
+    pub fn host_name(&self) -> &str {
+        &self.host
+    }
+
+    fn instance(&self, service: Service) -> String<64> {
+        let mut instance = String::new();
+        let _ = write!(instance, "{}.{}", self.name, service.name());
+        instance
+    }
+
+    /// Records answering a question for `name` of type `qtype`
+    fn answer(&self, name: &str, qtype: u16, answers: &mut Vec<Record, 8>) {
+        let wanted = |rtype: u16| qtype == rtype || qtype == TYPE_ANY;
+        let mut add = |record: Record| {
+            if !answers.contains(&record) {
+                let _ = answers.push(record);
+            }
+        };
+
+        if name == self.host.as_str() && wanted(TYPE_A) {
+            add(Record::Address);
+        }
+        for service in Service::ALL {
+            if name == SERVICES && wanted(TYPE_PTR) {
+                add(Record::Type(service));
+            }
+            if name == service.name() && wanted(TYPE_PTR) {
+                add(Record::Pointer(service));
+            }
+            if name == self.instance(service).as_str() {
+                if wanted(TYPE_SRV) {
+                    add(Record::Server(service));
+                }
+                if wanted(TYPE_TXT) {
+                    add(Record::Text(service));
+                }
+            }
+        }
+    }
+
+    /// Answer a query received from `legacy` resolvers, those not sending
+    /// from the mDNS port, or from mDNS queriers
+    ///
+    /// `None` when the packet is not a query or asks for none of our names.
+    pub fn respond(
+        &self,
+        query: &[u8],
+        legacy: bool,
+        host: &Host,
+        out: &mut [u8],
+    ) -> Option<Reply> {
+        let header = query.get(..HEADER_LEN)?;
+        let flags = u16::from_be_bytes([header[2], header[3]]);
+        let questions = u16::from_be_bytes([header[4], header[5]]);
+        // A response, or an opcode other than a standard query
+        if flags & 0xF800 != 0 {
+            return None;
+        }
+
+        let mut answers = Vec::new();
+        let mut unicast = legacy;
+        let mut pos = HEADER_LEN;
+        for _ in 0..questions {
+            let (name, end) = read_name(query, pos)?;
+            let question = query.get(end..end + 4)?;
+            let qtype = u16::from_be_bytes([question[0], question[1]]);
+            let qclass = u16::from_be_bytes([question[2], question[3]]);
+            pos = end + 4;
+
+            if matches!(qclass & !CLASS_TOP_BIT, CLASS_IN | CLASS_ANY) {
+                let before = answers.len();
+                self.answer(&name, qtype, &mut answers);
+                unicast |= answers.len() > before && qclass & CLASS_TOP_BIT != 0;
+            }
+        }
+        if answers.is_empty() {
+            return None;
+        }
+
+        // Records the querier will need next
+        let mut additional = Vec::<Record, 8>::new();
+        for record in &answers {
+            let needed: &[Record] = match *record {
+                Record::Pointer(service) => &[
+                    Record::Server(service),
+                    Record::Text(service),
+                    Record::Address,
+                ],
+                Record::Server(_) => &[Record::Address],
+                _ => &[],
+            };
+            for record in needed {
+                if !answers.contains(record) && !additional.contains(record) {
+                    let _ = additional.push(*record);
+                }
+            }
+        }
+
+        let mut writer = Writer { buf: out, pos: 0 };
+        if legacy {
+            // Same ID and questions, the pointers in them stay valid
+            writer.bytes(&query[..2])?;
+            writer.u16(0x8400)?;
+            writer.u16(questions)?;
+        } else {
+            writer.bytes(&[0, 0, 0x84, 0, 0, 0])?;
+        }
+        writer.u16(answers.len() as u16)?;
+        writer.u16(0)?;
+        writer.u16(additional.len() as u16)?;
+        if legacy {
+            writer.bytes(&query[HEADER_LEN..pos])?;
+        }
+        for record in answers.iter().chain(&additional) {
+            self.write_record(&mut writer, *record, host, legacy)?;
+        }
+        Some(Reply {
+            len: writer.pos,
+            unicast,
+        })
+    }
+
+    /// Unsolicited response with every record, sent when the address is
+    /// taken, returns its length
+    pub fn announce(&self, host: &Host, out: &mut [u8]) -> Option<usize> {
+        let mut records = Vec::<Record, 8>::new();
+        let _ = records.push(Record::Address);
+        for service in Service::ALL {
+            let _ = records.push(Record::Pointer(service));
+            let _ = records.push(Record::Server(service));
+            let _ = records.push(Record::Text(service));
+        }
+
+        let mut writer = Writer { buf: out, pos: 0 };
+        writer.bytes(&[0, 0, 0x84, 0, 0, 0])?;
+        writer.u16(records.len() as u16)?;
+        writer.bytes(&[0, 0, 0, 0])?;
+        for record in records {
+            self.write_record(&mut writer, record, host, false)?;
+        }
+        Some(writer.pos)
+    }
+
+    fn write_record(
+        &self,
+        writer: &mut Writer,
+        record: Record,
+        host: &Host,
+        legacy: bool,
+    ) -> Option<()> {
+        let instance;
+        let (name, rtype, ttl) = match record {
+            Record::Address => (self.host.as_str(), TYPE_A, HOST_TTL_S),
+            Record::Type(_) => (SERVICES, TYPE_PTR, SERVICE_TTL_S),
+            Record::Pointer(service) => (service.name(), TYPE_PTR, SERVICE_TTL_S),
+            Record::Server(service) => {
+                instance = self.instance(service);
+                (instance.as_str(), TYPE_SRV, HOST_TTL_S)
+            }
+            Record::Text(service) => {
+                instance = self.instance(service);
+                (instance.as_str(), TYPE_TXT, SERVICE_TTL_S)
+            }
+        };
+        let (class, ttl) = match legacy {
+            true => (CLASS_IN, LEGACY_TTL_S),
+            false if record.is_unique() => (CLASS_IN | CLASS_TOP_BIT, ttl),
+            false => (CLASS_IN, ttl),
+        };
+        writer.name(name)?;
+        writer.u16(rtype)?;
+        writer.u16(class)?;
+        writer.bytes(&ttl.to_be_bytes())?;
+
+        // Data length, known once the data is written
+        let len_at = writer.pos;
+        writer.u16(0)?;
+        match record {
+            Record::Address => writer.bytes(&host.address)?,
+            Record::Type(service) => writer.name(service.name())?,
+            Record::Pointer(service) => writer.name(&self.instance(service))?,
+            Record::Server(_) => {
+                // Priority and weight
+                writer.bytes(&[0, 0, 0, 0])?;
+                writer.u16(host.port)?;
+                writer.name(&self.host)?;
+            }
+            Record::Text(service) => {
+                writer.text(format_args!("version={}", env!("CARGO_PKG_VERSION")))?;
+                writer.text(format_args!("sensors={}", sensor_list(host.sensors)))?;
+                match service {
+                    Service::Http => writer.text(format_args!("path=/"))?,
+                    Service::Gonk => writer.text(format_args!("api=/api/v1"))?,
+                }
+            }
+        }
+        let len = (writer.pos - len_at - 2) as u16;
+        writer.buf[len_at..len_at + 2].copy_from_slice(&len.to_be_bytes());
+        Some(())
+    }
+}
+
+/// Quantities measured, e.g. "temperature,humidity,pressure"
+fn sensor_list(sensors: Capabilities) -> String<64> {
+    let mut list = String::new();
+    for (measured, name) in [
+        (sensors.temperature, "temperature"),
+        (sensors.humidity, "humidity"),
+        (sensors.pressure, "pressure"),
+        (sensors.gas_resistance, "gas_resistance"),
+        (sensors.co2, "co2"),
+    ] {
+        if measured {
+            if !list.is_empty() {
+                let _ = list.push(',');
+            }
+            let _ = list.push_str(name);
+        }
+    }
+    list
+}
+
+/// Name at `pos` in lowercase with dots, and the position after it
+///
+/// Names too long to be ours come out empty.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String<MAX_NAME>, usize)> {
+    let mut name = String::<MAX_NAME>::new();
+    let mut end = None;
+    let mut pointers = 0;
+    let mut fits = true;
+    loop {
+        let len = *packet.get(pos)? as usize;
+        match len {
+            0 => {
+                end.get_or_insert(pos + 1);
+                break;
+            }
+            // Compression pointer to an earlier name
+            0xC0.. => {
+                let low = *packet.get(pos + 1)? as usize;
+                end.get_or_insert(pos + 2);
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                pos = (len & 0x3F) << 8 | low;
+            }
+            1..=63 => {
+                let label = packet.get(pos + 1..pos + 1 + len)?;
+                if !name.is_empty() {
+                    fits &= name.push('.').is_ok();
+                }
+                for &byte in label {
+                    fits &= name.push(byte.to_ascii_lowercase() as char).is_ok();
+                }
+                pos += 1 + len;
+            }
+            _ => return None,
+        }
+    }
+    if !fits {
+        name.clear();
+    }
+    Some((name, end?))
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        self.buf
+            .get_mut(self.pos..self.pos + bytes.len())?
+            .copy_from_slice(bytes);
+        self.pos += bytes.len();
+        Some(())
+    }
+
+    fn u16(&mut self, value: u16) -> Option<()> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// Name without compression
+    fn name(&mut self, name: &str) -> Option<()> {
+        for label in name.split('.') {
+            self.bytes(&[u8::try_from(label.len()).ok().filter(|&len| len <= 63)?])?;
+            self.bytes(label.as_bytes())?;
+        }
+        self.bytes(&[0])
+    }
+
+    /// Character string of a TXT record
+    fn text(&mut self, text: core::fmt::Arguments) -> Option<()> {
+        let mut string = String::<255>::new();
+        string.write_fmt(text).ok()?;
+        self.bytes(&[string.len() as u8])?;
+        self.bytes(string.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x24, 0x6F, 0x28, 0x01, 0xAB, 0x0C];
+    const HOST: Host = Host {
+        address: [192, 168, 1, 20],
+        port: 80,
+        sensors: Capabilities {
+            temperature: true,
+            humidity: true,
+            pressure: true,
+            gas_resistance: false,
+            co2: false,
+        },
+    };
+
+    /// Query with one question per name and type
+    fn query(id: u16, questions: &[(&str, u16, u16)]) -> Vec<u8, 256> {
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&id.to_be_bytes()).unwrap();
+        packet.extend_from_slice(&[0, 0]).unwrap();
+        packet
+            .extend_from_slice(&(questions.len() as u16).to_be_bytes())
+            .unwrap();
+        packet.extend_from_slice(&[0; 6]).unwrap();
+        for (name, qtype, qclass) in questions {
+            let mut buf = [0u8; 128];
+            let mut writer = Writer {
+                buf: &mut buf,
+                pos: 0,
+            };
+            writer.name(name).unwrap();
+            writer.u16(*qtype).unwrap();
+            writer.u16(*qclass).unwrap();
+            let len = writer.pos;
+            packet.extend_from_slice(&buf[..len]).unwrap();
+        }
+        packet
+    }
+
+    /// Name, type and data of the records of a response, in order
+    fn records(packet: &[u8]) -> Vec<(String<MAX_NAME>, u16, u16, u32, Vec<u8, 128>), 12> {
+        let count = |at: usize| u16::from_be_bytes([packet[at], packet[at + 1]]);
+        let mut pos = HEADER_LEN;
+        for _ in 0..count(4) {
+            pos = read_name(packet, pos).unwrap().1 + 4;
+        }
+        let mut records = Vec::new();
+        for _ in 0..count(6) + count(8) + count(10) {
+            let (name, end) = read_name(packet, pos).unwrap();
+            let field = |at: usize| u16::from_be_bytes([packet[end + at], packet[end + at + 1]]);
+            let ttl = (field(4) as u32) << 16 | field(6) as u32;
+            let len = field(8) as usize;
+            let data = Vec::from_slice(&packet[end + 10..end + 10 + len]).unwrap();
+            records.push((name, field(0), field(2), ttl, data)).unwrap();
+            pos = end + 10 + len;
+        }
+        assert_eq!(pos, packet.len());
+        records
+    }
+
+    #[test]
+    fn answers_host_name() {
+        let responder = Responder::new(MAC);
+        assert_eq!(responder.host_name(), "gonk-246f2801ab0c.local");
+
+        let mut out = [0u8; MAX_PACKET];
+        let packet = query(7, &[("Gonk-246F2801AB0C.local", TYPE_A, CLASS_IN)]);
+        let reply = responder.respond(&packet, false, &HOST, &mut out).unwrap();
+
+        assert!(!reply.unicast);
+        assert_eq!(out[..12], [0, 0, 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+        let records = records(&out[..reply.len]);
+        assert_eq!(records.len(), 1);
+        let (name, rtype, class, ttl, data) = &records[0];
+        assert_eq!(name, "gonk-246f2801ab0c.local");
+        assert_eq!((*rtype, *class, *ttl), (TYPE_A, 0x8001, HOST_TTL_S));
+        assert_eq!(data[..], [192, 168, 1, 20]);
+
+        // Other hosts and types are left to others
+        let other = query(7, &[("printer.local", TYPE_A, CLASS_IN)]);
+        assert_eq!(responder.respond(&other, false, &HOST, &mut out), None);
+        let ipv6 = query(7, &[("gonk-246f2801ab0c.local", 28, CLASS_IN)]);
+        assert_eq!(responder.respond(&ipv6, false, &HOST, &mut out), None);
+    }
+
+    #[test]
+    fn advertises_services() {
+        let responder = Responder::new(MAC);
+        let mut out = [0u8; MAX_PACKET];
+
+        let packet = query(0, &[("_services._dns-sd._udp.local", TYPE_PTR, CLASS_IN)]);
+        let reply = responder.respond(&packet, false, &HOST, &mut out).unwrap();
+        let types = records(&out[..reply.len]);
+        assert_eq!(types.len(), 2);
+        assert_eq!(types[1].4[..], *b"\x05_gonk\x04_tcp\x05local\x00");
+
+        // The querier asked for a unicast response
+        let packet = query(0, &[("_gonk._tcp.local", TYPE_PTR, CLASS_IN | 0x8000)]);
+        let reply = responder.respond(&packet, false, &HOST, &mut out).unwrap();
+        assert!(reply.unicast);
+        let records = records(&out[..reply.len]);
+        let kinds: Vec<(u16, u16), 4> = records.iter().map(|r| (r.1, r.2)).collect();
+        assert_eq!(
+            kinds,
+            [
+                (TYPE_PTR, 1),
+                (TYPE_SRV, 0x8001),
+                (TYPE_TXT, 0x8001),
+                (TYPE_A, 0x8001)
+            ]
+        );
+        assert_eq!(
+            records[0].4[..],
+            *b"\x11gonk-246f2801ab0c\x05_gonk\x04_tcp\x05local\x00"
+        );
+        assert_eq!(records[1].0, "gonk-246f2801ab0c._gonk._tcp.local");
+        assert_eq!(
+            records[1].4[..],
+            *b"\x00\x00\x00\x00\x00\x50\x11gonk-246f2801ab0c\x05local\x00"
+        );
+        assert_eq!(
+            records[2].4[..],
+            *b"\x0dversion=0.1.0\x25sensors=temperature,humidity,pressure\x0bapi=/api/v1"
+        );
+    }
+
+    #[test]
+    fn follows_compressed_names() {
+        let responder = Responder::new(MAC);
+        let mut out = [0u8; MAX_PACKET];
+
+        // Second question "_tcp.local" then "_http" pointing into the first
+        let mut packet = query(0, &[("_gonk._tcp.local", TYPE_TXT, CLASS_IN)]);
+        packet[5] = 2;
+        packet
+            .extend_from_slice(&[5, b'_', b'h', b't', b't', b'p', 0xC0, 18, 0, 12, 0, 1])
+            .unwrap();
+        let reply = responder.respond(&packet, false, &HOST, &mut out).unwrap();
+        let records = records(&out[..reply.len]);
+        assert_eq!(records[0].0, "_http._tcp.local");
+        assert_eq!(records.len(), 4);
+
+        // A pointer loop
+        let mut looping = query(0, &[]);
+        looping[5] = 1;
+        looping.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1]).unwrap();
+        assert_eq!(responder.respond(&looping, false, &HOST, &mut out), None);
+    }
+
+    #[test]
+    fn answers_legacy_resolvers() {
+        let responder = Responder::new(MAC);
+        let mut out = [0u8; MAX_PACKET];
+        let packet = query(0x1234, &[("gonk-246f2801ab0c.local", TYPE_ANY, CLASS_IN)]);
+        let reply = responder.respond(&packet, true, &HOST, &mut out).unwrap();
+
+        assert!(reply.unicast);
+        assert_eq!(out[..6], [0x12, 0x34, 0x84, 0, 0, 1]);
+        assert_eq!(out[12..packet.len()], packet[12..]);
+        let records = records(&out[..reply.len]);
+        assert_eq!(records[0].2, CLASS_IN);
+        assert_eq!(records[0].3, LEGACY_TTL_S);
+    }
+
+    #[test]
+    fn announces_every_record() {
+        let responder = Responder::new(MAC);
+        let mut out = [0u8; MAX_PACKET];
+        let len = responder.announce(&HOST, &mut out).unwrap();
+
+        let records = records(&out[..len]);
+        assert_eq!(records.len(), 7);
+        assert_eq!(records[0].1, TYPE_A);
+        assert_eq!(records[1].0, "_http._tcp.local");
+        assert_eq!(
+            records[3].4[..],
+            *b"\x0dversion=0.1.0\x25sensors=temperature,humidity,pressure\x06path=/"
+        );
+        assert_eq!(responder.announce(&HOST, &mut out[..100]), None);
+
+        // Responses from other hosts are not answered
+        out[2] = 0x84;
+        assert_eq!(
+            responder.respond(&out[..len], false, &HOST, &mut [0; 512]),
+            None
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! On-device settings menu (hardware-independent)
+//!
+//! A green click selects the next item, a blue click changes its value and a
+//! green long press leaves the menu, as does a blue click on "Done".
+
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::error::GonkError;
+use crate::input::ButtonEvent;
+use crate::screen::Button;
+use crate::settings::{
+    CONTRAST_LEVELS, REFRESH_INTERVALS_S, Rotation, SCREEN_TIMEOUTS_S, Settings, TemperatureUnit,
+    format_seconds, next_option,
+};
+use crate::traits::Display;
+
+/// Number of item lines that fit below the title
+const VISIBLE_ITEMS: usize = 4;
+const LINE_HEIGHT: i32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Item {
+    Units,
+    RefreshInterval,
+    Contrast,
+    ScreenTimeout,
+    Rotation,
+    Done,
+}
+
+impl Item {
+    pub const ALL: [Item; 6] = [
+        Item::Units,
+        Item::RefreshInterval,
+        Item::Contrast,
+        Item::ScreenTimeout,
+        Item::Rotation,
+        Item::Done,
+    ];
+
+    /// Menu line of the item showing its value in `settings`
+    pub fn label(self, settings: &Settings) -> String<24> {
+        let mut buffer = String::new();
+        let _ = match self {
+            Item::Units => write!(buffer, "Units:{}", settings.units.suffix()),
+            Item::RefreshInterval => write!(
+                buffer,
+                "Refresh: {}",
+                format_seconds(settings.refresh_interval_s)
+            ),
+            Item::Contrast => write!(
+                buffer,
+                "Contrast: {}%",
+                settings.contrast as u32 * 100 / 255
+            ),
+            Item::ScreenTimeout => match settings.screen_timeout_s {
+                0 => write!(buffer, "Screen off: never"),
+                secs => write!(buffer, "Screen off: {}", format_seconds(secs)),
+            },
+            Item::Rotation => match settings.rotation {
+                Rotation::Normal => write!(buffer, "Rotation: 0"),
+                Rotation::Flipped => write!(buffer, "Rotation: 180"),
+            },
+            Item::Done => write!(buffer, "Done"),
+        };
+        buffer
+    }
+
+    /// Change the value of the item to the next option
+    fn advance(self, settings: &mut Settings) {
+        match self {
+            Item::Units => {
+                settings.units = match settings.units {
+                    TemperatureUnit::Celsius => TemperatureUnit::Fahrenheit,
+                    TemperatureUnit::Fahrenheit => TemperatureUnit::Celsius,
+                }
+            }
+            Item::RefreshInterval => {
+                settings.refresh_interval_s =
+                    next_option(&REFRESH_INTERVALS_S, settings.refresh_interval_s)
+            }
+            Item::Contrast => settings.contrast = next_option(&CONTRAST_LEVELS, settings.contrast),
+            Item::ScreenTimeout => {
+                settings.screen_timeout_s =
+                    next_option(&SCREEN_TIMEOUTS_S, settings.screen_timeout_s)
+            }
+            Item::Rotation => {
+                settings.rotation = match settings.rotation {
+                    Rotation::Normal => Rotation::Flipped,
+                    Rotation::Flipped => Rotation::Normal,
+                }
+            }
+            Item::Done => {}
+        }
+    }
+}
+
+/// Result of a button event in the menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuAction {
+    /// The event did nothing
+    None,
+    Redraw,
+    /// The menu is closed, with the edited settings
+    Close(Settings),
+}
+
+/// Settings being edited
+pub struct Menu {
+    settings: Settings,
+    selected: usize,
+}
+
+impl Menu {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            settings,
+            selected: 0,
+        }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn selected(&self) -> Item {
+        Item::ALL[self.selected]
+    }
+
+    pub fn handle(&mut self, event: ButtonEvent<Button>) -> MenuAction {
+        match event {
+            ButtonEvent::Click(Button::Green) => {
+                self.selected = (self.selected + 1) % Item::ALL.len();
+                MenuAction::Redraw
+            }
+            ButtonEvent::Click(Button::Blue) | ButtonEvent::Repeat(Button::Blue) => {
+                match self.selected() {
+                    Item::Done => MenuAction::Close(self.settings),
+                    item => {
+                        item.advance(&mut self.settings);
+                        MenuAction::Redraw
+                    }
+                }
+            }
+            ButtonEvent::LongPress(Button::Green) => MenuAction::Close(self.settings),
+            _ => MenuAction::None,
+        }
+    }
+
+    /// Draw the menu, scrolled to keep the selected item visible
+    pub fn render<D: Display + ?Sized>(&self, display: &mut D) -> Result<(), GonkError> {
+        display.clear()?;
+        display.draw_text("Settings", 0, 0)?;
+        display.draw_line(
+            0,
+            LINE_HEIGHT + LINE_HEIGHT / 2,
+            127,
+            LINE_HEIGHT + LINE_HEIGHT / 2,
+        )?;
+
+        let first = self.selected.saturating_sub(VISIBLE_ITEMS - 1);
+        let mut y = 2 * LINE_HEIGHT;
+        for (index, item) in Item::ALL.iter().enumerate().skip(first).take(VISIBLE_ITEMS) {
+            let mut line = String::<32>::new();
+            let marker = if index == self.selected { '>' } else { ' ' };
+            let _ = write!(line, "{} {}", marker, item.label(&self.settings));
+            display.draw_text(&line, 0, y)?;
+            y += LINE_HEIGHT;
+        }
+
+        display.update()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockDisplay;
+
+    fn green() -> ButtonEvent<Button> {
+        ButtonEvent::Click(Button::Green)
+    }
+
+    fn blue() -> ButtonEvent<Button> {
+        ButtonEvent::Click(Button::Blue)
+    }
+
+    #[test]
+    fn edits_selected_item() {
+        let mut menu = Menu::new(Settings::default());
+
+        assert_eq!(menu.handle(blue()), MenuAction::Redraw);
+        assert_eq!(menu.settings().units, TemperatureUnit::Fahrenheit);
+
+        menu.handle(green());
+        menu.handle(blue());
+        assert_eq!(menu.settings().refresh_interval_s, 10);
+    }
+
+    #[test]
+    fn steps_value_from_api_to_next_option() {
+        let mut menu = Menu::new(Settings {
+            refresh_interval_s: 45,
+            ..Settings::default()
+        });
+
+        menu.handle(green());
+        menu.handle(blue());
+        assert_eq!(menu.settings().refresh_interval_s, 60);
+    }
+
+    #[test]
+    fn done_closes_with_settings() {
+        let mut menu = Menu::new(Settings::default());
+        for _ in 0..4 {
+            menu.handle(green());
+        }
+        menu.handle(blue());
+        menu.handle(green());
+
+        assert_eq!(menu.selected(), Item::Done);
+        let expected = Settings {
+            rotation: Rotation::Flipped,
+            ..Settings::default()
+        };
+        assert_eq!(menu.handle(blue()), MenuAction::Close(expected));
+    }
+
+    #[test]
+    fn long_press_closes() {
+        let mut menu = Menu::new(Settings::default());
+
+        assert_eq!(
+            menu.handle(ButtonEvent::LongPress(Button::Green)),
+            MenuAction::Close(Settings::default())
+        );
+        assert_eq!(
+            menu.handle(ButtonEvent::DoubleClick(Button::Blue)),
+            MenuAction::None
+        );
+    }
+
+    #[test]
+    fn labels_show_values() {
+        let settings = Settings {
+            contrast: 255,
+            screen_timeout_s: 300,
+            ..Settings::default()
+        };
+
+        assert_eq!(Item::Units.label(&settings).as_str(), "Units: C");
+        assert_eq!(
+            Item::RefreshInterval.label(&settings).as_str(),
+            "Refresh: 6 s"
+        );
+        assert_eq!(Item::Contrast.label(&settings).as_str(), "Contrast: 100%");
+        assert_eq!(
+            Item::ScreenTimeout.label(&settings).as_str(),
+            "Screen off: 5 min"
+        );
+    }
+
+    #[test]
+    fn scrolls_to_selected_item() {
+        let mut display = MockDisplay::new();
+        let mut menu = Menu::new(Settings::default());
+        menu.render(&mut display).unwrap();
+        assert!(display.texts().eq([
+            "Settings",
+            "> Units: C",
+            "  Refresh: 6 s",
+            "  Contrast: 100%",
+            "  Screen off: never",
+        ]));
+
+        for _ in 0..5 {
+            menu.handle(green());
+        }
+        menu.render(&mut display).unwrap();
+        assert!(display.texts().eq([
+            "Settings",
+            "  Contrast: 100%",
+            "  Screen off: never",
+            "  Rotation: 0",
+            "> Done",
+        ]));
+    }
+}
@@ -0,0 +1,163 @@
+//! Prometheus text exposition of the model (hardware-independent)
+//!
+//! Served on `GET /metrics` for scraping. Gauges without a current value,
+//! e.g. before the first reading or once it is stale, are left out rather
+//! than reported as NaN.
+
+use core::fmt::{self, Write};
+
+use embassy_time::Instant;
+
+use crate::model::Model;
+
+/// Content type of exposition format 0.0.4
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Gauge,
+    Counter,
+}
+
+/// Write the help and type lines of a metric, then its sample if present
+fn write_metric<W: Write>(
+    out: &mut W,
+    name: &str,
+    help: &str,
+    kind: Kind,
+    value: Option<impl fmt::Display>,
+) -> fmt::Result {
+    let kind = match kind {
+        Kind::Gauge => "gauge",
+        Kind::Counter => "counter",
+    };
+    write!(out, "# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind)?;
+    match value {
+        Some(value) => writeln!(out, "{} {}", name, value),
+        None => Ok(()),
+    }
+}
+
+pub fn write_metrics<W: Write>(out: &mut W, model: &Model, now: Instant) -> fmt::Result {
+    use Kind::{Counter, Gauge};
+
+    write_metric(
+        out,
+        "gonk_temperature_celsius",
+        "Indoor temperature.",
+        Gauge,
+        model.current(model.temperature, now),
+    )?;
+    write_metric(
+        out,
+        "gonk_humidity_percent",
+        "Indoor relative humidity.",
+        Gauge,
+        model.current(model.humidity, now),
+    )?;
+    write_metric(
+        out,
+        "gonk_pressure_pascals",
+        "Indoor air pressure.",
+        Gauge,
+        model.current(model.pressure, now),
+    )?;
+    write_metric(
+        out,
+        "gonk_supply_voltage_volts",
+        "Supply or battery voltage.",
+        Gauge,
+        model.supply_voltage,
+    )?;
+
+    let counters = &model.counters;
+    write_metric(
+        out,
+        "gonk_sensor_read_errors_total",
+        "Failed BME280 reads.",
+        Counter,
+        Some(counters.sensor_read_errors),
+    )?;
+    write_metric(
+        out,
+        "gonk_wifi_reconnects_total",
+        "WiFi link losses followed by a reconnection.",
+        Counter,
+        Some(counters.wifi_reconnects),
+    )?;
+    write_metric(
+        out,
+        "gonk_display_failures_total",
+        "Failed display updates.",
+        Counter,
+        Some(counters.display_failures),
+    )?;
+
+    write_metric(
+        out,
+        "gonk_uptime_seconds",
+        "Time since boot.",
+        Gauge,
+        Some(now.as_secs()),
+    )?;
+    write_metric(
+        out,
+        "gonk_free_heap_bytes",
+        "Free heap memory.",
+        Gauge,
+        model.free_heap,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::Reading;
+    use heapless::String;
+
+    #[test]
+    fn writes_exposition_format() {
+        let mut model = Model::new();
+        model.record_reading(&Reading {
+            temperature: Some(21.5),
+            humidity: Some(40.25),
+            pressure: Some(101325.0),
+            ..Reading::new(Instant::from_secs(10))
+        });
+        model.supply_voltage = Some(3.75);
+        model.counters.sensor_read_errors = 2;
+        model.counters.wifi_reconnects = 1;
+        model.free_heap = Some(51_200);
+
+        let mut text = String::<2048>::new();
+        write_metrics(&mut text, &model, Instant::from_secs(12)).unwrap();
+        assert!(text.starts_with(
+            "# HELP gonk_temperature_celsius Indoor temperature.\n\
+# TYPE gonk_temperature_celsius gauge\n\
+gonk_temperature_celsius 21.5\n"
+        ));
+        for sample in [
+            "\ngonk_humidity_percent 40.25\n",
+            "\ngonk_pressure_pascals 101325\n",
+            "\ngonk_supply_voltage_volts 3.75\n",
+            "\n# TYPE gonk_sensor_read_errors_total counter\ngonk_sensor_read_errors_total 2\n",
+            "\ngonk_wifi_reconnects_total 1\n",
+            "\ngonk_display_failures_total 0\n",
+            "\ngonk_uptime_seconds 12\n",
+        ] {
+            assert!(text.contains(sample), "{}", sample);
+        }
+        assert!(text.ends_with("gonk_free_heap_bytes 51200\n"));
+    }
+
+    #[test]
+    fn leaves_out_missing_values() {
+        let mut text = String::<2048>::new();
+        write_metrics(&mut text, &Model::new(), Instant::from_secs(5)).unwrap();
+
+        assert!(text.contains("# TYPE gonk_temperature_celsius gauge\n# HELP"));
+        assert!(!text.contains("gonk_supply_voltage_volts 0"));
+        assert!(text.contains("\ngonk_sensor_read_errors_total 0\n"));
+        assert!(text.ends_with("# TYPE gonk_free_heap_bytes gauge\n"));
+    }
+}
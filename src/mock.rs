@@ -0,0 +1,494 @@
+//! Mock hardware recording every call, for host-side tests
+
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+
+use embassy_time::Instant;
+use heapless::{Deque, String, Vec};
+
+use crate::error::{DisplayError, GonkError, NetworkError, SensorError, StorageError, UpdateError};
+use crate::settings::Rotation;
+use crate::traits::{
+    Capabilities, Display, EnvironmentalSensor, FirmwareSlots, HttpClient, I2cBus, Reading, Storage,
+};
+
+/// Environmental sensor returning queued readings
+#[derive(Default)]
+pub struct MockSensor {
+    readings: Deque<Result<Reading, GonkError>, 16>,
+    pub capabilities: Capabilities,
+    pub init_calls: u32,
+    pub read_calls: u32,
+}
+
+impl MockSensor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue the result of a future `read()` call
+    pub fn push_reading(&mut self, reading: Result<Reading, GonkError>) {
+        self.readings
+            .push_back(reading)
+            .expect("too many queued readings");
+    }
+
+    /// Queue a temperature-only reading
+    pub fn push_temperature(&mut self, temperature: f32) {
+        self.push_reading(Ok(Reading {
+            temperature: Some(temperature),
+            ..Reading::new(Instant::from_ticks(0))
+        }));
+    }
+}
+
+impl EnvironmentalSensor for MockSensor {
+    fn init(&mut self) -> Result<(), GonkError> {
+        self.init_calls += 1;
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    fn read(&mut self) -> Result<Reading, GonkError> {
+        self.read_calls += 1;
+        self.readings
+            .pop_front()
+            .unwrap_or(Err(SensorError::NoData.into()))
+    }
+}
+
+/// One call made on a `MockDisplay`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisplayCall {
+    Init,
+    Clear,
+    Text(String<32>, i32, i32),
+    Line(i32, i32, i32, i32),
+    Update,
+    Contrast(u8),
+    Rotation(Rotation),
+    Power(bool),
+}
+
+/// Display recording the calls made on it
+#[derive(Default)]
+pub struct MockDisplay {
+    pub calls: Vec<DisplayCall, 64>,
+    /// Fail every `update()` with this error
+    pub update_error: Option<GonkError>,
+}
+
+impl MockDisplay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Text drawn since the last `clear()`
+    pub fn texts(&self) -> impl Iterator<Item = &str> {
+        let start = self
+            .calls
+            .iter()
+            .rposition(|call| *call == DisplayCall::Clear)
+            .map_or(0, |i| i + 1);
+
+        self.calls[start..].iter().filter_map(|call| match call {
+            DisplayCall::Text(text, _, _) => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Number of times the display was flushed
+    pub fn updates(&self) -> usize {
+        self.calls
+            .iter()
+            .filter(|call| **call == DisplayCall::Update)
+            .count()
+    }
+
+    fn record(&mut self, call: DisplayCall) {
+        self.calls.push(call).expect("too many display calls");
+    }
+}
+
+impl Display for MockDisplay {
+    fn init(&mut self) -> Result<(), GonkError> {
+        self.record(DisplayCall::Init);
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), GonkError> {
+        self.record(DisplayCall::Clear);
+        Ok(())
+    }
+
+    fn draw_text(&mut self, text: &str, x: i32, y: i32) -> Result<(), GonkError> {
+        let text = String::try_from(text).map_err(|_| DisplayError::Draw)?;
+        self.record(DisplayCall::Text(text, x, y));
+        Ok(())
+    }
+
+    fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32) -> Result<(), GonkError> {
+        self.record(DisplayCall::Line(x0, y0, x1, y1));
+        Ok(())
+    }
+
+    fn update(&mut self) -> Result<(), GonkError> {
+        self.record(DisplayCall::Update);
+        match self.update_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn set_contrast(&mut self, contrast: u8) -> Result<(), GonkError> {
+        self.record(DisplayCall::Contrast(contrast));
+        Ok(())
+    }
+
+    fn set_rotation(&mut self, rotation: Rotation) -> Result<(), GonkError> {
+        self.record(DisplayCall::Rotation(rotation));
+        Ok(())
+    }
+
+    fn set_power(&mut self, on: bool) -> Result<(), GonkError> {
+        self.record(DisplayCall::Power(on));
+        Ok(())
+    }
+}
+
+/// I2C bus with a single device exposing a 256 byte register file
+pub struct MockI2c {
+    pub address: u8,
+    pub registers: [u8; 256],
+    /// Every `write()` made on the bus, address and payload
+    pub writes: Vec<(u8, Vec<u8, 8>), 32>,
+}
+
+impl MockI2c {
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            registers: [0; 256],
+            writes: Vec::new(),
+        }
+    }
+
+    /// Load a register dump starting at `register`
+    pub fn load(&mut self, register: u8, values: &[u8]) {
+        let start = register as usize;
+        self.registers[start..start + values.len()].copy_from_slice(values);
+    }
+}
+
+impl I2cBus for MockI2c {
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), GonkError> {
+        if addr != self.address {
+            return Err(GonkError::NACK);
+        }
+
+        let payload = Vec::from_slice(bytes).expect("write too long");
+        self.writes.push((addr, payload)).expect("too many writes");
+
+        // Register pointer followed by auto-incremented register values
+        if let Some((&register, values)) = bytes.split_first() {
+            self.load(register, values);
+        }
+        Ok(())
+    }
+
+    fn write_read(&mut self, addr: u8, write: &[u8], read: &mut [u8]) -> Result<(), GonkError> {
+        if addr != self.address {
+            return Err(GonkError::NACK);
+        }
+
+        let start = write.first().copied().unwrap_or(0) as usize;
+        let end = start + read.len();
+        if end > self.registers.len() {
+            return Err(GonkError::NACK);
+        }
+        read.copy_from_slice(&self.registers[start..end]);
+        Ok(())
+    }
+}
+
+/// Erased flash area of 4 KiB
+pub struct MockStorage {
+    pub data: [u8; 8192],
+    pub writes: u32,
+    /// Simulate a power loss: the next write stops after this many bytes
+    pub tear_after: Option<usize>,
+}
+
+impl MockStorage {
+    pub fn new() -> Self {
+        Self {
+            data: [0xFF; 8192],
+            writes: 0,
+            tear_after: None,
+        }
+    }
+
+    fn range(&self, offset: u32, len: usize) -> Result<core::ops::Range<usize>, GonkError> {
+        let start = offset as usize;
+        if start + len > self.data.len() {
+            return Err(StorageError::Io.into());
+        }
+        Ok(start..start + len)
+    }
+}
+
+impl Default for MockStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Storage for MockStorage {
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), GonkError> {
+        let range = self.range(offset, buf.len())?;
+        buf.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), GonkError> {
+        let range = self.range(offset, data.len())?;
+        if let Some(len) = self.tear_after.take() {
+            let torn = range.start..range.start + len.min(data.len());
+            self.data[torn.clone()].copy_from_slice(&data[..torn.len()]);
+            return Err(StorageError::Io.into());
+        }
+        self.data[range].copy_from_slice(data);
+        self.writes += 1;
+        Ok(())
+    }
+}
+
+/// Update slot keeping the image written to it
+pub struct MockSlots {
+    pub image: Vec<u8, 16384>,
+    pub capacity: u32,
+    pub writes: u32,
+    pub activated: bool,
+}
+
+impl MockSlots {
+    pub fn new(capacity: u32) -> Self {
+        Self {
+            image: Vec::new(),
+            capacity,
+            writes: 0,
+            activated: false,
+        }
+    }
+}
+
+impl FirmwareSlots for MockSlots {
+    fn capacity(&mut self) -> Result<u32, GonkError> {
+        Ok(self.capacity)
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), GonkError> {
+        if offset as usize + data.len() > self.capacity as usize {
+            return Err(UpdateError::TooLarge.into());
+        }
+        self.image
+            .resize(offset as usize, 0xFF)
+            .map_err(|_| StorageError::Io)?;
+        self.image
+            .extend_from_slice(data)
+            .map_err(|_| StorageError::Io)?;
+        self.writes += 1;
+        Ok(())
+    }
+
+    fn activate(&mut self) -> Result<(), GonkError> {
+        self.activated = true;
+        Ok(())
+    }
+}
+
+/// Run a future that never waits on anything but the mocks
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut context = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+/// Stand-in HTTP server answering canned responses by request path
+#[derive(Default)]
+pub struct MockHttpServer<'a> {
+    /// Path prefix and complete response, head included
+    routes: Vec<(&'a str, &'a str), 4>,
+    /// Host and port of each exchange
+    pub hosts: Vec<(String<64>, u16), 8>,
+    /// Raw requests received
+    pub requests: Vec<String<256>, 8>,
+    /// Error returned instead of a response
+    pub fail: Option<GonkError>,
+}
+
+impl<'a> MockHttpServer<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer `response` to requests whose path starts with `path`
+    pub fn route(&mut self, path: &'a str, response: &'a str) {
+        self.routes.push((path, response)).expect("too many routes");
+    }
+}
+
+impl HttpClient for MockHttpServer<'_> {
+    async fn exchange(
+        &mut self,
+        host: &str,
+        port: u16,
+        request: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, GonkError> {
+        if let Some(error) = self.fail {
+            return Err(error);
+        }
+        let request = core::str::from_utf8(request).map_err(|_| NetworkError::BadRequest)?;
+        let _ = self.hosts.push((String::try_from(host).unwrap(), port));
+        let _ = self.requests.push(String::try_from(request).unwrap());
+
+        let path = request.split(' ').nth(1).unwrap_or("");
+        let reply = self
+            .routes
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix))
+            .map_or(
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+                |(_, r)| r,
+            );
+        let buf = response
+            .get_mut(..reply.len())
+            .ok_or(NetworkError::BadResponse)?;
+        buf.copy_from_slice(reply.as_bytes());
+        Ok(reply.len())
+    }
+}
+
+/// Byte stream replaying scripted input and recording output, EOF once the
+/// input runs out
+#[derive(Default)]
+pub struct MockConnection {
+    input: Deque<u8, 256>,
+    pub output: Vec<u8, 2048>,
+}
+
+impl MockConnection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue bytes for future reads
+    pub fn push_input(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.input.push_back(byte).expect("too much queued input");
+        }
+    }
+}
+
+impl embedded_io_async::ErrorType for MockConnection {
+    type Error = embedded_io_async::ErrorKind;
+}
+
+impl embedded_io_async::Read for MockConnection {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let mut len = 0;
+        while len < buf.len() {
+            match self.input.pop_front() {
+                Some(byte) => buf[len] = byte,
+                None => break,
+            }
+            len += 1;
+        }
+        Ok(len)
+    }
+}
+
+impl embedded_io_async::Write for MockConnection {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.output
+            .extend_from_slice(buf)
+            .map_err(|_| embedded_io_async::ErrorKind::OutOfMemory)?;
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn i2c_reads_back_written_registers() {
+        let mut i2c = MockI2c::new(0x76);
+        i2c.write(0x76, &[0xF4, 0x25, 0xA0]).unwrap();
+
+        let mut buf = [0u8; 2];
+        i2c.write_read(0x76, &[0xF4], &mut buf).unwrap();
+        assert_eq!(buf, [0x25, 0xA0]);
+        assert_eq!(i2c.writes.len(), 1);
+    }
+
+    #[test]
+    fn i2c_nacks_other_addresses() {
+        let mut i2c = MockI2c::new(0x76);
+        let mut buf = [0u8; 1];
+
+        assert_eq!(i2c.write(0x77, &[0x00]), Err(GonkError::NACK));
+        assert_eq!(i2c.write_read(0x3C, &[], &mut buf), Err(GonkError::NACK));
+        assert!(i2c.writes.is_empty());
+    }
+
+    #[test]
+    fn sensor_replays_queued_readings() {
+        let mut sensor = MockSensor::new();
+        sensor.push_temperature(21.5);
+        sensor.push_reading(Err(GonkError::Timeout));
+
+        assert_eq!(sensor.read_temperature(), Ok(21.5));
+        assert_eq!(sensor.read_temperature(), Err(GonkError::Timeout));
+        assert!(sensor.read().is_err());
+        assert_eq!(sensor.read_calls, 3);
+    }
+
+    #[test]
+    fn sensor_without_temperature_reports_error() {
+        let mut sensor = MockSensor::new();
+        sensor.push_reading(Ok(Reading {
+            co2: Some(800),
+            ..Reading::new(Instant::from_ticks(0))
+        }));
+
+        assert_eq!(
+            sensor.read_temperature(),
+            Err(SensorError::Unsupported.into())
+        );
+    }
+
+    #[test]
+    fn display_texts_start_after_last_clear() {
+        let mut display = MockDisplay::new();
+        display.draw_text("old", 0, 0).unwrap();
+        display.clear().unwrap();
+        display.draw_text("new", 0, 10).unwrap();
+        display.update().unwrap();
+
+        assert!(display.texts().eq(["new"]));
+        assert_eq!(display.updates(), 1);
+    }
+}
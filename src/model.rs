@@ -1,10 +1,295 @@
 // Model of the data read in this app
 
-use heapless::String;
+use core::fmt::Write;
+
+use embassy_time::{Duration, Instant};
+use heapless::{HistoryBuf, String};
+
+use crate::clock::Clock;
+use crate::config::Thresholds;
+use crate::error::ErrorCounters;
+use crate::ota::BootInfo;
+use crate::settings::TemperatureUnit;
+use crate::traits::{Capabilities, Reading};
+use crate::tz::{LocalTime, TimeFormat, TimeZone};
+use crate::weather::Weather;
+use crate::wifi::WifiState;
+
+/// Sensor values older than this are no longer shown, unless readings are
+/// taken less often
+pub const STALE_AFTER: Duration = Duration::from_secs(60);
+
+/// Number of readings kept for trends
+pub const HISTORY_LEN: usize = 120;
+
+/// How current the sensor values of the model are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freshness {
+    /// No successful reading yet
+    Missing,
+    Fresh,
+    /// The last successful reading is this old
+    Stale(Duration),
+}
+
+/// Failures and events counted since boot, for monitoring
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Counters {
+    /// Sensor reads that returned an error
+    pub sensor_read_errors: u32,
+    /// Times the WiFi link dropped and was looked for again
+    pub wifi_reconnects: u32,
+    /// Display updates or power changes that failed
+    pub display_failures: u32,
+}
 
 pub struct Model {
-    pub temperature: f32,
-    pub pressure: f32,
-    pub humidity: f32,
+    /// Last good temperature in Celsius
+    pub temperature: Option<f32>,
+    /// Last good pressure in Pa
+    pub pressure: Option<f32>,
+    /// Last good relative humidity in %
+    pub humidity: Option<f32>,
+    /// When the last good reading was taken
+    pub last_reading: Option<Instant>,
+    /// Most recent successful readings, oldest first when iterated in order
+    pub history: HistoryBuf<Reading, HISTORY_LEN>,
     pub ip_address: String<16>,
+    pub wifi: WifiState,
+    /// Quantities the sensor measures, once it is detected
+    pub sensors: Capabilities,
+    /// Last measured supply or battery voltage in V
+    pub supply_voltage: Option<f32>,
+    /// Outdoor weather, when OpenWeather is configured
+    pub weather: Option<Weather>,
+    pub errors: ErrorCounters,
+    pub counters: Counters,
+    /// Free heap in bytes, as last measured
+    pub free_heap: Option<usize>,
+    /// Age from which sensor values are stale, see `set_refresh_interval()`
+    pub stale_after: Duration,
+    /// Firmware slot running
+    pub boot: BootInfo,
+    /// Wall-clock time, once synchronized
+    pub clock: Clock,
+    /// Unit temperatures are shown in
+    pub units: TemperatureUnit,
+    /// Limits of the comfort levels shown with the temperature
+    pub thresholds: Thresholds,
+    /// Time zone times are shown in
+    pub timezone: TimeZone,
+    pub time_format: TimeFormat,
+}
+
+impl Model {
+    pub fn new() -> Self {
+        Self {
+            temperature: None,
+            pressure: None,
+            humidity: None,
+            last_reading: None,
+            history: HistoryBuf::new(),
+            ip_address: String::try_from("UNKNOWN").unwrap(),
+            wifi: WifiState::Disconnected,
+            sensors: Capabilities::default(),
+            supply_voltage: None,
+            weather: None,
+            errors: ErrorCounters::default(),
+            counters: Counters::default(),
+            free_heap: None,
+            stale_after: STALE_AFTER,
+            boot: BootInfo::default(),
+            clock: Clock::new(),
+            units: TemperatureUnit::Celsius,
+            thresholds: Thresholds::default(),
+            timezone: TimeZone::utc(),
+            time_format: TimeFormat::H24,
+        }
+    }
+
+    /// Store every quantity present in a successful reading
+    pub fn record_reading(&mut self, reading: &Reading) {
+        if reading.temperature.is_some() {
+            self.temperature = reading.temperature;
+        }
+        if reading.humidity.is_some() {
+            self.humidity = reading.humidity;
+        }
+        if reading.pressure.is_some() {
+            self.pressure = reading.pressure;
+        }
+        self.last_reading = Some(reading.timestamp);
+        self.history.write(*reading);
+    }
+
+    /// Take the time between readings into account for staleness: a value
+    /// stays fresh for two intervals, and at least `STALE_AFTER`
+    pub fn set_refresh_interval(&mut self, interval: Duration) {
+        self.stale_after = STALE_AFTER.max(interval * 2);
+    }
+
+    pub fn freshness(&self, now: Instant) -> Freshness {
+        match self.last_reading {
+            None => Freshness::Missing,
+            Some(at) => {
+                let age = now.saturating_duration_since(at);
+                if age > self.stale_after {
+                    Freshness::Stale(age)
+                } else {
+                    Freshness::Fresh
+                }
+            }
+        }
+    }
+
+    /// Current temperature in the display unit
+    pub fn display_temperature(&self, now: Instant) -> Option<f32> {
+        self.current(self.temperature, now)
+            .map(|t| self.units.convert(t))
+    }
+
+    /// Comfort level of the temperature, while it is fresh
+    pub fn comfort(&self, now: Instant) -> Option<&'static str> {
+        self.current(self.temperature, now)
+            .map(|t| self.thresholds.status(t))
+    }
+
+    /// Local date and time at the instant `at`, once the clock is synchronized
+    pub fn local_time(&self, at: Instant) -> Option<LocalTime> {
+        self.clock.unix_time(at).map(|utc| self.timezone.local(utc))
+    }
+
+    /// A value if it is fresh enough to be shown
+    pub fn current(&self, value: Option<f32>, now: Instant) -> Option<f32> {
+        match self.freshness(now) {
+            Freshness::Fresh => value,
+            _ => None,
+        }
+    }
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Format an optional value, "--" when there is nothing to show
+pub fn format_value(value: Option<f32>, precision: usize, unit: &str) -> String<16> {
+    let mut buffer = String::new();
+    let _ = match value {
+        Some(v) => write!(buffer, "{:.*}{}", precision, v, unit),
+        None => write!(buffer, "--"),
+    };
+    buffer
+}
+
+/// Format a staleness age, e.g. "stale (3 min)"
+pub fn format_stale(age: Duration) -> String<16> {
+    let mut buffer = String::new();
+    let secs = age.as_secs();
+    let _ = if secs < 60 {
+        write!(buffer, "stale ({} s)", secs)
+    } else if secs < 3600 {
+        write!(buffer, "stale ({} min)", secs / 60)
+    } else {
+        write!(buffer, "stale ({} h)", secs / 3600)
+    };
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading_at(secs: u64) -> Reading {
+        Reading {
+            temperature: Some(21.5),
+            pressure: Some(101325.0),
+            ..Reading::new(Instant::from_secs(secs))
+        }
+    }
+
+    #[test]
+    fn starts_without_values() {
+        let model = Model::new();
+
+        assert_eq!(model.freshness(Instant::from_secs(10)), Freshness::Missing);
+        assert_eq!(
+            model.current(model.temperature, Instant::from_secs(10)),
+            None
+        );
+    }
+
+    #[test]
+    fn keeps_quantities_missing_from_reading() {
+        let mut model = Model::new();
+        model.humidity = Some(40.0);
+        model.record_reading(&reading_at(5));
+
+        assert_eq!(model.temperature, Some(21.5));
+        assert_eq!(model.humidity, Some(40.0));
+        assert_eq!(model.last_reading, Some(Instant::from_secs(5)));
+        assert_eq!(model.history.recent(), Some(&reading_at(5)));
+    }
+
+    #[test]
+    fn slow_readings_stay_fresh_for_an_interval() {
+        let mut model = Model::new();
+        model.set_refresh_interval(Duration::from_secs(600));
+        model.record_reading(&reading_at(100));
+
+        assert_eq!(model.freshness(Instant::from_secs(700)), Freshness::Fresh);
+        assert!(
+            model
+                .current(model.temperature, Instant::from_secs(700))
+                .is_some()
+        );
+        assert_eq!(
+            model.freshness(Instant::from_secs(1400)),
+            Freshness::Stale(Duration::from_secs(1300))
+        );
+
+        // Frequent readings keep the usual threshold
+        model.set_refresh_interval(Duration::from_secs(6));
+        assert_eq!(model.freshness(Instant::from_secs(160)), Freshness::Fresh);
+        assert!(matches!(
+            model.freshness(Instant::from_secs(161)),
+            Freshness::Stale(_)
+        ));
+    }
+
+    #[test]
+    fn becomes_stale_after_threshold() {
+        let mut model = Model::new();
+        model.record_reading(&reading_at(100));
+
+        assert_eq!(model.freshness(Instant::from_secs(160)), Freshness::Fresh);
+        assert_eq!(
+            model.freshness(Instant::from_secs(280)),
+            Freshness::Stale(Duration::from_secs(180))
+        );
+        assert_eq!(
+            model.current(model.temperature, Instant::from_secs(280)),
+            None
+        );
+    }
+
+    #[test]
+    fn formats_missing_values_and_age() {
+        assert_eq!(format_value(Some(21.456), 2, " C").as_str(), "21.46 C");
+        assert_eq!(format_value(None, 2, " C").as_str(), "--");
+        assert_eq!(
+            format_stale(Duration::from_secs(45)).as_str(),
+            "stale (45 s)"
+        );
+        assert_eq!(
+            format_stale(Duration::from_secs(190)).as_str(),
+            "stale (3 min)"
+        );
+        assert_eq!(
+            format_stale(Duration::from_secs(7300)).as_str(),
+            "stale (2 h)"
+        );
+    }
 }
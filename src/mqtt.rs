@@ -0,0 +1,590 @@
+//! MQTT 3.1.1 publisher with Home Assistant discovery (hardware-independent)
+//!
+//! The device publishes its readings as one JSON object on
+//! `gonk/<id>/state`, with QoS 0. Its availability is kept on the retained
+//! `gonk/<id>/status` topic: "online" once connected, and "offline" set by the
+//! broker through the last will when the connection is lost.
+//!
+//! Home Assistant finds the sensors from the retained discovery messages on
+//! `homeassistant/sensor/gonk_<id>/<sensor>/config`, sent at every connection.
+
+use core::fmt::{self, Write as _};
+
+use embassy_time::{Duration, Instant};
+use embedded_io_async::{Read, Write};
+use heapless::{String, Vec};
+
+use crate::error::{GonkError, NetworkError};
+use crate::json::ObjectWriter;
+use crate::model::Model;
+use crate::wifi::WifiState;
+
+pub const PORT: u16 = 1883;
+/// Longest silence before the broker drops the connection
+pub const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// Time between two publications of the readings, and pings
+pub const PUBLISH_INTERVAL: Duration = Duration::from_secs(30);
+/// Delay before the first reconnection
+pub const RECONNECT_BASE: Duration = Duration::from_secs(2);
+/// Longest delay between two connection attempts
+pub const RECONNECT_MAX: Duration = Duration::from_secs(300);
+/// Largest packet sent or received, discovery messages being the longest
+pub const MAX_PACKET: usize = 1024;
+/// Topic prefix of Home Assistant discovery
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PINGREQ: u8 = 0xC0;
+const PINGRESP: u8 = 0xD0;
+const DISCONNECT: u8 = 0xE0;
+const RETAIN: u8 = 0x01;
+/// Protocol level of MQTT 3.1.1
+const LEVEL: u8 = 4;
+
+pub type Packet = Vec<u8, MAX_PACKET>;
+
+/// Connection parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options<'a> {
+    pub client_id: &'a str,
+    /// Empty for an anonymous connection
+    pub username: &'a str,
+    pub password: &'a str,
+    /// Retained message the broker publishes when the connection is lost
+    pub will: Option<(&'a str, &'a str)>,
+}
+
+/// Host and port of a "host[:port]" broker address
+pub fn parse_broker(broker: &str) -> Option<(&str, u16)> {
+    let broker = broker.trim();
+    let (host, port) = match broker.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()?),
+        None => (broker, PORT),
+    };
+    (!host.is_empty() && port != 0).then_some((host, port))
+}
+
+/// Delay before the next connection attempt after `failures` in a row
+pub fn reconnect_delay(failures: u32) -> Duration {
+    let exponent = failures.saturating_sub(1).min(16);
+    (RECONNECT_BASE * (1 << exponent)).min(RECONNECT_MAX)
+}
+
+fn push(packet: &mut Packet, bytes: &[u8]) -> Result<(), GonkError> {
+    packet
+        .extend_from_slice(bytes)
+        .map_err(|_| NetworkError::BadRequest.into())
+}
+
+/// String or binary data prefixed with its length
+fn push_field(packet: &mut Packet, bytes: &[u8]) -> Result<(), GonkError> {
+    let len = u16::try_from(bytes.len()).map_err(|_| NetworkError::BadRequest)?;
+    push(packet, &len.to_be_bytes())?;
+    push(packet, bytes)
+}
+
+/// Fixed header then `body`
+fn frame(header: u8, body: &[u8]) -> Result<Packet, GonkError> {
+    let mut packet = Packet::new();
+    push(&mut packet, &[header])?;
+    // Remaining length, 7 bits per byte, least significant first
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        push(&mut packet, &[byte])?;
+        if len == 0 {
+            break;
+        }
+    }
+    push(&mut packet, body)?;
+    Ok(packet)
+}
+
+pub fn connect_packet(options: &Options) -> Result<Packet, GonkError> {
+    let mut flags = 0x02; // Clean session
+    if options.will.is_some() {
+        flags |= 0x04 | 0x20; // Will, retained, QoS 0
+    }
+    if !options.username.is_empty() {
+        flags |= 0x80;
+        if !options.password.is_empty() {
+            flags |= 0x40;
+        }
+    }
+
+    let mut body = Packet::new();
+    push_field(&mut body, b"MQTT")?;
+    push(&mut body, &[LEVEL, flags])?;
+    push(&mut body, &(KEEP_ALIVE.as_secs() as u16).to_be_bytes())?;
+    push_field(&mut body, options.client_id.as_bytes())?;
+    if let Some((topic, message)) = options.will {
+        push_field(&mut body, topic.as_bytes())?;
+        push_field(&mut body, message.as_bytes())?;
+    }
+    if !options.username.is_empty() {
+        push_field(&mut body, options.username.as_bytes())?;
+        if !options.password.is_empty() {
+            push_field(&mut body, options.password.as_bytes())?;
+        }
+    }
+    frame(CONNECT, &body)
+}
+
+/// QoS 0 publication
+pub fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> Result<Packet, GonkError> {
+    let mut body = Packet::new();
+    push_field(&mut body, topic.as_bytes())?;
+    push(&mut body, payload)?;
+    frame(PUBLISH | if retain { RETAIN } else { 0 }, &body)
+}
+
+/// Client of a broker over any byte stream, e.g. a TCP socket
+pub struct MqttClient<C> {
+    connection: C,
+    buf: [u8; MAX_PACKET],
+}
+
+impl<C: Read + Write> MqttClient<C> {
+    /// Open a session and wait for the broker to accept it
+    pub async fn connect(connection: C, options: &Options<'_>) -> Result<Self, GonkError> {
+        let mut client = Self {
+            connection,
+            buf: [0; MAX_PACKET],
+        };
+        client.send(&connect_packet(options)?).await?;
+        let (header, body) = client.receive().await?;
+        match (header, body) {
+            (CONNACK, [_, 0]) => Ok(client),
+            (CONNACK, [_, code]) => Err(NetworkError::Refused(*code).into()),
+            _ => Err(NetworkError::BadResponse.into()),
+        }
+    }
+
+    pub async fn publish(
+        &mut self,
+        topic: &str,
+        payload: &[u8],
+        retain: bool,
+    ) -> Result<(), GonkError> {
+        self.send(&publish_packet(topic, payload, retain)?).await
+    }
+
+    /// Check that the broker is still there
+    pub async fn ping(&mut self) -> Result<(), GonkError> {
+        self.send(&[PINGREQ, 0]).await?;
+        loop {
+            // Nothing is subscribed to, anything else is ignored
+            if self.receive().await?.0 == PINGRESP {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Leave without the broker publishing the last will
+    pub async fn disconnect(mut self) -> Result<(), GonkError> {
+        self.send(&[DISCONNECT, 0]).await
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), GonkError> {
+        self.connection
+            .write_all(packet)
+            .await
+            .map_err(|_| NetworkError::Io)?;
+        self.connection
+            .flush()
+            .await
+            .map_err(|_| NetworkError::Io.into())
+    }
+
+    /// Next packet, its type and flags, and its body
+    async fn receive(&mut self) -> Result<(u8, &[u8]), GonkError> {
+        let mut byte = [0u8; 1];
+        self.read_exact(&mut byte).await?;
+        let header = byte[0];
+
+        let mut len = 0usize;
+        for shift in (0..4).map(|i| 7 * i) {
+            self.read_exact(&mut byte).await?;
+            len |= ((byte[0] & 0x7F) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        if len > MAX_PACKET || byte[0] & 0x80 != 0 {
+            return Err(NetworkError::BadResponse.into());
+        }
+
+        self.connection
+            .read_exact(&mut self.buf[..len])
+            .await
+            .map_err(|_| NetworkError::Io)?;
+        Ok((header, &self.buf[..len]))
+    }
+
+    async fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), GonkError> {
+        self.connection
+            .read_exact(buf)
+            .await
+            .map_err(|_| NetworkError::Io.into())
+    }
+}
+
+/// Reading published to Home Assistant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sensor {
+    /// Member of the state object, and last part of the unique ID
+    pub key: &'static str,
+    pub name: &'static str,
+    pub device_class: &'static str,
+    pub unit: &'static str,
+    /// Shown on the device page rather than with the readings
+    pub diagnostic: bool,
+}
+
+pub const SENSORS: [Sensor; 5] = [
+    Sensor {
+        key: "temperature",
+        name: "Temperature",
+        device_class: "temperature",
+        unit: "°C",
+        diagnostic: false,
+    },
+    Sensor {
+        key: "humidity",
+        name: "Humidity",
+        device_class: "humidity",
+        unit: "%",
+        diagnostic: false,
+    },
+    Sensor {
+        key: "pressure",
+        name: "Pressure",
+        device_class: "atmospheric_pressure",
+        unit: "hPa",
+        diagnostic: false,
+    },
+    Sensor {
+        key: "battery_voltage",
+        name: "Battery voltage",
+        device_class: "voltage",
+        unit: "V",
+        diagnostic: true,
+    },
+    Sensor {
+        key: "rssi",
+        name: "WiFi signal",
+        device_class: "signal_strength",
+        unit: "dBm",
+        diagnostic: true,
+    },
+];
+
+/// Topics of one device, `id` being its MAC address in hexadecimal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topics {
+    id: String<12>,
+    pub state: String<32>,
+    pub status: String<32>,
+}
+
+impl Topics {
+    pub fn new(mac: [u8; 6]) -> Self {
+        let mut id = String::new();
+        for byte in mac {
+            let _ = write!(id, "{:02x}", byte);
+        }
+        let mut state = String::new();
+        let _ = write!(state, "gonk/{}/state", id);
+        let mut status = String::new();
+        let _ = write!(status, "gonk/{}/status", id);
+        Self { id, state, status }
+    }
+
+    /// Client identifier, unique on the broker
+    pub fn client_id(&self) -> String<20> {
+        let mut client_id = String::new();
+        let _ = write!(client_id, "gonk-{}", self.id);
+        client_id
+    }
+
+    pub fn discovery(&self, sensor: &Sensor) -> String<80> {
+        let mut topic = String::new();
+        let _ = write!(
+            topic,
+            "{}/sensor/gonk_{}/{}/config",
+            DISCOVERY_PREFIX, self.id, sensor.key
+        );
+        topic
+    }
+
+    /// Home Assistant discovery message of `sensor`
+    pub fn write_discovery<W: fmt::Write>(&self, out: &mut W, sensor: &Sensor) -> fmt::Result {
+        let mut object = ObjectWriter::new(out)?;
+        object.string("name", sensor.name)?;
+        let unique_id = object.member("unique_id")?;
+        write!(unique_id, "\"gonk_{}_{}\"", self.id, sensor.key)?;
+        object.string("state_topic", &self.state)?;
+        let template = object.member("value_template")?;
+        write!(template, "\"{{{{ value_json.{} }}}}\"", sensor.key)?;
+        object.string("device_class", sensor.device_class)?;
+        object.string("unit_of_measurement", sensor.unit)?;
+        object.string("state_class", "measurement")?;
+        if sensor.diagnostic {
+            object.string("entity_category", "diagnostic")?;
+        }
+        object.string("availability_topic", &self.status)?;
+
+        let mut device = ObjectWriter::new(object.member("device")?)?;
+        let identifiers = device.member("identifiers")?;
+        write!(identifiers, "[\"gonk_{}\"]", self.id)?;
+        let name = device.member("name")?;
+        write!(name, "\"Gonk {}\"", &self.id[6..])?;
+        device.string("model", "Gonk")?;
+        device.string("sw_version", env!("CARGO_PKG_VERSION"))?;
+        device.finish()?;
+        object.finish()
+    }
+}
+
+/// Readings of the model as published on the state topic
+pub fn write_state<W: fmt::Write>(out: &mut W, model: &Model, now: Instant) -> fmt::Result {
+    let mut object = ObjectWriter::new(out)?;
+    object.number("temperature", model.current(model.temperature, now), 2)?;
+    object.number("humidity", model.current(model.humidity, now), 1)?;
+    let pressure = model.current(model.pressure, now).map(|p| p / 100.0);
+    object.number("pressure", pressure, 1)?;
+    object.number("battery_voltage", model.supply_voltage, 2)?;
+    match &model.wifi {
+        WifiState::Connected(link) => object.integer("rssi", link.rssi as i64)?,
+        _ => object.null("rssi")?,
+    }
+    object.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockConnection, block_on};
+    use crate::traits::Reading;
+    use crate::wifi::Link;
+
+    const MAC: [u8; 6] = [0x24, 0x6F, 0x28, 0x01, 0xAB, 0x0C];
+
+    #[test]
+    fn encodes_connect() {
+        let options = Options {
+            client_id: "gonk",
+            username: "user",
+            password: "pw",
+            will: Some(("gonk/s", "offline")),
+        };
+        let packet = connect_packet(&options).unwrap();
+
+        let mut expected = Packet::new();
+        expected.extend_from_slice(&[0x10, 43]).unwrap();
+        expected
+            .extend_from_slice(b"\x00\x04MQTT\x04\xE6\x00\x3C\x00\x04gonk")
+            .unwrap();
+        expected
+            .extend_from_slice(b"\x00\x06gonk/s\x00\x07offline")
+            .unwrap();
+        expected
+            .extend_from_slice(b"\x00\x04user\x00\x02pw")
+            .unwrap();
+        assert_eq!(packet, expected);
+
+        let anonymous = Options {
+            client_id: "gonk",
+            username: "",
+            password: "",
+            will: None,
+        };
+        assert_eq!(connect_packet(&anonymous).unwrap()[9], 0x02);
+    }
+
+    #[test]
+    fn encodes_long_publish() {
+        let payload = [b'x'; 200];
+        let packet = publish_packet("a/b", &payload, true).unwrap();
+
+        // 2 + 3 + 200 = 205 bytes, two bytes of remaining length
+        assert_eq!(packet[..6], [0x31, 0xCD, 0x01, 0x00, 0x03, b'a']);
+        assert_eq!(packet.len(), 3 + 205);
+        assert!(publish_packet("a/b", &[0; MAX_PACKET], false).is_err());
+    }
+
+    #[test]
+    fn connects_publishes_and_pings() {
+        let mut connection = MockConnection::new();
+        connection.push_input(&[0x20, 0x02, 0x00, 0x00]);
+        // A retained message the broker sends, then the ping response
+        connection.push_input(&[0x30, 0x03, 0x00, 0x01, b't']);
+        connection.push_input(&[0xD0, 0x00]);
+        let options = Options {
+            client_id: "gonk",
+            username: "",
+            password: "",
+            will: None,
+        };
+
+        let mut client = block_on(MqttClient::connect(&mut connection, &options)).unwrap();
+        block_on(client.publish("t", b"42", false)).unwrap();
+        block_on(client.ping()).unwrap();
+        block_on(client.disconnect()).unwrap();
+
+        let sent = &connection.output;
+        assert_eq!(sent[..2], [0x10, 16]);
+        assert_eq!(
+            sent[18..],
+            [
+                0x30, 0x05, 0x00, 0x01, b't', b'4', b'2', 0xC0, 0x00, 0xE0, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_refused_and_lost_connections() {
+        let options = Options {
+            client_id: "gonk",
+            username: "user",
+            password: "wrong",
+            will: None,
+        };
+        let mut connection = MockConnection::new();
+        connection.push_input(&[0x20, 0x02, 0x00, 0x05]);
+        assert_eq!(
+            block_on(MqttClient::connect(&mut connection, &options)).err(),
+            Some(NetworkError::Refused(5).into())
+        );
+
+        let mut connection = MockConnection::new();
+        connection.push_input(&[0x20, 0x02]);
+        assert_eq!(
+            block_on(MqttClient::connect(&mut connection, &options)).err(),
+            Some(NetworkError::Io.into())
+        );
+    }
+
+    #[test]
+    fn writes_discovery_and_state() {
+        let topics = Topics::new(MAC);
+        assert_eq!(topics.client_id(), "gonk-246f2801ab0c");
+        assert_eq!(topics.status, "gonk/246f2801ab0c/status");
+        assert_eq!(
+            topics.discovery(&SENSORS[0]),
+            "homeassistant/sensor/gonk_246f2801ab0c/temperature/config"
+        );
+
+        let mut message = String::<MAX_PACKET>::new();
+        topics.write_discovery(&mut message, &SENSORS[4]).unwrap();
+        assert_eq!(
+            message,
+            "{\"name\":\"WiFi signal\",\"unique_id\":\"gonk_246f2801ab0c_rssi\",\
+\"state_topic\":\"gonk/246f2801ab0c/state\",\"value_template\":\"{{ value_json.rssi }}\",\
+\"device_class\":\"signal_strength\",\"unit_of_measurement\":\"dBm\",\
+\"state_class\":\"measurement\",\"entity_category\":\"diagnostic\",\
+\"availability_topic\":\"gonk/246f2801ab0c/status\",\"device\":{\
+\"identifiers\":[\"gonk_246f2801ab0c\"],\"name\":\"Gonk 01ab0c\",\"model\":\"Gonk\",\
+\"sw_version\":\"0.1.0\"}}"
+        );
+
+        let mut model = Model::new();
+        model.record_reading(&Reading {
+            temperature: Some(21.456),
+            humidity: Some(40.0),
+            pressure: Some(101325.0),
+            ..Reading::new(Instant::from_secs(10))
+        });
+        model.supply_voltage = Some(3.912);
+        model.wifi = WifiState::Connected(Link {
+            ssid: String::try_from("home").unwrap(),
+            bssid: MAC,
+            channel: 6,
+            rssi: -61,
+        });
+        let mut state = String::<128>::new();
+        write_state(&mut state, &model, Instant::from_secs(13)).unwrap();
+        assert_eq!(
+            state,
+            "{\"temperature\":21.46,\"humidity\":40.0,\"pressure\":1013.2,\
+\"battery_voltage\":3.91,\"rssi\":-61}"
+        );
+    }
+
+    #[test]
+    fn parses_broker_addresses() {
+        assert_eq!(parse_broker("mqtt.local"), Some(("mqtt.local", 1883)));
+        assert_eq!(
+            parse_broker(" 192.168.1.2:8883 "),
+            Some(("192.168.1.2", 8883))
+        );
+        assert_eq!(parse_broker(""), None);
+        assert_eq!(parse_broker("host:"), None);
+        assert_eq!(parse_broker("host:0"), None);
+    }
+
+    #[test]
+    fn backs_off_between_reconnections() {
+        assert_eq!(reconnect_delay(1), RECONNECT_BASE);
+        assert_eq!(reconnect_delay(3), Duration::from_secs(8));
+        assert_eq!(reconnect_delay(40), RECONNECT_MAX);
+    }
+
+    /// Against a real broker: `mosquitto -v` then run the ignored tests of
+    /// this module on the host, and watch with
+    /// `mosquitto_sub -v -t 'gonk/#' -t 'homeassistant/#'`
+    #[test]
+    #[ignore = "needs an MQTT broker on localhost:1883"]
+    fn publishes_to_local_broker() {
+        extern crate std;
+        use std::io::{Read as _, Write as _};
+
+        struct Stream(std::net::TcpStream);
+        impl embedded_io_async::ErrorType for Stream {
+            type Error = embedded_io_async::ErrorKind;
+        }
+        impl Read for Stream {
+            async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+                self.0
+                    .read(buf)
+                    .map_err(|_| embedded_io_async::ErrorKind::Other)
+            }
+        }
+        impl Write for Stream {
+            async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+                self.0
+                    .write(buf)
+                    .map_err(|_| embedded_io_async::ErrorKind::Other)
+            }
+            async fn flush(&mut self) -> Result<(), Self::Error> {
+                self.0
+                    .flush()
+                    .map_err(|_| embedded_io_async::ErrorKind::Other)
+            }
+        }
+
+        let topics = Topics::new(MAC);
+        let stream = std::net::TcpStream::connect(("localhost", PORT)).unwrap();
+        let options = Options {
+            client_id: &topics.client_id(),
+            username: "",
+            password: "",
+            will: Some((&topics.status, "offline")),
+        };
+        let mut client = block_on(MqttClient::connect(Stream(stream), &options)).unwrap();
+        block_on(client.publish(&topics.status, b"online", true)).unwrap();
+        let mut message = String::<MAX_PACKET>::new();
+        topics.write_discovery(&mut message, &SENSORS[0]).unwrap();
+        let topic = topics.discovery(&SENSORS[0]);
+        block_on(client.publish(&topic, message.as_bytes(), true)).unwrap();
+        block_on(client.publish(&topics.state, b"{\"temperature\":21.5}", false)).unwrap();
+        block_on(client.ping()).unwrap();
+        block_on(client.disconnect()).unwrap();
+    }
+}
@@ -0,0 +1,601 @@
+//! Network services running on an embassy-net stack
+
+use core::net::Ipv4Addr;
+
+use embassy_net::dns::DnsQueryType;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer, with_deadline};
+use embedded_io_async::Write;
+use heapless::String;
+
+use crate::api;
+use crate::config::{Configuration, WifiNetwork};
+use crate::error::{GonkError, NetworkError, UpdateError};
+use crate::http::{self, MAX_REQUEST, Method, Request, Status};
+use crate::mdns::{self, Host, Responder};
+use crate::model::Model;
+use crate::mqtt::{self, MqttClient, Options, SENSORS, Topics};
+use crate::ota::Update;
+use crate::portal::{
+    self, DHCP_CLIENT_PORT, DHCP_SERVER_PORT, DNS_PORT, DhcpServer, PORTAL_ADDRESS, PortalReply,
+};
+use crate::sntp;
+use crate::traits::{FirmwareSlots, HttpClient, Storage};
+
+const HTTP_PORT: u16 = 80;
+/// Time a client has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time a server may stay silent while answering
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Static address of the device on the access point
+pub fn portal_config() -> embassy_net::Config {
+    let address = Ipv4Addr::from(PORTAL_ADDRESS);
+    embassy_net::Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(address, 24),
+        gateway: Some(address),
+        dns_servers: Default::default(),
+    })
+}
+
+/// Answer every DNS query with the portal address
+pub async fn run_dns(stack: Stack<'_>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0u8; 1024];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(DNS_PORT).unwrap();
+
+    let mut query = [0u8; 512];
+    let mut response = [0u8; 512];
+    loop {
+        let Ok((len, meta)) = socket.recv_from(&mut query).await else {
+            continue;
+        };
+        if let Some(len) = portal::dns_response(&query[..len], PORTAL_ADDRESS, &mut response) {
+            let _ = socket.send_to(&response[..len], meta.endpoint).await;
+        }
+    }
+}
+
+/// Answer mDNS queries for the device and its services on the station
+/// network, and announce them whenever the address changes
+pub async fn run_mdns<M: RawMutex>(
+    stack: Stack<'_>,
+    model: &Mutex<M, Model>,
+    responder: &Responder,
+) -> ! {
+    if stack
+        .join_multicast_group(Ipv4Addr::from(mdns::GROUP))
+        .is_err()
+    {
+        // Unicast queries to port 5353 are still answered
+        model.lock().await.errors.record(&NetworkError::Io.into());
+    }
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 2048];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0u8; 2048];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(mdns::PORT).unwrap();
+
+    let group = IpEndpoint::from((Ipv4Addr::from(mdns::GROUP), mdns::PORT));
+    let mut query = [0u8; mdns::MAX_PACKET];
+    let mut response = [0u8; mdns::MAX_PACKET];
+    let mut announced = None;
+    let mut announcements = 0;
+    let mut next_announcement = Instant::now();
+    loop {
+        stack.wait_config_up().await;
+        let Some(config) = stack.config_v4() else {
+            continue;
+        };
+        let host = Host {
+            address: config.address.address().octets(),
+            port: HTTP_PORT,
+            sensors: model.lock().await.sensors,
+        };
+        if announced != Some(host.address) {
+            announced = Some(host.address);
+            announcements = mdns::ANNOUNCEMENTS;
+            next_announcement = Instant::now();
+        }
+
+        // Wake up now and then to notice a new address
+        let deadline = match announcements {
+            0 => Instant::now() + mdns::ADDRESS_CHECK,
+            _ => next_announcement,
+        };
+        match with_deadline(deadline, socket.recv_from(&mut query)).await {
+            Ok(Ok((len, meta))) => {
+                let legacy = meta.endpoint.port != mdns::PORT;
+                let reply = responder.respond(&query[..len], legacy, &host, &mut response);
+                if let Some(reply) = reply {
+                    let to = if reply.unicast { meta.endpoint } else { group };
+                    let _ = socket.send_to(&response[..reply.len], to).await;
+                }
+            }
+            Ok(Err(_)) => {}
+            Err(_) if announcements > 0 => {
+                if let Some(len) = responder.announce(&host, &mut response) {
+                    let _ = socket.send_to(&response[..len], group).await;
+                }
+                announcements -= 1;
+                next_announcement = Instant::now() + mdns::ANNOUNCE_INTERVAL;
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// Lease addresses on the access point subnet
+pub async fn run_dhcp(stack: Stack<'_>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 1536];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0u8; 1536];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(DHCP_SERVER_PORT).unwrap();
+
+    let mut server = DhcpServer::new(PORTAL_ADDRESS);
+    let mut request = [0u8; 576];
+    let mut reply = [0u8; 576];
+    loop {
+        let Ok((len, _)) = socket.recv_from(&mut request).await else {
+            continue;
+        };
+        if let Some(len) = server.handle(&request[..len], &mut reply) {
+            // The client has no address yet
+            let _ = socket
+                .send_to(&reply[..len], (Ipv4Addr::BROADCAST, DHCP_CLIENT_PORT))
+                .await;
+        }
+    }
+}
+
+/// First IPv4 address of `host`, from the DNS servers given by DHCP
+pub async fn resolve(stack: Stack<'_>, host: &str) -> Result<IpAddress, GonkError> {
+    let addresses = stack
+        .dns_query(host, DnsQueryType::A)
+        .await
+        .map_err(|_| NetworkError::Dns)?;
+    addresses.first().copied().ok_or(NetworkError::Dns.into())
+}
+
+/// Server time from one SNTP exchange with `server`
+///
+/// `nonce` identifies the reply, it should be random.
+pub async fn sntp_sample(
+    stack: Stack<'_>,
+    server: &str,
+    nonce: u64,
+) -> Result<sntp::Sample, GonkError> {
+    let address = resolve(stack, server).await?;
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0u8; 256];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0u8; 128];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    // Any free local port
+    socket.bind(0).map_err(|_| NetworkError::Io)?;
+
+    let mut packet = [0u8; sntp::PACKET_LEN];
+    sntp::write_request(&mut packet, nonce);
+    let sent = Instant::now();
+    socket
+        .send_to(&packet, (address, sntp::PORT))
+        .await
+        .map_err(|_| NetworkError::Io)?;
+
+    let mut reply = [0u8; 128];
+    loop {
+        let (len, meta) = socket
+            .recv_from(&mut reply)
+            .await
+            .map_err(|_| NetworkError::Io)?;
+        let received = Instant::now();
+        if meta.endpoint.addr == address {
+            return sntp::parse_reply(&reply[..len], nonce, sent, received);
+        }
+    }
+}
+
+/// HTTP client opening a TCP connection for every exchange
+pub struct TcpHttpClient<'a> {
+    stack: Stack<'a>,
+    rx_buffer: [u8; 1536],
+    tx_buffer: [u8; 512],
+}
+
+impl<'a> TcpHttpClient<'a> {
+    pub fn new(stack: Stack<'a>) -> Self {
+        Self {
+            stack,
+            rx_buffer: [0; 1536],
+            tx_buffer: [0; 512],
+        }
+    }
+}
+
+impl HttpClient for TcpHttpClient<'_> {
+    async fn exchange(
+        &mut self,
+        host: &str,
+        port: u16,
+        request: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, GonkError> {
+        let address = resolve(self.stack, host).await?;
+        let mut socket = TcpSocket::new(self.stack, &mut self.rx_buffer, &mut self.tx_buffer);
+        socket.set_timeout(Some(RESPONSE_TIMEOUT));
+        socket
+            .connect((address, port))
+            .await
+            .map_err(|_| NetworkError::Connect)?;
+        socket
+            .write_all(request)
+            .await
+            .map_err(|_| NetworkError::Io)?;
+
+        let mut len = 0;
+        loop {
+            if len == response.len() {
+                socket.abort();
+                return Err(NetworkError::BadResponse.into());
+            }
+            match socket.read(&mut response[len..]).await {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(_) => return Err(NetworkError::Io.into()),
+            }
+        }
+        socket.close();
+        let _ = socket.flush().await;
+        Ok(len)
+    }
+}
+
+/// Read a request into `buf`, returns its length
+///
+/// For the requests `streamed` selects, only the head is waited for and the
+/// body is left to be read from the socket.
+async fn read_request(
+    socket: &mut TcpSocket<'_>,
+    buf: &mut [u8],
+    streamed: fn(&Request) -> bool,
+) -> Result<usize, Status> {
+    let mut len = 0;
+    loop {
+        if len == buf.len() {
+            return Err(Status::PayloadTooLarge);
+        }
+        match socket.read(&mut buf[len..]).await {
+            Ok(0) | Err(_) => return Err(Status::BadRequest),
+            Ok(n) => len += n,
+        }
+        match http::parse_head(&buf[..len]) {
+            Ok(Some(head)) if streamed(&head) => return Ok(len),
+            Ok(_) => {}
+            Err(_) => return Err(Status::BadRequest),
+        }
+        match http::parse_request(&buf[..len]) {
+            Ok(Some(_)) => return Ok(len),
+            Ok(None) => {}
+            Err(_) => return Err(Status::BadRequest),
+        }
+    }
+}
+
+/// Check the firmware upload of `request`, write its image to the update
+/// slot and activate it
+async fn install_upload<M: RawMutex, S: Storage, F: FirmwareSlots>(
+    socket: &mut TcpSocket<'_>,
+    request: &Request<'_>,
+    model: &Mutex<M, Model>,
+    config: &Mutex<M, Configuration<S>>,
+    firmware: &Mutex<M, F>,
+    installed: &Signal<M, ()>,
+    body: &mut api::Body,
+) -> api::Reply {
+    let mut slots = firmware.lock().await;
+    let capacity = slots.capacity();
+    let begun = api::begin_update(request, &config.lock().await.current, capacity, body);
+    let mut update = match begun {
+        Ok(update) => update,
+        Err(reply) => return reply,
+    };
+
+    let result = receive_image(socket, request, &mut update, &mut *slots)
+        .await
+        .and_then(|()| update.finish(&mut *slots));
+    match &result {
+        Ok(()) => installed.signal(()),
+        Err(e) => model.lock().await.errors.record(e),
+    }
+    api::finish_update(result, body)
+}
+
+/// Write the image uploaded by `request` to the update slot, the part of it
+/// read with the head first
+async fn receive_image<F: FirmwareSlots>(
+    socket: &mut TcpSocket<'_>,
+    request: &Request<'_>,
+    update: &mut Update,
+    slots: &mut F,
+) -> Result<(), GonkError> {
+    let received = &request.body[..request.body.len().min(request.content_length)];
+    update.write(slots, received)?;
+    let mut buf = [0u8; 1024];
+    while update.received() < update.size() {
+        match socket.read(&mut buf).await {
+            Ok(0) | Err(_) => return Err(UpdateError::Incomplete.into()),
+            Ok(n) => update.write(slots, &buf[..n])?,
+        }
+    }
+    Ok(())
+}
+
+async fn respond(
+    socket: &mut TcpSocket<'_>,
+    status: Status,
+    content_type: &str,
+    body: &[u8],
+    location: Option<&str>,
+) -> Result<(), GonkError> {
+    let head = http::response_head(status, content_type, body.len(), location);
+    socket
+        .write_all(head.as_bytes())
+        .await
+        .map_err(|_| NetworkError::Io)?;
+    socket.write_all(body).await.map_err(|_| NetworkError::Io)?;
+    socket.flush().await.map_err(|_| NetworkError::Io.into())
+}
+
+/// Serve the setup page, the networks entered are signalled to `credentials`
+pub async fn run_portal<M: RawMutex>(stack: Stack<'_>, credentials: &Signal<M, WifiNetwork>) -> ! {
+    let mut rx_buffer = [0u8; 1024];
+    let mut tx_buffer = [0u8; 2048];
+    let mut request = [0u8; MAX_REQUEST];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(REQUEST_TIMEOUT));
+        if socket.accept(HTTP_PORT).await.is_err() {
+            continue;
+        }
+
+        let result = match read_request(&mut socket, &mut request, |_| false).await {
+            Ok(len) => {
+                // Complete, it was parsed by `read_request()`
+                let Ok(Some(parsed)) = http::parse_request(&request[..len]) else {
+                    continue;
+                };
+                let reply = portal::handle_request(&parsed);
+                if let PortalReply::Saved(network) = &reply {
+                    credentials.signal(network.clone());
+                }
+
+                let mut page = String::<2048>::new();
+                if parsed.method != Method::Head {
+                    let _ = reply.write_page(&mut page);
+                }
+                respond(
+                    &mut socket,
+                    reply.status(),
+                    "text/html; charset=utf-8",
+                    page.as_bytes(),
+                    reply.location(),
+                )
+                .await
+            }
+            Err(status) => {
+                let reason = status.reason().as_bytes();
+                respond(&mut socket, status, "text/plain", reason, None).await
+            }
+        };
+        // A failed response only concerns the client that went away
+        let _ = result;
+        socket.close();
+        let _ = socket.flush().await;
+    }
+}
+
+/// Serve the dashboard and the JSON API from the model
+///
+/// Configuration changes are saved before `changed` is signalled. Uploaded
+/// firmware is written to `firmware`, `installed` is signalled once an image
+/// was checked and activated.
+pub async fn run_server<M: RawMutex, S: Storage, F: FirmwareSlots>(
+    stack: Stack<'_>,
+    model: &Mutex<M, Model>,
+    config: &Mutex<M, Configuration<S>>,
+    changed: &Signal<M, ()>,
+    firmware: &Mutex<M, F>,
+    installed: &Signal<M, ()>,
+) -> ! {
+    let mut rx_buffer = [0u8; 1024];
+    let mut tx_buffer = [0u8; 4096];
+    let mut request = [0u8; MAX_REQUEST];
+    let mut body = api::Body::new();
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(REQUEST_TIMEOUT));
+        if socket.accept(HTTP_PORT).await.is_err() {
+            continue;
+        }
+
+        let result = match read_request(&mut socket, &mut request, api::is_firmware_upload).await {
+            Ok(len) => {
+                // Complete or streamed, it was parsed by `read_request()`
+                let Ok(Some(head)) = http::parse_head(&request[..len]) else {
+                    continue;
+                };
+                let reply = if api::is_firmware_upload(&head) {
+                    install_upload(
+                        &mut socket,
+                        &head,
+                        model,
+                        config,
+                        firmware,
+                        installed,
+                        &mut body,
+                    )
+                    .await
+                } else {
+                    // Complete, it was parsed by `read_request()`
+                    let Ok(Some(parsed)) = http::parse_request(&request[..len]) else {
+                        continue;
+                    };
+                    if api::is_config_request(&parsed) {
+                        let mut configuration = config.lock().await;
+                        let (mut reply, updated) =
+                            api::handle_config(&parsed, &configuration.current, &mut body);
+                        if let Some(updated) = updated {
+                            configuration.current = updated;
+                            match configuration.save() {
+                                Ok(()) => changed.signal(()),
+                                Err(e) => {
+                                    model.lock().await.errors.record(&e);
+                                    reply =
+                                        api::Reply::error(Status::InternalServerError, &mut body);
+                                }
+                            }
+                        }
+                        reply
+                    } else {
+                        let m = model.lock().await;
+                        api::handle(&parsed, &m, Instant::now(), &mut body)
+                    }
+                };
+                let content = match head.method {
+                    Method::Head => &[][..],
+                    _ => body.as_bytes(),
+                };
+                respond(&mut socket, reply.status, reply.content_type, content, None).await
+            }
+            Err(status) => {
+                let reason = status.reason().as_bytes();
+                respond(&mut socket, status, "text/plain", reason, None).await
+            }
+        };
+        // A failed response only concerns the client that went away
+        let _ = result;
+        socket.close();
+        let _ = socket.flush().await;
+    }
+}
+
+/// MQTT broker settings of the configuration
+fn mqtt_settings<S: Storage>(
+    configuration: &Configuration<S>,
+) -> (String<48>, String<32>, String<64>) {
+    let config = &configuration.current;
+    (
+        config.mqtt_broker.clone(),
+        config.mqtt_username.clone(),
+        config.mqtt_password.clone(),
+    )
+}
+
+/// Publish the readings of the model to the configured MQTT broker
+///
+/// Announces the sensors to Home Assistant, then publishes the state every
+/// `PUBLISH_INTERVAL`. Returns when the broker settings change, or with the
+/// error that ended the session. `failures` is reset once the broker accepted
+/// the connection.
+pub async fn run_mqtt_session<M: RawMutex, S: Storage>(
+    stack: Stack<'_>,
+    model: &Mutex<M, Model>,
+    config: &Mutex<M, Configuration<S>>,
+    topics: &Topics,
+    failures: &mut u32,
+) -> Result<(), GonkError> {
+    let settings = mqtt_settings(&*config.lock().await);
+    let (broker, username, password) = &settings;
+    let Some((host, port)) = mqtt::parse_broker(broker) else {
+        return Ok(());
+    };
+
+    let address = resolve(stack, host).await?;
+    let mut rx_buffer = [0u8; 256];
+    let mut tx_buffer = [0u8; mqtt::MAX_PACKET];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.set_timeout(Some(mqtt::KEEP_ALIVE));
+    socket
+        .connect((address, port))
+        .await
+        .map_err(|_| NetworkError::Connect)?;
+
+    let client_id = topics.client_id();
+    let options = Options {
+        client_id: &client_id,
+        username,
+        password,
+        will: Some((&topics.status, "offline")),
+    };
+    let mut client = MqttClient::connect(&mut socket, &options).await?;
+    *failures = 0;
+
+    client.publish(&topics.status, b"online", true).await?;
+    let mut message = String::<{ mqtt::MAX_PACKET }>::new();
+    for sensor in &SENSORS {
+        message.clear();
+        topics
+            .write_discovery(&mut message, sensor)
+            .map_err(|_| NetworkError::BadRequest)?;
+        client
+            .publish(&topics.discovery(sensor), message.as_bytes(), true)
+            .await?;
+    }
+
+    loop {
+        message.clear();
+        mqtt::write_state(&mut message, &*model.lock().await, Instant::now())
+            .map_err(|_| NetworkError::BadRequest)?;
+        client
+            .publish(&topics.state, message.as_bytes(), false)
+            .await?;
+
+        Timer::after(mqtt::PUBLISH_INTERVAL).await;
+        if mqtt_settings(&*config.lock().await) != settings {
+            // Leaving cleanly, the broker does not publish the last will
+            let _ = client.publish(&topics.status, b"offline", true).await;
+            let _ = client.disconnect().await;
+            socket.close();
+            let _ = socket.flush().await;
+            return Ok(());
+        }
+        client.ping().await?;
+    }
+}
@@ -0,0 +1,294 @@
+//! Firmware updates over the network, with rollback (hardware-independent)
+//!
+//! An uploaded image is written to the application slot not running while
+//! its SHA-256 is computed, along with its HMAC-SHA-256 when an update key is
+//! configured. The slot is only booted when the digest is the one announced
+//! and the image is signed with the key.
+//!
+//! The new image then runs on trial. It confirms itself once it has run for
+//! `CONFIRM_AFTER` and joined the WiFi network, so that it can be updated
+//! again. When it has not after `TRIAL_TIMEOUT`, the previous image is booted
+//! back. Images crashing earlier are rolled back by the bootloader, when it
+//! is built with rollback support.
+
+use embassy_time::{Duration, Instant};
+
+use crate::error::{GonkError, UpdateError};
+use crate::model::Model;
+use crate::sha256::{self, DIGEST_LEN, Hmac, Sha256};
+use crate::traits::FirmwareSlots;
+use crate::wifi::WifiState;
+
+/// Flash sector, the image is written a sector at a time
+pub const SECTOR: usize = 4096;
+/// First byte of an ESP application image
+const IMAGE_MAGIC: u8 = 0xE9;
+/// Time a new image runs before it is kept
+pub const CONFIRM_AFTER: Duration = Duration::from_secs(60);
+/// Time a new image has to join the network before it is rolled back
+pub const TRIAL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Image being received into the update slot
+pub struct Update {
+    /// Length announced
+    size: u32,
+    received: u32,
+    digest: [u8; DIGEST_LEN],
+    signature: Option<[u8; DIGEST_LEN]>,
+    hasher: Sha256,
+    /// Set when an update key is configured
+    mac: Option<Hmac>,
+    /// Data not yet written, the sector after `written`
+    sector: [u8; SECTOR],
+    buffered: usize,
+    written: u32,
+}
+
+impl Update {
+    /// Start receiving an image of `size` bytes with the SHA-256 `digest`
+    ///
+    /// When `key` is not empty the image must come with its HMAC-SHA-256
+    /// `signature` under that key. `capacity` is the size of the update slot.
+    pub fn begin(
+        size: u32,
+        digest: [u8; DIGEST_LEN],
+        signature: Option<[u8; DIGEST_LEN]>,
+        key: &str,
+        capacity: u32,
+    ) -> Result<Self, GonkError> {
+        if size == 0 {
+            return Err(UpdateError::Incomplete.into());
+        }
+        if size > capacity {
+            return Err(UpdateError::TooLarge.into());
+        }
+        if !key.is_empty() && signature.is_none() {
+            return Err(UpdateError::BadSignature.into());
+        }
+        Ok(Self {
+            size,
+            received: 0,
+            digest,
+            signature,
+            hasher: Sha256::new(),
+            mac: (!key.is_empty()).then(|| Hmac::new(key.as_bytes())),
+            sector: [0xFF; SECTOR],
+            buffered: 0,
+            written: 0,
+        })
+    }
+
+    /// Length announced
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Bytes received so far
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    /// Take the next piece of the image
+    pub fn write<F: FirmwareSlots>(&mut self, slots: &mut F, data: &[u8]) -> Result<(), GonkError> {
+        if data.len() > (self.size - self.received) as usize {
+            return Err(UpdateError::TooLarge.into());
+        }
+        if self.received == 0 && data.first().is_some_and(|&byte| byte != IMAGE_MAGIC) {
+            return Err(UpdateError::NotAnImage.into());
+        }
+        self.hasher.update(data);
+        if let Some(mac) = &mut self.mac {
+            mac.update(data);
+        }
+        self.received += data.len() as u32;
+
+        let mut data = data;
+        while !data.is_empty() {
+            let taken = data.len().min(SECTOR - self.buffered);
+            self.sector[self.buffered..self.buffered + taken].copy_from_slice(&data[..taken]);
+            self.buffered += taken;
+            data = &data[taken..];
+            if self.buffered == SECTOR {
+                slots.write(self.written, &self.sector)?;
+                self.written += SECTOR as u32;
+                self.buffered = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the image received and boot it from the next reset
+    pub fn finish<F: FirmwareSlots>(mut self, slots: &mut F) -> Result<(), GonkError> {
+        if self.received < self.size {
+            return Err(UpdateError::Incomplete.into());
+        }
+        if !sha256::equal(&self.hasher.finish(), &self.digest) {
+            return Err(UpdateError::DigestMismatch.into());
+        }
+        if let Some(mac) = self.mac {
+            let signed = self
+                .signature
+                .is_some_and(|signature| sha256::equal(&mac.finish(), &signature));
+            if !signed {
+                return Err(UpdateError::BadSignature.into());
+            }
+        }
+
+        if self.buffered > 0 {
+            slots.write(self.written, &self.sector[..self.buffered])?;
+            self.written += self.buffered as u32;
+        }
+        slots.activate()
+    }
+}
+
+/// Image running, as found at boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BootInfo {
+    /// OTA slot booted, `None` for a factory image
+    pub slot: Option<u8>,
+    /// The image was just installed and is not confirmed yet
+    pub trial: bool,
+}
+
+/// What to do with an image on trial
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Wait,
+    /// Healthy, keep booting it
+    Confirm,
+    /// Boot the previous image back
+    RollBack,
+}
+
+/// Judge an image on trial from the model, `now` being the time since boot
+pub fn judge_trial(model: &Model, now: Instant) -> Verdict {
+    let elapsed = Duration::from_ticks(now.as_ticks());
+    if elapsed >= CONFIRM_AFTER && matches!(model.wifi, WifiState::Connected(_)) {
+        Verdict::Confirm
+    } else if elapsed >= TRIAL_TIMEOUT {
+        Verdict::RollBack
+    } else {
+        Verdict::Wait
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockSlots;
+    use crate::wifi::Link;
+
+    fn image(len: usize) -> heapless::Vec<u8, 12288> {
+        (0..len)
+            .map(|i| if i == 0 { IMAGE_MAGIC } else { (i * 7) as u8 })
+            .collect()
+    }
+
+    /// Send `image` in pieces of `piece` bytes, then finish the update
+    fn send(
+        slots: &mut MockSlots,
+        mut update: Update,
+        image: &[u8],
+        piece: usize,
+    ) -> Result<(), GonkError> {
+        for chunk in image.chunks(piece) {
+            update.write(slots, chunk)?;
+        }
+        update.finish(slots)
+    }
+
+    #[test]
+    fn writes_and_activates_image() {
+        let image = image(10_000);
+        let mut slots = MockSlots::new(16_384);
+        let update = Update::begin(10_000, sha256::digest(&image), None, "", 16_384).unwrap();
+        assert_eq!(update.size(), 10_000);
+
+        send(&mut slots, update, &image, 1000).unwrap();
+        assert_eq!(slots.image, image);
+        // Two full sectors, then the rest
+        assert_eq!(slots.writes, 3);
+        assert!(slots.activated);
+    }
+
+    #[test]
+    fn rejects_bad_images() {
+        let image = image(5000);
+        let digest = sha256::digest(&image);
+        let begin = |size| Update::begin(size, digest, None, "", 8192);
+        let mut slots = MockSlots::new(8192);
+
+        assert_eq!(begin(9000).err(), Some(UpdateError::TooLarge.into()));
+        assert_eq!(begin(0).err(), Some(UpdateError::Incomplete.into()));
+
+        let mut update = begin(5000).unwrap();
+        assert_eq!(
+            update.write(&mut slots, b"GIF89a"),
+            Err(UpdateError::NotAnImage.into())
+        );
+
+        let mut update = begin(5000).unwrap();
+        update.write(&mut slots, &image[..3000]).unwrap();
+        assert_eq!(update.received(), 3000);
+        assert_eq!(
+            update.write(&mut slots, &image[..3000]),
+            Err(UpdateError::TooLarge.into())
+        );
+        assert_eq!(
+            update.finish(&mut slots),
+            Err(UpdateError::Incomplete.into())
+        );
+
+        let mut corrupted = image.clone();
+        corrupted[4321] ^= 1;
+        assert_eq!(
+            send(&mut slots, begin(5000).unwrap(), &corrupted, 512),
+            Err(UpdateError::DigestMismatch.into())
+        );
+        assert!(!slots.activated);
+    }
+
+    #[test]
+    fn checks_signature() {
+        let image = image(3000);
+        let digest = sha256::digest(&image);
+        let mut mac = Hmac::new(b"update key");
+        mac.update(&image);
+        let signature = mac.finish();
+        let mut slots = MockSlots::new(8192);
+
+        assert_eq!(
+            Update::begin(3000, digest, None, "update key", 8192).err(),
+            Some(UpdateError::BadSignature.into())
+        );
+        let update = Update::begin(3000, digest, Some(signature), "other key", 8192).unwrap();
+        assert_eq!(
+            send(&mut slots, update, &image, 700),
+            Err(UpdateError::BadSignature.into())
+        );
+        assert!(!slots.activated);
+
+        let update = Update::begin(3000, digest, Some(signature), "update key", 8192).unwrap();
+        send(&mut slots, update, &image, 700).unwrap();
+        assert!(slots.activated);
+    }
+
+    #[test]
+    fn judges_image_on_trial() {
+        let mut model = Model::new();
+        let at = Instant::from_secs;
+        assert_eq!(judge_trial(&model, at(5)), Verdict::Wait);
+        assert_eq!(judge_trial(&model, at(120)), Verdict::Wait);
+        assert_eq!(judge_trial(&model, at(600)), Verdict::RollBack);
+
+        model.wifi = WifiState::Connected(Link {
+            ssid: heapless::String::try_from("home").unwrap(),
+            bssid: [0; 6],
+            channel: 1,
+            rssi: -50,
+        });
+        assert_eq!(judge_trial(&model, at(5)), Verdict::Wait);
+        assert_eq!(judge_trial(&model, at(60)), Verdict::Confirm);
+    }
+}
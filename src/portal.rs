@@ -0,0 +1,545 @@
+//! WiFi provisioning portal (hardware-independent)
+//!
+//! When no stored network works the device opens an access point. Its DHCP
+//! server hands out addresses in the access point subnet and every DNS query
+//! is answered with the device address, so that phones joining the access
+//! point show the setup page where the SSID and password are entered.
+
+use core::fmt::{self, Write};
+
+use embassy_time::Duration;
+use heapless::String;
+
+use crate::config::WifiNetwork;
+use crate::http::{self, Method, Request, Status};
+
+/// Address of the device on the access point subnet
+pub const PORTAL_ADDRESS: [u8; 4] = [192, 168, 4, 1];
+pub const PORTAL_URL: &str = "http://192.168.4.1/";
+/// Connection attempts failing in a row before the portal opens
+pub const FAILURES_BEFORE_PORTAL: u32 = 3;
+/// How long the portal stays open before the known networks are tried again,
+/// in case they were only out of reach for a while
+pub const PORTAL_TIMEOUT: Duration = Duration::from_secs(600);
+
+pub const DNS_PORT: u16 = 53;
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+/// Access point name, made unique with the end of the MAC address
+pub fn portal_ssid(mac: [u8; 6]) -> String<32> {
+    let mut ssid = String::new();
+    let _ = write!(ssid, "Gonk-Setup-{:02X}{:02X}", mac[4], mac[5]);
+    ssid
+}
+
+const DNS_HEADER_LEN: usize = 12;
+const DNS_TYPE_A: u16 = 1;
+const DNS_TYPE_ANY: u16 = 255;
+const DNS_CLASS_IN: u16 = 1;
+const DNS_TTL_S: u32 = 60;
+
+/// Answer a DNS query with `address` whatever the name, returns the length
+/// of the response written to `out`
+///
+/// `None` when the packet is not a standard query for one name.
+pub fn dns_response(query: &[u8], address: [u8; 4], out: &mut [u8]) -> Option<usize> {
+    let header = query.get(..DNS_HEADER_LEN)?;
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    // A response, or an opcode other than a standard query
+    if flags & 0xF800 != 0 || questions != 1 {
+        return None;
+    }
+
+    let mut pos = DNS_HEADER_LEN;
+    loop {
+        let len = *query.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // Compression pointers are not expected in a question
+        if len > 63 {
+            return None;
+        }
+        pos += len;
+    }
+    let question = query.get(pos..pos + 4)?;
+    let qtype = u16::from_be_bytes([question[0], question[1]]);
+    let qclass = u16::from_be_bytes([question[2], question[3]]);
+    let question_end = pos + 4;
+
+    let answered = matches!(qtype, DNS_TYPE_A | DNS_TYPE_ANY) && qclass == DNS_CLASS_IN;
+    let len = question_end + if answered { 16 } else { 0 };
+    let out = out.get_mut(..len)?;
+
+    out[..question_end].copy_from_slice(&query[..question_end]);
+    // Authoritative response, recursion desired copied from the query
+    let flags = 0x8400 | (flags & 0x0100);
+    out[2..4].copy_from_slice(&flags.to_be_bytes());
+    out[6..8].copy_from_slice(&(answered as u16).to_be_bytes());
+    out[8..12].fill(0);
+
+    if answered {
+        let answer = &mut out[question_end..];
+        // Name pointing to the question
+        answer[..2].copy_from_slice(&[0xC0, DNS_HEADER_LEN as u8]);
+        answer[2..4].copy_from_slice(&DNS_TYPE_A.to_be_bytes());
+        answer[4..6].copy_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        answer[6..10].copy_from_slice(&DNS_TTL_S.to_be_bytes());
+        answer[10..12].copy_from_slice(&4u16.to_be_bytes());
+        answer[12..16].copy_from_slice(&address);
+    }
+    Some(len)
+}
+
+const DHCP_OPTIONS: usize = 240;
+const DHCP_MIN_LEN: usize = 300;
+const DHCP_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const DHCP_LEASE_S: u32 = 3600;
+/// Last byte of the first address handed out
+const FIRST_LEASE: u8 = 100;
+const MAX_LEASES: usize = 8;
+
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_ACK: u8 = 5;
+const DHCP_NAK: u8 = 6;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_ADDRESS: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+/// Value of a DHCP option
+fn dhcp_option(options: &[u8], code: u8) -> Option<&[u8]> {
+    let mut pos = 0;
+    while let Some(&option) = options.get(pos) {
+        match option {
+            OPTION_PAD => pos += 1,
+            OPTION_END => return None,
+            _ => {
+                let len = *options.get(pos + 1)? as usize;
+                let value = options.get(pos + 2..pos + 2 + len)?;
+                if option == code {
+                    return Some(value);
+                }
+                pos += 2 + len;
+            }
+        }
+    }
+    None
+}
+
+/// DHCP server of the access point, the device is the router and DNS server
+pub struct DhcpServer {
+    address: [u8; 4],
+    /// MAC address holding each lease
+    leases: [Option<[u8; 6]>; MAX_LEASES],
+    /// Lease given away when all are taken
+    next_reused: usize,
+}
+
+impl DhcpServer {
+    pub fn new(address: [u8; 4]) -> Self {
+        Self {
+            address,
+            leases: [None; MAX_LEASES],
+            next_reused: 0,
+        }
+    }
+
+    fn lease_address(&self, index: usize) -> [u8; 4] {
+        let [a, b, c, _] = self.address;
+        [a, b, c, FIRST_LEASE + index as u8]
+    }
+
+    /// Lease of `mac`, taking a free one or the oldest given away
+    fn lease(&mut self, mac: [u8; 6]) -> usize {
+        if let Some(index) = self.leases.iter().position(|lease| *lease == Some(mac)) {
+            return index;
+        }
+        let index = match self.leases.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                let index = self.next_reused;
+                self.next_reused = (index + 1) % MAX_LEASES;
+                index
+            }
+        };
+        self.leases[index] = Some(mac);
+        index
+    }
+
+    /// Answer a client message, returns the length of the reply written to
+    /// `out`, to be broadcast to the client port
+    ///
+    /// `None` when there is nothing to answer.
+    pub fn handle(&mut self, request: &[u8], out: &mut [u8]) -> Option<usize> {
+        if request.len() < DHCP_OPTIONS || request[..3] != [1, 1, 6] {
+            return None;
+        }
+        if request[236..DHCP_OPTIONS] != DHCP_COOKIE {
+            return None;
+        }
+        let options = &request[DHCP_OPTIONS..];
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&request[28..34]);
+
+        let kind = *dhcp_option(options, OPTION_MESSAGE_TYPE)?.first()?;
+        if kind != DHCP_DISCOVER && kind != DHCP_REQUEST {
+            return None;
+        }
+        if dhcp_option(options, OPTION_SERVER_ID).is_some_and(|id| id != self.address) {
+            // The client chose another server
+            return None;
+        }
+        let index = self.lease(mac);
+        let lease = self.lease_address(index);
+
+        let reply = if kind == DHCP_DISCOVER {
+            DHCP_OFFER
+        } else {
+            let requested = match dhcp_option(options, OPTION_REQUESTED_ADDRESS) {
+                Some(requested) => requested,
+                None => &request[12..16],
+            };
+            if requested == lease {
+                DHCP_ACK
+            } else {
+                DHCP_NAK
+            }
+        };
+
+        let out = out.get_mut(..DHCP_MIN_LEN)?;
+        out.fill(0);
+        // Boot reply, Ethernet, 6 bytes hardware address
+        out[..3].copy_from_slice(&[2, 1, 6]);
+        // Transaction id, seconds and flags
+        out[4..12].copy_from_slice(&request[4..12]);
+        if reply != DHCP_NAK {
+            out[16..20].copy_from_slice(&lease);
+        }
+        out[20..24].copy_from_slice(&self.address);
+        out[24..44].copy_from_slice(&request[24..44]);
+        out[236..DHCP_OPTIONS].copy_from_slice(&DHCP_COOKIE);
+
+        let mut pos = DHCP_OPTIONS;
+        let mut option = |code: u8, value: &[u8]| {
+            out[pos] = code;
+            out[pos + 1] = value.len() as u8;
+            out[pos + 2..pos + 2 + value.len()].copy_from_slice(value);
+            pos += 2 + value.len();
+        };
+        option(OPTION_MESSAGE_TYPE, &[reply]);
+        option(OPTION_SERVER_ID, &self.address);
+        if reply != DHCP_NAK {
+            option(OPTION_LEASE_TIME, &DHCP_LEASE_S.to_be_bytes());
+            option(OPTION_SUBNET_MASK, &[255, 255, 255, 0]);
+            option(OPTION_ROUTER, &self.address);
+            option(OPTION_DNS, &self.address);
+        }
+        out[pos] = OPTION_END;
+
+        Some(DHCP_MIN_LEN)
+    }
+}
+
+/// Result of a request to the setup page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortalReply {
+    Form,
+    /// The form was filled in with a network to connect to
+    Saved(WifiNetwork),
+    /// The form was filled in wrongly, with the reason
+    Invalid(&'static str),
+    /// Any other page leads to the setup page, which is what makes phones
+    /// open it when they join the access point
+    Redirect,
+}
+
+/// Check the credentials entered in the form
+fn parse_form(form: &str) -> Result<WifiNetwork, &'static str> {
+    let ssid = http::form_value::<32>(form, "ssid").unwrap_or_default();
+    let password = match http::form_value::<64>(form, "password") {
+        Some(password) => password,
+        None if form.split('&').any(|pair| pair.starts_with("password=")) => {
+            return Err("The password is too long");
+        }
+        None => String::new(),
+    };
+    if ssid.is_empty() {
+        return Err("Enter the network name");
+    }
+    let network = WifiNetwork { ssid, password };
+    if !network.has_valid_password() {
+        return Err("The password must have 8 to 63 characters");
+    }
+    Ok(network)
+}
+
+/// Handle a request to the portal web server
+pub fn handle_request(request: &Request) -> PortalReply {
+    match (request.method, request.path) {
+        (Method::Get | Method::Head, "/") => PortalReply::Form,
+        (Method::Post, "/save") => match request.body_str().map(parse_form) {
+            Some(Ok(network)) => PortalReply::Saved(network),
+            Some(Err(reason)) => PortalReply::Invalid(reason),
+            None => PortalReply::Invalid("The form could not be read"),
+        },
+        _ => PortalReply::Redirect,
+    }
+}
+
+const FORM: &str = "<form method=\"post\" action=\"/save\">\
+<p><label>Network<br><input name=\"ssid\" maxlength=\"32\" required></label></p>\
+<p><label>Password<br><input name=\"password\" type=\"password\" maxlength=\"63\"></label></p>\
+<p><button>Save</button></p></form>";
+
+impl PortalReply {
+    pub fn status(&self) -> Status {
+        match self {
+            PortalReply::Form | PortalReply::Saved(_) => Status::Ok,
+            PortalReply::Invalid(_) => Status::BadRequest,
+            PortalReply::Redirect => Status::Found,
+        }
+    }
+
+    pub fn location(&self) -> Option<&'static str> {
+        matches!(self, PortalReply::Redirect).then_some(PORTAL_URL)
+    }
+
+    /// HTML page of the reply, nothing for a redirect
+    pub fn write_page<W: Write>(&self, out: &mut W) -> fmt::Result {
+        if *self == PortalReply::Redirect {
+            return Ok(());
+        }
+        out.write_str(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+<meta name=\"viewport\" content=\"width=device-width\"><title>Gonk setup</title></head>\
+<body><h1>Gonk WiFi setup</h1>",
+        )?;
+        match self {
+            PortalReply::Form => out.write_str(FORM)?,
+            PortalReply::Invalid(reason) => {
+                write!(out, "<p><strong>{}</strong></p>{}", reason, FORM)?;
+            }
+            PortalReply::Saved(network) => {
+                out.write_str("<p>Saved, the device now connects to ")?;
+                http::write_html_escaped(out, &network.ssid)?;
+                out.write_str(". This page can be closed.</p>")?;
+            }
+            PortalReply::Redirect => {}
+        }
+        out.write_str("</body></html>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::parse_request;
+
+    /// Query for example.com with the given type
+    fn dns_query(qtype: u16) -> heapless::Vec<u8, 64> {
+        let mut query = heapless::Vec::new();
+        query
+            .extend_from_slice(&[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0])
+            .unwrap();
+        query.extend_from_slice(b"\x07example\x03com\x00").unwrap();
+        query.extend_from_slice(&qtype.to_be_bytes()).unwrap();
+        query.extend_from_slice(&[0, 1]).unwrap();
+        query
+    }
+
+    #[test]
+    fn answers_every_name_with_portal_address() {
+        let query = dns_query(DNS_TYPE_A);
+        let mut out = [0u8; 128];
+        let len = dns_response(&query, PORTAL_ADDRESS, &mut out).unwrap();
+
+        assert_eq!(len, query.len() + 16);
+        assert_eq!(&out[..2], &[0x12, 0x34]);
+        assert_eq!(&out[2..4], &[0x85, 0x00]);
+        assert_eq!(&out[6..8], &[0, 1]);
+        assert_eq!(&out[12..query.len()], &query[12..]);
+        assert_eq!(&out[len - 4..len], &PORTAL_ADDRESS);
+    }
+
+    #[test]
+    fn answers_other_types_without_records() {
+        let query = dns_query(28);
+        let mut out = [0u8; 128];
+        let len = dns_response(&query, PORTAL_ADDRESS, &mut out).unwrap();
+
+        assert_eq!(len, query.len());
+        assert_eq!(&out[6..8], &[0, 0]);
+    }
+
+    #[test]
+    fn ignores_non_queries() {
+        let mut out = [0u8; 128];
+        let mut response = dns_query(DNS_TYPE_A);
+        response[2] |= 0x80;
+
+        assert_eq!(dns_response(&response, PORTAL_ADDRESS, &mut out), None);
+        assert_eq!(dns_response(&[0; 5], PORTAL_ADDRESS, &mut out), None);
+        let truncated = dns_query(DNS_TYPE_A);
+        assert_eq!(
+            dns_response(&truncated[..20], PORTAL_ADDRESS, &mut out),
+            None
+        );
+    }
+
+    fn dhcp_message(mac: [u8; 6], kind: u8, requested: Option<[u8; 4]>) -> [u8; 300] {
+        let mut message = [0u8; 300];
+        message[..3].copy_from_slice(&[1, 1, 6]);
+        message[4..8].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        message[28..34].copy_from_slice(&mac);
+        message[236..240].copy_from_slice(&DHCP_COOKIE);
+        message[240..243].copy_from_slice(&[OPTION_MESSAGE_TYPE, 1, kind]);
+        let mut pos = 243;
+        if let Some(address) = requested {
+            message[pos..pos + 2].copy_from_slice(&[OPTION_REQUESTED_ADDRESS, 4]);
+            message[pos + 2..pos + 6].copy_from_slice(&address);
+            pos += 6;
+        }
+        message[pos] = OPTION_END;
+        message
+    }
+
+    const PHONE: [u8; 6] = [0x02, 0, 0, 0, 0, 1];
+    const LAPTOP: [u8; 6] = [0x02, 0, 0, 0, 0, 2];
+
+    #[test]
+    fn offers_and_acknowledges_lease() {
+        let mut server = DhcpServer::new(PORTAL_ADDRESS);
+        let mut out = [0u8; 512];
+
+        let len = server
+            .handle(&dhcp_message(PHONE, DHCP_DISCOVER, None), &mut out)
+            .unwrap();
+        assert_eq!(len, 300);
+        assert_eq!(out[0], 2);
+        assert_eq!(&out[4..8], &[0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(&out[16..20], &[192, 168, 4, 100]);
+        assert_eq!(&out[28..34], &PHONE);
+        let options = &out[DHCP_OPTIONS..len];
+        assert_eq!(
+            dhcp_option(options, OPTION_MESSAGE_TYPE),
+            Some(&[DHCP_OFFER][..])
+        );
+        assert_eq!(dhcp_option(options, OPTION_DNS), Some(&PORTAL_ADDRESS[..]));
+
+        let request = dhcp_message(PHONE, DHCP_REQUEST, Some([192, 168, 4, 100]));
+        let len = server.handle(&request, &mut out).unwrap();
+        let options = &out[DHCP_OPTIONS..len];
+        assert_eq!(
+            dhcp_option(options, OPTION_MESSAGE_TYPE),
+            Some(&[DHCP_ACK][..])
+        );
+
+        server.handle(&dhcp_message(LAPTOP, DHCP_DISCOVER, None), &mut out);
+        assert_eq!(&out[16..20], &[192, 168, 4, 101]);
+    }
+
+    #[test]
+    fn refuses_foreign_address() {
+        let mut server = DhcpServer::new(PORTAL_ADDRESS);
+        let mut out = [0u8; 512];
+
+        let request = dhcp_message(PHONE, DHCP_REQUEST, Some([10, 0, 0, 7]));
+        let len = server.handle(&request, &mut out).unwrap();
+        let options = &out[DHCP_OPTIONS..len];
+
+        assert_eq!(
+            dhcp_option(options, OPTION_MESSAGE_TYPE),
+            Some(&[DHCP_NAK][..])
+        );
+        assert_eq!(&out[16..20], &[0, 0, 0, 0]);
+        assert_eq!(server.handle(&[0u8; 100], &mut out), None);
+    }
+
+    #[test]
+    fn reuses_oldest_lease_when_full() {
+        let mut server = DhcpServer::new(PORTAL_ADDRESS);
+        let mut out = [0u8; 512];
+        for i in 0..=MAX_LEASES as u8 {
+            let mac = [0x02, 0, 0, 0, 1, i];
+            server.handle(&dhcp_message(mac, DHCP_DISCOVER, None), &mut out);
+        }
+
+        assert_eq!(&out[16..20], &[192, 168, 4, 100]);
+    }
+
+    fn reply(raw: &[u8]) -> PortalReply {
+        handle_request(&parse_request(raw).unwrap().unwrap())
+    }
+
+    #[test]
+    fn serves_form_and_redirects_everything_else() {
+        assert_eq!(reply(b"GET / HTTP/1.1\r\n\r\n"), PortalReply::Form);
+
+        let redirect = reply(b"GET /generate_204 HTTP/1.1\r\n\r\n");
+        assert_eq!(redirect, PortalReply::Redirect);
+        assert_eq!(redirect.status(), Status::Found);
+        assert_eq!(redirect.location(), Some(PORTAL_URL));
+    }
+
+    #[test]
+    fn saves_submitted_network() {
+        let saved = reply(
+            b"POST /save HTTP/1.1\r\nContent-Length: 30\r\n\r\nssid=My+Home&password=hunter22",
+        );
+
+        assert_eq!(
+            saved,
+            PortalReply::Saved(WifiNetwork::new("My Home", "hunter22").unwrap())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_form() {
+        assert_eq!(
+            reply(b"POST /save HTTP/1.1\r\nContent-Length: 19\r\n\r\nssid=&password=abc1"),
+            PortalReply::Invalid("Enter the network name")
+        );
+        assert_eq!(
+            reply(b"POST /save HTTP/1.1\r\nContent-Length: 20\r\n\r\nssid=a&password=abc1"),
+            PortalReply::Invalid("The password must have 8 to 63 characters")
+        );
+        assert_eq!(
+            parse_form("ssid=open"),
+            Ok(WifiNetwork::new("open", "").unwrap())
+        );
+    }
+
+    #[test]
+    fn pages_escape_network_name() {
+        let mut page = String::<512>::new();
+        PortalReply::Saved(WifiNetwork::new("<Home>", "").unwrap())
+            .write_page(&mut page)
+            .unwrap();
+
+        assert!(page.contains("connects to &lt;Home&gt;."));
+
+        let mut page = String::<1024>::new();
+        PortalReply::Form.write_page(&mut page).unwrap();
+        assert!(page.contains("action=\"/save\""));
+    }
+
+    #[test]
+    fn ssid_ends_with_mac() {
+        assert_eq!(
+            portal_ssid([0x24, 0x6F, 0x28, 0x01, 0xAB, 0x0C]),
+            "Gonk-Setup-AB0C"
+        );
+    }
+}
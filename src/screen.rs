@@ -0,0 +1,1032 @@
+//! Display pages and button navigation (hardware-independent)
+//!
+//! A green click moves to the next page, holding it keeps moving, and a blue
+//! click toggles auto-rotate, or opens the menu on the settings page. Holding
+//! blue opens the WiFi setup portal. Button events reach the display loop
+//! through a `ButtonChannel`. The pages shown, their order and auto-rotate are
+//! set by the `Layout` of the configuration.
+
+use core::fmt::Write;
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant};
+use heapless::{String, Vec};
+
+use crate::error::GonkError;
+use crate::input::ButtonEvent;
+use crate::logic;
+use crate::menu::{Item, Menu, MenuAction};
+use crate::model::{Model, format_value};
+use crate::portal;
+use crate::settings::Settings;
+use crate::traits::Display;
+use crate::wifi::WifiState;
+
+/// How long a page stays up in auto-rotate mode by default, in seconds
+pub const DEFAULT_ROTATE_EVERY_S: u16 = 10;
+
+/// Number of points of the temperature chart on the trends page
+const CHART_POINTS: usize = 25;
+const CHART_TOP: i32 = 42;
+const CHART_BOTTOM: i32 = 63;
+const LINE_HEIGHT: i32 = 10;
+
+/// Front panel buttons
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    /// GPIO12
+    Green,
+    /// GPIO13
+    Blue,
+}
+
+/// Button events from the input task to the display loop
+pub type ButtonChannel = Channel<CriticalSectionRawMutex, ButtonEvent<Button>, 4>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Page {
+    Readings,
+    Trends,
+    /// Indoor and outdoor conditions side by side
+    Weather,
+    /// Local date and time
+    Clock,
+    Network,
+    System,
+    Settings,
+}
+
+impl Page {
+    pub const ALL: [Page; 7] = [
+        Page::Readings,
+        Page::Trends,
+        Page::Weather,
+        Page::Clock,
+        Page::Network,
+        Page::System,
+        Page::Settings,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Page::Readings => "Gonk Sensor Readings",
+            Page::Trends => "Trends",
+            Page::Weather => "Weather",
+            Page::Clock => "Clock",
+            Page::Network => "Network",
+            Page::System => "System",
+            Page::Settings => "Settings",
+        }
+    }
+
+    /// Name used by the configuration API
+    pub fn name(self) -> &'static str {
+        match self {
+            Page::Readings => "readings",
+            Page::Trends => "trends",
+            Page::Weather => "weather",
+            Page::Clock => "clock",
+            Page::Network => "network",
+            Page::System => "system",
+            Page::Settings => "settings",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|page| page.name() == name)
+    }
+
+    /// Position in `ALL`, as stored in the configuration
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+}
+
+/// Pages shown and how they rotate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// Pages the green button moves through, in order
+    pub pages: Vec<Page, { Page::ALL.len() }>,
+    /// Whether auto-rotate is on at startup
+    pub auto_rotate: bool,
+    /// How long a page stays up in auto-rotate mode, in seconds
+    pub rotate_every_s: u16,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            pages: Page::ALL.into_iter().collect(),
+            auto_rotate: false,
+            rotate_every_s: DEFAULT_ROTATE_EVERY_S,
+        }
+    }
+}
+
+impl Layout {
+    pub fn rotate_every(&self) -> Duration {
+        Duration::from_secs(self.rotate_every_s.max(1) as u64)
+    }
+
+    /// Every page is shown once at most, and the settings page is shown so
+    /// that the menu stays reachable
+    pub fn is_valid(&self) -> bool {
+        let pages = &self.pages;
+        pages.contains(&Page::Settings)
+            && pages
+                .iter()
+                .enumerate()
+                .all(|(i, page)| !pages[..i].contains(page))
+    }
+
+    /// Page following `page`, wrapping around
+    fn next(&self, page: Page) -> Page {
+        let next = match self.pages.iter().position(|&shown| shown == page) {
+            Some(index) => self.pages.get(index + 1),
+            None => None,
+        };
+        next.or(self.pages.first())
+            .copied()
+            .unwrap_or(Page::Settings)
+    }
+
+    /// Following page shown by auto-rotate, which skips the settings
+    fn next_rotated(&self, page: Page) -> Page {
+        match self.next(page) {
+            Page::Settings => self.next(Page::Settings),
+            page => page,
+        }
+    }
+}
+
+/// Result of a button event on the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// The event did nothing
+    None,
+    Redraw,
+    /// The settings menu was closed with changes, to apply and save
+    Apply(Settings),
+    /// Open the WiFi setup portal
+    Provision,
+}
+
+/// Page currently shown, the auto-rotate state and the settings menu
+pub struct Screen {
+    page: Page,
+    auto_rotate: bool,
+    layout: Layout,
+    /// When the page was last changed or auto-rotate was toggled
+    shown_since: Instant,
+    /// Last button event, for the screen timeout
+    last_activity: Instant,
+    settings: Settings,
+    menu: Option<Menu>,
+}
+
+impl Screen {
+    pub fn new(now: Instant, settings: Settings) -> Self {
+        Self {
+            page: Page::Readings,
+            auto_rotate: false,
+            layout: Layout::default(),
+            shown_since: now,
+            last_activity: now,
+            settings,
+            menu: None,
+        }
+    }
+
+    pub fn page(&self) -> Page {
+        self.page
+    }
+
+    pub fn auto_rotate(&self) -> bool {
+        self.auto_rotate
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// Replace the layout, leaving a page it no longer shows
+    ///
+    /// Auto-rotate is reset to the one of the layout when the layout changes.
+    pub fn set_layout(&mut self, layout: Layout, now: Instant) {
+        if layout == self.layout {
+            return;
+        }
+        self.auto_rotate = layout.auto_rotate;
+        if !layout.pages.contains(&self.page) {
+            self.page = layout.next(self.page);
+        }
+        self.shown_since = now;
+        self.layout = layout;
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Replace the settings changed elsewhere, closing the menu
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+        self.menu = None;
+    }
+
+    /// The settings menu, when it is open
+    pub fn menu(&self) -> Option<&Menu> {
+        self.menu.as_ref()
+    }
+
+    pub fn show(&mut self, page: Page, now: Instant) {
+        self.page = page;
+        self.shown_since = now;
+    }
+
+    /// Apply a button event
+    ///
+    /// The first event after the screen timed out only wakes the screen up.
+    pub fn handle(&mut self, event: ButtonEvent<Button>, now: Instant) -> Action {
+        let was_awake = self.awake(now);
+        self.last_activity = now;
+        if !was_awake {
+            return Action::Redraw;
+        }
+
+        if let Some(menu) = &mut self.menu {
+            return match menu.handle(event) {
+                MenuAction::None => Action::None,
+                MenuAction::Redraw => Action::Redraw,
+                MenuAction::Close(settings) => {
+                    self.menu = None;
+                    if settings == self.settings {
+                        Action::Redraw
+                    } else {
+                        self.settings = settings;
+                        Action::Apply(settings)
+                    }
+                }
+            };
+        }
+
+        match event {
+            ButtonEvent::Click(Button::Green) | ButtonEvent::Repeat(Button::Green) => {
+                self.show(self.layout.next(self.page), now)
+            }
+            ButtonEvent::Click(Button::Blue) if self.page == Page::Settings => {
+                self.menu = Some(Menu::new(self.settings));
+            }
+            ButtonEvent::Click(Button::Blue) => {
+                self.auto_rotate = !self.auto_rotate;
+                self.shown_since = now;
+            }
+            ButtonEvent::LongPress(Button::Blue) => {
+                // Show where to connect to
+                self.show(Page::Network, now);
+                return Action::Provision;
+            }
+            _ => return Action::None,
+        }
+        Action::Redraw
+    }
+
+    /// When auto-rotate moves to the next page, `None` when it is off
+    pub fn next_rotation(&self) -> Option<Instant> {
+        (self.auto_rotate && self.menu.is_none())
+            .then(|| self.shown_since + self.layout.rotate_every())
+    }
+
+    /// Rotate the page if it is due, returns `true` when the page changed
+    pub fn tick(&mut self, now: Instant) -> bool {
+        match self.next_rotation() {
+            Some(at) if now >= at => {
+                self.show(self.layout.next_rotated(self.page), now);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// When the screen turns off without button events, `None` when it stays on
+    pub fn sleep_at(&self) -> Option<Instant> {
+        self.settings
+            .screen_timeout()
+            .map(|timeout| self.last_activity + timeout)
+    }
+
+    /// Whether the screen is on
+    pub fn awake(&self, now: Instant) -> bool {
+        self.sleep_at().is_none_or(|at| now < at)
+    }
+}
+
+/// Draw the current page of the screen
+pub fn render<D: Display + ?Sized>(
+    display: &mut D,
+    screen: &Screen,
+    model: &Model,
+    now: Instant,
+) -> Result<(), GonkError> {
+    if let Some(menu) = screen.menu() {
+        return menu.render(display);
+    }
+
+    let page = screen.page();
+    match page {
+        Page::Readings => logic::update_display_with_model(display, model, now),
+        Page::Trends => draw_page(display, page, |display| draw_trends(display, model)),
+        Page::Weather => draw_page(display, page, |display| draw_weather(display, model, now)),
+        Page::Clock => draw_page(display, page, |display| draw_clock(display, model, now)),
+        Page::Network => draw_page(display, page, |display| draw_network(display, model)),
+        Page::System => draw_page(display, page, |display| draw_system(display, model, now)),
+        Page::Settings => draw_page(display, page, |display| draw_settings(display, screen)),
+    }
+}
+
+/// Draw a page with its title above `body`
+fn draw_page<D: Display + ?Sized>(
+    display: &mut D,
+    page: Page,
+    body: impl FnOnce(&mut D) -> Result<(), GonkError>,
+) -> Result<(), GonkError> {
+    display.clear()?;
+    display.draw_text(page.title(), 0, 0)?;
+    display.draw_line(
+        0,
+        LINE_HEIGHT + LINE_HEIGHT / 2,
+        127,
+        LINE_HEIGHT + LINE_HEIGHT / 2,
+    )?;
+    body(display)?;
+    display.update()
+}
+
+/// Draw text lines below the page header
+fn draw_lines<D: Display + ?Sized>(display: &mut D, lines: &[&str]) -> Result<(), GonkError> {
+    let mut y = 2 * LINE_HEIGHT;
+    for line in lines {
+        display.draw_text(line, 0, y)?;
+        y += LINE_HEIGHT;
+    }
+    Ok(())
+}
+
+fn draw_trends<D: Display + ?Sized>(display: &mut D, model: &Model) -> Result<(), GonkError> {
+    let mut temperatures = model
+        .history
+        .oldest_ordered()
+        .filter_map(|reading| reading.temperature);
+
+    let Some(first) = temperatures.next() else {
+        return draw_lines(display, &["No data yet"]);
+    };
+    let (min, max, last) = temperatures.fold((first, first, first), |(min, max, _), t| {
+        (min.min(t), max.max(t), t)
+    });
+
+    let units = model.units;
+    let mut range = String::<32>::new();
+    let _ = write!(
+        range,
+        "Temp: {:.1}..{:.1}{}",
+        units.convert(min),
+        units.convert(max),
+        units.suffix()
+    );
+    let mut trend = String::<32>::new();
+    let _ = write!(
+        trend,
+        "Trend: {:+.2}{}",
+        units.convert_delta(last - first),
+        units.suffix()
+    );
+    draw_lines(display, &[range.as_str(), trend.as_str()])?;
+
+    draw_chart(display, model, min, max)
+}
+
+/// Temperature history as a line chart at the bottom of the screen
+fn draw_chart<D: Display + ?Sized>(
+    display: &mut D,
+    model: &Model,
+    min: f32,
+    max: f32,
+) -> Result<(), GonkError> {
+    let count = model
+        .history
+        .oldest_ordered()
+        .filter(|reading| reading.temperature.is_some())
+        .count();
+    if count < 2 {
+        return Ok(());
+    }
+
+    // Keep at most CHART_POINTS evenly spread samples
+    let step = count.div_ceil(CHART_POINTS);
+    let points = (count - 1) / step + 1;
+    let span = if max > min { max - min } else { 1.0 };
+
+    let mut previous: Option<(i32, i32)> = None;
+    let samples = model
+        .history
+        .oldest_ordered()
+        .filter_map(|reading| reading.temperature)
+        .step_by(step);
+    for (i, t) in samples.enumerate() {
+        let x = if points > 1 {
+            (i * 127 / (points - 1)) as i32
+        } else {
+            0
+        };
+        let y = CHART_BOTTOM - ((t - min) / span * (CHART_BOTTOM - CHART_TOP) as f32) as i32;
+        if let Some((x0, y0)) = previous {
+            display.draw_line(x0, y0, x, y)?;
+        }
+        previous = Some((x, y));
+    }
+    Ok(())
+}
+
+fn draw_weather<D: Display + ?Sized>(
+    display: &mut D,
+    model: &Model,
+    now: Instant,
+) -> Result<(), GonkError> {
+    let Some(weather) = model.weather.as_ref().filter(|w| w.is_current(now)) else {
+        return draw_lines(display, &["No weather data"]);
+    };
+    let units = model.units;
+    let current = &weather.current;
+
+    let mut header = String::<32>::new();
+    let _ = write!(header, "{:>11}{:>7}", "In", "Out");
+    let mut temperature = String::<32>::new();
+    let _ = write!(
+        temperature,
+        "Temp {:>6} {:>6}{}",
+        format_value(model.display_temperature(now), 1, ""),
+        format_value(Some(units.convert(current.temperature)), 1, ""),
+        units.suffix()
+    );
+    let mut humidity = String::<32>::new();
+    let _ = write!(
+        humidity,
+        "Hum  {:>6} {:>6} %",
+        format_value(model.current(model.humidity, now), 0, ""),
+        format_value(Some(current.humidity), 0, "")
+    );
+    let mut outlook = String::<48>::new();
+    if let Some((min, max)) = weather.forecast_range() {
+        let _ = write!(
+            outlook,
+            "{:.0}..{:.0}{} ",
+            units.convert(min),
+            units.convert(max),
+            units.suffix()
+        );
+    }
+    let _ = outlook.push_str(&current.description);
+
+    draw_lines(
+        display,
+        &[
+            header.as_str(),
+            temperature.as_str(),
+            humidity.as_str(),
+            outlook.as_str(),
+        ],
+    )
+}
+
+fn draw_clock<D: Display + ?Sized>(
+    display: &mut D,
+    model: &Model,
+    now: Instant,
+) -> Result<(), GonkError> {
+    let (Some(local), Some(synced)) = (model.local_time(now), model.clock.last_sync()) else {
+        return draw_lines(display, &["Waiting for", "time sync"]);
+    };
+
+    // Without seconds, the page is redrawn every minute
+    let mut time = String::<32>::new();
+    let _ = write!(
+        time,
+        "{} {}",
+        model.time_format.format(&local.time, false),
+        model.timezone.abbreviation(local.dst)
+    );
+    let mut sync = String::<32>::new();
+    let _ = write!(
+        sync,
+        "Synced {} min ago",
+        now.saturating_duration_since(synced).as_secs() / 60
+    );
+    draw_lines(
+        display,
+        &[
+            time.as_str(),
+            local.time.format_date().as_str(),
+            sync.as_str(),
+        ],
+    )
+}
+
+fn draw_network<D: Display + ?Sized>(display: &mut D, model: &Model) -> Result<(), GonkError> {
+    let mut ip = String::<32>::new();
+    let _ = write!(ip, "IP: {}", model.ip_address);
+    let mut status = String::<32>::new();
+    let mut signal = String::<32>::new();
+    match &model.wifi {
+        WifiState::Provisioning(ssid) => {
+            return draw_lines(
+                display,
+                &["Setup: join WiFi", ssid.as_str(), portal::PORTAL_URL],
+            );
+        }
+        WifiState::Connected(link) => {
+            let _ = write!(status, "WiFi: {}", link.ssid);
+            let _ = write!(signal, "{} dBm, ch {}", link.rssi, link.channel);
+        }
+        WifiState::Connecting(ssid) => {
+            let _ = write!(status, "Joining {}", ssid);
+        }
+        WifiState::Scanning => {
+            let _ = status.push_str("Scanning");
+        }
+        WifiState::Disconnected => {
+            let _ = status.push_str("Disconnected");
+        }
+    }
+    let mut errors = String::<32>::new();
+    let _ = write!(errors, "Errors: {}", model.errors.network);
+
+    if signal.is_empty() {
+        draw_lines(display, &[ip.as_str(), status.as_str(), errors.as_str()])
+    } else {
+        draw_lines(
+            display,
+            &[
+                ip.as_str(),
+                status.as_str(),
+                signal.as_str(),
+                errors.as_str(),
+            ],
+        )
+    }
+}
+
+fn draw_system<D: Display + ?Sized>(
+    display: &mut D,
+    model: &Model,
+    now: Instant,
+) -> Result<(), GonkError> {
+    let mut uptime = String::<32>::new();
+    let _ = write!(uptime, "Uptime: {}", format_uptime(now));
+    let mut version = String::<32>::new();
+    let _ = write!(version, "Firmware: {}", env!("CARGO_PKG_VERSION"));
+    let mut errors = String::<32>::new();
+    let _ = write!(errors, "Errors: {}", model.errors.total());
+    draw_lines(
+        display,
+        &[uptime.as_str(), version.as_str(), errors.as_str()],
+    )
+}
+
+fn draw_settings<D: Display + ?Sized>(display: &mut D, screen: &Screen) -> Result<(), GonkError> {
+    let settings = screen.settings();
+    let mut rotate = String::<32>::new();
+    let _ = if screen.auto_rotate() {
+        write!(rotate, "Auto-rotate: {} s", screen.layout().rotate_every_s)
+    } else {
+        write!(rotate, "Auto-rotate: off")
+    };
+    draw_lines(
+        display,
+        &[
+            Item::Units.label(settings).as_str(),
+            Item::RefreshInterval.label(settings).as_str(),
+            rotate.as_str(),
+            "Blue: edit",
+        ],
+    )
+}
+
+/// Time since boot, e.g. "2 d 03:04:05"
+pub fn format_uptime(now: Instant) -> String<16> {
+    let mut buffer = String::new();
+    let secs = now.as_secs();
+    let (days, hours, minutes, seconds) =
+        (secs / 86_400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    let _ = if days > 0 {
+        write!(
+            buffer,
+            "{} d {:02}:{:02}:{:02}",
+            days, hours, minutes, seconds
+        )
+    } else {
+        write!(buffer, "{:02}:{:02}:{:02}", hours, minutes, seconds)
+    };
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{DisplayCall, MockDisplay};
+    use crate::traits::Reading;
+    use crate::tz::{TimeFormat, TimeZone};
+    use crate::weather::{CurrentWeather, Forecast, ForecastEntry, Weather};
+    use crate::wifi::Link;
+
+    fn model_with_temperatures(temperatures: &[f32]) -> Model {
+        let mut model = Model::new();
+        for (i, t) in temperatures.iter().enumerate() {
+            model.record_reading(&Reading {
+                temperature: Some(*t),
+                ..Reading::new(Instant::from_secs(i as u64 * 6))
+            });
+        }
+        model
+    }
+
+    #[test]
+    fn green_cycles_through_every_page() {
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+        let mut seen = [Page::Readings; 7];
+        for page in seen.iter_mut() {
+            *page = screen.page();
+            assert_eq!(
+                screen.handle(ButtonEvent::Click(Button::Green), Instant::from_secs(1)),
+                Action::Redraw
+            );
+        }
+
+        assert_eq!(seen, Page::ALL);
+        assert_eq!(screen.page(), Page::Readings);
+    }
+
+    #[test]
+    fn auto_rotate_skips_settings() {
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+        screen.show(Page::System, Instant::from_secs(0));
+        assert!(!screen.tick(Instant::from_secs(60)));
+
+        screen.handle(ButtonEvent::Click(Button::Blue), Instant::from_secs(100));
+        assert_eq!(screen.next_rotation(), Some(Instant::from_secs(110)));
+        assert!(!screen.tick(Instant::from_secs(109)));
+        assert!(screen.tick(Instant::from_secs(110)));
+        assert_eq!(screen.page(), Page::Readings);
+        assert_eq!(screen.next_rotation(), Some(Instant::from_secs(120)));
+    }
+
+    #[test]
+    fn follows_configured_layout() {
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+        screen.show(Page::Trends, Instant::from_secs(0));
+        let layout = Layout {
+            pages: [Page::Clock, Page::Readings, Page::Settings]
+                .into_iter()
+                .collect(),
+            auto_rotate: true,
+            rotate_every_s: 30,
+        };
+        screen.set_layout(layout, Instant::from_secs(5));
+
+        // The trends page is no longer shown
+        assert_eq!(screen.page(), Page::Clock);
+        assert!(screen.auto_rotate());
+        assert_eq!(screen.next_rotation(), Some(Instant::from_secs(35)));
+        assert!(screen.tick(Instant::from_secs(35)));
+        assert_eq!(screen.page(), Page::Readings);
+        assert!(screen.tick(Instant::from_secs(65)));
+        assert_eq!(screen.page(), Page::Clock);
+
+        let mut seen = [Page::Readings; 3];
+        for page in seen.iter_mut() {
+            screen.handle(ButtonEvent::Click(Button::Green), Instant::from_secs(70));
+            *page = screen.page();
+        }
+        assert_eq!(seen, [Page::Readings, Page::Settings, Page::Clock]);
+
+        // Setting the same layout again keeps auto-rotate as toggled
+        screen.handle(ButtonEvent::Click(Button::Blue), Instant::from_secs(71));
+        screen.set_layout(screen.layout().clone(), Instant::from_secs(72));
+        assert!(!screen.auto_rotate());
+    }
+
+    #[test]
+    fn layout_keeps_settings_reachable() {
+        let layout = |pages: &[Page]| Layout {
+            pages: pages.iter().copied().collect(),
+            ..Layout::default()
+        };
+
+        assert!(Layout::default().is_valid());
+        assert!(layout(&[Page::Settings]).is_valid());
+        assert!(!layout(&[Page::Readings, Page::Clock]).is_valid());
+        assert!(!layout(&[Page::Readings, Page::Settings, Page::Readings]).is_valid());
+    }
+
+    #[test]
+    fn names_pages() {
+        for page in Page::ALL {
+            assert_eq!(Page::from_name(page.name()), Some(page));
+            assert_eq!(Page::from_index(page.index()), Some(page));
+        }
+        assert_eq!(Page::from_name("graphs"), None);
+        assert_eq!(Page::from_index(7), None);
+    }
+
+    #[test]
+    fn manual_navigation_restarts_rotation_timer() {
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+        screen.handle(ButtonEvent::Click(Button::Blue), Instant::from_secs(0));
+        screen.handle(ButtonEvent::Click(Button::Green), Instant::from_secs(8));
+
+        assert_eq!(screen.page(), Page::Trends);
+        assert!(!screen.tick(Instant::from_secs(10)));
+        assert_eq!(screen.next_rotation(), Some(Instant::from_secs(18)));
+    }
+
+    #[test]
+    fn holding_green_keeps_moving() {
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+
+        assert_eq!(
+            screen.handle(ButtonEvent::LongPress(Button::Green), Instant::from_secs(1)),
+            Action::None
+        );
+        screen.handle(ButtonEvent::Repeat(Button::Green), Instant::from_secs(1));
+        screen.handle(ButtonEvent::Repeat(Button::Green), Instant::from_secs(1));
+        assert_eq!(screen.page(), Page::Weather);
+    }
+
+    #[test]
+    fn holding_blue_opens_setup_portal() {
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+
+        assert_eq!(
+            screen.handle(ButtonEvent::LongPress(Button::Blue), Instant::from_secs(1)),
+            Action::Provision
+        );
+        assert_eq!(screen.page(), Page::Network);
+
+        let mut model = Model::new();
+        model.wifi = WifiState::Provisioning(String::try_from("Gonk-Setup-AB0C").unwrap());
+        let mut display = MockDisplay::new();
+        render(&mut display, &screen, &model, Instant::from_secs(1)).unwrap();
+        assert!(display.texts().eq([
+            "Network",
+            "Setup: join WiFi",
+            "Gonk-Setup-AB0C",
+            "http://192.168.4.1/",
+        ]));
+    }
+
+    #[test]
+    fn network_page_shows_link() {
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+        screen.show(Page::Network, Instant::from_secs(0));
+        let mut model = Model::new();
+        model.ip_address = String::try_from("192.168.1.20").unwrap();
+        model.wifi = WifiState::Connected(Link {
+            ssid: String::try_from("home").unwrap(),
+            bssid: [0x24, 0x6F, 0x28, 0x01, 0xAB, 0x0C],
+            channel: 6,
+            rssi: -61,
+        });
+
+        let mut display = MockDisplay::new();
+        render(&mut display, &screen, &model, Instant::from_secs(1)).unwrap();
+        assert!(display.texts().eq([
+            "Network",
+            "IP: 192.168.1.20",
+            "WiFi: home",
+            "-61 dBm, ch 6",
+            "Errors: 0",
+        ]));
+    }
+
+    #[test]
+    fn settings_menu_applies_changes() {
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+        screen.show(Page::Settings, Instant::from_secs(0));
+        screen.handle(ButtonEvent::Click(Button::Blue), Instant::from_secs(1));
+        assert!(screen.menu().is_some());
+        assert!(!screen.auto_rotate());
+
+        screen.handle(ButtonEvent::Click(Button::Blue), Instant::from_secs(2));
+        let action = screen.handle(ButtonEvent::LongPress(Button::Green), Instant::from_secs(3));
+
+        let expected = Settings {
+            units: crate::settings::TemperatureUnit::Fahrenheit,
+            ..Settings::default()
+        };
+        assert_eq!(action, Action::Apply(expected));
+        assert_eq!(screen.settings(), &expected);
+        assert!(screen.menu().is_none());
+    }
+
+    #[test]
+    fn unchanged_menu_only_redraws() {
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+        screen.show(Page::Settings, Instant::from_secs(0));
+        screen.handle(ButtonEvent::Click(Button::Blue), Instant::from_secs(1));
+
+        assert_eq!(
+            screen.handle(ButtonEvent::LongPress(Button::Green), Instant::from_secs(2)),
+            Action::Redraw
+        );
+    }
+
+    #[test]
+    fn screen_timeout_swallows_wake_up_event() {
+        let settings = Settings {
+            screen_timeout_s: 30,
+            ..Settings::default()
+        };
+        let mut screen = Screen::new(Instant::from_secs(0), settings);
+        screen.handle(ButtonEvent::Click(Button::Green), Instant::from_secs(10));
+        assert_eq!(screen.sleep_at(), Some(Instant::from_secs(40)));
+        assert!(screen.awake(Instant::from_secs(39)));
+        assert!(!screen.awake(Instant::from_secs(40)));
+
+        screen.handle(ButtonEvent::Click(Button::Green), Instant::from_secs(50));
+        assert_eq!(screen.page(), Page::Trends);
+        assert!(screen.awake(Instant::from_secs(50)));
+    }
+
+    #[test]
+    fn readings_page_is_the_main_screen() {
+        let mut display = MockDisplay::new();
+        let screen = Screen::new(Instant::from_secs(0), Settings::default());
+        let model = Model::new();
+
+        render(&mut display, &screen, &model, Instant::from_secs(0)).unwrap();
+
+        assert_eq!(display.texts().next(), Some("Gonk Sensor Readings"));
+        assert_eq!(display.updates(), 1);
+    }
+
+    #[test]
+    fn trends_page_shows_range_and_chart() {
+        let mut display = MockDisplay::new();
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+        screen.show(Page::Trends, Instant::from_secs(0));
+        let model = model_with_temperatures(&[20.0, 21.0, 20.5, 22.0]);
+
+        render(&mut display, &screen, &model, Instant::from_secs(30)).unwrap();
+
+        assert!(
+            display
+                .texts()
+                .eq(["Trends", "Temp: 20.0..22.0 C", "Trend: +2.00 C"])
+        );
+        let chart: heapless::Vec<_, 8> = display
+            .calls
+            .iter()
+            .filter(|call| matches!(call, DisplayCall::Line(_, y, _, _) if *y >= CHART_TOP))
+            .collect();
+        assert_eq!(chart.len(), 3);
+        assert_eq!(chart[0], &DisplayCall::Line(0, 63, 42, 53));
+        assert_eq!(chart[2], &DisplayCall::Line(84, 58, 127, 42));
+    }
+
+    #[test]
+    fn chart_is_downsampled() {
+        let mut display = MockDisplay::new();
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+        screen.show(Page::Trends, Instant::from_secs(0));
+        let temperatures: [f32; 120] = core::array::from_fn(|i| 20.0 + i as f32 / 10.0);
+        let model = model_with_temperatures(&temperatures);
+
+        render(&mut display, &screen, &model, Instant::from_secs(0)).unwrap();
+
+        let segments = display
+            .calls
+            .iter()
+            .filter(|call| matches!(call, DisplayCall::Line(_, y, _, _) if *y >= CHART_TOP))
+            .count();
+        assert!(segments < CHART_POINTS);
+    }
+
+    #[test]
+    fn trends_page_without_history() {
+        let mut display = MockDisplay::new();
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+        screen.show(Page::Trends, Instant::from_secs(0));
+
+        render(&mut display, &screen, &Model::new(), Instant::from_secs(0)).unwrap();
+
+        assert!(display.texts().eq(["Trends", "No data yet"]));
+    }
+
+    #[test]
+    fn weather_page_compares_indoor_and_outdoor() {
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+        screen.show(Page::Weather, Instant::from_secs(0));
+        let mut model = model_with_temperatures(&[21.46]);
+        model.humidity = Some(40.0);
+        let mut display = MockDisplay::new();
+
+        render(&mut display, &screen, &model, Instant::from_secs(5)).unwrap();
+        assert!(display.texts().eq(["Weather", "No weather data"]));
+
+        let mut forecast = Forecast::new();
+        for (i, temperature) in [9.0, 14.6, 11.0].into_iter().enumerate() {
+            forecast
+                .push(ForecastEntry {
+                    time: i as i64 * 10800,
+                    temperature,
+                    humidity: 70.0,
+                    precipitation: 0.0,
+                    description: String::new(),
+                })
+                .unwrap();
+        }
+        model.weather = Some(Weather {
+            current: CurrentWeather {
+                place: String::try_from("Berlin").unwrap(),
+                observed_at: 0,
+                temperature: 12.34,
+                feels_like: 11.5,
+                humidity: 81.0,
+                pressure: 101200.0,
+                wind_speed: 4.1,
+                description: String::try_from("light rain").unwrap(),
+            },
+            forecast,
+            updated: Instant::from_secs(0),
+        });
+        let mut display = MockDisplay::new();
+        render(&mut display, &screen, &model, Instant::from_secs(5)).unwrap();
+
+        assert!(display.texts().eq([
+            "Weather",
+            "         In    Out",
+            "Temp   21.5   12.3 C",
+            "Hum      40     81 %",
+            "9..15 C light rain",
+        ]));
+    }
+
+    #[test]
+    fn clock_page_shows_local_time() {
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+        screen.show(Page::Clock, Instant::from_secs(0));
+        let mut model = Model::new();
+        let mut display = MockDisplay::new();
+
+        render(&mut display, &screen, &model, Instant::from_secs(5)).unwrap();
+        assert!(display.texts().eq(["Clock", "Waiting for", "time sync"]));
+
+        // 2026-10-17 12:00:00 UTC
+        model
+            .clock
+            .synchronize(Instant::from_secs(10), 1_792_238_400_000_000);
+        let mut display = MockDisplay::new();
+        render(&mut display, &screen, &model, Instant::from_secs(3_919)).unwrap();
+        assert!(
+            display
+                .texts()
+                .eq(["Clock", "13:05 UTC", "Sat 17 Oct 2026", "Synced 65 min ago",])
+        );
+
+        model.timezone = TimeZone::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap();
+        model.time_format = TimeFormat::H12;
+        let mut display = MockDisplay::new();
+        render(&mut display, &screen, &model, Instant::from_secs(3_919)).unwrap();
+        assert!(display.texts().any(|text| text == "3:05 PM CEST"));
+    }
+
+    #[test]
+    fn system_page_shows_uptime() {
+        let mut display = MockDisplay::new();
+        let mut screen = Screen::new(Instant::from_secs(0), Settings::default());
+        screen.show(Page::System, Instant::from_secs(0));
+
+        render(
+            &mut display,
+            &screen,
+            &Model::new(),
+            Instant::from_secs(3723),
+        )
+        .unwrap();
+
+        assert!(display.texts().any(|text| text == "Uptime: 01:02:03"));
+    }
+
+    #[test]
+    fn formats_uptime_with_days() {
+        assert_eq!(format_uptime(Instant::from_secs(59)).as_str(), "00:00:59");
+        assert_eq!(
+            format_uptime(Instant::from_secs(2 * 86_400 + 3 * 3600 + 4 * 60 + 5)).as_str(),
+            "2 d 03:04:05"
+        );
+    }
+}
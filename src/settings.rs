@@ -0,0 +1,140 @@
+//! User settings of the stored configuration (hardware-independent)
+
+use core::fmt::Write;
+
+use embassy_time::Duration;
+use heapless::String;
+
+/// Refresh intervals offered by the menu, in seconds
+pub const REFRESH_INTERVALS_S: [u16; 5] = [2, 6, 10, 30, 60];
+/// Contrast levels offered by the menu
+pub const CONTRAST_LEVELS: [u8; 4] = [32, 96, 160, 255];
+/// Screen timeouts offered by the menu in seconds, 0 keeps the screen on
+pub const SCREEN_TIMEOUTS_S: [u16; 4] = [0, 30, 60, 300];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+    /// Convert a temperature given in Celsius
+    pub fn convert(self, celsius: f32) -> f32 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => celsius * 9.0 / 5.0 + 32.0,
+        }
+    }
+
+    /// Convert a temperature difference given in Celsius
+    pub fn convert_delta(self, celsius: f32) -> f32 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => celsius * 9.0 / 5.0,
+        }
+    }
+
+    /// Unit suffix, with the leading space used by `format_value()`
+    pub fn suffix(self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => " C",
+            TemperatureUnit::Fahrenheit => " F",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Normal,
+    /// Upside down, for a display mounted the other way around
+    Flipped,
+}
+
+/// Settings changed from the on-device menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub units: TemperatureUnit,
+    /// Time between two sensor readings in seconds
+    pub refresh_interval_s: u16,
+    pub contrast: u8,
+    /// Idle time before the screen is turned off in seconds, 0 keeps it on
+    pub screen_timeout_s: u16,
+    pub rotation: Rotation,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            units: TemperatureUnit::Celsius,
+            refresh_interval_s: 6,
+            contrast: 255,
+            screen_timeout_s: 0,
+            rotation: Rotation::Normal,
+        }
+    }
+}
+
+impl Settings {
+    pub fn refresh_interval(&self) -> Duration {
+        Duration::from_secs(self.refresh_interval_s.max(1) as u64)
+    }
+
+    pub fn screen_timeout(&self) -> Option<Duration> {
+        (self.screen_timeout_s > 0).then(|| Duration::from_secs(self.screen_timeout_s as u64))
+    }
+}
+
+/// Option following `current` in `options`, wrapping around
+///
+/// `options` are in increasing order. A value set through the API that is not
+/// an option moves to the next larger one.
+pub fn next_option<T: Copy + PartialOrd>(options: &[T], current: T) -> T {
+    let next = options.iter().find(|&&option| option > current);
+    *next.unwrap_or(&options[0])
+}
+
+/// Human readable duration in seconds, e.g. "30 s" or "5 min"
+pub fn format_seconds(secs: u16) -> String<16> {
+    let mut buffer = String::new();
+    let _ = if secs >= 60 && secs.is_multiple_of(60) {
+        write!(buffer, "{} min", secs / 60)
+    } else {
+        write!(buffer, "{} s", secs)
+    };
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_units() {
+        assert_eq!(TemperatureUnit::Fahrenheit.convert(20.0), 68.0);
+        assert_eq!(TemperatureUnit::Fahrenheit.convert_delta(2.0), 3.6);
+        assert_eq!(TemperatureUnit::Celsius.convert(20.0), 20.0);
+    }
+
+    #[test]
+    fn cycles_options() {
+        assert_eq!(next_option(&REFRESH_INTERVALS_S, 6), 10);
+        assert_eq!(next_option(&REFRESH_INTERVALS_S, 60), 2);
+    }
+
+    #[test]
+    fn moves_other_values_to_next_larger_option() {
+        assert_eq!(next_option(&REFRESH_INTERVALS_S, 7), 10);
+        assert_eq!(next_option(&REFRESH_INTERVALS_S, 45), 60);
+        assert_eq!(next_option(&REFRESH_INTERVALS_S, 3600), 2);
+        assert_eq!(next_option(&SCREEN_TIMEOUTS_S, 45), 60);
+        assert_eq!(next_option(&CONTRAST_LEVELS, 200), 255);
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_seconds(30).as_str(), "30 s");
+        assert_eq!(format_seconds(300).as_str(), "5 min");
+        assert_eq!(format_seconds(90).as_str(), "90 s");
+    }
+}
@@ -0,0 +1,174 @@
+//! SNTP client packets, RFC 4330 (hardware-independent)
+//!
+//! A request carries a random transmit timestamp instead of the time, which
+//! is not known yet. The server echoes it as the originate timestamp, so that
+//! stray or forged replies are told apart. The server time is taken at the
+//! middle of the round trip, minus the time the server spent on the request.
+
+use embassy_time::{Duration, Instant};
+
+use crate::error::{GonkError, NetworkError};
+
+pub const PORT: u16 = 123;
+/// Server asked for the time
+pub const DEFAULT_SERVER: &str = "pool.ntp.org";
+/// Length of a packet without extensions
+pub const PACKET_LEN: usize = 48;
+
+/// Seconds from 1900-01-01, the NTP epoch, to 1970-01-01
+const UNIX_OFFSET: i64 = 2_208_988_800;
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+/// Leap indicator of a server whose clock is not synchronized
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+/// Server time measured by one exchange
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// Unix time in microseconds at `at`
+    pub unix_micros: i64,
+    /// Local instant the reply was received
+    pub at: Instant,
+    /// Round trip time, without the time spent by the server
+    pub delay: Duration,
+    /// Distance of the server from a reference clock
+    pub stratum: u8,
+}
+
+/// Write a client request, `nonce` is echoed back by the server
+pub fn write_request(packet: &mut [u8; PACKET_LEN], nonce: u64) {
+    packet.fill(0);
+    packet[0] = (VERSION << 3) | MODE_CLIENT;
+    packet[40..48].copy_from_slice(&nonce.to_be_bytes());
+}
+
+/// NTP timestamp, 32.32 fixed point seconds since 1900, as Unix microseconds
+///
+/// Timestamps with the top bit clear are taken to be after the 2036 wrap.
+fn unix_micros(timestamp: u64) -> i64 {
+    let mut seconds = (timestamp >> 32) as i64;
+    if seconds < 0x8000_0000 {
+        seconds += 1 << 32;
+    }
+    let fraction = ((timestamp & 0xFFFF_FFFF) * 1_000_000) >> 32;
+    (seconds - UNIX_OFFSET) * 1_000_000 + fraction as i64
+}
+
+/// Read the reply to the request with `nonce` sent at `sent` and received at
+/// `received`
+pub fn parse_reply(
+    packet: &[u8],
+    nonce: u64,
+    sent: Instant,
+    received: Instant,
+) -> Result<Sample, GonkError> {
+    let packet: &[u8; PACKET_LEN] = packet
+        .get(..PACKET_LEN)
+        .and_then(|p| p.try_into().ok())
+        .ok_or(NetworkError::BadResponse)?;
+    let timestamp = |offset: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&packet[offset..offset + 8]);
+        u64::from_be_bytes(bytes)
+    };
+
+    let leap = packet[0] >> 6;
+    let mode = packet[0] & 0x07;
+    let stratum = packet[1];
+    let (originate, receive, transmit) = (timestamp(24), timestamp(32), timestamp(40));
+    // Stratum 0 is a "kiss-o'-death", the server asks to stop or slow down
+    if mode != MODE_SERVER
+        || leap == LEAP_UNSYNCHRONIZED
+        || !(1..16).contains(&stratum)
+        || originate != nonce
+        || transmit == 0
+    {
+        return Err(NetworkError::BadResponse.into());
+    }
+
+    let server_receive = unix_micros(receive);
+    let server_transmit = unix_micros(transmit);
+    let round_trip = received.saturating_duration_since(sent).as_micros() as i64;
+    let delay = (round_trip - (server_transmit - server_receive)).max(0);
+    Ok(Sample {
+        unix_micros: server_transmit + delay / 2,
+        at: received,
+        delay: Duration::from_micros(delay as u64),
+        stratum,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NONCE: u64 = 0x1234_5678_9ABC_DEF0;
+    /// 2026-10-17 12:00:00 UTC as NTP seconds
+    const NOON: u64 = 1_792_238_400 + UNIX_OFFSET as u64;
+
+    fn reply(receive: u64, transmit: u64) -> [u8; PACKET_LEN] {
+        let mut packet = [0u8; PACKET_LEN];
+        packet[0] = (VERSION << 3) | MODE_SERVER;
+        packet[1] = 2;
+        packet[24..32].copy_from_slice(&NONCE.to_be_bytes());
+        packet[32..40].copy_from_slice(&receive.to_be_bytes());
+        packet[40..48].copy_from_slice(&transmit.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn writes_requests() {
+        let mut packet = [0xFFu8; PACKET_LEN];
+        write_request(&mut packet, NONCE);
+
+        assert_eq!(packet[0], 0x23);
+        assert!(packet[1..40].iter().all(|&b| b == 0));
+        assert_eq!(packet[40..], NONCE.to_be_bytes());
+    }
+
+    #[test]
+    fn takes_time_at_middle_of_round_trip() {
+        // Received at noon, answered 1/4 s later, after a 100 ms round trip
+        let receive = NOON << 32;
+        let transmit = receive + (1 << 30);
+        let sample = parse_reply(
+            &reply(receive, transmit),
+            NONCE,
+            Instant::from_millis(1_000),
+            Instant::from_millis(1_350),
+        )
+        .unwrap();
+
+        assert_eq!(sample.delay, Duration::from_millis(100));
+        assert_eq!(sample.at, Instant::from_millis(1_350));
+        assert_eq!(sample.unix_micros, 1_792_238_400_300_000);
+        assert_eq!(sample.stratum, 2);
+    }
+
+    #[test]
+    fn rejects_unexpected_replies() {
+        let at = Instant::from_secs(1);
+        let good = reply(NOON << 32, NOON << 32);
+        assert!(parse_reply(&good, NONCE, at, at).is_ok());
+        assert!(parse_reply(&good[..47], NONCE, at, at).is_err());
+        assert!(parse_reply(&good, NONCE + 1, at, at).is_err());
+
+        let mut kiss = good;
+        kiss[1] = 0;
+        assert!(parse_reply(&kiss, NONCE, at, at).is_err());
+        let mut unsynchronized = good;
+        unsynchronized[0] |= 0xC0;
+        assert!(parse_reply(&unsynchronized, NONCE, at, at).is_err());
+        let mut request = good;
+        write_request(&mut request, NONCE);
+        assert!(parse_reply(&request, NONCE, at, at).is_err());
+    }
+
+    #[test]
+    fn handles_era_wrap() {
+        // 2036-02-07 06:28:16 UTC, when NTP seconds wrap to 0
+        assert_eq!(unix_micros(0), 2_085_978_496_000_000);
+        assert_eq!(unix_micros(UNIX_OFFSET as u64 * (1 << 32)), 0);
+    }
+}
@@ -1,31 +1,192 @@
 //! Hardware abstraction traits
 
-/// Trait for temperature sensors
-pub trait TemperatureSensor {
+use embassy_time::Instant;
+
+use crate::error::{GonkError, SensorError};
+use crate::settings::Rotation;
+
+/// Quantities an environmental sensor is able to measure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    pub temperature: bool,
+    pub humidity: bool,
+    pub pressure: bool,
+    pub gas_resistance: bool,
+    pub co2: bool,
+}
+
+/// One timestamped sensor reading, quantities the sensor does not measure are `None`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    /// When the measurement was taken
+    pub timestamp: Instant,
+    /// Temperature in Celsius
+    pub temperature: Option<f32>,
+    /// Relative humidity in %
+    pub humidity: Option<f32>,
+    /// Pressure in Pa
+    pub pressure: Option<f32>,
+    /// Gas resistance in Ohm
+    pub gas_resistance: Option<f32>,
+    /// CO2 concentration in ppm
+    pub co2: Option<u16>,
+}
+
+impl Reading {
+    /// Empty reading taken at `timestamp`
+    pub fn new(timestamp: Instant) -> Self {
+        Self {
+            timestamp,
+            temperature: None,
+            humidity: None,
+            pressure: None,
+            gas_resistance: None,
+            co2: None,
+        }
+    }
+}
+
+/// Trait for temperature, humidity, pressure and air quality sensors
+pub trait EnvironmentalSensor {
     /// Initialize the sensor
-    fn init(&mut self) -> Result<(), &'static str>;
-    
+    fn init(&mut self) -> Result<(), GonkError>;
+
+    /// Quantities returned by `read()`
+    fn capabilities(&self) -> Capabilities;
+
+    /// Take a measurement
+    fn read(&mut self) -> Result<Reading, GonkError>;
+
     /// Read temperature in Celsius
-    fn read_temperature(&mut self) -> Result<f32, &'static str>;
+    fn read_temperature(&mut self) -> Result<f32, GonkError> {
+        self.read()?
+            .temperature
+            .ok_or(SensorError::Unsupported.into())
+    }
+}
+
+/// Display backends supported by the firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayType {
+    /// Waveshare 2.13" V2 e-paper panel on SPI
+    EPaper,
+    /// 128x64 OLED on I2C
+    SSD1306,
+}
+
+impl DisplayType {
+    /// Parse a configured display name, "epaper" or "ssd1306"
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("epaper") {
+            Some(DisplayType::EPaper)
+        } else if name.eq_ignore_ascii_case("ssd1306") {
+            Some(DisplayType::SSD1306)
+        } else {
+            None
+        }
+    }
 }
 
 /// Trait for display devices
 pub trait Display {
     /// Initialize the display
-    fn init(&mut self) -> Result<(), &'static str>;
-    
+    fn init(&mut self) -> Result<(), GonkError>;
+
     /// Clear the display
-    fn clear(&mut self) -> Result<(), &'static str>;
-    
+    fn clear(&mut self) -> Result<(), GonkError>;
+
     /// Draw text at specified position
-    fn draw_text(&mut self, text: &str, x: i32, y: i32) -> Result<(), &'static str>;
-    
+    fn draw_text(&mut self, text: &str, x: i32, y: i32) -> Result<(), GonkError>;
+
+    /// Draw a one pixel wide line between two points
+    fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32) -> Result<(), GonkError>;
+
     /// Update/flush the display (show the buffer)
-    fn update(&mut self) -> Result<(), &'static str>;
+    fn update(&mut self) -> Result<(), GonkError>;
+
+    /// Set the brightness, ignored by displays without one
+    fn set_contrast(&mut self, _contrast: u8) -> Result<(), GonkError> {
+        Ok(())
+    }
+
+    /// Rotate the content, applied from the next frame
+    fn set_rotation(&mut self, _rotation: Rotation) -> Result<(), GonkError> {
+        Ok(())
+    }
+
+    /// Turn the panel on or off, ignored by displays keeping their image unpowered
+    fn set_power(&mut self, _on: bool) -> Result<(), GonkError> {
+        Ok(())
+    }
+}
+
+/// Stand-in for a missing or broken panel, so that the firmware runs without
+/// a screen
+#[derive(Debug, Default)]
+pub struct NoDisplay;
+
+impl Display for NoDisplay {
+    fn init(&mut self) -> Result<(), GonkError> {
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), GonkError> {
+        Ok(())
+    }
+
+    fn draw_text(&mut self, _text: &str, _x: i32, _y: i32) -> Result<(), GonkError> {
+        Ok(())
+    }
+
+    fn draw_line(&mut self, _x0: i32, _y0: i32, _x1: i32, _y1: i32) -> Result<(), GonkError> {
+        Ok(())
+    }
+
+    fn update(&mut self) -> Result<(), GonkError> {
+        Ok(())
+    }
 }
 
 /// Trait for I2C operations
 pub trait I2cBus {
-    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), &'static str>;
-    fn write_read(&mut self, addr: u8, write: &[u8], read: &mut [u8]) -> Result<(), &'static str>;
+    fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), GonkError>;
+    fn write_read(&mut self, addr: u8, write: &[u8], read: &mut [u8]) -> Result<(), GonkError>;
+}
+
+/// Trait for exchanging a request and its response with a server
+// Implemented and called with concrete types only, no `Send` bound is needed
+#[allow(async_fn_in_trait)]
+pub trait HttpClient {
+    /// Send `request` to `host` on `port` and read the response until the
+    /// server closes the connection, returns the length read into `response`
+    async fn exchange(
+        &mut self,
+        host: &str,
+        port: u16,
+        request: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, GonkError>;
+}
+
+/// Trait for a small storage area kept across reboots
+pub trait Storage {
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), GonkError>;
+
+    /// Write `data`, erasing the flash as needed
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), GonkError>;
+}
+
+/// Application slots of the flash, for firmware updates
+///
+/// The image is written to the slot not running, which is booted on trial
+/// once activated.
+pub trait FirmwareSlots {
+    /// Size of the slot an update is written to
+    fn capacity(&mut self) -> Result<u32, GonkError>;
+
+    /// Write `data` at `offset` of that slot, erasing the flash as needed
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), GonkError>;
+
+    /// Boot that slot from the next reset
+    fn activate(&mut self) -> Result<(), GonkError>;
 }
@@ -0,0 +1,530 @@
+//! POSIX time zones and local time formatting (hardware-independent)
+//!
+//! A time zone is given as a POSIX TZ string, e.g. `CET-1CEST,M3.5.0,M10.5.0/3`:
+//! standard time name and offset, then optionally the daylight saving time
+//! name, offset and the rules of its start and end. Offsets count hours west
+//! of Greenwich, so Central Europe is `-1`. Names are letters, or anything
+//! quoted in angle brackets such as `<+0530>`.
+//!
+//! Rules are `Mm.w.d` (day `d` of week `w` of month `m`, week 5 being the
+//! last), `Jn` (day of year 1 to 365, never counting February 29th) or `n`
+//! (day of year 0 to 365), each optionally followed by `/time` in local time,
+//! 02:00 by default. Daylight saving time without rules follows the US rules.
+
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::clock::{DateTime, days_from_civil};
+
+/// Longest TZ string kept in the configuration
+pub const MAX_TZ_LEN: usize = 48;
+
+const HOUR: i32 = 3600;
+/// Rule times can be anywhere within a week, RFC 8536
+const MAX_RULE_HOURS: i32 = 167;
+/// Offsets go a little past a day
+const MAX_OFFSET_HOURS: i32 = 24;
+/// Rules used when daylight saving time has none, as with glibc
+const DEFAULT_RULES: (Rule, Rule) = (
+    Rule {
+        date: RuleDate::MonthWeekDay {
+            month: 3,
+            week: 2,
+            weekday: 0,
+        },
+        time: 2 * HOUR,
+    },
+    Rule {
+        date: RuleDate::MonthWeekDay {
+            month: 11,
+            week: 1,
+            weekday: 0,
+        },
+        time: 2 * HOUR,
+    },
+);
+
+/// Day a daylight saving time transition happens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleDate {
+    /// Day 1 to 365 of the year, February 29th is never counted
+    Julian(u16),
+    /// Day 0 to 365 of the year
+    DayOfYear(u16),
+    /// Weekday (0 is Sunday) of the week of the month, 5 is the last week
+    MonthWeekDay { month: u8, week: u8, weekday: u8 },
+}
+
+/// Transition between standard and daylight saving time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    pub date: RuleDate,
+    /// Local time of the transition, in seconds after midnight
+    pub time: i32,
+}
+
+impl Rule {
+    /// Local seconds since 1970-01-01 of the transition in `year`
+    fn local_time(&self, year: i32) -> i64 {
+        let january = days_from_civil(year, 1, 1);
+        let day = match self.date {
+            RuleDate::Julian(n) => {
+                let leap_day = is_leap_year(year) && n >= 60;
+                january + n as i64 - 1 + leap_day as i64
+            }
+            RuleDate::DayOfYear(n) => january + n as i64,
+            RuleDate::MonthWeekDay {
+                month,
+                week,
+                weekday,
+            } => {
+                let first = days_from_civil(year, month, 1);
+                let next = if month == 12 {
+                    days_from_civil(year + 1, 1, 1)
+                } else {
+                    days_from_civil(year, month + 1, 1)
+                };
+                // 1970-01-01 was a Thursday
+                let first_weekday = (first + 4).rem_euclid(7);
+                let mut day = first + (weekday as i64 - first_weekday).rem_euclid(7);
+                day += 7 * (week as i64 - 1);
+                while day >= next {
+                    day -= 7;
+                }
+                day
+            }
+        };
+        day * 86_400 + self.time as i64
+    }
+}
+
+/// Daylight saving time of a time zone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dst {
+    pub name: String<8>,
+    /// Seconds east of UTC
+    pub offset: i32,
+    pub start: Rule,
+    pub end: Rule,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeZone {
+    pub name: String<8>,
+    /// Seconds east of UTC of standard time
+    pub offset: i32,
+    pub dst: Option<Dst>,
+}
+
+/// Local date and time, with the offset in effect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub time: DateTime,
+    /// Seconds east of UTC
+    pub offset: i32,
+    pub dst: bool,
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        Self::utc()
+    }
+}
+
+impl TimeZone {
+    pub fn utc() -> Self {
+        Self {
+            name: String::try_from("UTC").unwrap(),
+            offset: 0,
+            dst: None,
+        }
+    }
+
+    /// `None` when `tz` is not a valid POSIX TZ string, empty is UTC
+    pub fn parse(tz: &str) -> Option<Self> {
+        if tz.trim().is_empty() {
+            return Some(Self::utc());
+        }
+        let mut cursor = Cursor {
+            text: tz.trim().as_bytes(),
+            pos: 0,
+        };
+        let name = cursor.name()?;
+        let offset = -cursor.offset(MAX_OFFSET_HOURS)?;
+        if cursor.done() {
+            return Some(Self {
+                name,
+                offset,
+                dst: None,
+            });
+        }
+
+        let dst_name = cursor.name()?;
+        let dst_offset = match cursor.peek() {
+            None | Some(b',') => offset + HOUR,
+            Some(_) => -cursor.offset(MAX_OFFSET_HOURS)?,
+        };
+        let (start, end) = if cursor.done() {
+            DEFAULT_RULES
+        } else {
+            cursor.expect(b',')?;
+            let start = cursor.rule()?;
+            cursor.expect(b',')?;
+            (start, cursor.rule()?)
+        };
+        if !cursor.done() {
+            return None;
+        }
+        Some(Self {
+            name,
+            offset,
+            dst: Some(Dst {
+                name: dst_name,
+                offset: dst_offset,
+                start,
+                end,
+            }),
+        })
+    }
+
+    /// Offset east of UTC at the Unix time `utc`, and whether it is daylight
+    /// saving time
+    pub fn offset_at(&self, utc: i64) -> (i32, bool) {
+        let Some(dst) = &self.dst else {
+            return (self.offset, false);
+        };
+        let year = DateTime::from_unix(utc + self.offset as i64).year;
+        // Start is given in standard time, end in daylight saving time
+        let start = dst.start.local_time(year) - self.offset as i64;
+        let end = dst.end.local_time(year) - dst.offset as i64;
+        let in_dst = if start < end {
+            start <= utc && utc < end
+        } else {
+            // Southern hemisphere, daylight saving time spans the new year
+            !(end <= utc && utc < start)
+        };
+        if in_dst {
+            (dst.offset, true)
+        } else {
+            (self.offset, false)
+        }
+    }
+
+    /// Local date and time at the Unix time `utc`
+    pub fn local(&self, utc: i64) -> LocalTime {
+        let (offset, dst) = self.offset_at(utc);
+        LocalTime {
+            time: DateTime::from_unix(utc + offset as i64),
+            offset,
+            dst,
+        }
+    }
+
+    /// Name of standard or daylight saving time, e.g. "CEST"
+    pub fn abbreviation(&self, dst: bool) -> &str {
+        match &self.dst {
+            Some(daylight) if dst => &daylight.name,
+            _ => &self.name,
+        }
+    }
+}
+
+fn is_leap_year(year: i32) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// Reads a TZ string from left to right
+struct Cursor<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Cursor<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn done(&self) -> bool {
+        self.pos == self.text.len()
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        (self.peek()? == byte).then(|| self.pos += 1)
+    }
+
+    /// Three or more letters, or any name in angle brackets
+    fn name(&mut self) -> Option<String<8>> {
+        let quoted = self.peek() == Some(b'<');
+        if quoted {
+            self.pos += 1;
+        }
+        let start = self.pos;
+        while let Some(byte) = self.peek() {
+            let allowed = byte.is_ascii_alphabetic()
+                || (quoted && (byte.is_ascii_digit() || byte == b'+' || byte == b'-'));
+            if !allowed {
+                break;
+            }
+            self.pos += 1;
+        }
+        let name = core::str::from_utf8(&self.text[start..self.pos]).ok()?;
+        if quoted {
+            self.expect(b'>')?;
+        }
+        if name.len() < 3 {
+            return None;
+        }
+        String::try_from(name).ok()
+    }
+
+    /// Number of one or more digits
+    fn number(&mut self, max: u32) -> Option<u32> {
+        let start = self.pos;
+        let mut value: u32 = 0;
+        while let Some(byte @ b'0'..=b'9') = self.peek() {
+            value = value.checked_mul(10)? + (byte - b'0') as u32;
+            self.pos += 1;
+        }
+        (self.pos > start && value <= max).then_some(value)
+    }
+
+    /// `[+-]hh[:mm[:ss]]` in seconds, hours up to `max_hours`
+    fn offset(&mut self, max_hours: i32) -> Option<i32> {
+        let sign = match self.peek() {
+            Some(b'-') => {
+                self.pos += 1;
+                -1
+            }
+            Some(b'+') => {
+                self.pos += 1;
+                1
+            }
+            _ => 1,
+        };
+        let mut seconds = self.number(max_hours as u32)? as i32 * HOUR;
+        for unit in [60, 1] {
+            if self.peek() != Some(b':') {
+                break;
+            }
+            self.pos += 1;
+            seconds += self.number(59)? as i32 * unit;
+        }
+        Some(sign * seconds)
+    }
+
+    fn rule(&mut self) -> Option<Rule> {
+        let date = match self.peek()? {
+            b'J' => {
+                self.pos += 1;
+                RuleDate::Julian(self.number(365).filter(|&n| n >= 1)? as u16)
+            }
+            b'M' => {
+                self.pos += 1;
+                let month = self.number(12).filter(|&m| m >= 1)? as u8;
+                self.expect(b'.')?;
+                let week = self.number(5).filter(|&w| w >= 1)? as u8;
+                self.expect(b'.')?;
+                let weekday = self.number(6)? as u8;
+                RuleDate::MonthWeekDay {
+                    month,
+                    week,
+                    weekday,
+                }
+            }
+            _ => RuleDate::DayOfYear(self.number(365)? as u16),
+        };
+        let time = if self.peek() == Some(b'/') {
+            self.pos += 1;
+            self.offset(MAX_RULE_HOURS)?
+        } else {
+            2 * HOUR
+        };
+        Some(Rule { date, time })
+    }
+}
+
+/// How times of day are shown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// e.g. "13:05"
+    #[default]
+    H24,
+    /// e.g. "1:05 PM"
+    H12,
+}
+
+impl TimeFormat {
+    /// Time of day, with the seconds when `seconds` is set
+    pub fn format(self, time: &DateTime, seconds: bool) -> String<11> {
+        let mut buffer = String::new();
+        let _ = match self {
+            TimeFormat::H24 => write!(buffer, "{:02}:{:02}", time.hour, time.minute),
+            TimeFormat::H12 => {
+                let hour = match time.hour % 12 {
+                    0 => 12,
+                    hour => hour,
+                };
+                write!(buffer, "{}:{:02}", hour, time.minute)
+            }
+        };
+        if seconds {
+            let _ = write!(buffer, ":{:02}", time.second);
+        }
+        if self == TimeFormat::H12 {
+            let _ = buffer.push_str(if time.hour < 12 { " AM" } else { " PM" });
+        }
+        buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BERLIN: &str = "CET-1CEST,M3.5.0,M10.5.0/3";
+
+    fn local(tz: &TimeZone, utc: i64) -> (String<11>, &str) {
+        let local = tz.local(utc);
+        (
+            TimeFormat::H24.format(&local.time, true),
+            tz.abbreviation(local.dst),
+        )
+    }
+
+    #[test]
+    fn parses_tz_strings() {
+        let berlin = TimeZone::parse(BERLIN).unwrap();
+        assert_eq!(berlin.name, "CET");
+        assert_eq!(berlin.offset, 3600);
+        let dst = berlin.dst.unwrap();
+        assert_eq!((dst.name.as_str(), dst.offset), ("CEST", 7200));
+        assert_eq!(
+            dst.end,
+            Rule {
+                date: RuleDate::MonthWeekDay {
+                    month: 10,
+                    week: 5,
+                    weekday: 0
+                },
+                time: 3 * HOUR
+            }
+        );
+
+        let india = TimeZone::parse("<+0530>-5:30").unwrap();
+        assert_eq!((india.name.as_str(), india.offset), ("+0530", 19_800));
+        assert!(india.dst.is_none());
+
+        let greenland = TimeZone::parse("<-02>2<-01>,M3.5.0/-1,M10.5.0/0").unwrap();
+        assert_eq!(greenland.offset, -7200);
+        assert_eq!(greenland.dst.as_ref().unwrap().offset, -3600);
+        assert_eq!(greenland.dst.unwrap().start.time, -HOUR);
+
+        assert_eq!(TimeZone::parse(""), Some(TimeZone::utc()));
+        assert_eq!(TimeZone::parse("UTC0"), Some(TimeZone::utc()));
+    }
+
+    #[test]
+    fn rejects_invalid_tz_strings() {
+        for tz in [
+            "CET",
+            "CE-1",
+            "CET-25",
+            "CET-1CEST,M13.5.0,M10.5.0",
+            "CET-1CEST,M3.6.0,M10.5.0",
+            "CET-1CEST,M3.5.7,M10.5.0",
+            "CET-1CEST,M3.5.0",
+            "CET-1CEST,J0,J365",
+            "CET-1CEST,M3.5.0,M10.5.0/168",
+            "CET-1CEST,M3.5.0,M10.5.0x",
+            "<+0530-5:30",
+            ":Europe/Berlin",
+            "LONGERNAME-1",
+        ] {
+            assert_eq!(TimeZone::parse(tz), None, "{}", tz);
+        }
+    }
+
+    #[test]
+    fn switches_at_european_transitions() {
+        let berlin = TimeZone::parse(BERLIN).unwrap();
+
+        assert_eq!(
+            local(&berlin, 1_774_745_999),
+            ("01:59:59".try_into().unwrap(), "CET")
+        );
+        assert_eq!(
+            local(&berlin, 1_774_746_000),
+            ("03:00:00".try_into().unwrap(), "CEST")
+        );
+        assert_eq!(
+            local(&berlin, 1_792_889_999),
+            ("02:59:59".try_into().unwrap(), "CEST")
+        );
+        assert_eq!(
+            local(&berlin, 1_792_890_000),
+            ("02:00:00".try_into().unwrap(), "CET")
+        );
+        assert_eq!(berlin.local(1_792_890_000).time.day, 25);
+    }
+
+    #[test]
+    fn defaults_to_us_rules() {
+        let new_york = TimeZone::parse("EST5EDT").unwrap();
+        assert_eq!(new_york.offset, -5 * HOUR);
+
+        assert_eq!(new_york.offset_at(1_772_953_199), (-5 * HOUR, false));
+        assert_eq!(new_york.offset_at(1_772_953_200), (-4 * HOUR, true));
+        assert_eq!(new_york.offset_at(1_793_512_799), (-4 * HOUR, true));
+        assert_eq!(new_york.offset_at(1_793_512_800), (-5 * HOUR, false));
+        assert_eq!(TimeZone::parse("EST5EDT,M3.2.0,M11.1.0"), Some(new_york));
+    }
+
+    #[test]
+    fn handles_southern_hemisphere() {
+        let sydney = TimeZone::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+
+        // New year is in daylight saving time
+        assert_eq!(
+            local(&sydney, 1_767_225_600),
+            ("11:00:00".try_into().unwrap(), "AEDT")
+        );
+        assert_eq!(sydney.offset_at(1_775_318_399), (11 * HOUR, true));
+        assert_eq!(sydney.offset_at(1_775_318_400), (10 * HOUR, false));
+        assert_eq!(sydney.offset_at(1_791_043_199), (10 * HOUR, false));
+        assert_eq!(sydney.offset_at(1_791_043_200), (11 * HOUR, true));
+    }
+
+    #[test]
+    fn counts_julian_days() {
+        let march = |date| {
+            let rule = Rule { date, time: 0 };
+            DateTime::from_unix(rule.local_time(2028))
+        };
+
+        // J60 is March 1st even in leap years, day 59 is February 29th
+        let day = march(RuleDate::Julian(60));
+        assert_eq!((day.month, day.day), (3, 1));
+        let day = march(RuleDate::DayOfYear(59));
+        assert_eq!((day.month, day.day), (2, 29));
+        // Last Sunday of a month with five Sundays
+        let day = march(RuleDate::MonthWeekDay {
+            month: 4,
+            week: 5,
+            weekday: 0,
+        });
+        assert_eq!((day.month, day.day), (4, 30));
+    }
+
+    #[test]
+    fn formats_12_and_24_hours() {
+        let time = |hour: i64| DateTime::from_unix(hour * 3600 + 5 * 60 + 9);
+
+        assert_eq!(TimeFormat::H24.format(&time(13), false), "13:05");
+        assert_eq!(TimeFormat::H24.format(&time(0), true), "00:05:09");
+        assert_eq!(TimeFormat::H12.format(&time(13), false), "1:05 PM");
+        assert_eq!(TimeFormat::H12.format(&time(0), true), "12:05:09 AM");
+        assert_eq!(TimeFormat::H12.format(&time(12), false), "12:05 PM");
+        assert_eq!(TimeFormat::H12.format(&time(11), false), "11:05 AM");
+    }
+}
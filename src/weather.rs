@@ -0,0 +1,524 @@
+//! OpenWeather current conditions and forecast (hardware-independent)
+//!
+//! The weather at the configured location is fetched from the OpenWeather
+//! 2.5 API in metric units: the current conditions, then a forecast in steps
+//! of three hours. Only what the display and the API show is kept from the
+//! responses, which are read with the streaming JSON parser.
+//!
+//! The location is a place name, e.g. "Berlin,DE", or a "latitude,longitude"
+//! pair.
+
+use core::fmt::{self, Write};
+
+use embassy_time::{Duration, Instant};
+use heapless::{String, Vec};
+
+use crate::error::{GonkError, NetworkError};
+use crate::http;
+use crate::json::{self, Parser};
+use crate::traits::HttpClient;
+
+pub const HOST: &str = "api.openweathermap.org";
+pub const PORT: u16 = 80;
+/// Time between two updates
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Time before trying again after a failed update
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// Weather older than this is no longer shown
+pub const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+/// Forecast entries kept, three hours apart
+pub const FORECAST_LEN: usize = 8;
+/// Largest response read, a forecast of `FORECAST_LEN` entries takes about 5 KB
+pub const MAX_RESPONSE: usize = 8 * 1024;
+
+/// Conditions observed at the location
+#[derive(Debug, Clone, PartialEq)]
+pub struct CurrentWeather {
+    /// Name of the place, as known by OpenWeather
+    pub place: String<32>,
+    /// Unix time of the observation
+    pub observed_at: i64,
+    /// Temperature in Celsius
+    pub temperature: f32,
+    /// Apparent temperature in Celsius
+    pub feels_like: f32,
+    /// Relative humidity in %
+    pub humidity: f32,
+    /// Pressure at sea level in Pa
+    pub pressure: f32,
+    /// Wind speed in m/s
+    pub wind_speed: f32,
+    /// e.g. "light rain"
+    pub description: String<32>,
+}
+
+/// Forecast conditions at some time
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForecastEntry {
+    /// Unix time the forecast is for
+    pub time: i64,
+    /// Temperature in Celsius
+    pub temperature: f32,
+    /// Relative humidity in %
+    pub humidity: f32,
+    /// Probability of precipitation, from 0 to 1
+    pub precipitation: f32,
+    pub description: String<32>,
+}
+
+pub type Forecast = Vec<ForecastEntry, FORECAST_LEN>;
+
+/// Outdoor weather, as shown next to the readings
+#[derive(Debug, Clone, PartialEq)]
+pub struct Weather {
+    pub current: CurrentWeather,
+    /// Soonest first
+    pub forecast: Forecast,
+    /// When it was fetched
+    pub updated: Instant,
+}
+
+impl Weather {
+    /// Whether it is recent enough to be shown
+    pub fn is_current(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.updated) <= STALE_AFTER
+    }
+
+    /// Lowest and highest forecast temperatures
+    pub fn forecast_range(&self) -> Option<(f32, f32)> {
+        let mut temperatures = self.forecast.iter().map(|entry| entry.temperature);
+        let first = temperatures.next()?;
+        Some(temperatures.fold((first, first), |(min, max), t| (min.min(t), max.max(t))))
+    }
+}
+
+/// Data asked from the API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endpoint {
+    Current,
+    Forecast,
+}
+
+/// Client of the OpenWeather API, over any HTTP transport
+#[derive(Debug, Clone, Copy)]
+pub struct WeatherClient<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    location: &'a str,
+    api_key: &'a str,
+}
+
+impl<'a> WeatherClient<'a> {
+    /// `None` when the location or the API key is not configured
+    pub fn new(location: &'a str, api_key: &'a str) -> Option<Self> {
+        if location.trim().is_empty() || api_key.is_empty() {
+            return None;
+        }
+        Some(Self {
+            host: HOST,
+            port: PORT,
+            location: location.trim(),
+            api_key,
+        })
+    }
+
+    /// Write the HTTP request for `endpoint`
+    pub fn write_request<W: Write>(&self, out: &mut W, endpoint: Endpoint) -> fmt::Result {
+        let path = match endpoint {
+            Endpoint::Current => "/data/2.5/weather",
+            Endpoint::Forecast => "/data/2.5/forecast",
+        };
+        write!(out, "GET {}?", path)?;
+        match coordinates(self.location) {
+            Some((lat, lon)) => write!(out, "lat={}&lon={}", lat, lon)?,
+            None => {
+                out.write_str("q=")?;
+                http::write_url_encoded(out, self.location)?;
+            }
+        }
+        out.write_str("&units=metric&appid=")?;
+        http::write_url_encoded(out, self.api_key)?;
+        if endpoint == Endpoint::Forecast {
+            write!(out, "&cnt={}", FORECAST_LEN)?;
+        }
+        // HTTP/1.0 so that the body is neither chunked nor kept alive
+        write!(
+            out,
+            " HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\n\r\n",
+            self.host
+        )
+    }
+
+    /// Body of the response to `endpoint`, read into `buf`
+    async fn get<'b, C: HttpClient>(
+        &self,
+        client: &mut C,
+        endpoint: Endpoint,
+        buf: &'b mut [u8],
+    ) -> Result<&'b str, GonkError> {
+        let mut request = String::<256>::new();
+        self.write_request(&mut request, endpoint)
+            .map_err(|_| NetworkError::BadRequest)?;
+        let len = client
+            .exchange(self.host, self.port, request.as_bytes(), buf)
+            .await?;
+
+        let response = http::parse_response(&buf[..len])?;
+        if response.status != 200 {
+            return Err(NetworkError::Status(response.status).into());
+        }
+        core::str::from_utf8(response.body).map_err(|_| NetworkError::BadResponse.into())
+    }
+
+    pub async fn current<C: HttpClient>(
+        &self,
+        client: &mut C,
+        buf: &mut [u8],
+    ) -> Result<CurrentWeather, GonkError> {
+        parse_current(self.get(client, Endpoint::Current, buf).await?)
+    }
+
+    pub async fn forecast<C: HttpClient>(
+        &self,
+        client: &mut C,
+        buf: &mut [u8],
+    ) -> Result<Forecast, GonkError> {
+        parse_forecast(self.get(client, Endpoint::Forecast, buf).await?)
+    }
+
+    /// Current conditions and forecast, `buf` holds one response at a time
+    pub async fn fetch<C: HttpClient>(
+        &self,
+        client: &mut C,
+        buf: &mut [u8],
+        now: Instant,
+    ) -> Result<Weather, GonkError> {
+        let current = self.current(client, buf).await?;
+        let forecast = self.forecast(client, buf).await?;
+        Ok(Weather {
+            current,
+            forecast,
+            updated: now,
+        })
+    }
+}
+
+/// Latitude and longitude of a "lat,lon" location
+fn coordinates(location: &str) -> Option<(f32, f32)> {
+    let (lat, lon) = location.split_once(',')?;
+    let lat: f32 = lat.trim().parse().ok()?;
+    let lon: f32 = lon.trim().parse().ok()?;
+    ((-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)).then_some((lat, lon))
+}
+
+/// Members of the "main" object shared by both responses
+#[derive(Default)]
+struct Main {
+    temperature: Option<f32>,
+    feels_like: Option<f32>,
+    humidity: Option<f32>,
+    pressure: Option<f32>,
+}
+
+fn read_main(parser: &mut Parser) -> Result<Main, json::Error> {
+    let mut main = Main::default();
+    parser.object(|parser, name| {
+        match name {
+            "temp" => main.temperature = Some(parser.number()?),
+            "feels_like" => main.feels_like = Some(parser.number()?),
+            "humidity" => main.humidity = Some(parser.number()?),
+            "pressure" => main.pressure = Some(parser.number()? * 100.0),
+            _ => parser.skip()?,
+        }
+        Ok::<_, json::Error>(())
+    })?;
+    Ok(main)
+}
+
+/// Description of the first, primary, condition of a "weather" array
+fn read_description(parser: &mut Parser) -> Result<String<32>, json::Error> {
+    let mut description = String::new();
+    let mut first = true;
+    parser.array(|parser| {
+        parser.object(|parser, name| {
+            match name {
+                "description" if first => description = parser.truncated_string()?,
+                _ => parser.skip()?,
+            }
+            Ok::<_, json::Error>(())
+        })?;
+        first = false;
+        Ok::<_, json::Error>(())
+    })?;
+    Ok(description)
+}
+
+/// Read a current weather response
+pub fn parse_current(text: &str) -> Result<CurrentWeather, GonkError> {
+    let mut parser = Parser::new(text);
+    let mut place = String::new();
+    let mut observed_at = None;
+    let mut main = Main::default();
+    let mut wind_speed = None;
+    let mut description = String::new();
+    parser
+        .object(|parser, name| {
+            match name {
+                "name" => place = parser.truncated_string()?,
+                "dt" => observed_at = Some(parser.integer()?),
+                "main" => main = read_main(parser)?,
+                "wind" => parser.object(|parser, name| {
+                    match name {
+                        "speed" => wind_speed = Some(parser.number()?),
+                        _ => parser.skip()?,
+                    }
+                    Ok::<_, json::Error>(())
+                })?,
+                "weather" => description = read_description(parser)?,
+                _ => parser.skip()?,
+            }
+            Ok::<_, json::Error>(())
+        })
+        .and_then(|_| parser.finish())
+        .map_err(|_| NetworkError::BadResponse)?;
+
+    let (Some(observed_at), Some(temperature), Some(humidity), Some(pressure)) =
+        (observed_at, main.temperature, main.humidity, main.pressure)
+    else {
+        return Err(NetworkError::BadResponse.into());
+    };
+    Ok(CurrentWeather {
+        place,
+        observed_at,
+        temperature,
+        feels_like: main.feels_like.unwrap_or(temperature),
+        humidity,
+        pressure,
+        wind_speed: wind_speed.unwrap_or(0.0),
+        description,
+    })
+}
+
+/// Read a forecast response, entries past `FORECAST_LEN` are dropped
+pub fn parse_forecast(text: &str) -> Result<Forecast, GonkError> {
+    let mut parser = Parser::new(text);
+    let mut forecast = Forecast::new();
+    let mut complete = true;
+    parser
+        .object(|parser, name| {
+            if name != "list" {
+                return parser.skip();
+            }
+            parser.array(|parser| {
+                let mut time = None;
+                let mut main = Main::default();
+                let mut precipitation = 0.0;
+                let mut description = String::new();
+                parser.object(|parser, name| {
+                    match name {
+                        "dt" => time = Some(parser.integer()?),
+                        "main" => main = read_main(parser)?,
+                        "pop" => precipitation = parser.number()?,
+                        "weather" => description = read_description(parser)?,
+                        _ => parser.skip()?,
+                    }
+                    Ok::<_, json::Error>(())
+                })?;
+
+                let (Some(time), Some(temperature), Some(humidity)) =
+                    (time, main.temperature, main.humidity)
+                else {
+                    complete = false;
+                    return Ok(());
+                };
+                let _ = forecast.push(ForecastEntry {
+                    time,
+                    temperature,
+                    humidity,
+                    precipitation,
+                    description,
+                });
+                Ok::<_, json::Error>(())
+            })
+        })
+        .and_then(|_| parser.finish())
+        .map_err(|_| NetworkError::BadResponse)?;
+
+    if !complete {
+        return Err(NetworkError::BadResponse.into());
+    }
+    Ok(forecast)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockHttpServer, block_on};
+
+    const CURRENT: &str = r#"{"coord":{"lon":13.41,"lat":52.52},
+"weather":[{"id":500,"main":"Rain","description":"light rain","icon":"10d"},
+{"id":701,"main":"Mist","description":"mist","icon":"50d"}],"base":"stations",
+"main":{"temp":12.34,"feels_like":11.5,"temp_min":11.1,"temp_max":13.9,
+"pressure":1012,"humidity":81,"sea_level":1012,"grnd_level":1007},
+"visibility":10000,"wind":{"speed":4.12,"deg":250},"rain":{"1h":0.3},
+"clouds":{"all":75},"dt":1760700000,"sys":{"type":2,"id":2011538,"country":"DE",
+"sunrise":1760678751,"sunset":1760716513},"timezone":7200,"id":2950159,
+"name":"Berlin","cod":200}"#;
+
+    fn forecast_entry(dt: i64, temp: f32, pop: f32) -> String<512> {
+        let mut entry = String::new();
+        write!(
+            entry,
+            r#"{{"dt":{},"main":{{"temp":{},"feels_like":9.8,"pressure":1013,
+"humidity":75}},"weather":[{{"id":802,"main":"Clouds",
+"description":"scattered clouds","icon":"03d"}}],"clouds":{{"all":40}},
+"wind":{{"speed":3.2,"deg":240,"gust":6.1}},"visibility":10000,"pop":{},
+"sys":{{"pod":"d"}},"dt_txt":"2025-10-17 12:00:00"}}"#,
+            dt, temp, pop
+        )
+        .unwrap();
+        entry
+    }
+
+    fn forecast_body(count: usize) -> String<8192> {
+        let mut body = String::new();
+        body.push_str(r#"{"cod":"200","message":0,"cnt":9,"list":["#)
+            .unwrap();
+        for i in 0..count {
+            if i > 0 {
+                body.push(',').unwrap();
+            }
+            let temp = 10.0 + i as f32;
+            let entry = forecast_entry(1760702400 + i as i64 * 10800, temp, 0.25);
+            body.push_str(&entry).unwrap();
+        }
+        body.push_str(
+            r#"],"city":{"id":2950159,"name":"Berlin","coord":{"lat":52.52,"lon":13.41},
+"country":"DE","timezone":7200}}"#,
+        )
+        .unwrap();
+        body
+    }
+
+    fn ok(body: &str) -> String<8192> {
+        let mut response = String::new();
+        write!(
+            response,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json; charset=utf-8\r\n\
+Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        response
+    }
+
+    #[test]
+    fn parses_current_weather() {
+        let current = parse_current(CURRENT).unwrap();
+
+        assert_eq!(current.place, "Berlin");
+        assert_eq!(current.observed_at, 1760700000);
+        assert_eq!(current.temperature, 12.34);
+        assert_eq!(current.feels_like, 11.5);
+        assert_eq!(current.humidity, 81.0);
+        assert_eq!(current.pressure, 101200.0);
+        assert_eq!(current.wind_speed, 4.12);
+        assert_eq!(current.description, "light rain");
+    }
+
+    #[test]
+    fn parses_forecast() {
+        let forecast = parse_forecast(&forecast_body(FORECAST_LEN + 1)).unwrap();
+
+        assert_eq!(forecast.len(), FORECAST_LEN);
+        assert_eq!(forecast[0].time, 1760702400);
+        assert_eq!(forecast[0].temperature, 10.0);
+        assert_eq!(forecast[7].temperature, 17.0);
+        assert_eq!(forecast[0].humidity, 75.0);
+        assert_eq!(forecast[0].precipitation, 0.25);
+        assert_eq!(forecast[0].description, "scattered clouds");
+    }
+
+    #[test]
+    fn rejects_incomplete_responses() {
+        let bad = Err(NetworkError::BadResponse.into());
+        assert_eq!(parse_current(r#"{"name":"Berlin","dt":1}"#), bad);
+        assert_eq!(parse_current(&CURRENT[..100]), bad);
+        assert_eq!(parse_current("[]"), bad);
+        assert_eq!(
+            parse_forecast(r#"{"list":[{"dt":1,"main":{"temp":3}}]}"#),
+            Err(NetworkError::BadResponse.into())
+        );
+        assert_eq!(parse_forecast(r#"{"cod":"200","list":[]}"#), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn builds_requests() {
+        let mut request = String::<256>::new();
+        let client = WeatherClient::new("São Paulo,BR", "abc123").unwrap();
+        client
+            .write_request(&mut request, Endpoint::Current)
+            .unwrap();
+        assert!(request.starts_with(
+            "GET /data/2.5/weather?q=S%C3%A3o%20Paulo%2CBR&units=metric&appid=abc123 HTTP/1.0\r\n\
+Host: api.openweathermap.org\r\n"
+        ));
+
+        request.clear();
+        let client = WeatherClient::new(" 52.52, 13.41 ", "abc123").unwrap();
+        client
+            .write_request(&mut request, Endpoint::Forecast)
+            .unwrap();
+        assert!(request.starts_with(
+            "GET /data/2.5/forecast?lat=52.52&lon=13.41&units=metric&appid=abc123&cnt=8 "
+        ));
+
+        assert!(WeatherClient::new("", "abc123").is_none());
+        assert!(WeatherClient::new("Berlin", "").is_none());
+    }
+
+    #[test]
+    fn fetches_from_server() {
+        let current = ok(CURRENT);
+        let forecast = ok(&forecast_body(3));
+        let mut server = MockHttpServer::new();
+        server.route("/data/2.5/weather?", &current);
+        server.route("/data/2.5/forecast?", &forecast);
+        let client = WeatherClient::new("Berlin,DE", "abc123").unwrap();
+
+        let mut buf = [0u8; MAX_RESPONSE];
+        let now = Instant::from_secs(100);
+        let weather = block_on(client.fetch(&mut server, &mut buf, now)).unwrap();
+
+        assert_eq!(weather.current.place, "Berlin");
+        assert_eq!(weather.forecast.len(), 3);
+        assert_eq!(weather.forecast_range(), Some((10.0, 12.0)));
+        assert_eq!(weather.updated, now);
+        assert!(weather.is_current(now + STALE_AFTER));
+        assert!(!weather.is_current(now + STALE_AFTER + Duration::from_secs(1)));
+        assert_eq!(server.requests.len(), 2);
+        assert_eq!(server.hosts[0], (String::try_from(HOST).unwrap(), PORT));
+    }
+
+    #[test]
+    fn reports_server_errors() {
+        let unauthorized = "HTTP/1.1 401 Unauthorized\r\nContent-Length: 24\r\n\r\n\
+{\"cod\":401,\"message\":\"\"}";
+        let mut server = MockHttpServer::new();
+        server.route("/data/2.5/weather?", unauthorized);
+        let client = WeatherClient::new("Berlin,DE", "wrong").unwrap();
+        let mut buf = [0u8; MAX_RESPONSE];
+
+        assert_eq!(
+            block_on(client.fetch(&mut server, &mut buf, Instant::from_secs(0))),
+            Err(NetworkError::Status(401).into())
+        );
+
+        server.fail = Some(NetworkError::Dns.into());
+        assert_eq!(
+            block_on(client.current(&mut server, &mut buf)),
+            Err(NetworkError::Dns.into())
+        );
+    }
+}
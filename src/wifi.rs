@@ -0,0 +1,308 @@
+//! WiFi network selection and reconnect policy (hardware-independent)
+//!
+//! Among the known networks seen by a scan, the strongest is joined, with a
+//! penalty for every failed attempt so that a network refusing the device
+//! does not hide the others. Failed attempts are retried with an exponential
+//! backoff with jitter.
+
+use core::fmt::Write;
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, pubsub::PubSubChannel};
+use embassy_time::Duration;
+use heapless::{String, Vec};
+
+use crate::config::{MAX_NETWORKS, WifiNetwork};
+use crate::portal::FAILURES_BEFORE_PORTAL;
+
+/// Delay before the first retry
+pub const BACKOFF_BASE: Duration = Duration::from_secs(2);
+/// Longest delay between two attempts
+pub const BACKOFF_MAX: Duration = Duration::from_secs(300);
+/// Signal strength taken off a network for each of its failed attempts, in dB
+const FAILURE_PENALTY_DB: i16 = 10;
+
+/// Access point seen by a scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanResult {
+    pub ssid: String<32>,
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    /// Signal strength in dBm
+    pub rssi: i8,
+}
+
+/// Association with an access point
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub ssid: String<32>,
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    /// Signal strength in dBm
+    pub rssi: i8,
+}
+
+impl From<ScanResult> for Link {
+    fn from(ap: ScanResult) -> Self {
+        Self {
+            ssid: ap.ssid,
+            bssid: ap.bssid,
+            channel: ap.channel,
+            rssi: ap.rssi,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WifiState {
+    Disconnected,
+    Scanning,
+    /// Joining the network with this SSID
+    Connecting(String<32>),
+    Connected(Link),
+    /// The setup portal is open on the access point with this SSID
+    Provisioning(String<32>),
+}
+
+/// WiFi state changes, for the tasks that depend on the network
+pub type WifiEvents = PubSubChannel<CriticalSectionRawMutex, WifiState, 4, 4, 1>;
+
+/// What the connection task should do after a scan
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Join the access point, `network` is the matching known network
+    Connect {
+        network: WifiNetwork,
+        ap: ScanResult,
+    },
+    /// No known network is in range
+    NothingVisible,
+    /// Open the setup portal, no network is known or none of them works
+    Provision,
+}
+
+/// Known network selection, failure counts and retry delays
+#[derive(Debug, Default)]
+pub struct WifiManager {
+    /// Failed attempts per SSID since its last successful connection
+    failures: Vec<(String<32>, u16), { 2 * MAX_NETWORKS }>,
+    /// Attempts failed in a row, whatever the network
+    consecutive: u32,
+}
+
+impl WifiManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Failed attempts of the network `ssid` since it last worked
+    pub fn failures(&self, ssid: &str) -> u16 {
+        self.failures
+            .iter()
+            .find(|(name, _)| name == ssid)
+            .map_or(0, |(_, count)| *count)
+    }
+
+    /// Attempts failed in a row
+    pub fn consecutive_failures(&self) -> u32 {
+        self.consecutive
+    }
+
+    /// Pick what to do given the known networks and the scan results
+    pub fn decide(&self, known: &[WifiNetwork], scan: &[ScanResult]) -> Decision {
+        if known.is_empty() || self.consecutive >= FAILURES_BEFORE_PORTAL {
+            return Decision::Provision;
+        }
+
+        let score = |network: &WifiNetwork, ap: &ScanResult| {
+            let penalty = self.failures(&network.ssid) as i16 * FAILURE_PENALTY_DB;
+            ap.rssi as i16 - penalty
+        };
+        let best = scan
+            .iter()
+            .filter_map(|ap| Some((known.iter().find(|n| n.ssid == ap.ssid)?, ap)))
+            .max_by_key(|(network, ap)| score(network, ap));
+
+        match best {
+            Some((network, ap)) => Decision::Connect {
+                network: network.clone(),
+                ap: ap.clone(),
+            },
+            None => Decision::NothingVisible,
+        }
+    }
+
+    pub fn record_success(&mut self, ssid: &str) {
+        self.failures.retain(|(name, _)| name != ssid);
+        self.consecutive = 0;
+    }
+
+    /// Count a failed attempt, `None` when no known network was visible
+    pub fn record_failure(&mut self, ssid: Option<&str>) {
+        self.consecutive = self.consecutive.saturating_add(1);
+        let Some(ssid) = ssid else {
+            return;
+        };
+
+        if let Some((_, count)) = self.failures.iter_mut().find(|(name, _)| name == ssid) {
+            *count = count.saturating_add(1);
+            return;
+        }
+        if self.failures.is_full() {
+            self.failures.remove(0);
+        }
+        if let Ok(name) = String::try_from(ssid) {
+            let _ = self.failures.push((name, 1));
+        }
+    }
+
+    /// Start over, e.g. after new credentials were entered
+    pub fn reset(&mut self) {
+        self.failures.clear();
+        self.consecutive = 0;
+    }
+
+    /// Delay before the next attempt, growing with the consecutive failures
+    ///
+    /// The delay is picked in the upper half of the backoff window from
+    /// `random`, so that devices failing together do not retry together.
+    pub fn retry_delay(&self, random: u32) -> Duration {
+        let exponent = self.consecutive.saturating_sub(1).min(16);
+        let window = (BACKOFF_BASE * (1 << exponent)).min(BACKOFF_MAX);
+        let half = window.as_millis() / 2;
+        Duration::from_millis(half + random as u64 % (half + 1))
+    }
+}
+
+/// BSSID as shown to people, e.g. "24:6F:28:01:AB:0C"
+pub fn format_bssid(bssid: [u8; 6]) -> String<17> {
+    let mut buffer = String::new();
+    for (i, byte) in bssid.iter().enumerate() {
+        let separator = if i == 0 { "" } else { ":" };
+        let _ = write!(buffer, "{}{:02X}", separator, byte);
+    }
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known() -> [WifiNetwork; 2] {
+        [
+            WifiNetwork::new("home", "password1").unwrap(),
+            WifiNetwork::new("office", "password2").unwrap(),
+        ]
+    }
+
+    fn ap(ssid: &str, rssi: i8) -> ScanResult {
+        ScanResult {
+            ssid: String::try_from(ssid).unwrap(),
+            bssid: [0x24, 0x6F, 0x28, 0, 0, rssi as u8],
+            channel: 6,
+            rssi,
+        }
+    }
+
+    fn chosen(decision: Decision) -> (String<32>, i8) {
+        match decision {
+            Decision::Connect { network, ap } => {
+                assert_eq!(network.ssid, ap.ssid);
+                (network.ssid, ap.rssi)
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn picks_strongest_known_network() {
+        let manager = WifiManager::new();
+        let scan = [
+            ap("neighbour", -30),
+            ap("home", -70),
+            ap("office", -55),
+            ap("home", -60),
+        ];
+
+        let (ssid, rssi) = chosen(manager.decide(&known(), &scan));
+        assert_eq!((ssid.as_str(), rssi), ("office", -55));
+    }
+
+    #[test]
+    fn failures_lower_a_network() {
+        let mut manager = WifiManager::new();
+        let scan = [ap("home", -70), ap("office", -55)];
+        manager.record_failure(Some("office"));
+        manager.record_failure(Some("office"));
+        manager.record_success("neighbour");
+        assert_eq!(manager.failures("office"), 2);
+
+        let (ssid, _) = chosen(manager.decide(&known(), &scan));
+        assert_eq!(ssid, "home");
+
+        manager.record_success("office");
+        assert_eq!(manager.failures("office"), 0);
+        let (ssid, _) = chosen(manager.decide(&known(), &scan));
+        assert_eq!(ssid, "office");
+    }
+
+    #[test]
+    fn provisions_without_working_network() {
+        let mut manager = WifiManager::new();
+        assert_eq!(manager.decide(&[], &[ap("home", -40)]), Decision::Provision);
+        assert_eq!(
+            manager.decide(&known(), &[ap("neighbour", -40)]),
+            Decision::NothingVisible
+        );
+
+        for _ in 0..FAILURES_BEFORE_PORTAL {
+            manager.record_failure(None);
+        }
+        assert_eq!(
+            manager.decide(&known(), &[ap("home", -40)]),
+            Decision::Provision
+        );
+
+        manager.reset();
+        assert_eq!(manager.consecutive_failures(), 0);
+    }
+
+    #[test]
+    fn backoff_grows_with_jitter() {
+        let mut manager = WifiManager::new();
+        manager.record_failure(Some("home"));
+        assert_eq!(manager.retry_delay(0), Duration::from_secs(1));
+        assert_eq!(manager.retry_delay(u32::MAX), Duration::from_millis(1_619));
+        assert_eq!(manager.retry_delay(1_000), Duration::from_secs(2));
+
+        manager.record_failure(Some("home"));
+        manager.record_failure(Some("home"));
+        assert_eq!(manager.retry_delay(0), Duration::from_secs(4));
+
+        for _ in 0..20 {
+            manager.record_failure(None);
+        }
+        assert_eq!(manager.retry_delay(0), BACKOFF_MAX / 2);
+        assert!(manager.retry_delay(u32::MAX) <= BACKOFF_MAX);
+    }
+
+    #[test]
+    fn forgets_oldest_failure_counts() {
+        let mut manager = WifiManager::new();
+        for i in 0..=2 * MAX_NETWORKS {
+            let mut ssid = String::<32>::new();
+            write!(ssid, "net{}", i).unwrap();
+            manager.record_failure(Some(&ssid));
+        }
+
+        assert_eq!(manager.failures("net0"), 0);
+        assert_eq!(manager.failures("net8"), 1);
+    }
+
+    #[test]
+    fn formats_bssid() {
+        assert_eq!(
+            format_bssid([0x24, 0x6F, 0x28, 0x01, 0xAB, 0x0C]),
+            "24:6F:28:01:AB:0C"
+        );
+    }
+}